@@ -0,0 +1,27 @@
+use std::path::Path;
+use std::process::Command;
+
+// Packagers building outside of a git checkout can set XWLS_GIT_DESCRIBE themselves,
+// otherwise we try asking git and fall back to the crate version at runtime.
+fn main() {
+    println!("cargo:rerun-if-env-changed=XWLS_GIT_DESCRIBE");
+    if std::env::var_os("XWLS_GIT_DESCRIBE").is_some() {
+        return;
+    }
+
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+        println!("cargo:rerun-if-changed=.git/index");
+    }
+
+    let describe = Command::new("git")
+        .args(["describe", "--tags", "--always", "--dirty"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+
+    if let Some(describe) = describe {
+        println!("cargo:rustc-env=XWLS_GIT_DESCRIBE={}", describe.trim());
+    }
+}
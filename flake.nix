@@ -55,6 +55,8 @@
               xcb-util-cursor
             ];
 
+            XWLS_GIT_DESCRIBE = version;
+
             buildNoDefaultFeatures = true;
             buildFeatures = lib.optionals withSystemd [ "systemd" ];
 
@@ -1,3 +1,5 @@
+pub mod xdg_activation;
+
 use crate::server::{ObjectEvent, ObjectKey};
 use std::os::unix::net::UnixStream;
 use std::sync::{mpsc, Mutex, OnceLock};
@@ -18,6 +20,13 @@ use wayland_protocols::wp::relative_pointer::zv1::client::{
 };
 use wayland_protocols::{
     wp::{
+        content_type::v1::client::{
+            wp_content_type_manager_v1::WpContentTypeManagerV1, wp_content_type_v1::WpContentTypeV1,
+        },
+        idle_inhibit::zv1::client::{
+            zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+            zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+        },
         linux_dmabuf::zv1::client::{
             self as dmabuf,
             zwp_linux_dmabuf_feedback_v1::ZwpLinuxDmabufFeedbackV1 as DmabufFeedback,
@@ -41,9 +50,22 @@ use wayland_protocols::{
             zwp_tablet_tool_v2::ZwpTabletToolV2,
             zwp_tablet_v2::ZwpTabletV2,
         },
+        tearing_control::v1::client::{
+            wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+            wp_tearing_control_v1::WpTearingControlV1,
+        },
         viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
     },
     xdg::{
+        activation::v1::client::{
+            xdg_activation_token_v1::{self, XdgActivationTokenV1},
+            xdg_activation_v1::XdgActivationV1,
+        },
+        decoration::zv1::client::{
+            zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+            zxdg_toplevel_decoration_v1::ZxdgToplevelDecorationV1,
+        },
+        dialog::v1::client::{xdg_dialog_v1::XdgDialogV1, xdg_wm_dialog_v1::XdgWmDialogV1},
         shell::client::{
             xdg_popup::XdgPopup, xdg_positioner::XdgPositioner, xdg_surface::XdgSurface,
             xdg_toplevel::XdgToplevel, xdg_wm_base::XdgWmBase,
@@ -62,11 +84,25 @@ pub struct Globals {
     queued_events: Vec<mpsc::Receiver<(ObjectKey, ObjectEvent)>>,
     pub new_globals: Vec<Global>,
     pub selection: Option<wayland_client::protocol::wl_data_device::WlDataDevice>,
+    pub control_selection: Option<
+        wayland_protocols::ext::data_control::v1::client::ext_data_control_offer_v1::ExtDataControlOfferV1,
+    >,
     pub selection_requests: Vec<(
         String,
         smithay_client_toolkit::data_device_manager::WritePipe,
     )>,
     pub cancelled: bool,
+    /// The compositor sent a ping since this was last cleared.
+    pub pinged: bool,
+    /// Frame callbacks the compositor is done with, which still have to be passed on.
+    pub frames_done: Vec<FrameDone>,
+}
+
+pub struct FrameDone {
+    pub surface: ObjectKey,
+    pub callback: server::wl_callback::WlCallback,
+    pub callback_data: u32,
+    pub time: std::time::Instant,
 }
 
 pub type ClientQueueHandle = QueueHandle<Globals>;
@@ -100,8 +136,12 @@ impl ClientState {
         }
     }
 
-    pub fn read_events(&mut self) -> Vec<(ObjectKey, ObjectEvent)> {
-        let mut events = std::mem::take(&mut self.globals.events);
+    /// Moves all pending events into `events`, which should be empty.
+    /// The allocation of `events` is kept around for the next batch of events, so that passing the
+    /// same buffer in every time avoids reallocating on every dispatch.
+    pub fn read_events(&mut self, events: &mut Vec<(ObjectKey, ObjectEvent)>) {
+        debug_assert!(events.is_empty());
+        std::mem::swap(events, &mut self.globals.events);
         self.globals.queued_events.retain(|rx| {
             match rx.try_recv() {
                 Ok(event) => {
@@ -115,7 +155,6 @@ impl ClientState {
             events.extend(rx.try_iter());
             false
         });
-        events
     }
 }
 
@@ -123,7 +162,6 @@ pub type Event<T> = <T as Proxy>::Event;
 
 delegate_noop!(Globals: WlCompositor);
 delegate_noop!(Globals: WlRegion);
-delegate_noop!(Globals: ignore WlShm);
 delegate_noop!(Globals: ignore ZwpLinuxDmabufV1);
 delegate_noop!(Globals: ZwpRelativePointerManagerV1);
 delegate_noop!(Globals: ignore dmabuf::zwp_linux_buffer_params_v1::ZwpLinuxBufferParamsV1);
@@ -134,6 +172,18 @@ delegate_noop!(Globals: WpViewport);
 delegate_noop!(Globals: ZxdgOutputManagerV1);
 delegate_noop!(Globals: ZwpPointerConstraintsV1);
 delegate_noop!(Globals: ZwpTabletManagerV2);
+delegate_noop!(Globals: XdgWmDialogV1);
+delegate_noop!(Globals: XdgDialogV1);
+delegate_noop!(Globals: ZxdgDecorationManagerV1);
+// The mode the compositor picks is up to it, there's nothing to do about it either way.
+delegate_noop!(Globals: ignore ZxdgToplevelDecorationV1);
+delegate_noop!(Globals: XdgActivationV1);
+delegate_noop!(Globals: WpContentTypeManagerV1);
+delegate_noop!(Globals: WpContentTypeV1);
+delegate_noop!(Globals: WpTearingControlManagerV1);
+delegate_noop!(Globals: WpTearingControlV1);
+delegate_noop!(Globals: ZwpIdleInhibitManagerV1);
+delegate_noop!(Globals: ZwpIdleInhibitorV1);
 
 impl Dispatch<WlRegistry, GlobalListContents> for Globals {
     fn event(
@@ -161,7 +211,7 @@ impl Dispatch<WlRegistry, GlobalListContents> for Globals {
 
 impl Dispatch<XdgWmBase, ()> for Globals {
     fn event(
-        _: &mut Self,
+        state: &mut Self,
         base: &XdgWmBase,
         event: <XdgWmBase as Proxy>::Event,
         _: &(),
@@ -170,21 +220,45 @@ impl Dispatch<XdgWmBase, ()> for Globals {
     ) {
         if let Event::<XdgWmBase>::Ping { serial } = event {
             base.pong(serial);
+            state.pinged = true;
         }
     }
 }
 
-impl Dispatch<WlCallback, server::wl_callback::WlCallback> for Globals {
+impl Dispatch<XdgActivationTokenV1, (XdgActivationV1, WlSurface)> for Globals {
     fn event(
         _: &mut Self,
+        token: &XdgActivationTokenV1,
+        event: <XdgActivationTokenV1 as Proxy>::Event,
+        (activation, surface): &(XdgActivationV1, WlSurface),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let xdg_activation_token_v1::Event::Done { token: name } = event {
+            if surface.is_alive() {
+                activation.activate(name, surface);
+            }
+            token.destroy();
+        }
+    }
+}
+
+impl Dispatch<WlCallback, (server::wl_callback::WlCallback, ObjectKey)> for Globals {
+    fn event(
+        state: &mut Self,
         _: &WlCallback,
         event: <WlCallback as Proxy>::Event,
-        s_callback: &server::wl_callback::WlCallback,
+        (s_callback, surface): &(server::wl_callback::WlCallback, ObjectKey),
         _: &Connection,
         _: &QueueHandle<Self>,
     ) {
         if let Event::<WlCallback>::Done { callback_data } = event {
-            s_callback.done(callback_data);
+            state.frames_done.push(FrameDone {
+                surface: *surface,
+                callback: s_callback.clone(),
+                callback_data,
+                time: std::time::Instant::now(),
+            });
         }
     }
 }
@@ -211,6 +285,7 @@ push_events!(WlBuffer);
 push_events!(XdgSurface);
 push_events!(XdgToplevel);
 push_events!(XdgPopup);
+push_events!(WlShm);
 push_events!(WlSeat);
 push_events!(WlPointer);
 push_events!(WlOutput);
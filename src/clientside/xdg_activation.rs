@@ -0,0 +1,112 @@
+//! Activation tokens X clients were launched with.
+//!
+//! Launchers and terminals hand the apps they start an xdg_activation token, which X clients
+//! publish as _NET_STARTUP_ID. When several apps are started in quick succession, each one gets
+//! its own token, so tokens are kept per client until one of its toplevels shows up instead of
+//! the next toplevel to be mapped taking whichever token arrived last.
+//!
+//! _NET_STARTUP_ID stays on the windows of a client for as long as they exist, so it's read again
+//! for every window the client maps, but the token is only good for the first of them.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use xcb::x;
+
+/// Compositors stop accepting tokens after a while, so there's no point holding on to them
+/// forever.
+const TOKEN_LIFETIME: Duration = Duration::from_secs(30);
+
+/// Who a startup token was meant for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenOwner {
+    /// The client process, from _NET_WM_PID.
+    Pid(u32),
+    /// The window itself, for clients that don't set _NET_WM_PID.
+    Window(x::Window),
+}
+
+#[derive(Debug)]
+struct StartupToken {
+    token: String,
+    received: Instant,
+}
+
+#[derive(Debug, Default)]
+pub struct TokenBroker {
+    tokens: HashMap<TokenOwner, StartupToken>,
+    /// Tokens that were handed out or expired already, and when. They're forgotten after
+    /// [`TOKEN_LIFETIME`], since the compositor would refuse them by then anyway.
+    used: HashMap<String, Instant>,
+}
+
+impl TokenBroker {
+    /// Stores a client's token. A different token for the same client replaces the old one,
+    /// since it was launched again in the meantime. Tokens that were used already are ignored.
+    pub fn offer(&mut self, owner: TokenOwner, token: String) {
+        if self.used.contains_key(&token)
+            || self
+                .tokens
+                .get(&owner)
+                .is_some_and(|current| current.token == token)
+        {
+            return;
+        }
+        self.tokens.insert(
+            owner,
+            StartupToken {
+                token,
+                received: Instant::now(),
+            },
+        );
+    }
+
+    /// Hands out a client's token. Each token can only be used once.
+    pub fn take(&mut self, owner: TokenOwner) -> Option<String> {
+        let token = self.tokens.remove(&owner)?;
+        self.used.insert(token.token.clone(), Instant::now());
+        (token.received.elapsed() < TOKEN_LIFETIME).then_some(token.token)
+    }
+
+    pub fn expire(&mut self) {
+        self.used.retain(|_, used| used.elapsed() < TOKEN_LIFETIME);
+        self.tokens.retain(|_, token| {
+            let fresh = token.received.elapsed() < TOKEN_LIFETIME;
+            if !fresh {
+                // The windows of the client keep offering it.
+                self.used
+                    .insert(std::mem::take(&mut token.token), Instant::now());
+            }
+            fresh
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StartupToken, TokenBroker, TokenOwner, TOKEN_LIFETIME};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn expired_tokens_are_not_offered_again() {
+        let owner = TokenOwner::Pid(1);
+        let long_ago = Instant::now() - TOKEN_LIFETIME - Duration::from_secs(1);
+        let mut broker = TokenBroker::default();
+        broker.tokens.insert(
+            owner,
+            StartupToken {
+                token: "stale".to_string(),
+                received: long_ago,
+            },
+        );
+
+        broker.expire();
+        assert!(broker.tokens.is_empty());
+        broker.offer(owner, "stale".to_string());
+        assert_eq!(broker.take(owner), None);
+
+        // Forgotten once the compositor wouldn't accept them anymore.
+        broker.used.insert("stale".to_string(), long_ago);
+        broker.expire();
+        assert!(broker.used.is_empty());
+    }
+}
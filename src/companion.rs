@@ -0,0 +1,87 @@
+//! Helper programs started once Xwayland is up, i.e. a clipboard manager or an xsettings daemon,
+//! which would otherwise need a wrapper script waiting for the display to appear.
+
+use log::{info, warn};
+use rustix::process::{kill_process_group, Pid, Signal};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+/// How long companions get to exit after SIGTERM before they're killed.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub(crate) struct Companions {
+    children: Vec<(String, Child)>,
+}
+
+impl Companions {
+    /// Runs each command through `sh -c`, with DISPLAY pointing at Xwayland. Each one gets its
+    /// own process group, so whatever the shell starts can be stopped along with it.
+    pub(crate) fn spawn(commands: &[String], display: &str) -> Self {
+        let children = commands
+            .iter()
+            .filter_map(|command| {
+                match Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("DISPLAY", display)
+                    .process_group(0)
+                    .spawn()
+                {
+                    Ok(child) => {
+                        info!("Started {command:?} (pid {})", child.id());
+                        Some((command.clone(), child))
+                    }
+                    Err(e) => {
+                        warn!("Couldn't start {command:?}: {e}");
+                        None
+                    }
+                }
+            })
+            .collect();
+        Self { children }
+    }
+
+    /// Collects the companions that exited on their own, so they don't linger as zombies.
+    pub(crate) fn reap(&mut self) {
+        self.children
+            .retain_mut(|(command, child)| match child.try_wait() {
+                Ok(Some(status)) => {
+                    warn!("{command:?} exited with {status}");
+                    false
+                }
+                Ok(None) => true,
+                Err(e) => {
+                    warn!("Couldn't check on {command:?}: {e}");
+                    false
+                }
+            });
+    }
+}
+
+impl Drop for Companions {
+    fn drop(&mut self) {
+        for (command, child) in &self.children {
+            if let Err(e) = kill_process_group(Pid::from_child(child), Signal::Term) {
+                warn!("Couldn't stop {command:?}: {e}");
+            }
+        }
+        let deadline = Instant::now() + STOP_TIMEOUT;
+        for (command, child) in &mut self.children {
+            loop {
+                match child.try_wait() {
+                    Ok(None) if Instant::now() < deadline => {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Ok(None) => {
+                        warn!("{command:?} didn't stop in time, killing it");
+                        let _ = kill_process_group(Pid::from_child(child), Signal::Kill);
+                        let _ = child.wait();
+                        break;
+                    }
+                    Ok(Some(_)) | Err(_) => break,
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,603 @@
+use crate::xstate::{RoleOverride, WindowType, WmClass};
+use std::collections::HashMap;
+use std::time::Duration;
+use wayland_protocols::xdg::shell::client::xdg_positioner::ConstraintAdjustment;
+
+/// Runtime options for satellite, usually set from the command line.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    /// Which Wayland mime types are advertised to X11 clients.
+    pub clipboard_to_x11: MimeFilter,
+    /// Which X11 selection targets are advertised to Wayland clients.
+    pub clipboard_to_wayland: MimeFilter,
+    /// When X11 windows are restacked on the X side.
+    pub raise_policy: RaisePolicy,
+    /// Which part of WM_CLASS is used as the app ID of toplevels.
+    pub app_id_source: AppIdSource,
+    /// Clipboard data bigger than this many bytes is not forwarded.
+    pub clipboard_max_size: Option<usize>,
+    /// How long the toplevel of an unmapped window is kept, in case the window is mapped again.
+    pub unmap_grace: Option<Duration>,
+    /// If set, new windows only get a role once they have a buffer, or after this long.
+    pub content_timeout: Option<Duration>,
+    /// Compositor output names mapped to the names of the RandR outputs Xwayland creates for
+    /// them, for compositors where the two don't match.
+    pub output_names: HashMap<String, String>,
+    /// If set, this output stays the primary RandR output, instead of the one with the focused
+    /// window. Either a compositor or a RandR output name.
+    pub primary_output: Option<String>,
+    /// Windows that were asked to close and don't answer pings are killed after this long.
+    pub kill_unresponsive: Option<Duration>,
+    /// If set, window titles are transliterated to this character set.
+    pub title_charset: Option<TitleCharset>,
+    /// X extensions to enable or disable in Xwayland.
+    pub extensions: Vec<ExtensionToggle>,
+    /// Shell commands started once Xwayland is ready, and stopped along with satellite.
+    pub companions: Vec<String>,
+    /// What override-redirect popups are positioned relative to.
+    pub popup_anchor: PopupAnchor,
+    /// How the window of Wine's virtual desktop is shown.
+    pub wine_desktop: WineDesktop,
+    /// What happens to windows sized to span several outputs.
+    pub spanning_windows: SpanningWindows,
+    /// Overrides for `popup_anchor`, by WM_CLASS class or instance name.
+    pub popup_anchor_rules: HashMap<String, PopupAnchor>,
+    /// Whether Xwayland is only started once the first X client connects.
+    pub lazy: bool,
+    /// WM_CLASS class or instance names of windows that stay toplevels when another client
+    /// reparents them into one of its windows.
+    pub keep_toplevel: Vec<String>,
+    /// Roles forced on windows by their _NET_WM_WINDOW_TYPE.
+    pub window_roles: Vec<WindowRoleRule>,
+    /// WM_WINDOW_ROLE patterns of windows that are always toplevels, on top of
+    /// [`TOPLEVEL_ROLES`].
+    pub toplevel_roles: Vec<String>,
+    /// Overrides for how popups of each window type are kept on screen. Only the constraint
+    /// adjustment depends on the type: the anchor and gravity come from the placement picked
+    /// with `popup_anchor`, since they decide where X's position for the popup ends up.
+    pub popup_constraints: HashMap<WindowType, ConstraintAdjustment>,
+    /// Decoration modes asked of the compositor, by WM_CLASS class or instance name.
+    pub decoration_modes: HashMap<String, DecorationMode>,
+}
+
+/// WM_WINDOW_ROLE patterns of torn-off menus, which keep the type of a menu.
+pub const TOPLEVEL_ROLES: &[&str] = &["tearoff", "tear-off", "torn-off"];
+
+impl Config {
+    /// Whether a window with the given WM_WINDOW_ROLE is made a toplevel. Roles match if they
+    /// contain one of the patterns, ignoring case.
+    pub fn is_toplevel_role(&self, role: &str) -> bool {
+        let role = role.to_lowercase();
+        TOPLEVEL_ROLES
+            .iter()
+            .copied()
+            .chain(self.toplevel_roles.iter().map(String::as_str))
+            .any(|pattern| role.contains(&pattern.to_lowercase()))
+    }
+
+    /// What a popup with the given WM_CLASS is positioned relative to.
+    pub fn popup_anchor(&self, class: Option<&WmClass>) -> PopupAnchor {
+        class
+            .and_then(|class| {
+                self.popup_anchor_rules
+                    .get(&class.class)
+                    .or_else(|| self.popup_anchor_rules.get(&class.instance))
+            })
+            .copied()
+            .unwrap_or(self.popup_anchor)
+    }
+
+    /// The role a rule forces on a window, if any. Rules for the window's WM_CLASS take
+    /// precedence over ones for any class.
+    pub fn window_role(
+        &self,
+        window_type: WindowType,
+        class: Option<&WmClass>,
+    ) -> Option<RoleOverride> {
+        let matching = |rule: &&WindowRoleRule| rule.window_type == window_type;
+        let class_rule = self.window_roles.iter().filter(matching).find(|rule| {
+            rule.class.as_ref().is_some_and(|name| {
+                class.is_some_and(|class| *name == class.class || *name == class.instance)
+            })
+        });
+        class_rule
+            .or_else(|| {
+                self.window_roles
+                    .iter()
+                    .filter(matching)
+                    .find(|rule| rule.class.is_none())
+            })
+            .map(|rule| rule.role)
+    }
+
+    /// The decoration mode forced on a window with the given WM_CLASS, if any. Otherwise the
+    /// compositor decides.
+    pub fn decoration_mode(&self, class: Option<&WmClass>) -> Option<DecorationMode> {
+        let class = class?;
+        self.decoration_modes
+            .get(&class.class)
+            .or_else(|| self.decoration_modes.get(&class.instance))
+            .copied()
+    }
+
+    /// How the compositor may move a popup of the given type that doesn't fit on screen.
+    pub fn popup_constraints(&self, window_type: WindowType) -> ConstraintAdjustment {
+        if let Some(constraints) = self.popup_constraints.get(&window_type) {
+            return *constraints;
+        }
+        match window_type {
+            // Tooltips are usually below the pointer, and should rather go above it than cover it.
+            WindowType::Tooltip => ConstraintAdjustment::SlideX | ConstraintAdjustment::FlipY,
+            // Long combo box lists are scrollable, so they can be made shorter.
+            WindowType::Combo => ConstraintAdjustment::SlideX | ConstraintAdjustment::ResizeY,
+            _ => ConstraintAdjustment::SlideX | ConstraintAdjustment::SlideY,
+        }
+    }
+}
+
+/// Parses a size in bytes, with an optional `K`, `M` or `G` suffix (i.e. `64M`).
+pub fn parse_size(s: &str) -> Result<usize, String> {
+    let (num, multiplier) = match s.char_indices().last() {
+        Some((idx, 'K' | 'k')) => (&s[..idx], 1 << 10),
+        Some((idx, 'M' | 'm')) => (&s[..idx], 1 << 20),
+        Some((idx, 'G' | 'g')) => (&s[..idx], 1 << 30),
+        _ => (s, 1),
+    };
+    num.parse::<usize>()
+        .ok()
+        .and_then(|num| num.checked_mul(multiplier))
+        .ok_or_else(|| format!("invalid size: {s}"))
+}
+
+/// The X extensions Xwayland allows toggling on the command line. Extensions the X server can't
+/// turn off (i.e. XInputExtension or SYNC) are left out, and so are the ones only Xorg has, since
+/// Xwayland refuses to start when asked for an extension it doesn't know.
+const TOGGLEABLE_EXTENSIONS: &[&str] = &[
+    "COMPOSITE",
+    "DAMAGE",
+    "DOUBLE-BUFFER",
+    "DPMS",
+    "GLX",
+    "MIT-SCREEN-SAVER",
+    "MIT-SHM",
+    "RANDR",
+    "RECORD",
+    "RENDER",
+    "SECURITY",
+    "SHAPE",
+    "X-Resource",
+    "XFIXES",
+    "XFree86-VidModeExtension",
+    "XINERAMA",
+    "XTEST",
+    "XVideo",
+];
+
+/// Toggleable extensions xwayland-satellite can't work without.
+const REQUIRED_EXTENSIONS: &[&str] = &["COMPOSITE", "RANDR", "XFIXES"];
+
+/// An X extension passed to Xwayland with `+extension` or `-extension`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtensionToggle {
+    pub name: String,
+    pub enable: bool,
+}
+
+impl ExtensionToggle {
+    /// Checks the name against the extensions Xwayland can toggle, since it refuses to start with
+    /// an unknown one.
+    pub fn new(name: &str, enable: bool) -> Result<Self, String> {
+        let Some(known) = TOGGLEABLE_EXTENSIONS
+            .iter()
+            .find(|ext| ext.eq_ignore_ascii_case(name))
+        else {
+            return Err(format!(
+                "unknown X extension: {name} (known extensions: {})",
+                TOGGLEABLE_EXTENSIONS.join(", ")
+            ));
+        };
+        if !enable && REQUIRED_EXTENSIONS.contains(known) {
+            return Err(format!("{known} is required by xwayland-satellite"));
+        }
+        Ok(Self {
+            name: known.to_string(),
+            enable,
+        })
+    }
+
+    /// The Xwayland arguments for this toggle.
+    pub fn args(&self) -> [&str; 2] {
+        let flag = if self.enable {
+            "+extension"
+        } else {
+            "-extension"
+        };
+        [flag, &self.name]
+    }
+}
+
+/// The part of an X11 window's WM_CLASS that becomes its app ID.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AppIdSource {
+    /// The class name, i.e. `firefox`.
+    #[default]
+    Class,
+    /// The instance name, i.e. `Navigator`.
+    Instance,
+}
+
+impl std::str::FromStr for AppIdSource {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "class" => Ok(Self::Class),
+            "instance" => Ok(Self::Instance),
+            other => Err(format!("unknown app id source: {other}")),
+        }
+    }
+}
+
+/// A character set window titles can be limited to.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TitleCharset {
+    Ascii,
+    /// ISO-8859-1.
+    Latin1,
+}
+
+impl std::str::FromStr for TitleCharset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Self::Ascii),
+            "latin1" => Ok(Self::Latin1),
+            other => Err(format!("unknown title charset: {other}")),
+        }
+    }
+}
+
+/// What the position of an override-redirect popup is given relative to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PopupAnchor {
+    /// The window the popup is for.
+    #[default]
+    Parent,
+    /// The pointer, if it's over the window the popup is for. Menus then still open at the
+    /// pointer when the parent's position is out of date, and get flipped around the pointer
+    /// instead of around the parent when they don't fit.
+    Pointer,
+    /// The center of the window the popup is for, regardless of where X puts it.
+    Center,
+}
+
+impl std::str::FromStr for PopupAnchor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "parent" => Ok(Self::Parent),
+            "pointer" => Ok(Self::Pointer),
+            "center" => Ok(Self::Center),
+            other => Err(format!("unknown popup anchor: {other}")),
+        }
+    }
+}
+
+/// How the window of Wine's virtual desktop is shown. The programs running in it are all drawn
+/// into that one window, sized like the screen of the virtual desktop.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WineDesktop {
+    /// Like any other window.
+    #[default]
+    Window,
+    Maximized,
+    /// Fullscreen, which suits games that expect to own the screen.
+    Fullscreen,
+}
+
+impl std::str::FromStr for WineDesktop {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "window" => Ok(Self::Window),
+            "maximized" => Ok(Self::Maximized),
+            "fullscreen" => Ok(Self::Fullscreen),
+            other => Err(format!("unknown wine desktop mode: {other}")),
+        }
+    }
+}
+
+/// What happens to windows that exactly cover several outputs, which games often do to be
+/// fullscreen on all of them.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SpanningWindows {
+    /// The window is fullscreened on the output at its top left, and resized to fit it.
+    #[default]
+    Fullscreen,
+    /// The window is mapped like any other.
+    Keep,
+}
+
+impl std::str::FromStr for SpanningWindows {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fullscreen" => Ok(Self::Fullscreen),
+            "keep" => Ok(Self::Keep),
+            other => Err(format!("unknown spanning window policy: {other}")),
+        }
+    }
+}
+
+/// Who draws a window's decorations, as asked of the compositor through xdg-decoration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DecorationMode {
+    /// The X client, which may also mean no decorations at all.
+    Client,
+    /// The compositor.
+    Server,
+}
+
+impl std::str::FromStr for DecorationMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client" => Ok(Self::Client),
+            "server" => Ok(Self::Server),
+            other => Err(format!("unknown decoration mode: {other}")),
+        }
+    }
+}
+
+/// Parses xdg_positioner constraint adjustments joined with `+` (i.e. `slide-x+flip-y`), or
+/// `none`.
+pub fn parse_constraint_adjustment(s: &str) -> Result<ConstraintAdjustment, String> {
+    if s == "none" {
+        return Ok(ConstraintAdjustment::None);
+    }
+    s.split('+')
+        .map(|adjustment| match adjustment {
+            "slide-x" => Ok(ConstraintAdjustment::SlideX),
+            "slide-y" => Ok(ConstraintAdjustment::SlideY),
+            "flip-x" => Ok(ConstraintAdjustment::FlipX),
+            "flip-y" => Ok(ConstraintAdjustment::FlipY),
+            "resize-x" => Ok(ConstraintAdjustment::ResizeX),
+            "resize-y" => Ok(ConstraintAdjustment::ResizeY),
+            other => Err(format!("unknown constraint adjustment: {other}")),
+        })
+        .collect()
+}
+
+/// Forces a role on windows of a type, optionally only for one WM_CLASS.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WindowRoleRule {
+    pub window_type: WindowType,
+    /// The WM_CLASS class or instance name the rule is limited to.
+    pub class: Option<String>,
+    pub role: RoleOverride,
+}
+
+impl std::str::FromStr for WindowRoleRule {
+    type Err = String;
+
+    /// Parses `<type>=<role>` or `<type>:<class>=<role>` (i.e. `utility:gimp=toplevel`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (selector, role) = s
+            .split_once('=')
+            .ok_or_else(|| format!("invalid window role rule: {s}"))?;
+        let (window_type, class) = match selector.split_once(':') {
+            Some((window_type, class)) => (window_type, Some(class.to_string())),
+            None => (selector, None),
+        };
+        Ok(Self {
+            window_type: window_type.parse()?,
+            class,
+            role: role.parse()?,
+        })
+    }
+}
+
+/// Controls which compositor events raise an X11 window to the top of the X11 stack.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RaisePolicy {
+    /// Raise a window when the pointer enters it.
+    #[default]
+    Enter,
+    /// Raise a window when the compositor activates it.
+    Focus,
+    /// Never restack windows.
+    Never,
+}
+
+impl std::str::FromStr for RaisePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "enter" => Ok(Self::Enter),
+            "focus" => Ok(Self::Focus),
+            "never" => Ok(Self::Never),
+            other => Err(format!("unknown raise policy: {other}")),
+        }
+    }
+}
+
+/// An allow/deny list of mime types. Patterns are either an exact mime type
+/// or a prefix ending in `*` (i.e. `image/*`).
+#[derive(Clone, Debug, Default)]
+pub struct MimeFilter {
+    /// If not empty, only mime types matching one of these patterns are forwarded.
+    pub allow: Vec<String>,
+    /// Mime types matching any of these patterns are never forwarded.
+    pub deny: Vec<String>,
+}
+
+impl MimeFilter {
+    pub fn forwards(&self, mime: &str) -> bool {
+        let matches = |pattern: &String| match pattern.strip_suffix('*') {
+            Some(prefix) => mime.starts_with(prefix),
+            None => pattern == mime,
+        };
+
+        (self.allow.is_empty() || self.allow.iter().any(matches)) && !self.deny.iter().any(matches)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_constraint_adjustment, parse_size, DecorationMode, ExtensionToggle, MimeFilter,
+        PopupAnchor, RaisePolicy, SpanningWindows, WindowRoleRule, WineDesktop,
+    };
+    use crate::xstate::{RoleOverride, WindowType};
+    use wayland_protocols::xdg::shell::client::xdg_positioner::ConstraintAdjustment;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("4096"), Ok(4096));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("2M"), Ok(2 << 20));
+        assert_eq!(parse_size("1G"), Ok(1 << 30));
+
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("12T").is_err());
+        assert!(parse_size("1.5M").is_err());
+        assert!(parse_size(&format!("{}G", usize::MAX)).is_err());
+    }
+
+    #[test]
+    fn mime_filters() {
+        let everything = MimeFilter::default();
+        assert!(everything.forwards("text/plain"));
+
+        let filter = MimeFilter {
+            allow: vec!["text/*".to_string(), "image/png".to_string()],
+            deny: vec!["text/html".to_string()],
+        };
+        assert!(filter.forwards("text/plain"));
+        assert!(filter.forwards("image/png"));
+        assert!(!filter.forwards("image/jpeg"));
+        assert!(!filter.forwards("text/html"));
+        assert!(!filter.forwards("application/text/plain"));
+
+        let deny_only = MimeFilter {
+            allow: Vec::new(),
+            deny: vec!["image/*".to_string()],
+        };
+        assert!(deny_only.forwards("text/plain"));
+        assert!(!deny_only.forwards("image/png"));
+    }
+
+    #[test]
+    fn window_role_rules() {
+        assert_eq!(
+            "utility=toplevel".parse(),
+            Ok(WindowRoleRule {
+                window_type: WindowType::Utility,
+                class: None,
+                role: RoleOverride::Toplevel,
+            })
+        );
+        assert_eq!(
+            "menu:gimp=popup".parse(),
+            Ok(WindowRoleRule {
+                window_type: WindowType::Menu,
+                class: Some("gimp".to_string()),
+                role: RoleOverride::Popup,
+            })
+        );
+
+        assert!("utility".parse::<WindowRoleRule>().is_err());
+        assert!("window=toplevel".parse::<WindowRoleRule>().is_err());
+        assert!("utility=floating".parse::<WindowRoleRule>().is_err());
+    }
+
+    #[test]
+    fn option_values() {
+        assert_eq!("enter".parse(), Ok(RaisePolicy::Enter));
+        assert_eq!("focus".parse(), Ok(RaisePolicy::Focus));
+        assert_eq!("never".parse(), Ok(RaisePolicy::Never));
+        assert!("always".parse::<RaisePolicy>().is_err());
+
+        assert_eq!("parent".parse(), Ok(PopupAnchor::Parent));
+        assert_eq!("pointer".parse(), Ok(PopupAnchor::Pointer));
+        assert_eq!("center".parse(), Ok(PopupAnchor::Center));
+        assert!("Center".parse::<PopupAnchor>().is_err());
+
+        assert_eq!("client".parse(), Ok(DecorationMode::Client));
+        assert_eq!("server".parse(), Ok(DecorationMode::Server));
+        assert!("none".parse::<DecorationMode>().is_err());
+
+        assert_eq!("fullscreen".parse(), Ok(SpanningWindows::Fullscreen));
+        assert_eq!("keep".parse(), Ok(SpanningWindows::Keep));
+        assert!("".parse::<SpanningWindows>().is_err());
+
+        assert_eq!("window".parse(), Ok(WineDesktop::Window));
+        assert_eq!("maximized".parse(), Ok(WineDesktop::Maximized));
+        assert_eq!("fullscreen".parse(), Ok(WineDesktop::Fullscreen));
+        assert!("maximised".parse::<WineDesktop>().is_err());
+    }
+
+    #[test]
+    fn constraint_adjustments() {
+        assert_eq!(
+            parse_constraint_adjustment("none"),
+            Ok(ConstraintAdjustment::None)
+        );
+        assert_eq!(
+            parse_constraint_adjustment("slide-x"),
+            Ok(ConstraintAdjustment::SlideX)
+        );
+        assert_eq!(
+            parse_constraint_adjustment("flip-x+slide-y+resize-y"),
+            Ok(ConstraintAdjustment::FlipX
+                | ConstraintAdjustment::SlideY
+                | ConstraintAdjustment::ResizeY)
+        );
+
+        assert!(parse_constraint_adjustment("").is_err());
+        assert!(parse_constraint_adjustment("slide").is_err());
+        assert!(parse_constraint_adjustment("slide-x+").is_err());
+        assert!(parse_constraint_adjustment("none+slide-x").is_err());
+    }
+
+    #[test]
+    fn extension_toggles() {
+        let shm = ExtensionToggle::new("MIT-SHM", false).unwrap();
+        assert_eq!(shm.name, "MIT-SHM");
+        assert_eq!(shm.args(), ["-extension", "MIT-SHM"]);
+        assert_eq!(
+            ExtensionToggle::new("SECURITY", true).unwrap().args(),
+            ["+extension", "SECURITY"]
+        );
+        assert!(ExtensionToggle::new("XINERAMA", false).is_ok());
+
+        assert!(ExtensionToggle::new("FOO", true).is_err());
+        assert!(ExtensionToggle::new("", false).is_err());
+
+        assert_eq!(
+            ExtensionToggle::new("mit-shm", true).unwrap().name,
+            "MIT-SHM"
+        );
+        assert_eq!(
+            ExtensionToggle::new("Xinerama", false).unwrap().name,
+            "XINERAMA"
+        );
+        assert_eq!(
+            ExtensionToggle::new("x-resource", false).unwrap().name,
+            "X-Resource"
+        );
+
+        assert!(ExtensionToggle::new("composite", false).is_err());
+        assert!(ExtensionToggle::new("RANDR", false).is_err());
+        assert!(ExtensionToggle::new("COMPOSITE", true).is_ok());
+    }
+}
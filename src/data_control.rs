@@ -0,0 +1,89 @@
+use crate::clientside::Globals;
+use log::warn;
+use std::sync::Mutex;
+use wayland_client::{
+    delegate_noop, event_created_child, Connection, Dispatch, Proxy, QueueHandle,
+};
+use wayland_protocols::ext::data_control::v1::client::{
+    ext_data_control_device_v1::{self, ExtDataControlDeviceV1, EVT_DATA_OFFER_OPCODE},
+    ext_data_control_manager_v1::ExtDataControlManagerV1,
+    ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+    ext_data_control_source_v1::{self, ExtDataControlSourceV1},
+};
+
+/// The mime types advertised by an offer.
+pub type OfferMimeTypes = Mutex<Vec<String>>;
+
+delegate_noop!(Globals: ExtDataControlManagerV1);
+
+impl Dispatch<ExtDataControlDeviceV1, ()> for Globals {
+    fn event(
+        state: &mut Self,
+        _: &ExtDataControlDeviceV1,
+        event: <ExtDataControlDeviceV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_data_control_device_v1::Event::DataOffer { .. } => {}
+            ext_data_control_device_v1::Event::Selection { id } => {
+                // An offer we never picked up is stale now.
+                if let Some(old) = std::mem::replace(&mut state.control_selection, id) {
+                    old.destroy();
+                }
+            }
+            ext_data_control_device_v1::Event::PrimarySelection { id } => {
+                // The primary selection is not bridged.
+                if let Some(offer) = id {
+                    offer.destroy();
+                }
+            }
+            ext_data_control_device_v1::Event::Finished => {
+                warn!("Data control device was invalidated, clipboard will stop working.");
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    event_created_child!(Globals, ExtDataControlDeviceV1, [
+        EVT_DATA_OFFER_OPCODE => (ExtDataControlOfferV1, OfferMimeTypes::default())
+    ]);
+}
+
+impl Dispatch<ExtDataControlOfferV1, OfferMimeTypes> for Globals {
+    fn event(
+        _: &mut Self,
+        _: &ExtDataControlOfferV1,
+        event: <ExtDataControlOfferV1 as Proxy>::Event,
+        mime_types: &OfferMimeTypes,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let ext_data_control_offer_v1::Event::Offer { mime_type } = event else {
+            unreachable!();
+        };
+        mime_types.lock().unwrap().push(mime_type);
+    }
+}
+
+impl Dispatch<ExtDataControlSourceV1, ()> for Globals {
+    fn event(
+        state: &mut Self,
+        _: &ExtDataControlSourceV1,
+        event: <ExtDataControlSourceV1 as Proxy>::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_data_control_source_v1::Event::Send { mime_type, fd } => {
+                state.selection_requests.push((mime_type, fd.into()));
+            }
+            ext_data_control_source_v1::Event::Cancelled => {
+                state.cancelled = true;
+            }
+            _ => unreachable!(),
+        }
+    }
+}
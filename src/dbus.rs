@@ -0,0 +1,129 @@
+//! The `org.freedesktop.XwaylandSatellite` service on the session bus, for session managers that
+//! would rather not deal with PID files and signals.
+
+use crate::ControlRequest;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::io::Write;
+use std::os::unix::net::UnixStream;
+use zbus::blocking::Connection;
+use zbus::names::BusName;
+use zbus::zvariant::Value;
+
+const NAME: &str = "org.freedesktop.XwaylandSatellite";
+const PATH: &str = "/org/freedesktop/XwaylandSatellite";
+
+struct Interface {
+    ready: bool,
+    display_name: String,
+    xwayland_pid: u32,
+    /// The main loop's end is polled alongside the Wayland and X connections.
+    control: UnixStream,
+}
+
+impl Interface {
+    fn send(&mut self, request: ControlRequest) -> zbus::fdo::Result<()> {
+        self.control
+            .write_all(&[request as u8])
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+}
+
+#[zbus::interface(name = "org.freedesktop.XwaylandSatellite")]
+impl Interface {
+    /// Whether Xwayland is up and X clients can connect.
+    #[zbus(property)]
+    fn ready(&self) -> bool {
+        self.ready
+    }
+
+    /// The X display (i.e. `:0`), or an empty string until satellite is ready.
+    #[zbus(property)]
+    fn display_name(&self) -> &str {
+        &self.display_name
+    }
+
+    #[zbus(property)]
+    fn xwayland_pid(&self) -> u32 {
+        self.xwayland_pid
+    }
+
+    fn quit(&mut self) -> zbus::fdo::Result<()> {
+        self.send(ControlRequest::Quit)
+    }
+
+    /// Restarts satellite along with Xwayland. Every X client is disconnected.
+    fn restart(&mut self) -> zbus::fdo::Result<()> {
+        self.send(ControlRequest::Restart)
+    }
+
+    /// Writes every window's geometry and frame pacing statistics to the log.
+    fn dump_state(&mut self) -> zbus::fdo::Result<()> {
+        self.send(ControlRequest::DumpState)
+    }
+}
+
+pub(crate) struct Service {
+    connection: Connection,
+}
+
+impl Service {
+    /// Claims the service name, or logs why it couldn't. Satellite works fine without the
+    /// service, so none of this is fatal.
+    pub(crate) fn start(control: &UnixStream, xwayland_pid: u32) -> Option<Self> {
+        let interface = Interface {
+            ready: false,
+            display_name: String::new(),
+            xwayland_pid,
+            control: control.try_clone().unwrap(),
+        };
+        let connection = zbus::blocking::connection::Builder::session()
+            .and_then(|builder| builder.name(NAME))
+            .and_then(|builder| builder.serve_at(PATH, interface))
+            .and_then(|builder| builder.build());
+
+        match connection {
+            Ok(connection) => {
+                info!("Registered {NAME} on the session bus");
+                Some(Self { connection })
+            }
+            Err(e) => {
+                warn!("Couldn't register {NAME} on the session bus: {e}");
+                None
+            }
+        }
+    }
+
+    pub(crate) fn set_ready(&self, display: &str) {
+        let interface = match self
+            .connection
+            .object_server()
+            .interface::<_, Interface>(PATH)
+        {
+            Ok(interface) => interface,
+            Err(e) => {
+                warn!("Couldn't update D-Bus state: {e}");
+                return;
+            }
+        };
+        {
+            let mut interface = interface.get_mut();
+            interface.ready = true;
+            interface.display_name = display.to_string();
+        }
+
+        let changed = HashMap::from([
+            ("Ready", Value::from(true)),
+            ("DisplayName", Value::from(display)),
+        ]);
+        if let Err(e) = self.connection.emit_signal(
+            None::<BusName>,
+            PATH,
+            "org.freedesktop.DBus.Properties",
+            "PropertiesChanged",
+            &(NAME, changed, Vec::<&str>::new()),
+        ) {
+            warn!("Couldn't announce ready state over D-Bus: {e}");
+        }
+    }
+}
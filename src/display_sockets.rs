@@ -0,0 +1,384 @@
+//! Listening sockets for an X display, bound by satellite itself so that Xwayland only needs to be
+//! started once a client connects to one of them.
+//!
+//! Under systemd, the sockets are also put into the service's file descriptor store. When
+//! satellite crashes or is restarted for an upgrade, the next instance gets the same sockets back
+//! instead of binding new ones, so clients connecting in the meantime are queued up rather than
+//! finding no X server.
+
+#[cfg(feature = "systemd")]
+use log::info;
+use log::{debug, warn};
+use rustix::event::{poll, PollFd, PollFlags};
+use rustix::process::{getpid, test_kill_process, Pid};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(feature = "systemd")]
+use std::os::fd::AsFd;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::unix::net::UnixListener;
+
+const SOCKET_DIR: &str = "/tmp/.X11-unix";
+
+/// X servers look for a free display in this range, so we do too.
+const DISPLAYS: std::ops::RangeInclusive<u32> = 0..=32;
+
+fn lock_path(display: u32) -> String {
+    format!("/tmp/.X{display}-lock")
+}
+
+fn socket_path(display: u32) -> String {
+    format!("{SOCKET_DIR}/X{display}")
+}
+
+/// Parses a display name like `:1` into its number.
+fn parse_display(display: &str) -> Option<u32> {
+    display.strip_prefix(':')?.parse().ok()
+}
+
+/// Takes the lock file X servers use to claim a display. Locks left behind by processes that are
+/// gone are taken over, which includes our own after satellite restarted itself, since exec
+/// keeps the PID.
+fn lock_display(display: u32) -> io::Result<()> {
+    let path = lock_path(display);
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let owner = fs::read_to_string(&path)?
+                .trim()
+                .parse::<i32>()
+                .ok()
+                .and_then(Pid::from_raw);
+            // EPERM means the owner is alive, it just isn't ours to signal.
+            let stale = owner.is_none_or(|pid| {
+                pid == getpid() || test_kill_process(pid) == Err(rustix::io::Errno::SRCH)
+            });
+            if !stale {
+                return Err(e);
+            }
+            debug!("taking over stale lock {path}");
+            fs::remove_file(&path)?;
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)?
+        }
+        Err(e) => return Err(e),
+    };
+    // The format X servers use.
+    writeln!(file, "{:>10}", getpid().as_raw_nonzero())
+}
+
+/// Names the stored sockets, so they can be told apart from anything else in the store.
+#[cfg(feature = "systemd")]
+fn fd_name(display: u32) -> String {
+    format!("x11-display-{display}")
+}
+
+/// Sends a notification along with file descriptors to the service manager, which sd-notify can't
+/// do without pulling in another dependency.
+#[cfg(feature = "systemd")]
+fn notify_with_fds(
+    notify_socket: &std::ffi::OsStr,
+    state: &str,
+    fds: &[BorrowedFd],
+) -> io::Result<()> {
+    use rustix::net::{sendmsg, SendAncillaryBuffer, SendAncillaryMessage, SendFlags};
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    match notify_socket.as_bytes().strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.connect_addr(&addr)?;
+        }
+        _ => socket.connect(notify_socket)?,
+    }
+
+    let mut space = vec![0; rustix::cmsg_space!(ScmRights(fds.len()))];
+    let mut control = SendAncillaryBuffer::new(&mut space);
+    if !fds.is_empty() {
+        control.push(SendAncillaryMessage::ScmRights(fds));
+    }
+    sendmsg(
+        &socket,
+        &[io::IoSlice::new(state.as_bytes())],
+        &mut control,
+        SendFlags::empty(),
+    )?;
+    Ok(())
+}
+
+pub(crate) struct DisplaySockets {
+    display: u32,
+    listeners: Vec<UnixListener>,
+    /// The service manager's notification socket, if the sockets were put in its fd store.
+    #[cfg(feature = "systemd")]
+    stored_in: Option<std::ffi::OsString>,
+}
+
+impl DisplaySockets {
+    /// Claims the given display (i.e. `:1`), or the first free one.
+    pub(crate) fn bind(display: Option<&str>) -> io::Result<Self> {
+        let display = display
+            .map(|display| {
+                parse_display(display).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        format!("invalid display: {display}"),
+                    )
+                })
+            })
+            .transpose()?;
+
+        #[cfg(feature = "systemd")]
+        if let Some(sockets) = Self::restore(display) {
+            info!(
+                "Took over the sockets of {} from a previous run",
+                sockets.name()
+            );
+            return Ok(sockets);
+        }
+
+        #[cfg_attr(not(feature = "systemd"), allow(unused_mut))]
+        let mut sockets = match display {
+            Some(number) => Self::bind_display(number)?,
+            None => DISPLAYS
+                .into_iter()
+                .find_map(|number| {
+                    Self::bind_display(number)
+                        .inspect_err(|e| debug!("display :{number} is unavailable: {e}"))
+                        .ok()
+                })
+                .ok_or_else(|| io::Error::new(io::ErrorKind::AddrInUse, "no free X display"))?,
+        };
+        #[cfg(feature = "systemd")]
+        sockets.store();
+        Ok(sockets)
+    }
+
+    fn bind_display(display: u32) -> io::Result<Self> {
+        lock_display(display)?;
+        // From here on, dropping the sockets releases the display again.
+        let mut sockets = Self {
+            display,
+            listeners: Vec::new(),
+            #[cfg(feature = "systemd")]
+            stored_in: None,
+        };
+
+        fs::create_dir_all(SOCKET_DIR)?;
+        let path = socket_path(display);
+        // Whoever made this socket is gone, or we couldn't have taken the lock.
+        let _ = fs::remove_file(&path);
+        sockets.listeners.push(UnixListener::bind(&path)?);
+
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::linux::net::SocketAddrExt;
+            use std::os::unix::net::SocketAddr;
+            let addr = SocketAddr::from_abstract_name(&path)?;
+            sockets.listeners.push(UnixListener::bind_addr(&addr)?);
+        }
+
+        Ok(sockets)
+    }
+
+    /// Adopts the sockets a previous run stored with the service manager, if it handed us any for
+    /// the wanted display. Anything else that was stored is dropped from the store.
+    #[cfg(feature = "systemd")]
+    fn restore(wanted: Option<u32>) -> Option<Self> {
+        use std::os::fd::FromRawFd;
+
+        let notify_socket = std::env::var_os("NOTIFY_SOCKET")?;
+        let fds = match sd_notify::listen_fds_with_names(true) {
+            Ok(fds) => fds,
+            Err(e) => {
+                warn!("Couldn't get the sockets passed by systemd: {e}");
+                return None;
+            }
+        };
+        let remove = |name: &str| {
+            let state = format!("FDSTOREREMOVE=1\nFDNAME={name}\n");
+            if let Err(e) = notify_with_fds(&notify_socket, &state, &[]) {
+                warn!("Couldn't remove {name:?} from the fd store: {e}");
+            }
+        };
+
+        let mut restored: Option<(u32, Vec<UnixListener>)> = None;
+        for (fd, name) in fds {
+            // Whatever systemd passes is ours to close.
+            let listener = unsafe { UnixListener::from_raw_fd(fd) };
+            let display = name
+                .strip_prefix("x11-display-")
+                .and_then(|number| number.parse::<u32>().ok())
+                .filter(|display| wanted.is_none_or(|wanted| wanted == *display));
+            match (display, &mut restored) {
+                (Some(display), None) => restored = Some((display, vec![listener])),
+                (Some(display), Some((restored, listeners))) if display == *restored => {
+                    listeners.push(listener);
+                }
+                _ => {
+                    debug!("dropping stored fd {name:?}");
+                    remove(&name);
+                }
+            }
+        }
+
+        let (display, listeners) = restored?;
+        if let Err(e) = lock_display(display) {
+            warn!("Couldn't lock :{display} again: {e}");
+            remove(&fd_name(display));
+            return None;
+        }
+        Some(Self {
+            display,
+            listeners,
+            stored_in: Some(notify_socket),
+        })
+    }
+
+    /// Puts the sockets into the service manager's fd store, if it has one for us.
+    #[cfg(feature = "systemd")]
+    fn store(&mut self) {
+        let Some(notify_socket) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        let state = format!("FDSTORE=1\nFDNAME={}\n", fd_name(self.display));
+        let fds: Vec<_> = self.listeners.iter().map(|l| l.as_fd()).collect();
+        match notify_with_fds(&notify_socket, &state, &fds) {
+            Ok(()) => self.stored_in = Some(notify_socket),
+            Err(e) => warn!("Couldn't store the X sockets with systemd: {e}"),
+        }
+    }
+
+    /// Takes the sockets back out of the fd store, so that a later run doesn't get sockets whose
+    /// files are gone.
+    #[cfg(feature = "systemd")]
+    fn release(&mut self) {
+        let Some(notify_socket) = self.stored_in.take() else {
+            return;
+        };
+        let state = format!("FDSTOREREMOVE=1\nFDNAME={}\n", fd_name(self.display));
+        if let Err(e) = notify_with_fds(&notify_socket, &state, &[]) {
+            warn!("Couldn't remove the X sockets from the fd store: {e}");
+        }
+    }
+
+    pub(crate) fn name(&self) -> String {
+        format!(":{}", self.display)
+    }
+
+    /// Blocks until a client connects, calling `dispatch` whenever `wayland` becomes readable
+    /// in the meantime. The connection is left pending for Xwayland to accept.
+    pub(crate) fn wait_for_client(&self, wayland: BorrowedFd, mut dispatch: impl FnMut()) {
+        loop {
+            let mut fds: Vec<_> = self
+                .listeners
+                .iter()
+                .map(|listener| PollFd::new(listener, PollFlags::IN))
+                .chain([PollFd::from_borrowed_fd(wayland, PollFlags::IN)])
+                .collect();
+            match poll(&mut fds, -1) {
+                Ok(_) => {}
+                Err(rustix::io::Errno::INTR) => continue,
+                Err(e) => panic!("Poll failed: {e:?}"),
+            }
+
+            let (wayland, listeners) = fds.split_last().unwrap();
+            let connected = listeners.iter().any(|fd| !fd.revents().is_empty());
+            let wayland_ready = !wayland.revents().is_empty();
+            drop(fds);
+            if wayland_ready {
+                dispatch();
+            }
+            if connected {
+                return;
+            }
+        }
+    }
+
+    /// The arguments handing the sockets to Xwayland, which has to inherit them.
+    pub(crate) fn xwayland_args(&self) -> Vec<String> {
+        let mut args = vec![self.name()];
+        for listener in &self.listeners {
+            rustix::io::fcntl_setfd(listener, rustix::io::FdFlags::empty()).unwrap();
+            args.push("-listenfd".to_string());
+            args.push(listener.as_raw_fd().to_string());
+        }
+        args
+    }
+}
+
+impl Drop for DisplaySockets {
+    fn drop(&mut self) {
+        #[cfg(feature = "systemd")]
+        self.release();
+        if !self.listeners.is_empty() {
+            if let Err(e) = fs::remove_file(socket_path(self.display)) {
+                warn!("Couldn't remove X socket of :{}: {e}", self.display);
+            }
+        }
+        if let Err(e) = fs::remove_file(lock_path(self.display)) {
+            warn!("Couldn't remove lock of :{}: {e}", self.display);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_display;
+
+    #[test]
+    fn display_names() {
+        assert_eq!(parse_display(":0"), Some(0));
+        assert_eq!(parse_display(":12"), Some(12));
+        assert_eq!(parse_display("12"), None);
+        assert_eq!(parse_display(":1.0"), None);
+    }
+
+    #[cfg(feature = "systemd")]
+    #[test]
+    fn fds_reach_the_notify_socket() {
+        use super::notify_with_fds;
+        use rustix::net::{recvmsg, RecvAncillaryBuffer, RecvAncillaryMessage, RecvFlags};
+        use std::os::fd::AsFd;
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("xwls-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let (stored, _) = UnixDatagram::pair().unwrap();
+
+        notify_with_fds(
+            path.as_os_str(),
+            "FDSTORE=1\nFDNAME=x11-display-1\n",
+            &[stored.as_fd()],
+        )
+        .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut buf = [0; 64];
+        let mut space = [0; rustix::cmsg_space!(ScmRights(1))];
+        let mut control = RecvAncillaryBuffer::new(&mut space);
+        let msg = recvmsg(
+            &receiver,
+            &mut [std::io::IoSliceMut::new(&mut buf)],
+            &mut control,
+            RecvFlags::empty(),
+        )
+        .unwrap();
+        assert_eq!(&buf[..msg.bytes], b"FDSTORE=1\nFDNAME=x11-display-1\n");
+        let fds: Vec<_> = control
+            .drain()
+            .flat_map(|msg| match msg {
+                RecvAncillaryMessage::ScmRights(fds) => fds.collect(),
+                _ => Vec::new(),
+            })
+            .collect();
+        assert_eq!(fds.len(), 1);
+    }
+}
@@ -1,9 +1,20 @@
 mod clientside;
+mod companion;
+pub mod config;
+mod data_control;
 mod data_device;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod display_sockets;
+#[cfg(feature = "self-test")]
+pub mod self_test;
 mod server;
 pub mod xstate;
 
-use crate::server::{PendingSurfaceState, ServerState};
+use crate::config::Config;
+use crate::server::{
+    FocusedOutput, PendingSurfaceState, PointerConstraint, ServerState, TiledEdges,
+};
 use crate::xstate::{RealConnection, XState};
 use log::{error, info};
 use rustix::event::{poll, PollFd, PollFlags};
@@ -11,7 +22,10 @@ use smithay_client_toolkit::data_device_manager::WritePipe;
 use std::io::{BufRead, BufReader, Read, Write};
 use std::os::fd::{AsFd, AsRawFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
 use wayland_server::{Display, ListeningSocket};
 use xcb::x;
 
@@ -22,14 +36,36 @@ pub trait XConnection: Sized + 'static {
     fn root_window(&self) -> x::Window;
     fn set_window_dims(&mut self, window: x::Window, dims: PendingSurfaceState);
     fn set_fullscreen(&mut self, window: x::Window, fullscreen: bool, data: Self::ExtraData);
+    fn set_minimized(&mut self, window: x::Window, minimized: bool, data: Self::ExtraData);
+    fn set_maximized(&mut self, window: x::Window, maximized: bool, data: Self::ExtraData);
     fn focus_window(
         &mut self,
         window: x::Window,
-        output_name: Option<String>,
+        output: Option<FocusedOutput>,
         data: Self::ExtraData,
     );
     fn close_window(&mut self, window: x::Window, data: Self::ExtraData);
-    fn raise_to_top(&mut self, window: x::Window);
+    fn ping(&mut self, window: x::Window, data: Self::ExtraData);
+    /// How long until the connection has timed work to do, if it has any.
+    fn next_timeout(&self) -> Option<Duration>;
+    /// Places a window directly above `sibling` in the stack.
+    fn stack_above(&mut self, window: x::Window, sibling: x::Window);
+    /// Tells the client of a window why something it asked for was refused or changed.
+    fn report_error(&mut self, window: x::Window, error: &str, data: Self::ExtraData);
+    /// Whether an X client holds an active pointer grab, i.e. for an open menu.
+    fn pointer_grabbed(&self) -> bool;
+    /// Tells the client of a window which of its edges are tiled, so it can leave out the shadows
+    /// and resize borders there. Tiling alone doesn't make the window maximized.
+    fn set_tiled_edges(&mut self, window: x::Window, edges: TiledEdges, data: Self::ExtraData);
+    /// Sets _NET_WORKAREA to the area toplevels are meant to fit in, as x, y, width and height.
+    fn set_work_area(&mut self, area: (i32, i32, i32, i32), data: Self::ExtraData);
+    /// Publishes the pointer constraint the compositor has in effect for a window, if any.
+    fn set_pointer_constraint(
+        &mut self,
+        window: x::Window,
+        constraint: Option<PointerConstraint>,
+        data: Self::ExtraData,
+    );
 }
 
 pub trait FromServerState<C: XConnection> {
@@ -43,6 +79,110 @@ pub trait X11Selection {
 
 type RealServerState = ServerState<RealConnection>;
 
+/// Requests to stop the main loop, sent from outside of it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+enum ControlRequest {
+    Quit,
+    Restart,
+    /// Logs the state of every window, without stopping anything.
+    DumpState,
+}
+
+impl ControlRequest {
+    fn from_byte(byte: u8) -> Option<Self> {
+        [Self::Quit, Self::Restart, Self::DumpState]
+            .into_iter()
+            .find(|request| *request as u8 == byte)
+    }
+}
+
+/// The write end of the control pipe, see [`handle_exit_signal`].
+static EXIT_SIGNAL_FD: AtomicI32 = AtomicI32::new(-1);
+
+/// Turns SIGTERM and SIGINT into a quit request, so Xwayland and the companions are stopped
+/// instead of outliving us.
+extern "C" fn handle_exit_signal(_: libc::c_int) {
+    let fd = EXIT_SIGNAL_FD.load(Ordering::Relaxed);
+    if fd < 0 {
+        return;
+    }
+    let request = ControlRequest::Quit as u8;
+    // Only async-signal-safe functions may be called here.
+    unsafe { libc::write(fd, (&request as *const u8).cast(), 1) };
+}
+
+/// Keeps [`handle_exit_signal`] installed for SIGTERM and SIGINT. Dropping it puts the default
+/// handlers back before the control pipe is closed, so a late signal can't write to a stale fd.
+struct ExitSignalHandler;
+
+impl ExitSignalHandler {
+    const SIGNALS: [libc::c_int; 2] = [libc::SIGTERM, libc::SIGINT];
+
+    fn install(fd: BorrowedFd) -> Self {
+        EXIT_SIGNAL_FD.store(fd.as_raw_fd(), Ordering::Relaxed);
+        for signal in Self::SIGNALS {
+            unsafe {
+                libc::signal(
+                    signal,
+                    handle_exit_signal as extern "C" fn(libc::c_int) as libc::sighandler_t,
+                )
+            };
+        }
+        Self
+    }
+}
+
+impl Drop for ExitSignalHandler {
+    fn drop(&mut self) {
+        for signal in Self::SIGNALS {
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+        EXIT_SIGNAL_FD.store(-1, Ordering::Relaxed);
+    }
+}
+
+/// Build and capability information, meant to be included in bug reports.
+#[derive(Debug, Clone, Copy)]
+pub struct Version {
+    /// `git describe` output for the build, or the crate version if it was unavailable.
+    pub describe: &'static str,
+    /// Optional features compiled into this build.
+    pub features: &'static [&'static str],
+    /// The oldest Xwayland release satellite supports.
+    pub min_xwayland: &'static str,
+}
+
+impl std::fmt::Display for Version {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "xwayland-satellite {} (features: [{}], Xwayland >= {})",
+            self.describe,
+            self.features.join(", "),
+            self.min_xwayland
+        )
+    }
+}
+
+pub fn version() -> Version {
+    Version {
+        describe: match option_env!("XWLS_GIT_DESCRIBE") {
+            Some(describe) => describe,
+            None => env!("CARGO_PKG_VERSION"),
+        },
+        features: &[
+            #[cfg(feature = "systemd")]
+            "systemd",
+            #[cfg(feature = "self-test")]
+            "self-test",
+            #[cfg(feature = "dbus")]
+            "dbus",
+        ],
+        min_xwayland: "23.1",
+    }
+}
+
 pub trait RunData {
     fn display(&self) -> Option<&str>;
     fn server(&self) -> Option<UnixStream> {
@@ -51,15 +191,48 @@ pub trait RunData {
     fn created_server(&self) {}
     fn connected_server(&self) {}
     fn xwayland_ready(&self, _display: String) {}
+    fn config(&self) -> Config {
+        Config::default()
+    }
+}
+
+fn notify_ready() {
+    #[cfg(feature = "systemd")]
+    {
+        match sd_notify::notify(true, &[sd_notify::NotifyState::Ready]) {
+            Ok(()) => info!("Successfully notified systemd of ready state."),
+            Err(e) => log::warn!("Systemd notify failed: {e:?}"),
+        }
+    }
+
+    #[cfg(not(feature = "systemd"))]
+    info!("Systemd support disabled.");
 }
 
 pub fn main(data: impl RunData) -> Option<()> {
+    info!("{}", version());
     let socket = ListeningSocket::bind_auto("wayland", 1..=128).unwrap();
     let mut display = Display::<RealServerState>::new().unwrap();
     let dh = display.handle();
+    let config = data.config();
     data.created_server();
 
-    let mut server_state = RealServerState::new(dh, data.server());
+    let mut server_state = RealServerState::new(dh, data.server(), config.clone());
+
+    let mut lazy_sockets = config.lazy.then(|| {
+        let sockets = display_sockets::DisplaySockets::bind(data.display())
+            .expect("Couldn't bind the X display");
+        info!(
+            "Listening on {}, Xwayland will be started once a client connects",
+            sockets.name()
+        );
+        // Whoever waits for us needs DISPLAY to be usable now, not once something uses it.
+        notify_ready();
+        let server_fd = unsafe { BorrowedFd::borrow_raw(server_state.clientside_fd().as_raw_fd()) };
+        sockets.wait_for_client(server_fd, || server_state.run());
+        info!("Client connected to {}, starting Xwayland", sockets.name());
+        sockets
+    });
 
     let (xsock_wl, xsock_xwl) = UnixStream::pair().unwrap();
     // Prevent creation of new Xwayland command from closing fd
@@ -68,9 +241,14 @@ pub fn main(data: impl RunData) -> Option<()> {
     let (ready_tx, ready_rx) = UnixStream::pair().unwrap();
     rustix::io::fcntl_setfd(&ready_tx, rustix::io::FdFlags::empty()).unwrap();
     let mut xwayland = Command::new("Xwayland");
-    if let Some(display) = data.display() {
+    if let Some(sockets) = &lazy_sockets {
+        xwayland.args(sockets.xwayland_args());
+    } else if let Some(display) = data.display() {
         xwayland.arg(display);
     }
+    for extension in &config.extensions {
+        xwayland.args(extension.args());
+    }
     let mut xwayland = xwayland
         .args([
             "-rootless",
@@ -83,6 +261,7 @@ pub fn main(data: impl RunData) -> Option<()> {
         .stderr(Stdio::piped())
         .spawn()
         .unwrap();
+    let xwayland_pid = xwayland.id();
 
     let (mut finish_tx, mut finish_rx) = UnixStream::pair().unwrap();
     let stderr = xwayland.stderr.take().unwrap();
@@ -112,6 +291,17 @@ pub fn main(data: impl RunData) -> Option<()> {
                     unsafe { Box::from_raw(data as *mut _) };
 
                 error!("Xwayland exited early with {status}");
+                if !config.extensions.is_empty() {
+                    let args: Vec<_> = config
+                        .extensions
+                        .iter()
+                        .map(|e| e.args().join(" "))
+                        .collect();
+                    error!(
+                        "Xwayland may have rejected the extensions it was started with ({}), see its output above",
+                        args.join(", ")
+                    );
+                }
                 return None;
             }
 
@@ -122,7 +312,11 @@ pub fn main(data: impl RunData) -> Option<()> {
             panic!("first poll failed: {e:?}")
         }
     };
-    drop(finish_rx);
+
+    let (control_tx, control_rx) = UnixStream::pair().unwrap();
+    let _exit_signals = ExitSignalHandler::install(control_tx.as_fd());
+    #[cfg(feature = "dbus")]
+    let dbus = dbus::Service::start(&control_tx, xwayland_pid);
 
     server_state.connect(connection);
     server_state.run();
@@ -139,44 +333,96 @@ pub fn main(data: impl RunData) -> Option<()> {
         PollFd::new(&xsock_wl, PollFlags::IN),
         PollFd::from_borrowed_fd(display_fd, PollFlags::IN),
         PollFd::new(&ready_rx, PollFlags::IN),
+        PollFd::new(&control_rx, PollFlags::IN),
     ];
 
     let mut ready = false;
+    let mut companions = None;
+    // Events xcb already read off the socket don't wake up poll, so they have to be checked for
+    // without waiting.
+    let mut x_pending = false;
     loop {
-        match poll(&mut fds, -1) {
+        let timeout = if x_pending {
+            0
+        } else {
+            server_state
+                .next_timeout()
+                .map_or(-1, |t| t.as_millis().try_into().unwrap_or(i32::MAX))
+        };
+        match poll(&mut fds, timeout) {
             Ok(_) => {
                 if !fds[3].revents().is_empty() {
                     ready = true;
                 }
             }
+            // A signal, which the control pipe has by now.
+            Err(rustix::io::Errno::INTR) => continue,
             Err(other) => panic!("Poll failed: {other:?}"),
         }
 
+        if !fds[4].revents().is_empty() {
+            let mut request = [0];
+            (&control_rx).read_exact(&mut request).unwrap();
+            let Some(request) = ControlRequest::from_byte(request[0]) else {
+                continue;
+            };
+            if request == ControlRequest::DumpState {
+                info!("{}", server_state.state_dump());
+                continue;
+            }
+            info!("{request:?} requested, stopping Xwayland");
+            rustix::process::kill_process(
+                rustix::process::Pid::from_raw(xwayland_pid as i32).unwrap(),
+                rustix::process::Signal::Term,
+            )
+            .unwrap();
+            // Wait for Xwayland to let go of the display before a new one tries to take it.
+            let mut data = [0; (usize::BITS / 8) as usize];
+            finish_rx.read_exact(&mut data).unwrap();
+            let data = usize::from_ne_bytes(data);
+            let status: Box<std::process::ExitStatus> = unsafe { Box::from_raw(data as *mut _) };
+            info!("Xwayland exited with {status}");
+            // Exec doesn't run destructors.
+            drop(companions.take());
+            drop(lazy_sockets.take());
+
+            match request {
+                ControlRequest::Quit => return Some(()),
+                ControlRequest::DumpState => unreachable!(),
+                ControlRequest::Restart => {
+                    let err = Command::new(std::env::current_exe().unwrap())
+                        .args(std::env::args_os().skip(1))
+                        .exec();
+                    panic!("Failed to restart: {err}");
+                }
+            }
+        }
+
         if xstate.is_none() && ready {
-            let xstate = xstate.insert(XState::new(xsock_wl.as_fd()));
+            let xstate = xstate.insert(XState::new(xsock_wl.as_fd(), config.clone()));
             let mut reader = BufReader::new(&ready_rx);
             let mut display = String::new();
             reader.read_line(&mut display).unwrap();
             display.pop();
             display.insert(0, ':');
             info!("Connected to Xwayland on {display}");
-            data.xwayland_ready(display);
+            #[cfg(feature = "dbus")]
+            if let Some(dbus) = &dbus {
+                dbus.set_ready(&display);
+            }
+            data.xwayland_ready(display.clone());
             xstate.server_state_setup(&mut server_state);
-
-            #[cfg(feature = "systemd")]
-            {
-                match sd_notify::notify(true, &[sd_notify::NotifyState::Ready]) {
-                    Ok(()) => info!("Successfully notified systemd of ready state."),
-                    Err(e) => log::warn!("Systemd notify failed: {e:?}"),
-                }
+            companions = Some(companion::Companions::spawn(&config.companions, &display));
+            if lazy_sockets.is_none() {
+                notify_ready();
             }
-
-            #[cfg(not(feature = "systemd"))]
-            info!("Systemd support disabled.");
         }
 
         if let Some(xstate) = &mut xstate {
-            xstate.handle_events(&mut server_state);
+            x_pending = xstate.handle_events(&mut server_state);
+        }
+        if let Some(companions) = &mut companions {
+            companions.reap();
         }
 
         display.dispatch_clients(&mut server_state).unwrap();
@@ -1,24 +1,212 @@
+use std::fmt::Display;
+use std::time::Duration;
+use xwayland_satellite::config::{
+    parse_constraint_adjustment, parse_size, Config, ExtensionToggle,
+};
+
 fn main() {
     pretty_env_logger::formatted_timed_builder()
         .filter_level(log::LevelFilter::Info)
         .parse_default_env()
         .init();
-    xwayland_satellite::main(RealData(get_display()));
+    xwayland_satellite::main(parse_args());
 }
 
-#[repr(transparent)]
-struct RealData(Option<String>);
+struct RealData {
+    display: Option<String>,
+    config: Config,
+}
 impl xwayland_satellite::RunData for RealData {
     fn display(&self) -> Option<&str> {
-        self.0.as_deref()
+        self.display.as_deref()
+    }
+
+    fn config(&self) -> Config {
+        self.config.clone()
     }
 }
 
-fn get_display() -> Option<String> {
-    let mut args: Vec<_> = std::env::args().collect();
-    if args.len() > 2 {
-        panic!("Unexpected arguments: {:?}", &args[2..]);
+fn parse_args() -> RealData {
+    let mut display = None;
+    let mut config = Config::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let list = match arg.as_str() {
+            "--clipboard-to-x11-allow" => &mut config.clipboard_to_x11.allow,
+            "--clipboard-to-x11-deny" => &mut config.clipboard_to_x11.deny,
+            "--clipboard-to-wayland-allow" => &mut config.clipboard_to_wayland.allow,
+            "--clipboard-to-wayland-deny" => &mut config.clipboard_to_wayland.deny,
+            "--raise-windows" => {
+                let value = value(&mut args, &arg);
+                config.raise_policy = parsed(&arg, value.parse());
+                continue;
+            }
+            "--clipboard-max-size" => {
+                let value = value(&mut args, &arg);
+                config.clipboard_max_size = Some(parsed(&arg, parse_size(&value)));
+                continue;
+            }
+            "--unmap-grace" => {
+                let value = value(&mut args, &arg);
+                let ms = parsed(&arg, value.parse());
+                config.unmap_grace = Some(Duration::from_millis(ms));
+                continue;
+            }
+            "--wait-for-content" => {
+                let value = value(&mut args, &arg);
+                let ms = parsed(&arg, value.parse());
+                config.content_timeout = Some(Duration::from_millis(ms));
+                continue;
+            }
+            "--kill-unresponsive" => {
+                let value = value(&mut args, &arg);
+                let ms = parsed(&arg, value.parse());
+                config.kill_unresponsive = Some(Duration::from_millis(ms));
+                continue;
+            }
+            "--title-charset" => {
+                let value = value(&mut args, &arg);
+                config.title_charset = Some(parsed(&arg, value.parse()));
+                continue;
+            }
+            "--output-name" => {
+                let value = value(&mut args, &arg);
+                for mapping in value.split(',') {
+                    let Some((wayland, randr)) = mapping.split_once('=') else {
+                        exit_with_error(format_args!("Invalid output name mapping: {mapping}"));
+                    };
+                    config
+                        .output_names
+                        .insert(wayland.to_string(), randr.to_string());
+                }
+                continue;
+            }
+            "--primary-output" => {
+                let value = value(&mut args, &arg);
+                config.primary_output = Some(value);
+                continue;
+            }
+            "--popup-anchor" => {
+                let value = value(&mut args, &arg);
+                match value.split_once('=') {
+                    Some((class, anchor)) => {
+                        config
+                            .popup_anchor_rules
+                            .insert(class.to_string(), parsed(&arg, anchor.parse()));
+                    }
+                    None => config.popup_anchor = parsed(&arg, value.parse()),
+                }
+                continue;
+            }
+            "--decorations" => {
+                let value = value(&mut args, &arg);
+                let Some((class, mode)) = value.split_once('=') else {
+                    exit_with_error(format_args!("{arg} expects CLASS=MODE, got {value}"));
+                };
+                config
+                    .decoration_modes
+                    .insert(class.to_string(), parsed(&arg, mode.parse()));
+                continue;
+            }
+            "--spanning-windows" => {
+                let value = value(&mut args, &arg);
+                config.spanning_windows = parsed(&arg, value.parse());
+                continue;
+            }
+            "--wine-desktop" => {
+                let value = value(&mut args, &arg);
+                config.wine_desktop = parsed(&arg, value.parse());
+                continue;
+            }
+            "--popup-constraints" => {
+                let value = value(&mut args, &arg);
+                let Some((window_type, constraints)) = value.split_once('=') else {
+                    exit_with_error(format_args!("Invalid popup constraints: {value}"));
+                };
+                config.popup_constraints.insert(
+                    parsed(&arg, window_type.parse()),
+                    parsed(&arg, parse_constraint_adjustment(constraints)),
+                );
+                continue;
+            }
+            "--app-id" => {
+                let value = value(&mut args, &arg);
+                config.app_id_source = parsed(&arg, value.parse());
+                continue;
+            }
+            "--spawn" => {
+                let value = value(&mut args, &arg);
+                config.companions.push(value);
+                continue;
+            }
+            "--window-role" => {
+                let value = value(&mut args, &arg);
+                config.window_roles.push(parsed(&arg, value.parse()));
+                continue;
+            }
+            "--toplevel-role" => {
+                let value = value(&mut args, &arg);
+                config.toplevel_roles.push(value);
+                continue;
+            }
+            "--keep-toplevel" => {
+                let value = value(&mut args, &arg);
+                config.keep_toplevel.push(value);
+                continue;
+            }
+            "--lazy" => {
+                config.lazy = true;
+                continue;
+            }
+            "+extension" | "-extension" => {
+                let value = value(&mut args, &arg);
+                let extension = parsed(&arg, ExtensionToggle::new(&value, arg.starts_with('+')));
+                config.extensions.push(extension);
+                continue;
+            }
+            #[cfg(feature = "self-test")]
+            "--self-test" => std::process::exit(xwayland_satellite::self_test::run()),
+            #[cfg(not(feature = "self-test"))]
+            "--self-test" => {
+                exit_with_error("xwayland-satellite was built without the self-test feature")
+            }
+            "--version" => {
+                println!("{}", xwayland_satellite::version());
+                std::process::exit(0);
+            }
+            flag if flag.starts_with('-') => {
+                exit_with_error(format_args!("Unknown option: {flag}"))
+            }
+            _ => {
+                if display.is_some() {
+                    exit_with_error(format_args!("Unexpected argument: {arg}"));
+                }
+                display = Some(arg);
+                continue;
+            }
+        };
+
+        let value = value(&mut args, &arg);
+        list.extend(value.split(',').map(str::to_string));
     }
 
-    (args.len() == 2).then(|| args.swap_remove(1))
+    RealData { display, config }
+}
+
+/// Reports a bad command line and exits, since there's nothing sensible to run with it.
+fn exit_with_error(message: impl Display) -> ! {
+    eprintln!("xwayland-satellite: {message}");
+    std::process::exit(1);
+}
+
+/// The value following an option.
+fn value(args: &mut impl Iterator<Item = String>, arg: &str) -> String {
+    args.next()
+        .unwrap_or_else(|| exit_with_error(format_args!("Missing value for {arg}")))
+}
+
+/// The value of an option, or the reason it couldn't be parsed.
+fn parsed<T>(arg: &str, value: Result<T, impl Display>) -> T {
+    value.unwrap_or_else(|e| exit_with_error(format_args!("Invalid value for {arg}: {e}")))
 }
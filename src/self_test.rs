@@ -0,0 +1,444 @@
+//! `xwayland-satellite --self-test`: runs satellite against an in-process test compositor and
+//! checks that the basics work with the installed Xwayland.
+
+use crate::RunData;
+use rustix::event::{poll, PollFd, PollFlags};
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::os::unix::net::UnixStream;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use wayland_protocols::xdg::shell::server::xdg_toplevel;
+use xcb::x;
+
+/// How long a single step of a check may take.
+const TIMEOUT: Duration = Duration::from_secs(2);
+const PAYLOAD: &[u8] = b"xwayland-satellite self-test";
+
+/// A check fails by panicking.
+type Check = fn(&mut SelfTest);
+
+#[derive(Default)]
+struct SelfTestData {
+    server: Mutex<Option<UnixStream>>,
+    display: Mutex<Option<String>>,
+}
+
+impl RunData for Arc<SelfTestData> {
+    fn display(&self) -> Option<&str> {
+        None
+    }
+
+    fn server(&self) -> Option<UnixStream> {
+        self.server.lock().unwrap().take()
+    }
+
+    fn xwayland_ready(&self, display: String) {
+        *self.display.lock().unwrap() = Some(display);
+    }
+}
+
+xcb::atoms_struct! {
+    struct Atoms {
+        clipboard => b"CLIPBOARD" only_if_exists = false,
+        targets => b"TARGETS" only_if_exists = false,
+        text_plain => b"text/plain" only_if_exists = false,
+        wm_check => b"_NET_SUPPORTING_WM_CHECK" only_if_exists = false,
+        self_test => b"_XWLS_SELF_TEST" only_if_exists = false,
+    }
+}
+
+struct SelfTest {
+    testwl: testwl::Server,
+    pollfd: PollFd<'static>,
+    x: xcb::Connection,
+    atoms: Atoms,
+    root: x::Window,
+    visual: x::Visualid,
+    wm_window: x::Window,
+    toplevel: Option<(x::Window, testwl::SurfaceId)>,
+}
+
+/// Runs every check, printing PASS or FAIL for each. Returns the exit code.
+pub fn run() -> i32 {
+    let mut test = match SelfTest::start() {
+        Ok(test) => test,
+        Err(e) => {
+            println!("FAIL startup: {e}");
+            return 1;
+        }
+    };
+    println!("PASS startup");
+
+    let checks: [(&str, Check); 5] = [
+        ("map toplevel", SelfTest::map_toplevel),
+        ("map popup", SelfTest::map_popup),
+        ("clipboard X11 -> Wayland", SelfTest::clipboard_to_wayland),
+        ("clipboard Wayland -> X11", SelfTest::clipboard_to_x11),
+        ("output scale change", SelfTest::scale_change),
+    ];
+
+    // Failed checks panic, which is reported below. Panics in satellite itself still need to be
+    // printed though.
+    let default_hook = std::panic::take_hook();
+    let main_thread = std::thread::current().id();
+    std::panic::set_hook(Box::new(move |info| {
+        if std::thread::current().id() != main_thread {
+            default_hook(info);
+        }
+    }));
+
+    let mut failed = 0;
+    for (name, check) in checks {
+        match catch_unwind(AssertUnwindSafe(|| check(&mut test))) {
+            Ok(()) => println!("PASS {name}"),
+            Err(e) => {
+                failed += 1;
+                let reason = e
+                    .downcast_ref::<String>()
+                    .map(String::as_str)
+                    .or_else(|| e.downcast_ref::<&str>().copied())
+                    .unwrap_or("unknown error");
+                println!("FAIL {name}: {reason}");
+            }
+        }
+    }
+
+    println!(
+        "{} of {} checks passed",
+        checks.len() - failed,
+        checks.len()
+    );
+    i32::from(failed > 0)
+}
+
+impl SelfTest {
+    fn start() -> Result<Self, String> {
+        let (ours, theirs) = UnixStream::pair().map_err(|e| e.to_string())?;
+        let mut testwl = testwl::Server::new(false);
+        testwl.connect(ours);
+
+        let data = Arc::new(SelfTestData {
+            server: Mutex::new(Some(theirs)),
+            ..Default::default()
+        });
+        let thread_data = data.clone();
+        let thread = std::thread::spawn(move || crate::main(thread_data));
+
+        // The fd lives as long as testwl, which is kept around with the pollfd
+        let fd = unsafe { BorrowedFd::borrow_raw(testwl.poll_fd().as_raw_fd()) };
+        let pollfd = PollFd::from_borrowed_fd(fd, PollFlags::IN);
+        let start = Instant::now();
+        let display = loop {
+            if let Some(display) = data.display.lock().unwrap().take() {
+                break display;
+            }
+            if thread.is_finished() {
+                return Err("Xwayland exited early, see the log above".into());
+            }
+            if start.elapsed() > Duration::from_secs(10) {
+                return Err("timed out waiting for Xwayland".into());
+            }
+            if poll(&mut [pollfd.clone()], 10).map_err(|e| e.to_string())? > 0 {
+                testwl.dispatch();
+            }
+        };
+
+        let (x, _) = xcb::Connection::connect(Some(&display))
+            .map_err(|e| format!("couldn't connect to {display}: {e}"))?;
+        let atoms = Atoms::intern_all(&x).map_err(|e| e.to_string())?;
+        let screen = x.get_setup().roots().next().unwrap();
+        let root = screen.root();
+        let visual = screen.root_visual();
+        let wm_window = x
+            .wait_for_reply(x.send_request(&x::GetProperty {
+                delete: false,
+                window: root,
+                property: atoms.wm_check,
+                r#type: x::ATOM_WINDOW,
+                long_offset: 0,
+                long_length: 1,
+            }))
+            .map_err(|e| e.to_string())?
+            .value::<x::Window>()
+            .first()
+            .copied()
+            .ok_or("satellite did not set _NET_SUPPORTING_WM_CHECK")?;
+
+        Ok(Self {
+            testwl,
+            pollfd,
+            x,
+            atoms,
+            root,
+            visual,
+            wm_window,
+            toplevel: None,
+        })
+    }
+
+    /// Handles requests from satellite until `done` returns something.
+    #[track_caller]
+    fn wait_for<T>(&mut self, what: &str, mut done: impl FnMut(&mut Self) -> Option<T>) -> T {
+        let start = Instant::now();
+        loop {
+            if let Some(ret) = done(self) {
+                return ret;
+            }
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {what}");
+            if poll(&mut [self.pollfd.clone()], 10).unwrap() > 0 {
+                self.testwl.dispatch();
+            }
+        }
+    }
+
+    #[track_caller]
+    fn wait_for_surface(
+        &mut self,
+        previous: Option<testwl::SurfaceId>,
+        role: &str,
+        has_role: fn(&testwl::SurfaceRole) -> bool,
+    ) -> testwl::SurfaceId {
+        self.wait_for(&format!("a {role} surface"), |test| {
+            let id = test
+                .testwl
+                .last_created_surface_id()
+                .filter(|id| Some(*id) != previous)?;
+            test.testwl
+                .get_surface_data(id)?
+                .role
+                .as_ref()
+                .filter(|role| has_role(role))?;
+            Some(id)
+        })
+    }
+
+    fn create_window(&self, x: i16, y: i16, override_redirect: bool) -> x::Window {
+        let wid = self.x.generate_id();
+        self.x
+            .send_and_check_request(&x::CreateWindow {
+                depth: 0,
+                wid,
+                parent: self.root,
+                x,
+                y,
+                width: 50,
+                height: 50,
+                border_width: 0,
+                class: x::WindowClass::InputOutput,
+                visual: self.visual,
+                value_list: &[x::Cw::OverrideRedirect(override_redirect)],
+            })
+            .expect("couldn't create window");
+        self.x
+            .send_and_check_request(&x::MapWindow { window: wid })
+            .expect("couldn't map window");
+        wid
+    }
+
+    fn size(&self, window: x::Window) -> (u16, u16) {
+        let geometry = self
+            .x
+            .wait_for_reply(self.x.send_request(&x::GetGeometry {
+                drawable: x::Drawable::Window(window),
+            }))
+            .expect("couldn't get window geometry");
+        (geometry.width(), geometry.height())
+    }
+
+    #[track_caller]
+    fn toplevel(&self) -> (x::Window, testwl::SurfaceId) {
+        self.toplevel
+            .expect("needs the toplevel from the first check")
+    }
+
+    fn map_toplevel(&mut self) {
+        let previous = self.testwl.last_created_surface_id();
+        let window = self.create_window(0, 0, false);
+        let surface = self.wait_for_surface(previous, "toplevel", |role| {
+            matches!(role, testwl::SurfaceRole::Toplevel(_))
+        });
+
+        self.testwl
+            .configure_toplevel(surface, 100, 100, vec![xdg_toplevel::State::Activated]);
+        self.testwl.focus_toplevel(surface);
+        self.wait_for("the window to be configured", |test| {
+            (test.size(window) == (100, 100)).then_some(())
+        });
+        self.toplevel = Some((window, surface));
+    }
+
+    fn map_popup(&mut self) {
+        self.toplevel();
+        let previous = self.testwl.last_created_surface_id();
+        let window = self.create_window(10, 10, true);
+        self.wait_for_surface(previous, "popup", |role| {
+            matches!(role, testwl::SurfaceRole::Popup(_))
+        });
+        let _ = self.x.send_and_check_request(&x::DestroyWindow { window });
+    }
+
+    fn clipboard_to_wayland(&mut self) {
+        let (window, _) = self.toplevel();
+        self.x
+            .send_and_check_request(&x::SetSelectionOwner {
+                owner: window,
+                selection: self.atoms.clipboard,
+                time: x::CURRENT_TIME,
+            })
+            .expect("couldn't take the clipboard");
+
+        let request = selection_request(&self.x);
+        assert_eq!(request.target(), self.atoms.targets, "expected TARGETS");
+        answer_selection_request(&self.x, &request, x::ATOM_ATOM, &[self.atoms.text_plain]);
+        self.wait_for("the selection to reach the compositor", |test| {
+            test.testwl
+                .data_source_mimes()
+                .iter()
+                .any(|mime| mime == "text/plain")
+                .then_some(())
+        });
+
+        let x = &self.x;
+        let data = self.testwl.paste_data(|_, _| {
+            let request = selection_request(x);
+            answer_selection_request(x, &request, request.target(), PAYLOAD);
+            true
+        });
+        assert_eq!(
+            data,
+            [testwl::PasteData {
+                mime_type: "text/plain".into(),
+                data: PAYLOAD.to_vec(),
+            }],
+            "pasted wrong data"
+        );
+    }
+
+    fn clipboard_to_x11(&mut self) {
+        let (window, _) = self.toplevel();
+        self.testwl.create_data_offer(vec![testwl::PasteData {
+            mime_type: "text/plain".into(),
+            data: PAYLOAD.to_vec(),
+        }]);
+        self.wait_for("satellite to take the clipboard", |test| {
+            let owner = test
+                .x
+                .wait_for_reply(test.x.send_request(&x::GetSelectionOwner {
+                    selection: test.atoms.clipboard,
+                }))
+                .unwrap()
+                .owner();
+            (owner == test.wm_window).then_some(())
+        });
+
+        self.x
+            .send_and_check_request(&x::ConvertSelection {
+                requestor: window,
+                selection: self.atoms.clipboard,
+                target: self.atoms.text_plain,
+                property: self.atoms.self_test,
+                time: x::CURRENT_TIME,
+            })
+            .expect("couldn't convert the clipboard");
+        let notify = self.wait_for("the clipboard contents", |test| {
+            match test.x.poll_for_event().unwrap()? {
+                xcb::Event::X(x::Event::SelectionNotify(e)) => Some(e),
+                _ => None,
+            }
+        });
+        assert_eq!(
+            notify.property(),
+            self.atoms.self_test,
+            "clipboard conversion was refused"
+        );
+
+        let reply = self
+            .x
+            .wait_for_reply(self.x.send_request(&x::GetProperty {
+                delete: true,
+                window,
+                property: self.atoms.self_test,
+                r#type: x::ATOM_ANY,
+                long_offset: 0,
+                long_length: 1024,
+            }))
+            .unwrap();
+        assert_eq!(reply.value::<u8>(), PAYLOAD, "pasted wrong data");
+    }
+
+    fn scale_change(&mut self) {
+        let (window, surface) = self.toplevel();
+        let previous = self.testwl.last_output().cloned();
+        self.testwl.new_output(0, 0);
+        let output = self.wait_for("satellite to bind the output", |test| {
+            test.testwl
+                .last_output()
+                .filter(|output| Some(*output) != previous.as_ref())
+                .cloned()
+        });
+        self.testwl.set_output_scale(&output, 2);
+        self.testwl.move_surface_to_output(surface, &output);
+
+        self.testwl
+            .configure_toplevel(surface, 200, 150, vec![xdg_toplevel::State::Activated]);
+        self.wait_for("the window to be configured", |test| {
+            (test.size(window) == (200, 150)).then_some(())
+        });
+        // Whichever buffer scale Xwayland picks for the output, what reaches the compositor has
+        // to come out at the configured size.
+        self.wait_for("a buffer at the configured size", |test| {
+            let data = test.testwl.get_surface_data(surface)?;
+            let size = data.buffer_size?;
+            let scale = data.buffer_scale;
+            (scale > 0 && (size.x / scale, size.y / scale) == (200, 150)).then_some(())
+        });
+    }
+}
+
+#[track_caller]
+fn selection_request(x: &xcb::Connection) -> x::SelectionRequestEvent {
+    let start = Instant::now();
+    loop {
+        if let Some(xcb::Event::X(x::Event::SelectionRequest(request))) =
+            x.poll_for_event().unwrap()
+        {
+            return request;
+        }
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "timed out waiting for a selection request"
+        );
+        let fd = unsafe { BorrowedFd::borrow_raw(x.as_raw_fd()) };
+        let _ = poll(&mut [PollFd::from_borrowed_fd(fd, PollFlags::IN)], 10);
+    }
+}
+
+fn answer_selection_request<P: x::PropEl>(
+    x: &xcb::Connection,
+    request: &x::SelectionRequestEvent,
+    r#type: x::Atom,
+    data: &[P],
+) {
+    x.send_and_check_request(&x::ChangeProperty {
+        mode: x::PropMode::Replace,
+        window: request.requestor(),
+        property: request.property(),
+        r#type,
+        data,
+    })
+    .expect("couldn't answer selection request");
+    x.send_and_check_request(&x::SendEvent {
+        propagate: false,
+        destination: x::SendEventDest::Window(request.requestor()),
+        event_mask: x::EventMask::empty(),
+        event: &x::SelectionNotifyEvent::new(
+            request.time(),
+            request.requestor(),
+            request.selection(),
+            request.target(),
+            request.property(),
+        ),
+    })
+    .expect("couldn't answer selection request");
+}
@@ -117,8 +117,10 @@ impl<C: XConnection> Dispatch<WlSurface, ObjectKey> for ServerState<C> {
         data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
         let surface: &SurfaceData = state.objects[*key].as_ref();
-        let configured =
-            surface.role.is_none() || surface.xdg().is_none() || surface.xdg().unwrap().configured;
+        let configured = surface.content_deadline.is_none()
+            && (surface.role.is_none()
+                || surface.xdg().is_none()
+                || surface.xdg().unwrap().configured);
 
         match request {
             Request::<WlSurface>::Attach { buffer, x, y } => {
@@ -128,16 +130,24 @@ impl<C: XConnection> Dispatch<WlSurface, ObjectKey> for ServerState<C> {
                 let buffer = buffer.as_ref().map(|b| {
                     let key: &ObjectKey = b.data().unwrap();
                     let data: &Buffer = state.objects[*key].as_ref();
-                    &data.client
+                    data
                 });
+                let buffer_size = buffer.map(|b| b.size);
+                let buffer = buffer.map(|b| b.client.clone());
 
                 if configured {
-                    surface.client.attach(buffer, x, y);
-                } else {
-                    let buffer = buffer.cloned();
-                    let surface: &mut SurfaceData = state.objects[*key].as_mut();
-                    surface.attach = Some(SurfaceAttach { buffer, x, y });
+                    surface.client.attach(buffer.as_ref(), x, y);
+                }
+                let surface: &mut SurfaceData = state.objects[*key].as_mut();
+                if !configured {
+                    surface.attach = Some(SurfaceAttach {
+                        buffer: buffer.clone(),
+                        x,
+                        y,
+                    });
                 }
+                surface.buffer = buffer;
+                surface.geometry.buffer_size = buffer_size;
             }
             Request::<WlSurface>::DamageBuffer {
                 x,
@@ -151,16 +161,24 @@ impl<C: XConnection> Dispatch<WlSurface, ObjectKey> for ServerState<C> {
             }
             Request::<WlSurface>::Frame { callback } => {
                 let cb = data_init.init(callback, ());
+                let surface: &mut SurfaceData = state.objects[*key].as_mut();
                 if configured {
-                    surface.client.frame(&state.qh, cb);
+                    surface.client.frame(&state.qh, (cb.clone(), *key));
+                    surface.pending_frames.push(cb);
                 } else {
-                    let surface: &mut SurfaceData = state.objects[*key].as_mut();
                     surface.frame_callback = Some(cb);
                 }
             }
             Request::<WlSurface>::Commit => {
-                if configured {
+                if surface.content_deadline.is_some() {
+                    state.content_committed(*key);
+                } else if configured {
+                    let surface: &mut SurfaceData = state.objects[*key].as_mut();
+                    surface.pacing.committed(Instant::now());
+                    state.update_opaque_region(*key);
+                    let surface: &SurfaceData = state.objects[*key].as_ref();
                     surface.client.commit();
+                    state.check_surface(*key);
                 }
             }
             Request::<WlSurface>::Destroy => {
@@ -169,8 +187,17 @@ impl<C: XConnection> Dispatch<WlSurface, ObjectKey> for ServerState<C> {
                 if let Some(window_data) = surface.window.and_then(|w| state.windows.get_mut(&w)) {
                     window_data.surface_key.take();
                 }
-                surface.destroy_role();
-                surface.client.destroy();
+                if let Some(hints) = surface.game_hints.take() {
+                    hints.destroy();
+                }
+                if let Some(viewport) = surface.viewport.take() {
+                    viewport.viewport.destroy();
+                }
+                if !state.linger(surface) {
+                    surface.destroy_role();
+                    surface.client.destroy();
+                    state.surface_aliases.retain(|_, to| to != key);
+                }
                 debug!(
                     "deleting key: {key:?} (surface {:?})",
                     surface.server.id().protocol_id()
@@ -178,6 +205,8 @@ impl<C: XConnection> Dispatch<WlSurface, ObjectKey> for ServerState<C> {
             }
             Request::<WlSurface>::SetBufferScale { scale } => {
                 surface.client.set_buffer_scale(scale);
+                let surface: &mut SurfaceData = state.objects[*key].as_mut();
+                surface.geometry.scale = scale;
             }
             Request::<WlSurface>::SetInputRegion { region } => {
                 let region = region.as_ref().map(|r| r.data().unwrap());
@@ -237,11 +266,21 @@ impl<C: XConnection>
                         key,
                         serial: Default::default(),
                         attach: None,
+                        buffer: None,
                         frame_callback: None,
                         role: None,
                         xwl: None,
                         window: None,
                         output_key: None,
+                        geometry: Default::default(),
+                        game_hints: None,
+                        opaque_region: None,
+                        pacing: Default::default(),
+                        content_deadline: None,
+                        suspended: false,
+                        held_frames: Vec::new(),
+                        pending_frames: Vec::new(),
+                        viewport: None,
                     }
                     .into()
                 });
@@ -305,7 +344,12 @@ impl<C: XConnection> Dispatch<WlShmPool, client::wl_shm_pool::WlShmPool> for Ser
                         key,
                     );
                     let server = data_init.init(id, key);
-                    Buffer { server, client }.into()
+                    Buffer {
+                        server,
+                        client,
+                        size: (width, height),
+                    }
+                    .into()
                 });
             }
             Request::<WlShmPool>::Resize { size } => {
@@ -320,20 +364,19 @@ impl<C: XConnection> Dispatch<WlShmPool, client::wl_shm_pool::WlShmPool> for Ser
     }
 }
 
-impl<C: XConnection> Dispatch<WlShm, ClientGlobalWrapper<client::wl_shm::WlShm>>
-    for ServerState<C>
-{
+impl<C: XConnection> Dispatch<WlShm, ObjectKey> for ServerState<C> {
     fn request(
         state: &mut Self,
         _: &wayland_server::Client,
         _: &WlShm,
         request: <WlShm as Resource>::Request,
-        client: &ClientGlobalWrapper<client::wl_shm::WlShm>,
+        key: &ObjectKey,
         _: &DisplayHandle,
         data_init: &mut wayland_server::DataInit<'_, Self>,
     ) {
         match request {
             Request::<WlShm>::CreatePool { id, fd, size } => {
+                let Shm { client, .. } = state.objects[*key].as_ref();
                 let c_pool = client.create_pool(fd.as_fd(), size, &state.qh, ());
                 data_init.init(id, c_pool);
             }
@@ -579,7 +622,12 @@ impl<C: XConnection>
                         key,
                     );
                     let server = data_init.init(buffer_id, key);
-                    Buffer { server, client }.into()
+                    Buffer {
+                        server,
+                        client,
+                        size: (width, height),
+                    }
+                    .into()
                 });
             }
             Add {
@@ -674,7 +722,10 @@ impl<C: XConnection> Dispatch<WlDrmServer, ObjectKey> for ServerState<C> {
             &ClientQueueHandle,
         ) -> client::wl_buffer::WlBuffer;
 
-        let mut bufs: Option<(Box<DrmFn>, wayland_server::New<WlBuffer>)> = None;
+        // The buffer constructor, its new id and its size
+        type DrmBuffer = (Box<DrmFn>, wayland_server::New<WlBuffer>, (i32, i32));
+
+        let mut bufs: Option<DrmBuffer> = None;
         match request {
             CreateBuffer {
                 id,
@@ -689,6 +740,7 @@ impl<C: XConnection> Dispatch<WlDrmServer, ObjectKey> for ServerState<C> {
                         drm.create_buffer(name, width, height, stride, format, qh, key)
                     }),
                     id,
+                    (width, height),
                 ));
             }
             CreatePlanarBuffer {
@@ -712,6 +764,7 @@ impl<C: XConnection> Dispatch<WlDrmServer, ObjectKey> for ServerState<C> {
                         )
                     }),
                     id,
+                    (width, height),
                 ));
             }
             CreatePrimeBuffer {
@@ -745,6 +798,7 @@ impl<C: XConnection> Dispatch<WlDrmServer, ObjectKey> for ServerState<C> {
                         )
                     }),
                     id,
+                    (width, height),
                 ));
             }
             Authenticate { id } => {
@@ -754,37 +808,62 @@ impl<C: XConnection> Dispatch<WlDrmServer, ObjectKey> for ServerState<C> {
             _ => unreachable!(),
         }
 
-        if let Some((buf_create, id)) = bufs {
+        if let Some((buf_create, id, size)) = bufs {
             state
                 .objects
                 .insert_from_other_objects([*key], |[drm_obj], key| {
                     let drm: &Drm = drm_obj.try_into().unwrap();
                     let client = buf_create(&drm.client, key, &state.qh);
                     let server = data_init.init(id, key);
-                    Buffer { client, server }.into()
+                    Buffer {
+                        client,
+                        server,
+                        size,
+                    }
+                    .into()
                 });
         }
     }
 }
 
-impl<C: XConnection> Dispatch<s_vp::wp_viewport::WpViewport, c_vp::wp_viewport::WpViewport>
-    for ServerState<C>
-{
+impl<C: XConnection> Dispatch<s_vp::wp_viewport::WpViewport, ObjectKey> for ServerState<C> {
     fn request(
-        _: &mut Self,
+        state: &mut Self,
         _: &wayland_server::Client,
         _: &s_vp::wp_viewport::WpViewport,
         request: <s_vp::wp_viewport::WpViewport as Resource>::Request,
-        c_viewport: &c_vp::wp_viewport::WpViewport,
+        surface_key: &ObjectKey,
         _: &DisplayHandle,
         _: &mut wayland_server::DataInit<'_, Self>,
     ) {
-        simple_event_shunt! {
-            c_viewport, request: s_vp::wp_viewport::Request => [
-                SetSource { x, y, width, height },
-                SetDestination { width, height },
-                Destroy
-            ]
+        let Some(surface) = state.objects.get_mut(*surface_key) else {
+            return;
+        };
+        let surface: &mut SurfaceData = surface.as_mut();
+        let Some(viewport) = &mut surface.viewport else {
+            return;
+        };
+        // Kept around in case the viewport has to be recreated on another wl_surface.
+        match request {
+            s_vp::wp_viewport::Request::SetSource {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                viewport.source = (x != -1.0).then_some((x, y, width, height));
+                viewport.viewport.set_source(x, y, width, height);
+            }
+            s_vp::wp_viewport::Request::SetDestination { width, height } => {
+                surface.geometry.viewport_destination = (width != -1).then_some((width, height));
+                viewport.viewport.set_destination(width, height);
+            }
+            s_vp::wp_viewport::Request::Destroy => {
+                viewport.viewport.destroy();
+                surface.viewport = None;
+                surface.geometry.viewport_destination = None;
+            }
+            _ => unreachable!(),
         }
     }
 }
@@ -806,12 +885,18 @@ impl<C: XConnection>
     ) {
         use s_vp::wp_viewporter;
         match request {
-            wp_viewporter::Request::GetViewport { id, surface } => 'get_viewport: {
-                let Some(c_surface) = state.get_client_surface_from_server(surface) else {
-                    break 'get_viewport;
+            wp_viewporter::Request::GetViewport { id, surface } => {
+                let surface_key: ObjectKey = surface.data().copied().unwrap();
+                data_init.init(id, surface_key);
+                let Some(surface) = state.objects.get_mut(surface_key) else {
+                    return;
                 };
-                let c_viewport = client.get_viewport(c_surface, &state.qh, ());
-                data_init.init(id, c_viewport);
+                let surface: &mut SurfaceData = surface.as_mut();
+                surface.viewport = Some(SurfaceViewport {
+                    viewporter: (**client).clone(),
+                    viewport: client.get_viewport(&surface.client, &state.qh, ()),
+                    source: None,
+                });
             }
             wp_viewporter::Request::Destroy => {
                 client.destroy();
@@ -877,6 +962,10 @@ impl<C: XConnection> Dispatch<ConfinedPointerServer, ObjectKey> for ServerState<
         _: &DisplayHandle,
         _: &mut wayland_server::DataInit<'_, Self>,
     ) {
+        if let cp::Request::Destroy = request {
+            state.pointer_constraint_changed(*key, false);
+            state.pointer_constraints.remove(*key);
+        }
         let confined_ptr: &ConfinedPointer = state.objects[*key].as_ref();
         simple_event_shunt! {
             confined_ptr.client, request: cp::Request => [
@@ -899,6 +988,10 @@ impl<C: XConnection> Dispatch<LockedPointerServer, ObjectKey> for ServerState<C>
         _: &DisplayHandle,
         _: &mut wayland_server::DataInit<'_, Self>,
     ) {
+        if let lp::Request::Destroy = request {
+            state.pointer_constraint_changed(*key, false);
+            state.pointer_constraints.remove(*key);
+        }
         let locked_ptr: &LockedPointer = state.objects[*key].as_ref();
         simple_event_shunt! {
             locked_ptr.client, request: lp::Request => [
@@ -937,9 +1030,11 @@ impl<C: XConnection>
             } => {
                 let surf_key: ObjectKey = surface.data().copied().unwrap();
                 let ptr_key: ObjectKey = pointer.data().copied().unwrap();
+                let mut new_key = None;
                 state.objects.insert_from_other_objects(
                     [surf_key, ptr_key],
                     |[surf_obj, ptr_obj], key| {
+                        new_key = Some(key);
                         let SurfaceData {
                             client: c_surface, ..
                         }: &SurfaceData = surf_obj.try_into().unwrap();
@@ -958,6 +1053,14 @@ impl<C: XConnection>
                         ConfinedPointer { client, server }.into()
                     },
                 );
+                state.pointer_constraints.insert(
+                    new_key.unwrap(),
+                    ConstrainedSurface {
+                        surface_key: surf_key,
+                        constraint: PointerConstraint::Confined,
+                        active: false,
+                    },
+                );
             }
             Request::LockPointer {
                 id,
@@ -968,9 +1071,11 @@ impl<C: XConnection>
             } => {
                 let surf_key: ObjectKey = surface.data().copied().unwrap();
                 let ptr_key: ObjectKey = pointer.data().copied().unwrap();
+                let mut new_key = None;
                 state.objects.insert_from_other_objects(
                     [surf_key, ptr_key],
                     |[surf_obj, ptr_obj], key| {
+                        new_key = Some(key);
                         let SurfaceData {
                             client: c_surface, ..
                         }: &SurfaceData = surf_obj.try_into().unwrap();
@@ -987,6 +1092,14 @@ impl<C: XConnection>
                         LockedPointer { client, server }.into()
                     },
                 );
+                state.pointer_constraints.insert(
+                    new_key.unwrap(),
+                    ConstrainedSurface {
+                        surface_key: surf_key,
+                        constraint: PointerConstraint::Locked,
+                        active: false,
+                    },
+                );
             }
             Request::Destroy => {
                 client.destroy();
@@ -1239,7 +1352,6 @@ macro_rules! global_dispatch_with_events {
     };
 }
 
-global_dispatch_no_events!(WlShm, client::wl_shm::WlShm);
 global_dispatch_no_events!(WlCompositor, client::wl_compositor::WlCompositor);
 global_dispatch_no_events!(RelativePointerManServer, RelativePointerManClient);
 global_dispatch_no_events!(
@@ -1281,8 +1393,9 @@ where
                 .registry()
                 .bind::<client::wl_seat::WlSeat, _, _>(data.name, server.version(), &state.qh, key);
             if let Some(c) = &mut state.clipboard_data {
-                c.device = Some(c.manager.get_data_device(&state.qh, &client));
+                c.backend.create_device(&state.qh, &client);
             }
+            state.seat.get_or_insert_with(|| client.clone());
             GenericObject { server, client }.into()
         });
     }
@@ -1312,6 +1425,7 @@ impl<C: XConnection> GlobalDispatch<WlOutput, Global> for ServerState<C> {
         });
     }
 }
+global_dispatch_with_events!(WlShm, client::wl_shm::WlShm);
 global_dispatch_with_events!(WlDrmServer, WlDrmClient);
 
 impl<C: XConnection> GlobalDispatch<XwaylandShellV1, ()> for ServerState<C> {
@@ -1398,7 +1512,7 @@ impl<C: XConnection> Dispatch<XwaylandSurfaceV1, ObjectKey> for ServerState<C> {
                     window_data.surface_key = Some(*key);
                     state.associated_windows.insert(*key, win);
                     if window_data.mapped {
-                        state.create_role_window(win, *key);
+                        state.map_when_drawn(win, *key);
                     }
                 }
             }
@@ -54,7 +54,7 @@ use wayland_protocols::{
 };
 use wayland_server::protocol::{
     wl_buffer::WlBuffer, wl_keyboard::WlKeyboard, wl_output::WlOutput, wl_pointer::WlPointer,
-    wl_seat::WlSeat, wl_touch::WlTouch,
+    wl_seat::WlSeat, wl_shm::WlShm, wl_touch::WlTouch,
 };
 
 #[derive(Debug)]
@@ -91,21 +91,20 @@ impl HandleEvent for SurfaceData {
 }
 
 impl SurfaceData {
-    fn get_output_name(&self, state: &ServerState<impl XConnection>) -> Option<String> {
-        let output_name = self
+    pub(super) fn get_output(
+        &self,
+        state: &ServerState<impl XConnection>,
+    ) -> Option<FocusedOutput> {
+        let output = self
             .output_key
             .and_then(|key| state.objects.get(key))
-            .map(|obj| <_ as AsRef<Output>>::as_ref(obj).name.clone());
+            .map(|obj| <_ as AsRef<Output>>::as_ref(obj).focused());
 
-        if output_name.is_none() {
-            warn!(
-                "{} has no output name ({:?})",
-                self.server.id(),
-                self.output_key
-            );
+        if output.is_none() {
+            warn!("{} has no output ({:?})", self.server.id(), self.output_key);
         }
 
-        output_name
+        output
     }
 
     fn surface_event<C: XConnection>(
@@ -141,7 +140,7 @@ impl SurfaceData {
                     output.windows.insert(window);
                     if self.window.is_some() && state.last_focused_toplevel == self.window {
                         let data = C::ExtraData::create(state);
-                        let output = self.get_output_name(state);
+                        let output = self.get_output(state);
                         let conn = state.connection.as_mut().unwrap();
                         debug!("focused window changed outputs - resetting primary output");
                         conn.focus_window(window, output, data);
@@ -165,53 +164,90 @@ impl SurfaceData {
     }
 
     fn xdg_event<C: XConnection>(&mut self, event: xdg_surface::Event, state: &mut ServerState<C>) {
-        let connection = state.connection.as_mut().unwrap();
         let xdg_surface::Event::Configure { serial } = event else {
             unreachable!();
         };
 
+        self.pacing.configured(Instant::now());
         let xdg = self.xdg_mut().unwrap();
         xdg.surface.ack_configure(serial);
-        xdg.configured = true;
+        let first_configure = !std::mem::replace(&mut xdg.configured, true);
 
         if let Some(pending) = xdg.pending.take() {
             let window = state.associated_windows[self.key];
+            let (parent_x, parent_y) = state.parent_visible_offset(window);
             let window = state.windows.get_mut(&window).unwrap();
-            let x = pending.x + window.output_offset.x;
-            let y = pending.y + window.output_offset.y;
+            // Configures are in terms of window geometry, which leaves out GTK's shadows.
+            let extents = window.attrs.frame_extents;
+            let (pending_x, pending_y) = match self.role {
+                Some(SurfaceRole::Popup(_)) => (
+                    pending.x + parent_x - extents.left,
+                    pending.y + parent_y - extents.top,
+                ),
+                _ => (pending.x, pending.y),
+            };
+            let x = x11_coord(window.window, pending_x + window.output_offset.x);
+            let y = x11_coord(window.window, pending_y + window.output_offset.y);
             let width = if pending.width > 0 {
-                pending.width as u16
+                x11_size(window.window, pending.width + extents.left + extents.right)
             } else {
                 window.attrs.dims.width
             };
             let height = if pending.height > 0 {
-                pending.height as u16
+                x11_size(window.window, pending.height + extents.top + extents.bottom)
             } else {
                 window.attrs.dims.height
             };
             debug!("configuring {:?}: {x}x{y}, {width}x{height}", window.window);
-            connection.set_window_dims(
+            let dims = WindowDims {
+                x,
+                y,
+                width,
+                height,
+            };
+            if dims != window.attrs.dims {
+                state
+                    .move_resize
+                    .take_if(|active| active.window == window.window);
+            }
+            state.connection.as_mut().unwrap().set_window_dims(
                 window.window,
                 PendingSurfaceState {
-                    x,
-                    y,
-                    width: width as _,
-                    height: height as _,
+                    x: x.into(),
+                    y: y.into(),
+                    width: width.into(),
+                    height: height.into(),
                 },
             );
-            window.attrs.dims = WindowDims {
-                x: x as i16,
-                y: y as i16,
-                width,
-                height,
-            };
+            let resized =
+                (dims.width, dims.height) != (window.attrs.dims.width, window.attrs.dims.height);
+            window.attrs.dims = dims;
+            window
+                .attrs
+                .apply_window_geometry(&self.xdg().unwrap().surface);
+            if let Some(SurfaceRole::Toplevel(Some(toplevel))) = &self.role {
+                if resized && window.attrs.pinned_size() {
+                    window.attrs.apply_size_limits(&toplevel.toplevel);
+                }
+            }
+        }
+
+        if let Some(SurfaceRole::Toplevel(Some(toplevel))) = &self.role {
+            let window = state.associated_windows[self.key];
+            if let Some(win) = state.windows.get_mut(&window) {
+                win.apply_pending_attrs(Some(&toplevel.toplevel), state.config.app_id_source);
+            }
+            if first_configure {
+                state.activate_new_toplevel(window, &self.client);
+            }
         }
 
         if let Some(SurfaceAttach { buffer, x, y }) = self.attach.take() {
             self.client.attach(buffer.as_ref(), x, y);
         }
         if let Some(cb) = self.frame_callback.take() {
-            self.client.frame(&state.qh, cb);
+            self.client.frame(&state.qh, (cb.clone(), self.key));
+            self.pending_frames.push(cb);
         }
         self.client.commit();
     }
@@ -241,14 +277,148 @@ impl SurfaceData {
                             data,
                         );
                     }
+
+                    let has_state = |s: xdg_toplevel::State| states.contains(&(u32::from(s) as u8));
+                    let maximized = has_state(xdg_toplevel::State::Maximized);
+                    if maximized != std::mem::replace(&mut toplevel.maximized, maximized) {
+                        let window = state.associated_windows[self.key];
+                        let data = C::ExtraData::create(state);
+                        state
+                            .connection
+                            .as_mut()
+                            .unwrap()
+                            .set_maximized(window, maximized, data);
+                    }
+                    // A maximized window is up against every edge of the output.
+                    let tiled = if maximized {
+                        TiledEdges::all()
+                    } else {
+                        [
+                            (xdg_toplevel::State::TiledTop, TiledEdges::TOP),
+                            (xdg_toplevel::State::TiledRight, TiledEdges::RIGHT),
+                            (xdg_toplevel::State::TiledBottom, TiledEdges::BOTTOM),
+                            (xdg_toplevel::State::TiledLeft, TiledEdges::LEFT),
+                        ]
+                        .into_iter()
+                        .filter(|(s, _)| has_state(*s))
+                        .fold(TiledEdges::empty(), |edges, (_, edge)| edges | edge)
+                    };
+                    let suspended = has_state(xdg_toplevel::State::Suspended);
+                    if !suspended {
+                        for frame in self.held_frames.drain(..) {
+                            frame.callback.done(frame.callback_data);
+                        }
+                    }
+                    if suspended != self.suspended {
+                        debug!(
+                            "{:?} {}",
+                            self.window,
+                            if suspended { "suspended" } else { "resumed" }
+                        );
+                    }
+                    self.suspended = suspended;
+
+                    if tiled != std::mem::replace(&mut toplevel.tiled, tiled) {
+                        let window = state.associated_windows[self.key];
+                        let data = C::ExtraData::create(state);
+                        state
+                            .connection
+                            .as_mut()
+                            .unwrap()
+                            .set_tiled_edges(window, tiled, data);
+                    }
+
+                    // The compositor doesn't tell us when a window is restored, but it will
+                    // (probably) be activated when it is.
+                    if toplevel.minimized
+                        && states.contains(&(u32::from(xdg_toplevel::State::Activated) as u8))
+                    {
+                        toplevel.minimized = false;
+                        let window = state.associated_windows[self.key];
+                        let data = C::ExtraData::create(state);
+                        state
+                            .connection
+                            .as_mut()
+                            .unwrap()
+                            .set_minimized(window, false, data);
+                    }
                 };
 
+                let attrs = state
+                    .associated_windows
+                    .get(self.key)
+                    .and_then(|window| state.windows.get(window))
+                    .map(|data| &data.attrs);
+                let bypass_compositor = attrs.is_some_and(|attrs| attrs.bypass_compositor);
+                // Windows that can't be resized keep their size unless they're fullscreened.
+                // Neither do games running at a resolution Xwayland emulates, as it scales their
+                // window up to the output with a viewport, which resizing would undo.
+                let keep_size = (attrs.is_some_and(|attrs| attrs.fixed_size())
+                    && !states.contains(&(u32::from(xdg_toplevel::State::Fullscreen) as u8)))
+                    || self.geometry.viewport_destination == Some((width, height));
+                self.update_game_hints(&state.game_globals, &state.qh, bypass_compositor);
+
+                let bounds = match &self.role {
+                    Some(SurfaceRole::Toplevel(Some(toplevel))) => toplevel.bounds,
+                    _ => None,
+                };
+                let (width, height) = if keep_size {
+                    (0, 0)
+                } else if let Some(((max_width, max_height), attrs)) = bounds.zip(attrs) {
+                    // When the size is left to the window, it should still fit on the output.
+                    let (_, _, visible_width, visible_height) = attrs.visible_geometry();
+                    let clamp = |size: i32, visible: i32, max: i32| {
+                        if size == 0 && max > 0 && visible > max {
+                            max
+                        } else {
+                            size
+                        }
+                    };
+                    (
+                        clamp(width, visible_width, max_width),
+                        clamp(height, visible_height, max_height),
+                    )
+                } else {
+                    (width, height)
+                };
+                // Terminals and the like only come in certain sizes, and would leave part of
+                // the size they're given unused. Fullscreen and maximized windows have to fill it
+                // regardless.
+                let free_size = ![
+                    xdg_toplevel::State::Fullscreen,
+                    xdg_toplevel::State::Maximized,
+                ]
+                .into_iter()
+                .any(|s| states.contains(&(u32::from(s) as u8)));
+                let hints = attrs.and_then(|attrs| Some((attrs.size_hints.as_ref()?, attrs)));
+                let (width, height) = match hints {
+                    Some((hints, attrs)) if free_size && width > 0 && height > 0 => {
+                        let extents = attrs.frame_extents;
+                        let extra_width = extents.left + extents.right;
+                        let extra_height = extents.top + extents.bottom;
+                        let (x_width, x_height) =
+                            hints.constrain(width + extra_width, height + extra_height);
+                        (x_width - extra_width, x_height - extra_height)
+                    }
+                    _ => (width, height),
+                };
                 self.xdg_mut().unwrap().pending = Some(PendingSurfaceState {
                     width,
                     height,
                     ..Default::default()
                 });
             }
+            xdg_toplevel::Event::ConfigureBounds { width, height } => {
+                debug!("toplevel bounds: {width}x{height}");
+                let bounds = (width > 0 && height > 0).then_some((width, height));
+                if let Some(SurfaceRole::Toplevel(Some(toplevel))) = &mut self.role {
+                    toplevel.bounds = bounds;
+                }
+                if let Some(bounds) = bounds {
+                    let window = state.associated_windows[self.key];
+                    state.update_work_area(window, bounds);
+                }
+            }
             xdg_toplevel::Event::Close => {
                 let window = state.associated_windows[self.key];
                 state.close_x_window(window);
@@ -257,7 +427,7 @@ impl SurfaceData {
         }
     }
 
-    fn popup_event<C: XConnection>(&mut self, event: xdg_popup::Event, _: &mut ServerState<C>) {
+    fn popup_event<C: XConnection>(&mut self, event: xdg_popup::Event, state: &mut ServerState<C>) {
         match event {
             xdg_popup::Event::Configure {
                 x,
@@ -274,6 +444,11 @@ impl SurfaceData {
                 });
             }
             xdg_popup::Event::Repositioned { .. } => {}
+            xdg_popup::Event::PopupDone => {
+                if let Some(window) = self.window {
+                    state.dismissed_popups.push(window);
+                }
+            }
             other => todo!("{other:?}"),
         }
     }
@@ -319,7 +494,13 @@ impl<S: Resource, C: Proxy> GenericObjectExt for GenericObject<S, C> {
     type Client = C;
 }
 
-pub type Buffer = GenericObject<WlBuffer, client::wl_buffer::WlBuffer>;
+pub struct Buffer {
+    pub server: WlBuffer,
+    pub client: client::wl_buffer::WlBuffer,
+    /// The size of the buffer in pixels.
+    pub size: (i32, i32),
+}
+
 impl HandleEvent for Buffer {
     type Event = client::wl_buffer::Event;
     fn handle_event<C: XConnection>(&mut self, _: Self::Event, _: &mut ServerState<C>) {
@@ -328,6 +509,37 @@ impl HandleEvent for Buffer {
     }
 }
 
+/// The shm formats Xwayland can create buffers in.
+const XWAYLAND_SHM_FORMATS: &[client::wl_shm::Format] = {
+    use client::wl_shm::Format;
+    &[
+        Format::Argb8888,
+        Format::Xrgb8888,
+        Format::Rgb565,
+        Format::Argb2101010,
+        Format::Xrgb2101010,
+    ]
+};
+
+pub type Shm = GenericObject<WlShm, client::wl_shm::WlShm>;
+impl HandleEvent for Shm {
+    type Event = client::wl_shm::Event;
+
+    fn handle_event<C: XConnection>(&mut self, event: Self::Event, _: &mut ServerState<C>) {
+        let client::wl_shm::Event::Format { format } = event else {
+            return;
+        };
+        // Only pass on formats Xwayland can use, so it doesn't pick one that the compositor
+        // supports but Xwayland can't fill correctly (or the other way around).
+        match format {
+            WEnum::Value(format) if XWAYLAND_SHM_FORMATS.contains(&format) => {
+                self.server.format(convert_wenum(WEnum::Value(format)));
+            }
+            other => trace!("not forwarding shm format {other:?}"),
+        }
+    }
+}
+
 pub type Seat = GenericObject<WlSeat, client::wl_seat::WlSeat>;
 impl HandleEvent for Seat {
     type Event = client::wl_seat::Event;
@@ -379,7 +591,7 @@ impl HandleEvent for Pointer {
                 surface_x,
                 surface_y,
             } => 'enter: {
-                let surface_key: ObjectKey = surface.data().copied().unwrap();
+                let surface_key = state.client_surface_key(surface);
                 let Some(surface_data): Option<&SurfaceData> =
                     state.objects.get(surface_key).map(|o| o.as_ref())
                 else {
@@ -392,8 +604,11 @@ impl HandleEvent for Pointer {
                     self.server
                         .enter(serial, &surface_data.server, surface_x, surface_y);
                     let window = surface_data.window.unwrap();
-                    state.connection.as_mut().unwrap().raise_to_top(window);
+                    if state.config.raise_policy == RaisePolicy::Enter {
+                        state.stacking.raise(window, &state.windows);
+                    }
                     state.last_hovered = Some(window);
+                    state.pointer_position = (surface_x, surface_y);
                 };
 
                 if matches!(surface_data.role, Some(SurfaceRole::Popup(_))) {
@@ -448,7 +663,7 @@ impl HandleEvent for Pointer {
                     else {
                         unreachable!();
                     };
-                    let surface_key: ObjectKey = surface.data().copied().unwrap();
+                    let surface_key = state.client_surface_key(surface);
                     if state.objects.get(surface_key).is_some() {
                         trace!("resending enter ({serial}) before motion");
                         let enter_event = client::wl_pointer::Event::Enter {
@@ -463,9 +678,33 @@ impl HandleEvent for Pointer {
                         warn!("could not move pointer to surface ({serial}): stale surface");
                     }
                 } else {
+                    state.pointer_position = (surface_x, surface_y);
                     self.server.motion(time, surface_x, surface_y);
                 }
             }
+            client::wl_pointer::Event::Button {
+                serial,
+                time,
+                button,
+                state: button_state,
+            } => {
+                if button_state == WEnum::Value(client::wl_pointer::ButtonState::Pressed) {
+                    state.click_serials.insert(button, serial);
+                    state.last_click_serial = Some(serial);
+                    state.last_input_serial = Some(serial);
+                } else if let Some(window) = state
+                    .move_resize
+                    .as_ref()
+                    .filter(|active| !active.op.is_keyboard())
+                    .map(|active| active.window)
+                {
+                    // The compositor's grab would have kept the release from us, so the move or
+                    // resize never got going.
+                    state.cancel_move_resize(window);
+                }
+                self.server
+                    .button(serial, time, button, convert_wenum(button_state));
+            }
             _ => simple_event_shunt! {
                 self.server, event: client::wl_pointer::Event => [
                     Enter {
@@ -494,12 +733,6 @@ impl HandleEvent for Pointer {
                         surface_y
                     },
                     Frame,
-                    Button {
-                        serial,
-                        time,
-                        button,
-                        |state| convert_wenum(state)
-                    },
                     Axis {
                         time,
                         |axis| convert_wenum(axis),
@@ -541,18 +774,19 @@ impl HandleEvent for Keyboard {
                 surface,
                 keys,
             } => {
-                let key: ObjectKey = surface.data().copied().unwrap();
+                let key = state.client_surface_key(&surface);
                 if let Some(data) = state
                     .objects
                     .get(key)
                     .map(<_ as AsRef<SurfaceData>>::as_ref)
                 {
                     state.last_kb_serial = Some(serial);
-                    let output_name = data.get_output_name(state);
-                    state.to_focus = Some(FocusData {
-                        window: data.window.unwrap(),
-                        output_name,
-                    });
+                    state.last_input_serial = Some(serial);
+                    let output = data.get_output(state);
+                    // X menus expect the window holding the grab to keep the focus, even when
+                    // the compositor gives it to one of the menu's popups.
+                    let (window, _) = state.menu_owner(data.window.unwrap());
+                    state.to_focus = Some(FocusData { window, output });
                     self.server.enter(serial, &data.server, keys);
                 }
             }
@@ -560,13 +794,14 @@ impl HandleEvent for Keyboard {
                 if !surface.is_alive() {
                     return;
                 }
-                let key: ObjectKey = surface.data().copied().unwrap();
+                let key = state.client_surface_key(&surface);
                 if let Some(data) = state
                     .objects
                     .get(key)
                     .map(<_ as AsRef<SurfaceData>>::as_ref)
                 {
-                    if state.to_focus.as_ref().map(|d| d.window) == Some(data.window.unwrap()) {
+                    let (window, _) = state.menu_owner(data.window.unwrap());
+                    if state.to_focus.as_ref().map(|d| d.window) == Some(window) {
                         state.to_focus.take();
                     } else {
                         state.unfocus = true;
@@ -667,7 +902,16 @@ pub struct Output {
     pub xdg: Option<XdgOutput>,
     windows: HashSet<x::Window>,
     position: OutputPosition,
+    /// The size of the current mode, which Xwayland uses without xdg_output.
+    mode_size: Option<(i32, i32)>,
+    /// Modes are given unrotated, so their width and height are swapped for rotated outputs.
+    rotated: bool,
+    logical_size: Option<(i32, i32)>,
     name: String,
+    make: String,
+    model: String,
+    /// In millimeters.
+    physical_size: (i32, i32),
 }
 
 impl Output {
@@ -678,7 +922,13 @@ impl Output {
             xdg: None,
             windows: HashSet::new(),
             position: OutputPosition::Wl { x: 0, y: 0 },
+            mode_size: None,
+            rotated: false,
+            logical_size: None,
             name: "<unknown>".to_string(),
+            make: String::new(),
+            model: String::new(),
+            physical_size: (0, 0),
         }
     }
 }
@@ -713,6 +963,44 @@ impl HandleEvent for Output {
 }
 
 impl Output {
+    fn focused(&self) -> FocusedOutput {
+        let (x, y) = match self.position {
+            OutputPosition::Xdg { x, y } => (x, y),
+            OutputPosition::Wl { x, y } => (x, y),
+        };
+        FocusedOutput {
+            name: self.name.clone(),
+            x,
+            y,
+        }
+    }
+
+    pub(super) fn identity(&self) -> OutputIdentity {
+        OutputIdentity {
+            output: self.focused(),
+            make: self.make.clone(),
+            model: self.model.clone(),
+            physical_size: self.physical_size,
+        }
+    }
+
+    /// The area of the root window the output covers, once its size is known.
+    pub(super) fn rect(&self) -> Option<(i32, i32, i32, i32)> {
+        let (x, y) = match self.position {
+            OutputPosition::Xdg { x, y } => (x, y),
+            OutputPosition::Wl { x, y } => (x, y),
+        };
+        let mode_size = self.mode_size.map(|(width, height)| {
+            if self.rotated {
+                (height, width)
+            } else {
+                (width, height)
+            }
+        });
+        let (width, height) = self.logical_size.or(mode_size)?;
+        Some((x, y, width, height))
+    }
+
     fn update_offset<C: XConnection>(
         &mut self,
         offset: OutputPosition,
@@ -749,8 +1037,41 @@ impl Output {
         event: client::wl_output::Event,
         state: &mut ServerState<C>,
     ) {
-        if let client::wl_output::Event::Geometry { x, y, .. } = event {
-            self.update_offset(OutputPosition::Wl { x, y }, state);
+        match event {
+            client::wl_output::Event::Geometry {
+                x,
+                y,
+                physical_width,
+                physical_height,
+                ref make,
+                ref model,
+                transform,
+                ..
+            } => {
+                self.make.clone_from(make);
+                self.model.clone_from(model);
+                self.physical_size = (physical_width, physical_height);
+                use client::wl_output::Transform;
+                self.rotated = matches!(
+                    transform,
+                    WEnum::Value(
+                        Transform::_90
+                            | Transform::_270
+                            | Transform::Flipped90
+                            | Transform::Flipped270
+                    )
+                );
+                self.update_offset(OutputPosition::Wl { x, y }, state);
+            }
+            client::wl_output::Event::Mode {
+                flags: WEnum::Value(flags),
+                width,
+                height,
+                ..
+            } if flags.contains(client::wl_output::Mode::Current) => {
+                self.mode_size = Some((width, height));
+            }
+            _ => {}
         }
 
         simple_event_shunt! {
@@ -789,8 +1110,14 @@ impl Output {
         event: zxdg_output_v1::Event,
         state: &mut ServerState<C>,
     ) {
-        if let zxdg_output_v1::Event::LogicalPosition { x, y } = event {
-            self.update_offset(OutputPosition::Xdg { x, y }, state);
+        match event {
+            zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                self.update_offset(OutputPosition::Xdg { x, y }, state);
+            }
+            zxdg_output_v1::Event::LogicalSize { width, height } => {
+                self.logical_size = Some((width, height));
+            }
+            _ => {}
         }
         let xdg = &self.xdg.as_ref().unwrap().server;
         simple_event_shunt! {
@@ -798,7 +1125,15 @@ impl Output {
                 LogicalPosition { x, y },
                 LogicalSize { width, height },
                 Done,
-                Name { name },
+                Name {
+                    |name| {
+                        // wl_output only has names since version 4.
+                        if self.server.version() < 4 {
+                            self.name = name.clone();
+                        }
+                        name
+                    }
+                },
                 Description { description }
             ]
         }
@@ -867,7 +1202,10 @@ pub type LockedPointer = GenericObject<LockedPointerServer, LockedPointerClient>
 impl HandleEvent for LockedPointer {
     type Event = zwp_locked_pointer_v1::Event;
 
-    fn handle_event<C: XConnection>(&mut self, event: Self::Event, _: &mut ServerState<C>) {
+    fn handle_event<C: XConnection>(&mut self, event: Self::Event, state: &mut ServerState<C>) {
+        let key: ObjectKey = self.server.data().copied().unwrap();
+        state
+            .pointer_constraint_changed(key, matches!(event, zwp_locked_pointer_v1::Event::Locked));
         simple_event_shunt! {
             self.server, event: zwp_locked_pointer_v1::Event => [
                 Locked,
@@ -881,7 +1219,12 @@ pub type ConfinedPointer = GenericObject<ConfinedPointerServer, ConfinedPointerC
 impl HandleEvent for ConfinedPointer {
     type Event = zwp_confined_pointer_v1::Event;
 
-    fn handle_event<C: XConnection>(&mut self, event: Self::Event, _: &mut ServerState<C>) {
+    fn handle_event<C: XConnection>(&mut self, event: Self::Event, state: &mut ServerState<C>) {
+        let key: ObjectKey = self.server.data().copied().unwrap();
+        state.pointer_constraint_changed(
+            key,
+            matches!(event, zwp_confined_pointer_v1::Event::Confined),
+        );
         simple_event_shunt! {
             self.server, event: zwp_confined_pointer_v1::Event => [
                 Confined,
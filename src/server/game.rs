@@ -0,0 +1,119 @@
+//! Presentation hints for fullscreen games.
+//!
+//! Games set _NET_WM_BYPASS_COMPOSITOR expecting to be unredirected like they were under X
+//! compositors. The closest Wayland equivalent is telling the compositor the surface is a game,
+//! that it may tear, and that the screen shouldn't idle while it's up.
+
+use super::{SurfaceData, SurfaceRole};
+use crate::clientside::ClientQueueHandle;
+use log::debug;
+use wayland_client::{globals::GlobalList, protocol::wl_surface::WlSurface};
+use wayland_protocols::wp::{
+    content_type::v1::client::{
+        wp_content_type_manager_v1::WpContentTypeManagerV1,
+        wp_content_type_v1::{self, WpContentTypeV1},
+    },
+    idle_inhibit::zv1::client::{
+        zwp_idle_inhibit_manager_v1::ZwpIdleInhibitManagerV1,
+        zwp_idle_inhibitor_v1::ZwpIdleInhibitorV1,
+    },
+    tearing_control::v1::client::{
+        wp_tearing_control_manager_v1::WpTearingControlManagerV1,
+        wp_tearing_control_v1::{self, WpTearingControlV1},
+    },
+};
+
+/// The compositor globals used for game hints. Any of them may be missing.
+pub(super) struct GameGlobals {
+    content_type: Option<WpContentTypeManagerV1>,
+    tearing_control: Option<WpTearingControlManagerV1>,
+    idle_inhibit: Option<ZwpIdleInhibitManagerV1>,
+}
+
+impl GameGlobals {
+    pub(super) fn bind(global_list: &GlobalList, qh: &ClientQueueHandle) -> Self {
+        Self {
+            content_type: global_list.bind(qh, 1..=1, ()).ok(),
+            tearing_control: global_list.bind(qh, 1..=1, ()).ok(),
+            idle_inhibit: global_list.bind(qh, 1..=1, ()).ok(),
+        }
+    }
+}
+
+/// The hint objects attached to a surface while it's treated as a game.
+#[derive(Debug)]
+pub(super) struct GameHints {
+    content_type: Option<WpContentTypeV1>,
+    tearing_control: Option<WpTearingControlV1>,
+    idle_inhibitor: Option<ZwpIdleInhibitorV1>,
+}
+
+impl GameHints {
+    pub(super) fn new(globals: &GameGlobals, surface: &WlSurface, qh: &ClientQueueHandle) -> Self {
+        let content_type = globals.content_type.as_ref().map(|manager| {
+            let content_type = manager.get_surface_content_type(surface, qh, ());
+            content_type.set_content_type(wp_content_type_v1::Type::Game);
+            content_type
+        });
+        let tearing_control = globals.tearing_control.as_ref().map(|manager| {
+            let tearing_control = manager.get_tearing_control(surface, qh, ());
+            tearing_control.set_presentation_hint(wp_tearing_control_v1::PresentationHint::Async);
+            tearing_control
+        });
+        let idle_inhibitor = globals
+            .idle_inhibit
+            .as_ref()
+            .map(|manager| manager.create_inhibitor(surface, qh, ()));
+
+        Self {
+            content_type,
+            tearing_control,
+            idle_inhibitor,
+        }
+    }
+
+    /// Reverts the surface to the default content type and presentation, and lets the screen
+    /// idle again.
+    pub(super) fn destroy(self) {
+        if let Some(content_type) = self.content_type {
+            content_type.destroy();
+        }
+        if let Some(tearing_control) = self.tearing_control {
+            tearing_control.destroy();
+        }
+        if let Some(idle_inhibitor) = self.idle_inhibitor {
+            idle_inhibitor.destroy();
+        }
+    }
+}
+
+impl SurfaceData {
+    /// Adds or removes the game hints, depending on whether this is a fullscreen toplevel that
+    /// asked to bypass the compositor.
+    pub(super) fn update_game_hints(
+        &mut self,
+        globals: &GameGlobals,
+        qh: &ClientQueueHandle,
+        bypass_compositor: bool,
+    ) {
+        let fullscreen = matches!(
+            self.role,
+            Some(SurfaceRole::Toplevel(Some(ref toplevel))) if toplevel.fullscreen
+        );
+        let is_game = bypass_compositor && fullscreen;
+        if is_game == self.game_hints.is_some() {
+            return;
+        }
+
+        debug!(
+            "{} game hints for {:?}",
+            if is_game { "adding" } else { "removing" },
+            self.window
+        );
+        if is_game {
+            self.game_hints = Some(GameHints::new(globals, &self.client, qh));
+        } else if let Some(hints) = self.game_hints.take() {
+            hints.destroy();
+        }
+    }
+}
@@ -0,0 +1,76 @@
+//! Consistency checks for committed surfaces, run in debug builds.
+//!
+//! A window that ends up blurry or cropped usually has one of its buffer size, buffer scale,
+//! viewport destination or X11 window size out of date, which is easy to miss from the logs alone.
+
+use crate::xstate::WindowDims;
+
+/// The parts of a surface's state that determine how big it ends up on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct SurfaceGeometry {
+    /// The size of the attached buffer in pixels.
+    pub buffer_size: Option<(i32, i32)>,
+    pub scale: i32,
+    pub viewport_destination: Option<(i32, i32)>,
+}
+
+impl SurfaceGeometry {
+    /// The size of the surface in surface coordinates.
+    pub fn size(&self) -> Option<(i32, i32)> {
+        self.viewport_destination.or_else(|| {
+            let (width, height) = self.buffer_size?;
+            let scale = self.scale.max(1);
+            Some((width / scale, height / scale))
+        })
+    }
+}
+
+impl Default for SurfaceGeometry {
+    fn default() -> Self {
+        Self {
+            buffer_size: None,
+            scale: 1,
+            viewport_destination: None,
+        }
+    }
+}
+
+/// Describes everything about a surface's geometry that doesn't add up.
+pub(super) fn check_surface(
+    geometry: &SurfaceGeometry,
+    window: Option<&WindowDims>,
+) -> Vec<String> {
+    let mut violations = Vec::new();
+    let Some((width, height)) = geometry.buffer_size else {
+        return violations;
+    };
+
+    if geometry.scale < 1 {
+        violations.push(format!("invalid buffer scale {}", geometry.scale));
+    } else if width % geometry.scale != 0 || height % geometry.scale != 0 {
+        violations.push(format!(
+            "buffer size {width}x{height} is not divisible by its scale {}",
+            geometry.scale
+        ));
+    }
+
+    if let Some((dest_width, dest_height)) = geometry.viewport_destination {
+        if dest_width <= 0 || dest_height <= 0 {
+            violations.push(format!(
+                "invalid viewport destination {dest_width}x{dest_height}"
+            ));
+        }
+    }
+
+    // Xwayland draws windows at their X11 size, so the buffer should match it.
+    if let Some(dims) = window {
+        if (width, height) != (dims.width.into(), dims.height.into()) {
+            violations.push(format!(
+                "buffer size {width}x{height} doesn't match the window size {}x{}",
+                dims.width, dims.height
+            ));
+        }
+    }
+
+    violations
+}
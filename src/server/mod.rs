@@ -1,41 +1,70 @@
 mod dispatch;
 mod event;
+mod game;
+mod invariants;
+mod pacing;
+mod placement;
 
 #[cfg(test)]
 mod tests;
 
 use self::event::*;
 use super::FromServerState;
+use crate::clientside::xdg_activation::{TokenBroker, TokenOwner};
 use crate::clientside::*;
-use crate::xstate::{Atoms, WindowDims, WmHints, WmName, WmNormalHints};
+use crate::config::{
+    AppIdSource, Config, DecorationMode, RaisePolicy, SpanningWindows, WineDesktop,
+};
+use crate::xstate::{
+    Atoms, FrameExtents, MoveResize, RoleOverride, WinGravity, WindowDims, WindowType, WmClass,
+    WmHints, WmName, WmNormalHints,
+};
 use crate::{X11Selection, XConnection};
+use bitflags::bitflags;
 use log::{debug, warn};
 use rustix::event::{poll, PollFd, PollFlags};
+use rustix::pipe::{pipe_with, PipeFlags};
 use slotmap::{new_key_type, HopSlotMap, SparseSecondaryMap};
 use smithay_client_toolkit::data_device_manager::{
     data_device::DataDevice, data_offer::SelectionOffer, data_source::CopyPasteSource,
-    DataDeviceManagerState,
+    DataDeviceManagerState, ReadPipe,
 };
 use std::collections::HashMap;
 use std::io::Read;
 use std::os::fd::{AsFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
 use std::rc::{Rc, Weak};
-use wayland_client::{globals::Global, protocol as client, Proxy};
+use std::time::{Duration, Instant};
+use wayland_client::{
+    globals::{Global, GlobalList},
+    protocol as client, Proxy,
+};
 use wayland_protocols::{
+    ext::data_control::v1::client::{
+        ext_data_control_device_v1::ExtDataControlDeviceV1,
+        ext_data_control_manager_v1::ExtDataControlManagerV1,
+        ext_data_control_offer_v1::ExtDataControlOfferV1,
+        ext_data_control_source_v1::ExtDataControlSourceV1,
+    },
     wp::{
         linux_dmabuf::zv1::{client as c_dmabuf, server as s_dmabuf},
         pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
         relative_pointer::zv1::server::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1,
         tablet::zv2::server::zwp_tablet_manager_v2::ZwpTabletManagerV2,
-        viewporter::server as s_vp,
+        viewporter::{client as c_vp, server as s_vp},
     },
     xdg::{
+        activation::v1::client::xdg_activation_v1::XdgActivationV1,
+        decoration::zv1::client::{
+            zxdg_decoration_manager_v1::ZxdgDecorationManagerV1,
+            zxdg_toplevel_decoration_v1::{self, ZxdgToplevelDecorationV1},
+        },
+        dialog::v1::client::{xdg_dialog_v1::XdgDialogV1, xdg_wm_dialog_v1::XdgWmDialogV1},
         shell::client::{
             xdg_popup::XdgPopup,
-            xdg_positioner::{Anchor, Gravity, XdgPositioner},
+            xdg_positioner::XdgPositioner,
             xdg_surface::XdgSurface,
-            xdg_toplevel::XdgToplevel,
+            xdg_toplevel::{ResizeEdge, XdgToplevel},
             xdg_wm_base::XdgWmBase,
         },
         xdg_output::zv1::server::zxdg_output_manager_v1::ZxdgOutputManagerV1,
@@ -77,6 +106,36 @@ where
     u32::from(wenum).try_into().unwrap()
 }
 
+/// Clamps a coordinate to the 16 bits X11 has for it.
+fn x11_coord(window: x::Window, value: i32) -> i16 {
+    i16::try_from(value).unwrap_or_else(|_| {
+        warn!("{window:?} coordinate {value} is out of range for X11");
+        value.clamp(i16::MIN.into(), i16::MAX.into()) as i16
+    })
+}
+
+/// Clamps a size to what X11 accepts for a window, which can't be empty.
+fn x11_size(window: x::Window, value: i32) -> u16 {
+    match u16::try_from(value) {
+        Ok(value) if value > 0 => value,
+        _ => {
+            warn!("{window:?} size {value} is out of range for X11");
+            value.clamp(1, u16::MAX.into()) as u16
+        }
+    }
+}
+
+/// xdg_positioner rejects empty sizes, which X11 windows can have.
+fn positioner_size(window: x::Window, dims: WindowDims) -> (i32, i32) {
+    if dims.width == 0 || dims.height == 0 {
+        warn!(
+            "{window:?} has an empty size ({}x{}), positioning it as 1x1",
+            dims.width, dims.height
+        );
+    }
+    (dims.width.max(1).into(), dims.height.max(1).into())
+}
+
 #[derive(Default, Debug)]
 pub struct WindowAttributes {
     pub override_redirect: bool,
@@ -84,8 +143,145 @@ pub struct WindowAttributes {
     pub dims: WindowDims,
     pub size_hints: Option<WmNormalHints>,
     pub title: Option<WmName>,
-    pub class: Option<String>,
+    pub class: Option<WmClass>,
     pub group: Option<x::Window>,
+    pub transient_for: Option<x::Window>,
+    pub modal: bool,
+    /// Whether _NET_WM_BYPASS_COMPOSITOR asks for compositing to be disabled.
+    pub bypass_compositor: bool,
+    pub frame_extents: FrameExtents,
+    /// Whether the window's visual has no alpha channel.
+    pub opaque: bool,
+    pub window_type: WindowType,
+    /// The role forced with _XWLS_ROLE, which takes effect the next time the window is mapped.
+    pub role_override: Option<RoleOverride>,
+    /// The window's WM_WINDOW_ROLE.
+    pub wm_role: Option<String>,
+    /// Whether _MOTIF_WM_HINTS leave resizing out of the window's functions.
+    pub motif_no_resize: bool,
+    /// Whether WM_HINTS say the window doesn't take input focus.
+    pub no_input: bool,
+    /// Whether WM_PROTOCOLS has WM_TAKE_FOCUS, which makes a no input window Globally Active.
+    pub take_focus: bool,
+    /// The area spanned by the monitors picked with _NET_WM_FULLSCREEN_MONITORS.
+    pub fullscreen_monitors: Option<(i32, i32, i32, i32)>,
+}
+
+/// The part of a window outside of its _GTK_FRAME_EXTENTS, relative to the window.
+fn visible_geometry(dims: WindowDims, extents: FrameExtents) -> (i32, i32, i32, i32) {
+    let width = i32::from(dims.width);
+    let height = i32::from(dims.height);
+    let x = extents.left.clamp(0, (width - 1).max(0));
+    let y = extents.top.clamp(0, (height - 1).max(0));
+    (
+        x,
+        y,
+        (width - x - extents.right).max(1),
+        (height - y - extents.bottom).max(1),
+    )
+}
+
+/// Passes a window's minimum and maximum size on to its toplevel. The maximum is brought down to
+/// a size the window's resize increments and aspect ratio allow, so the compositor doesn't offer
+/// one the window would refuse. Both are sizes of the whole window, while the toplevel's are of
+/// the part inside its _GTK_FRAME_EXTENTS.
+fn apply_size_hints(hints: &WmNormalHints, extents: FrameExtents, toplevel: &XdgToplevel) {
+    let frame_width = extents.left + extents.right;
+    let frame_height = extents.top + extents.bottom;
+    if let Some(min) = hints.effective_min_size() {
+        toplevel.set_min_size(
+            (min.width - frame_width).max(0),
+            (min.height - frame_height).max(0),
+        );
+    }
+    if let Some(max) = &hints.max_size {
+        let (width, height) = if max.width > 0 && max.height > 0 {
+            hints.constrain(max.width, max.height)
+        } else {
+            (max.width, max.height)
+        };
+        // 0 leaves that dimension unlimited, so it has to stay that way.
+        let without_frame = |size: i32, frame: i32| {
+            if size > 0 {
+                (size - frame).max(1)
+            } else {
+                size
+            }
+        };
+        toplevel.set_max_size(
+            without_frame(width, frame_width),
+            without_frame(height, frame_height),
+        );
+    }
+}
+
+impl WindowAttributes {
+    fn visible_geometry(&self) -> (i32, i32, i32, i32) {
+        visible_geometry(self.dims, self.frame_extents)
+    }
+
+    /// Whether the window can't be resized, because its minimum and maximum size are the same or
+    /// its Motif hints say so.
+    fn fixed_size(&self) -> bool {
+        self.motif_no_resize
+            || self
+                .size_hints
+                .as_ref()
+                .is_some_and(|hints| hints.min_size.is_some() && hints.min_size == hints.max_size)
+    }
+
+    /// Whether the window is kept at its current size, rather than the one its size hints allow.
+    /// Toplevels can't ask to be centered, but compositors that float windows of a fixed size
+    /// usually put splash screens in the middle of the screen that way.
+    fn pinned_size(&self) -> bool {
+        self.window_type == WindowType::Splash || self.motif_no_resize
+    }
+
+    /// Sends the toplevel its minimum and maximum size. Has to be called again whenever what it's
+    /// based on changes, since a pinned size goes stale as soon as the window is resized.
+    fn apply_size_limits(&self, toplevel: &XdgToplevel) {
+        if self.pinned_size() {
+            let (_, _, width, height) = self.visible_geometry();
+            toplevel.set_min_size(width, height);
+            toplevel.set_max_size(width, height);
+        } else if let Some(hints) = &self.size_hints {
+            apply_size_hints(hints, self.frame_extents, toplevel);
+        }
+    }
+
+    /// Whether this is the window of a Wine virtual desktop. It belongs to Wine's explorer.exe,
+    /// which also owns other windows (i.e. the file manager), so the desktop is told apart by its
+    /// "Wine desktop" or "<name> - Wine desktop" title.
+    fn is_wine_desktop(&self) -> bool {
+        let explorer = self.class.as_ref().is_some_and(|class| {
+            class.instance.eq_ignore_ascii_case("explorer.exe")
+                || class.class.eq_ignore_ascii_case("explorer.exe")
+        });
+        explorer
+            && self
+                .title
+                .as_ref()
+                .is_some_and(|title| title.name().ends_with("Wine desktop"))
+    }
+
+    /// Tells the compositor which part of the window is its actual content, if GTK said it's not
+    /// all of it.
+    fn apply_window_geometry(&self, xdg: &XdgSurface) {
+        if self.frame_extents != FrameExtents::default() {
+            let (x, y, width, height) = self.visible_geometry();
+            xdg.set_window_geometry(x, y, width, height);
+        }
+    }
+}
+
+/// Attribute changes that haven't been sent to the compositor yet. Changes that arrive while the
+/// toplevel is waiting for its first configure are held here, so that they all land in the same
+/// commit instead of racing the initial configure.
+#[derive(Default, Debug)]
+struct PendingWindowAttributes {
+    title: Option<WmName>,
+    class: Option<WmClass>,
+    size_hints: Option<WmNormalHints>,
 }
 
 #[derive(Debug, Default, PartialEq, Eq)]
@@ -101,8 +297,22 @@ struct WindowData {
     surface_key: Option<ObjectKey>,
     mapped: bool,
     attrs: WindowAttributes,
+    pending_attrs: PendingWindowAttributes,
     output_offset: WindowOutputOffset,
     output_key: Option<ObjectKey>,
+    /// Whether WM_HINTS has the urgency hint set.
+    urgent: bool,
+    /// Whether the window has _NET_WM_STATE_DEMANDS_ATTENTION set.
+    demands_attention: bool,
+    /// The window's _NET_WM_USER_TIME, the X timestamp of the last user interaction with it.
+    user_time: Option<u32>,
+    /// The window's _NET_WM_PID.
+    pid: Option<u32>,
+    /// The window's popup parent went away while it was mapped, so it's shown as a toplevel
+    /// until it's unmapped.
+    orphaned: bool,
+    /// The window was made a popup by a role override, rather than because it's override-redirect.
+    forced_popup: bool,
 }
 
 impl WindowData {
@@ -123,8 +333,48 @@ impl WindowData {
                 popup_for: parent,
                 ..Default::default()
             },
+            pending_attrs: PendingWindowAttributes::default(),
             output_offset: WindowOutputOffset::default(),
             output_key: None,
+            urgent: false,
+            demands_attention: false,
+            user_time: None,
+            pid: None,
+            orphaned: false,
+            forced_popup: false,
+        }
+    }
+
+    /// Whether the window has the urgency hint or _NET_WM_STATE_DEMANDS_ATTENTION set.
+    fn wants_attention(&self) -> bool {
+        self.urgent || self.demands_attention
+    }
+
+    /// Moves staged attribute changes into our attributes, and sends them to the toplevel if given.
+    fn apply_pending_attrs(&mut self, toplevel: Option<&XdgToplevel>, app_id_source: AppIdSource) {
+        let PendingWindowAttributes {
+            title,
+            class,
+            size_hints,
+        } = std::mem::take(&mut self.pending_attrs);
+
+        if let Some(title) = title {
+            if let Some(toplevel) = toplevel {
+                toplevel.set_title(title.name().to_string());
+            }
+            self.attrs.title = Some(title);
+        }
+        if let Some(class) = class {
+            if let Some(toplevel) = toplevel {
+                toplevel.set_app_id(class.app_id(app_id_source).to_string());
+            }
+            self.attrs.class = Some(class);
+        }
+        if let Some(hints) = size_hints {
+            self.attrs.size_hints = Some(hints);
+            if let Some(toplevel) = toplevel {
+                self.attrs.apply_size_limits(toplevel);
+            }
         }
     }
 
@@ -144,17 +394,23 @@ impl WindowData {
         }
 
         let dims = &mut self.attrs.dims;
-        dims.x += (offset.x - self.output_offset.x) as i16;
-        dims.y += (offset.y - self.output_offset.y) as i16;
+        dims.x = x11_coord(
+            self.window,
+            i32::from(dims.x) + offset.x - self.output_offset.x,
+        );
+        dims.y = x11_coord(
+            self.window,
+            i32::from(dims.y) + offset.y - self.output_offset.y,
+        );
         self.output_offset = offset;
 
         connection.set_window_dims(
             self.window,
             PendingSurfaceState {
-                x: dims.x as i32,
-                y: dims.y as i32,
-                width: self.attrs.dims.width as _,
-                height: self.attrs.dims.height as _,
+                x: dims.x.into(),
+                y: dims.y.into(),
+                width: dims.width.into(),
+                height: dims.height.into(),
             },
         );
 
@@ -175,10 +431,34 @@ pub struct SurfaceData {
     serial: Option<[u32; 2]>,
     frame_callback: Option<WlCallback>,
     attach: Option<SurfaceAttach>,
+    /// The buffer Xwayland last attached, to show again if the surface gets a new role.
+    buffer: Option<client::wl_buffer::WlBuffer>,
     role: Option<SurfaceRole>,
     xwl: Option<XwaylandSurfaceV1>,
     window: Option<x::Window>,
     output_key: Option<ObjectKey>,
+    geometry: invariants::SurfaceGeometry,
+    game_hints: Option<game::GameHints>,
+    /// The size of the opaque region last set on the surface.
+    opaque_region: Option<(i32, i32)>,
+    pacing: pacing::PacingStats,
+    /// Until when the surface's window waits for content before getting a role anyway.
+    content_deadline: Option<Instant>,
+    /// Whether the compositor suspended the surface's toplevel.
+    suspended: bool,
+    /// Frame callbacks held back while the surface is suspended.
+    held_frames: Vec<FrameDone>,
+    /// Frame callbacks passed on to the compositor that it hasn't answered yet.
+    pending_frames: Vec<WlCallback>,
+    viewport: Option<SurfaceViewport>,
+}
+
+/// The compositor side of the wp_viewport Xwayland created for a surface, with what's needed to
+/// create it again on another wl_surface.
+struct SurfaceViewport {
+    viewporter: c_vp::wp_viewporter::WpViewporter,
+    viewport: c_vp::wp_viewport::WpViewport,
+    source: Option<(f64, f64, f64, f64)>,
 }
 
 impl SurfaceData {
@@ -205,9 +485,18 @@ impl SurfaceData {
     }
 
     fn destroy_role(&mut self) {
+        if let Some(hints) = self.game_hints.take() {
+            hints.destroy();
+        }
         if let Some(role) = self.role.take() {
             match role {
                 SurfaceRole::Toplevel(Some(t)) => {
+                    if let Some(dialog) = t.dialog {
+                        dialog.destroy();
+                    }
+                    if let Some(decoration) = t.decoration {
+                        decoration.destroy();
+                    }
                     t.toplevel.destroy();
                     t.xdg.surface.destroy();
                 }
@@ -240,6 +529,14 @@ struct ToplevelData {
     toplevel: XdgToplevel,
     xdg: XdgSurfaceData,
     fullscreen: bool,
+    maximized: bool,
+    minimized: bool,
+    dialog: Option<XdgDialogV1>,
+    /// Only set when a decoration mode is forced on the window.
+    decoration: Option<ZxdgToplevelDecorationV1>,
+    /// The largest size the compositor wants the toplevel to have, from configure_bounds.
+    bounds: Option<(i32, i32)>,
+    tiled: TiledEdges,
 }
 
 #[derive(Debug)]
@@ -247,6 +544,11 @@ struct PopupData {
     popup: XdgPopup,
     positioner: XdgPositioner,
     xdg: XdgSurfaceData,
+    placement: Box<dyn placement::PlacementStrategy>,
+    /// Whether the popup has an xdg_popup grab, which its child popups then have to share.
+    grabbed: bool,
+    /// The compositor sent popup_done. The popup is kept until the X client unmaps its window.
+    dismissed: bool,
 }
 
 pub(crate) trait HandleEvent {
@@ -351,9 +653,12 @@ macro_rules! handle_event_enum {
 handle_event_enum! {
 
 /// Objects that generate client side events that we will have to process.
+// Most objects are surfaces anyway, so boxing them wouldn't save anything.
+#[allow(clippy::large_enum_variant)]
 pub(crate) enum Object {
     Surface(SurfaceData),
     Buffer(Buffer),
+    Shm(Shm),
     Seat(Seat),
     Pointer(Pointer),
     Keyboard(Keyboard),
@@ -471,7 +776,155 @@ new_key_type! {
 
 struct FocusData {
     window: x::Window,
-    output_name: Option<String>,
+    output: Option<FocusedOutput>,
+}
+
+/// A toplevel that outlived the surface of its X11 window, so that it can be reused if the window
+/// is mapped again soon. See [`Config::unmap_grace`].
+#[derive(Debug)]
+struct LingeringToplevel {
+    /// The key the clientside objects were created with.
+    key: ObjectKey,
+    client: client::wl_surface::WlSurface,
+    toplevel: ToplevelData,
+    output_key: Option<ObjectKey>,
+    expires: Instant,
+}
+
+/// The order mapped windows should be stacked in on the X side. Raises only change this order,
+/// and the X server is brought in line with it once per loop iteration, so that a burst of
+/// raises (i.e. several dialogs opening at once) doesn't cause a burst of restacks.
+/// Override-redirect windows stack themselves, so they're left out.
+#[derive(Debug, Default)]
+struct StackingManager {
+    /// Bottom to top.
+    order: Vec<x::Window>,
+    /// The X stack as of the last ConfigureNotify, bottom to top, including unmapped and
+    /// override-redirect windows.
+    applied: Vec<x::Window>,
+}
+
+impl StackingManager {
+    /// X puts new windows on top of the stack.
+    fn created(&mut self, window: x::Window) {
+        self.applied.retain(|w| *w != window);
+        self.applied.push(window);
+    }
+
+    /// The X server placed `window` directly above `sibling`, or at the bottom of the stack if
+    /// it's `WINDOW_NONE`.
+    fn stacked(&mut self, window: x::Window, sibling: x::Window) {
+        let idx = if sibling == x::WINDOW_NONE {
+            0
+        } else {
+            // Outside of what we know about, nothing can be said about the window.
+            let Some(idx) = self.applied.iter().position(|w| *w == sibling) else {
+                return;
+            };
+            idx + 1
+        };
+        let idx = match self.applied.iter().position(|w| *w == window) {
+            Some(current) => {
+                self.applied.remove(current);
+                if current < idx {
+                    idx - 1
+                } else {
+                    idx
+                }
+            }
+            None => idx,
+        };
+        self.applied.insert(idx, window);
+    }
+
+    /// Mapping doesn't restack, so the window is placed wherever it is in the X stack.
+    fn add(&mut self, window: x::Window) {
+        self.order.retain(|w| *w != window);
+        let position = |window: &x::Window| self.applied.iter().position(|w| w == window);
+        let idx = position(&window)
+            .and_then(|pos| {
+                self.order
+                    .iter()
+                    .position(|w| position(w).is_some_and(|other| other > pos))
+            })
+            .unwrap_or(self.order.len());
+        self.order.insert(idx, window);
+    }
+
+    fn unmapped(&mut self, window: x::Window) {
+        self.order.retain(|w| *w != window);
+    }
+
+    fn remove(&mut self, window: x::Window) {
+        self.order.retain(|w| *w != window);
+        self.applied.retain(|w| *w != window);
+    }
+
+    /// Moves a window to the top, along with its popups and transients so they stay above it.
+    fn raise(&mut self, window: x::Window, windows: &HashMap<x::Window, WindowData>) {
+        self.restack(window, None, x::StackMode::Above, windows);
+    }
+
+    /// Moves a window directly above or below `sibling`, or to the top or bottom of the stack
+    /// without one. Its popups and transients move along with it.
+    fn restack(
+        &mut self,
+        window: x::Window,
+        sibling: Option<x::Window>,
+        mode: x::StackMode,
+        windows: &HashMap<x::Window, WindowData>,
+    ) {
+        if !self.order.contains(&window) || sibling.is_some_and(|s| !self.order.contains(&s)) {
+            return;
+        }
+        let mut moved = vec![window];
+        // Children are usually above their parents already, so one pass catches whole chains.
+        for w in &self.order {
+            let parent = windows
+                .get(w)
+                .and_then(|data| data.attrs.popup_for.or(data.attrs.transient_for));
+            if parent.is_some_and(|parent| moved.contains(&parent)) && !moved.contains(w) {
+                moved.push(*w);
+            }
+        }
+        if sibling.is_some_and(|s| moved.contains(&s)) {
+            return;
+        }
+
+        let (moved, mut rest): (Vec<_>, Vec<_>) =
+            self.order.iter().partition(|w| moved.contains(w));
+        let sibling_idx = sibling.and_then(|s| rest.iter().position(|w| *w == s));
+        let idx = match (mode, sibling_idx) {
+            (x::StackMode::Below, Some(idx)) => idx,
+            (x::StackMode::Below, None) => 0,
+            (_, Some(idx)) => idx + 1,
+            (_, None) => rest.len(),
+        };
+        rest.splice(idx..idx, moved);
+        self.order = rest;
+    }
+
+    /// Restacks the windows whose position in the X stack doesn't match `order`. Each window is
+    /// put above the one below it, so the bottom window stays where it is.
+    fn reconcile(&mut self, connection: &mut impl XConnection) {
+        for idx in 1..self.order.len() {
+            let (below, window) = (self.order[idx - 1], self.order[idx]);
+            // Windows that aren't in `order` can be in between.
+            let above_below = self
+                .applied
+                .iter()
+                .skip_while(|w| **w != below)
+                .skip(1)
+                .find(|w| self.order.contains(w));
+            if above_below == Some(&window) {
+                continue;
+            }
+            connection.stack_above(window, below);
+            // The ConfigureNotify will say the same, but restacking again before it arrives
+            // would be wasted.
+            self.stacked(window, below);
+        }
+    }
 }
 
 pub struct ServerState<C: XConnection> {
@@ -479,8 +932,18 @@ pub struct ServerState<C: XConnection> {
     dh: DisplayHandle,
     clientside: ClientState,
     objects: ObjectMap,
+    /// Reused between calls to handle_clientside_events to avoid allocating for every batch.
+    event_buffer: Vec<(ObjectKey, ObjectEvent)>,
+    /// Same as `event_buffer`, but for frame callbacks, which arrive for every frame of every
+    /// window.
+    frame_buffer: Vec<FrameDone>,
     associated_windows: SparseSecondaryMap<ObjectKey, x::Window>,
+    /// The surface each pointer lock or confinement is for.
+    pointer_constraints: SparseSecondaryMap<ObjectKey, ConstrainedSurface>,
     windows: HashMap<x::Window, WindowData>,
+    lingering: HashMap<x::Window, LingeringToplevel>,
+    /// Maps the keys of reused clientside surfaces to the key of the surface now using them.
+    surface_aliases: HashMap<ObjectKey, ObjectKey>,
 
     qh: ClientQueueHandle,
     client: Option<Client>,
@@ -488,15 +951,40 @@ pub struct ServerState<C: XConnection> {
     unfocus: bool,
     last_focused_toplevel: Option<x::Window>,
     last_hovered: Option<x::Window>,
+    /// Where the pointer is on the surface of `last_hovered`.
+    pointer_position: (f64, f64),
+    /// Popups the compositor dismissed since the last batch of events.
+    dismissed_popups: Vec<x::Window>,
+    stacking: StackingManager,
     pub connection: Option<C>,
 
+    compositor: client::wl_compositor::WlCompositor,
     xdg_wm_base: XdgWmBase,
+    xdg_wm_dialog: Option<XdgWmDialogV1>,
+    decoration_manager: Option<ZxdgDecorationManagerV1>,
+    xdg_activation: Option<XdgActivationV1>,
+    startup_tokens: TokenBroker,
+    game_globals: game::GameGlobals,
     clipboard_data: Option<ClipboardData<C::X11Selection>>,
     last_kb_serial: Option<u32>,
+    /// The serial of the last press of each button, by evdev code.
+    click_serials: HashMap<u32, u32>,
+    /// The latest of `click_serials`.
+    last_click_serial: Option<u32>,
+    /// The latest of `last_kb_serial` and `click_serials`. Serials wrap around, so which one
+    /// is the latest can only be told by when it arrived.
+    last_input_serial: Option<u32>,
+    move_resize: Option<ActiveMoveResize>,
+    /// The usable area of each output, from the configure bounds of the toplevels on it.
+    output_work_areas: HashMap<ObjectKey, (i32, i32, i32, i32)>,
+    /// The last _NET_WORKAREA set.
+    work_area: Option<(i32, i32, i32, i32)>,
+    seat: Option<client::wl_seat::WlSeat>,
+    config: Config,
 }
 
 impl<C: XConnection> ServerState<C> {
-    pub fn new(dh: DisplayHandle, server_connection: Option<UnixStream>) -> Self {
+    pub fn new(dh: DisplayHandle, server_connection: Option<UnixStream>, config: Config) -> Self {
         let clientside = ClientState::new(server_connection);
         let qh = clientside.qh.clone();
 
@@ -509,16 +997,33 @@ impl<C: XConnection> ServerState<C> {
             warn!("xdg_wm_base version 2 detected. Popup repositioning will not work, and some popups may not work correctly.");
         }
 
-        let manager = DataDeviceManagerState::bind(&clientside.global_list, &qh)
-            .inspect_err(|e| {
-                warn!("Could not bind data device manager ({e:?}). Clipboard will not work.")
-            })
+        let compositor = clientside
+            .global_list
+            .bind::<client::wl_compositor::WlCompositor, _, _>(&qh, 1..=1, ())
+            .expect("Could not bind wl_compositor");
+
+        let xdg_wm_dialog = clientside
+            .global_list
+            .bind::<XdgWmDialogV1, _, _>(&qh, 1..=1, ())
             .ok();
-        let clipboard_data = manager.map(|manager| ClipboardData {
-            manager,
-            device: None,
-            source: None::<CopyPasteData<C::X11Selection>>,
-        });
+
+        let decoration_manager = clientside
+            .global_list
+            .bind::<ZxdgDecorationManagerV1, _, _>(&qh, 1..=1, ())
+            .ok();
+
+        let xdg_activation = clientside
+            .global_list
+            .bind::<XdgActivationV1, _, _>(&qh, 1..=1, ())
+            .ok();
+
+        let game_globals = game::GameGlobals::bind(&clientside.global_list, &qh);
+
+        let clipboard_data =
+            ClipboardBackend::bind(&clientside.global_list, &qh).map(|backend| ClipboardData {
+                backend,
+                source: None::<CopyPasteData<C::X11Selection>>,
+            });
 
         dh.create_global::<Self, XwaylandShellV1, _>(1, ());
         clientside
@@ -528,6 +1033,8 @@ impl<C: XConnection> ServerState<C> {
 
         Self {
             windows: HashMap::new(),
+            lingering: HashMap::new(),
+            surface_aliases: HashMap::new(),
             clientside,
             client: None,
             atoms: None,
@@ -537,12 +1044,32 @@ impl<C: XConnection> ServerState<C> {
             unfocus: false,
             last_focused_toplevel: None,
             last_hovered: None,
+            pointer_position: (0.0, 0.0),
+            dismissed_popups: Vec::new(),
+            stacking: StackingManager::default(),
             connection: None,
             objects: Default::default(),
+            event_buffer: Vec::new(),
+            frame_buffer: Vec::new(),
             associated_windows: Default::default(),
+            pointer_constraints: Default::default(),
+            compositor,
             xdg_wm_base,
+            xdg_wm_dialog,
+            decoration_manager,
+            xdg_activation,
+            startup_tokens: TokenBroker::default(),
+            game_globals,
             clipboard_data,
             last_kb_serial: None,
+            click_serials: HashMap::new(),
+            last_click_serial: None,
+            last_input_serial: None,
+            move_resize: None,
+            output_work_areas: HashMap::new(),
+            work_area: None,
+            seat: None,
+            config,
         }
     }
 
@@ -578,6 +1105,20 @@ impl<C: XConnection> ServerState<C> {
             window,
             WindowData::new(window, override_redirect, dims, parent),
         );
+        self.stacking.created(window);
+    }
+
+    /// Whether the window's title came from _NET_WM_NAME, which WM_NAME doesn't replace.
+    pub fn window_has_net_wm_name(&self, window: x::Window) -> bool {
+        self.windows.get(&window).is_some_and(|win| {
+            matches!(
+                win.pending_attrs
+                    .title
+                    .as_ref()
+                    .or(win.attrs.title.as_ref()),
+                Some(WmName::NetWmName(_))
+            )
+        })
     }
 
     pub fn set_win_title(&mut self, window: x::Window, name: WmName) {
@@ -586,52 +1127,29 @@ impl<C: XConnection> ServerState<C> {
             return;
         };
 
-        let new_title = match &mut win.attrs.title {
-            Some(w) => {
-                if matches!(w, WmName::NetWmName(_)) && matches!(name, WmName::WmName(_)) {
-                    debug!("skipping setting window name to {name:?} because a _NET_WM_NAME title is already set");
-                    None
-                } else {
-                    debug!("setting {window:?} title to {name:?}");
-                    *w = name;
-                    Some(w)
-                }
-            }
-            None => Some(win.attrs.title.insert(name)),
-        };
-
-        let Some(title) = new_title else {
+        let current = win
+            .pending_attrs
+            .title
+            .as_ref()
+            .or(win.attrs.title.as_ref());
+        if matches!(current, Some(WmName::NetWmName(_))) && matches!(name, WmName::WmName(_)) {
+            debug!("skipping setting window name to {name:?} because a _NET_WM_NAME title is already set");
             return;
-        };
-        if let Some(key) = win.surface_key {
-            if let Some(object) = self.objects.get(key) {
-                let surface: &SurfaceData = object.as_ref();
-                if let Some(SurfaceRole::Toplevel(Some(data))) = &surface.role {
-                    data.toplevel.set_title(title.name().to_string());
-                }
-            } else {
-                warn!("could not set window title: stale surface");
-            }
         }
+
+        debug!("setting {window:?} title to {name:?}");
+        win.pending_attrs.title = Some(name);
+        self.apply_window_attrs(window);
     }
 
-    pub fn set_win_class(&mut self, window: x::Window, class: String) {
+    pub fn set_win_class(&mut self, window: x::Window, class: WmClass) {
         let Some(win) = self.windows.get_mut(&window) else {
             debug!("not setting class for unknown window {window:?}");
             return;
         };
 
-        let class = win.attrs.class.insert(class);
-        if let Some(key) = win.surface_key {
-            if let Some(object) = self.objects.get(key) {
-                let surface: &SurfaceData = object.as_ref();
-                if let Some(SurfaceRole::Toplevel(Some(data))) = &surface.role {
-                    data.toplevel.set_app_id(class.to_string());
-                }
-            } else {
-                warn!("could not set window class: stale surface");
-            }
-        }
+        win.pending_attrs.class = Some(class);
+        self.apply_window_attrs(window);
     }
 
     pub fn set_win_hints(&mut self, window: x::Window, hints: WmHints) {
@@ -640,178 +1158,1120 @@ impl<C: XConnection> ServerState<C> {
             return;
         };
         win.attrs.group = hints.window_group;
+        win.attrs.no_input = hints.input == Some(false);
+        let wanted_attention = win.wants_attention();
+        win.urgent = hints.urgent;
+        self.attention_changed(window, wanted_attention);
     }
 
-    pub fn set_size_hints(&mut self, window: x::Window, hints: WmNormalHints) {
+    pub fn set_demands_attention(&mut self, window: x::Window, demands_attention: bool) {
         let Some(win) = self.windows.get_mut(&window) else {
-            debug!("not setting size hints for unknown window {window:?}");
             return;
         };
-
-        if win.attrs.size_hints.is_none() || *win.attrs.size_hints.as_ref().unwrap() != hints {
-            debug!("setting {window:?} hints {hints:?}");
-            if let Some(key) = win.surface_key {
-                if let Some(object) = self.objects.get(key) {
-                    let surface: &SurfaceData = object.as_ref();
-                    if let Some(SurfaceRole::Toplevel(Some(data))) = &surface.role {
-                        if let Some(min_size) = &hints.min_size {
-                            data.toplevel.set_min_size(min_size.width, min_size.height);
-                        }
-                        if let Some(max_size) = &hints.max_size {
-                            data.toplevel.set_max_size(max_size.width, max_size.height);
-                        }
-                    }
-                } else {
-                    warn!("could not set size hint on {window:?}: stale surface")
-                }
-            }
-            win.attrs.size_hints = Some(hints);
-        }
+        let wanted_attention = win.wants_attention();
+        win.demands_attention = demands_attention;
+        self.attention_changed(window, wanted_attention);
     }
 
-    pub fn set_window_serial(&mut self, window: x::Window, serial: [u32; 2]) {
-        let Some(win) = self.windows.get_mut(&window) else {
-            warn!("Tried to set serial for unknown window {window:?}");
+    /// Requests attention once the window starts wanting it, through either of the ways X has to
+    /// say so.
+    fn attention_changed(&self, window: x::Window, wanted_attention: bool) {
+        let win = &self.windows[&window];
+        if wanted_attention || !win.wants_attention() {
             return;
-        };
-        win.surface_serial = Some(serial);
-    }
+        }
 
-    pub fn can_reconfigure_window(&mut self, window: x::Window) -> bool {
-        let Some(win) = self.windows.get_mut(&window) else {
-            return true;
+        let Some(surface) = win
+            .surface_key
+            .and_then(|key| self.objects.get(key))
+            .map(<_ as AsRef<SurfaceData>>::as_ref)
+            .filter(|surface| matches!(surface.role, Some(SurfaceRole::Toplevel(Some(_)))))
+        else {
+            debug!("not requesting attention for {window:?}, it has no toplevel");
+            return;
         };
 
-        !win.mapped || win.attrs.override_redirect
+        debug!("requesting attention for {window:?}");
+        self.request_activation(&surface.client, false);
     }
 
-    pub fn reconfigure_window(&mut self, event: x::ConfigureNotifyEvent) {
-        let Some(win) = self.windows.get_mut(&event.window()) else {
-            debug!("not reconfiguring unknown window {:?}", event.window());
+    /// Asks the compositor to activate a toplevel surface. Unless `focus` is set, the request
+    /// has no serial, which compositors show as the window wanting attention instead of moving
+    /// focus to it.
+    fn request_activation(&self, surface: &client::wl_surface::WlSurface, focus: bool) {
+        let Some(activation) = self.xdg_activation.as_ref() else {
             return;
         };
-        let dims = WindowDims {
-            x: event.x(),
-            y: event.y(),
-            width: event.width(),
-            height: event.height(),
-        };
-        if dims == win.attrs.dims {
-            return;
+        let token =
+            activation.get_activation_token(&self.qh, (activation.clone(), surface.clone()));
+        // The token has to come from the window the user is interacting with.
+        let focused_surface = self
+            .last_focused_toplevel
+            .and_then(|window| self.windows.get(&window))
+            .and_then(|win| win.surface_key)
+            .and_then(|key| self.objects.get(key))
+            .map(|object| &<_ as AsRef<SurfaceData>>::as_ref(object).client);
+        match (focus, self.last_kb_serial, &self.seat) {
+            (true, Some(serial), Some(seat)) => {
+                token.set_serial(serial, seat);
+                token.set_surface(focused_surface.unwrap_or(surface));
+            }
+            _ => token.set_surface(surface),
         }
-        debug!("Reconfiguring {win:?} {:?}", dims);
-        if !win.mapped {
-            win.attrs.dims = dims;
-            return;
+        token.commit();
+    }
+
+    pub fn set_user_time(&mut self, window: x::Window, time: Option<u32>) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.user_time = time;
         }
+    }
 
-        if self.xdg_wm_base.version() < 3 {
-            return;
+    /// Decides whether a newly mapped window may take focus, by comparing its _NET_WM_USER_TIME
+    /// to that of the focused window. None if the window has no user time, so the compositor
+    /// should decide.
+    /// https://specifications.freedesktop.org/wm-spec/1.5/ar01s05.html#id-1.6.13
+    fn may_steal_focus(&self, window: x::Window) -> Option<bool> {
+        let time = self.windows.get(&window)?.user_time?;
+        // A user time of 0 means the window doesn't want focus when it's mapped.
+        if time == 0 {
+            return Some(false);
         }
+        let focused_time = self
+            .last_focused_toplevel
+            .filter(|focused| *focused != window)
+            .and_then(|focused| self.windows.get(&focused))
+            .and_then(|win| win.user_time);
+        // X timestamps wrap around, so compare them like the server does.
+        Some(focused_time.is_none_or(|focused| time.wrapping_sub(focused) as i32 > 0))
+    }
 
-        let Some(key) = win.surface_key else {
+    /// Activates a newly mapped toplevel if it was opened by the user, or has it request
+    /// attention if it would otherwise steal focus.
+    fn activate_new_toplevel(
+        &mut self,
+        window: x::Window,
+        surface: &client::wl_surface::WlSurface,
+    ) {
+        let focus = self.may_steal_focus(window);
+        // A window that doesn't want focus leaves the token for the next one its client maps.
+        if focus != Some(false) {
+            if let Some(token) = self.take_startup_token(window) {
+                if let Some(activation) = self.xdg_activation.as_ref() {
+                    debug!("activating new toplevel {window:?} with its startup token");
+                    activation.activate(token, surface);
+                    return;
+                }
+            }
+        }
+        let Some(focus) = focus else {
             return;
         };
+        debug!("activating new toplevel {window:?} (focus: {focus})");
+        self.request_activation(surface, focus);
+    }
 
-        let Some(data): Option<&mut SurfaceData> = self.objects.get_mut(key).map(|o| o.as_mut())
-        else {
+    /// Records the client process of a window, and the activation token it was launched with.
+    pub fn set_startup_id(&mut self, window: x::Window, pid: Option<u32>, token: Option<String>) {
+        let Some(win) = self.windows.get_mut(&window) else {
             return;
         };
-
-        match &data.role {
-            Some(SurfaceRole::Popup(Some(popup))) => {
-                popup.positioner.set_offset(
-                    event.x() as i32 - win.output_offset.x,
-                    event.y() as i32 - win.output_offset.y,
-                );
-                popup
-                    .positioner
-                    .set_size(event.width().into(), event.height().into());
-                popup.popup.reposition(&popup.positioner, 0);
-            }
-            other => warn!("Non popup ({other:?}) being reconfigured, behavior may be off."),
+        win.pid = pid;
+        if let Some(token) = token {
+            debug!("{window:?} was launched with activation token {token:?}");
+            let owner = pid.map_or(TokenOwner::Window(window), TokenOwner::Pid);
+            self.startup_tokens.offer(owner, token);
         }
     }
 
-    pub fn map_window(&mut self, window: x::Window) {
-        debug!("mapping {window:?}");
-
-        let Some(window) = self.windows.get_mut(&window) else {
-            debug!("not mapping unknown window {window:?}");
-            return;
-        };
-        window.mapped = true;
+    fn take_startup_token(&mut self, window: x::Window) -> Option<String> {
+        let pid = self.windows.get(&window)?.pid;
+        pid.and_then(|pid| self.startup_tokens.take(TokenOwner::Pid(pid)))
+            .or_else(|| self.startup_tokens.take(TokenOwner::Window(window)))
     }
 
-    pub fn unmap_window(&mut self, window: x::Window) {
+    pub fn set_size_hints(&mut self, window: x::Window, mut hints: WmNormalHints) {
         let Some(win) = self.windows.get_mut(&window) else {
+            debug!("not setting size hints for unknown window {window:?}");
             return;
         };
-        if !win.mapped {
-            return;
-        }
-        debug!("unmapping {window:?}");
 
-        if matches!(self.last_focused_toplevel, Some(x) if x == window) {
-            self.last_focused_toplevel.take();
+        // xdg_toplevel raises an error for negative sizes, or a maximum below the minimum
+        let mut errors = Vec::new();
+        for size in hints.min_size.iter_mut().chain(hints.max_size.iter_mut()) {
+            if size.width < 0 || size.height < 0 {
+                errors.push(format!("negative size hint ({size:?}) was clamped to 0"));
+                size.width = size.width.max(0);
+                size.height = size.height.max(0);
+            }
         }
-        if self.last_hovered == Some(window) {
-            self.last_hovered.take();
+        if let (Some(min), Some(max)) = (&hints.min_size, &hints.max_size) {
+            let below = |max: i32, min: i32| max != 0 && max < min;
+            if below(max.width, min.width) || below(max.height, min.height) {
+                errors.push(format!(
+                    "maximum size ({max:?}) below the minimum size ({min:?}) was ignored"
+                ));
+                hints.max_size = None;
+            }
         }
-        win.mapped = false;
 
-        if let Some(key) = win.surface_key.take() {
-            let Some(object) = self.objects.get_mut(key) else {
-                warn!("could not unmap {window:?}: stale surface");
-                return;
-            };
-            let surface: &mut SurfaceData = object.as_mut();
-            surface.destroy_role();
+        let current = win
+            .pending_attrs
+            .size_hints
+            .as_ref()
+            .or(win.attrs.size_hints.as_ref());
+        if current != Some(&hints) {
+            debug!("setting {window:?} hints {hints:?}");
+            win.pending_attrs.size_hints = Some(hints);
+            self.apply_window_attrs(window);
+        }
+        for error in errors {
+            self.report_error(window, error);
         }
     }
 
-    pub fn set_fullscreen(&mut self, window: x::Window, state: super::xstate::SetState) {
-        let Some(win) = self.windows.get(&window) else {
-            warn!("Tried to set unknown window {window:?} fullscreen");
+    /// Logs something satellite refused or changed on behalf of a window, and lets the window's
+    /// client know through _XWLS_LAST_ERROR.
+    fn report_error(&mut self, window: x::Window, error: String) {
+        warn!("{window:?}: {error}");
+        if self.connection.is_none() {
             return;
-        };
-        let Some(key) = win.surface_key else {
-            warn!("Tried to set window without surface fullscreen: {window:?}");
-            return;
-        };
-        let Some(object) = self.objects.get_mut(key) else {
-            warn!("Could not set fullscreen on {window:?}: stale surface");
-            return;
-        };
+        }
+        let data = C::ExtraData::create(self);
+        self.connection
+            .as_mut()
+            .unwrap()
+            .report_error(window, &error, data);
+    }
+
+    /// Applies staged attribute changes, unless the window's toplevel is still waiting
+    /// for its first configure. In that case they will be applied along with the configure.
+    fn apply_window_attrs(&mut self, window: x::Window) {
+        let win = self.windows.get_mut(&window).unwrap();
+        let surface: Option<&SurfaceData> = win
+            .surface_key
+            .and_then(|key| self.objects.get(key))
+            .map(|object| object.as_ref());
+
+        if win.surface_key.is_some() && surface.is_none() {
+            warn!("could not set attributes on {window:?}: stale surface");
+        }
+
+        match surface.and_then(|s| s.role.as_ref()) {
+            Some(SurfaceRole::Toplevel(Some(data))) => {
+                if data.xdg.configured {
+                    win.apply_pending_attrs(Some(&data.toplevel), self.config.app_id_source);
+                }
+            }
+            _ => win.apply_pending_attrs(None, self.config.app_id_source),
+        }
+    }
+
+    pub fn set_window_serial(&mut self, window: x::Window, serial: [u32; 2]) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            warn!("Tried to set serial for unknown window {window:?}");
+            return;
+        };
+        win.surface_serial = Some(serial);
+    }
+
+    pub fn can_reconfigure_window(&mut self, window: x::Window) -> bool {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return true;
+        };
+
+        !win.mapped || win.attrs.override_redirect
+    }
+
+    /// Handles the stacking part of a ConfigureRequest. Only Above and Below are supported, the
+    /// other modes depend on which windows overlap on screen, which only the compositor knows.
+    pub fn restack_window(
+        &mut self,
+        window: x::Window,
+        sibling: Option<x::Window>,
+        mode: x::StackMode,
+    ) {
+        if !matches!(mode, x::StackMode::Above | x::StackMode::Below) {
+            debug!("ignoring {mode:?} restack request for {window:?}");
+            return;
+        }
+        debug!("restacking {window:?} {mode:?} {sibling:?}");
+        self.stacking.restack(window, sibling, mode, &self.windows);
+    }
+
+    /// Handles _NET_RESTACK_WINDOW from pagers. The compositor stacks the toplevels on screen, so
+    /// a focused window that's raised is activated again to bring it to the front there too.
+    pub fn pager_restack_window(
+        &mut self,
+        window: x::Window,
+        sibling: Option<x::Window>,
+        mode: x::StackMode,
+    ) {
+        self.restack_window(window, sibling, mode);
+        if mode != x::StackMode::Above || self.last_focused_toplevel != Some(window) {
+            return;
+        }
+        let Some(surface) = self
+            .windows
+            .get(&window)
+            .and_then(|win| win.surface_key)
+            .and_then(|key| self.objects.get(key))
+            .map(<_ as AsRef<SurfaceData>>::as_ref)
+            .filter(|surface| matches!(surface.role, Some(SurfaceRole::Toplevel(Some(_)))))
+        else {
+            return;
+        };
+        debug!("reactivating {window:?} after restack");
+        self.request_activation(&surface.client, true);
+    }
+
+    /// Starts an interactive move or resize of a toplevel, as asked for with _NET_WM_MOVERESIZE.
+    /// The compositor only allows these in response to input, so the serial of the click or key
+    /// press that led to it has to be passed along.
+    pub fn start_move_resize(&mut self, window: x::Window, op: MoveResize, button: u32) {
+        let Some(toplevel) = self.window_toplevel(window) else {
+            debug!("not starting {op:?} of {window:?}, it has no toplevel");
+            return;
+        };
+        let Some(seat) = self.seat.as_ref() else {
+            return;
+        };
+        let serial = if op.is_keyboard() {
+            self.last_kb_serial
+        } else if button == 0 {
+            // No particular button, so whichever was pressed last.
+            self.last_click_serial
+        } else {
+            evdev_button(button).and_then(|button| self.click_serials.get(&button).copied())
+        };
+        let Some(serial) = serial else {
+            debug!("not starting {op:?} of {window:?} without any input to start it from");
+            return;
+        };
+
+        if matches!(op, MoveResize::Size(_) | MoveResize::SizeKeyboard)
+            && self.windows[&window].attrs.fixed_size()
+        {
+            debug!("not resizing {window:?}, it has a fixed size");
+            return;
+        }
+
+        debug!("starting {op:?} of {window:?} ({serial})");
+        match op {
+            MoveResize::Move | MoveResize::MoveKeyboard => toplevel._move(seat, serial),
+            MoveResize::Size(edge) => toplevel.resize(seat, serial, edge),
+            // The user picks the size with the arrow keys, which grow the window from the bottom
+            // right corner everywhere else too.
+            MoveResize::SizeKeyboard => toplevel.resize(seat, serial, ResizeEdge::BottomRight),
+        }
+        self.move_resize = Some(ActiveMoveResize {
+            window,
+            op,
+            original: self.windows[&window].attrs.dims,
+        });
+    }
+
+    /// Handles _NET_WM_MOVERESIZE_CANCEL, which clients send when the button was released before
+    /// the move or resize got going. There's no way to end the compositor's grab, but the window
+    /// is put back where it was unless the compositor already settled on a new geometry, which
+    /// ends the move or resize as far as satellite is concerned.
+    pub fn cancel_move_resize(&mut self, window: x::Window) {
+        let Some(active) = self.move_resize.take_if(|active| active.window == window) else {
+            debug!("{window:?} has no move or resize to cancel");
+            return;
+        };
+        debug!("cancelling {:?} of {window:?}", active.op);
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        if win.attrs.dims == active.original {
+            return;
+        }
+        let WindowDims {
+            x,
+            y,
+            width,
+            height,
+        } = active.original;
+        self.connection.as_mut().unwrap().set_window_dims(
+            window,
+            PendingSurfaceState {
+                x: x.into(),
+                y: y.into(),
+                width: width.into(),
+                height: height.into(),
+            },
+        );
+        win.attrs.dims = active.original;
+    }
+
+    pub fn set_transient_for(&mut self, window: x::Window, parent: Option<x::Window>) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        if win.attrs.transient_for == parent {
+            return;
+        }
+        win.attrs.transient_for = parent;
+
+        if let Some(toplevel) = self.window_toplevel(window) {
+            toplevel.set_parent(parent.and_then(|parent| self.window_toplevel(parent)));
+        }
+    }
+
+    pub fn set_modal(&mut self, window: x::Window, modal: bool) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        if win.attrs.modal == modal {
+            return;
+        }
+        win.attrs.modal = modal;
+        let has_parent = win.attrs.transient_for.is_some();
+
+        let Some(surface) = win
+            .surface_key
+            .and_then(|key| self.objects.get_mut(key))
+            .map(AsMut::<SurfaceData>::as_mut)
+        else {
+            return;
+        };
+        let Some(SurfaceRole::Toplevel(Some(toplevel))) = &mut surface.role else {
+            return;
+        };
+        match &toplevel.dialog {
+            Some(dialog) if modal => dialog.set_modal(),
+            Some(dialog) => dialog.unset_modal(),
+            // Toplevels only get a dialog object once they're modal, see create_role_window.
+            None if modal && has_parent => {
+                if let Some(wm_dialog) = &self.xdg_wm_dialog {
+                    let dialog = wm_dialog.get_xdg_dialog(&toplevel.toplevel, &self.qh, ());
+                    dialog.set_modal();
+                    toplevel.dialog = Some(dialog);
+                }
+            }
+            None => {}
+        }
+    }
+
+    pub fn window_modal(&self, window: x::Window) -> bool {
+        self.windows.get(&window).is_some_and(|win| win.attrs.modal)
+    }
+
+    pub fn window_demands_attention(&self, window: x::Window) -> bool {
+        self.windows
+            .get(&window)
+            .is_some_and(|win| win.demands_attention)
+    }
+
+    pub fn window_mapped(&self, window: x::Window) -> bool {
+        self.windows.get(&window).is_some_and(|win| win.mapped)
+    }
+
+    /// The win_gravity of a window's latest WM_NORMAL_HINTS.
+    pub fn window_gravity(&self, window: x::Window) -> Option<WinGravity> {
+        let win = self.windows.get(&window)?;
+        win.pending_attrs
+            .size_hints
+            .as_ref()
+            .or(win.attrs.size_hints.as_ref())?
+            .win_gravity
+    }
+
+    /// The geometry of a window as of its last ConfigureNotify.
+    pub fn window_dims(&self, window: x::Window) -> Option<WindowDims> {
+        self.windows.get(&window).map(|win| win.attrs.dims)
+    }
+
+    /// A report on every mapped window, meant to be logged when debugging.
+    pub fn state_dump(&self) -> String {
+        use std::fmt::Write;
+
+        let mut windows: Vec<_> = self.windows.values().filter(|win| win.mapped).collect();
+        windows.sort_by_key(|win| xcb::Xid::resource_id(&win.window));
+        let mut dump = format!("{} mapped windows", windows.len());
+        for win in windows {
+            let surface: Option<&SurfaceData> = win
+                .surface_key
+                .and_then(|key| self.objects.get(key))
+                .map(|object| object.as_ref());
+            let role = match surface.and_then(|surface| surface.role.as_ref()) {
+                Some(SurfaceRole::Toplevel(_)) => "toplevel",
+                Some(SurfaceRole::Popup(_)) => "popup",
+                None => "no role",
+            };
+            let dims = win.attrs.dims;
+            let _ = write!(
+                dump,
+                "\n{:?} {:?} ({role}, {}x{} at {},{})",
+                win.window,
+                win.attrs.title.as_ref().map_or("", |title| title.name()),
+                dims.width,
+                dims.height,
+                dims.x,
+                dims.y
+            );
+            if let Some(surface) = surface {
+                let _ = write!(dump, "\n    {}", surface.pacing);
+            }
+        }
+        dump
+    }
+
+    pub fn reconfigure_window(&mut self, event: x::ConfigureNotifyEvent) {
+        let (parent_x, parent_y) = self.parent_visible_offset(event.window());
+        let Some(win) = self.windows.get_mut(&event.window()) else {
+            debug!("not reconfiguring unknown window {:?}", event.window());
+            return;
+        };
+        self.stacking.stacked(event.window(), event.above_sibling());
+        let dims = WindowDims {
+            x: event.x(),
+            y: event.y(),
+            width: event.width(),
+            height: event.height(),
+        };
+        if dims == win.attrs.dims {
+            return;
+        }
+        debug!("Reconfiguring {win:?} {:?}", dims);
+        if !win.mapped {
+            win.attrs.dims = dims;
+            return;
+        }
+
+        if self.xdg_wm_base.version() < 3 {
+            return;
+        }
+
+        let Some(key) = win.surface_key else {
+            return;
+        };
+
+        let Some(data): Option<&mut SurfaceData> = self.objects.get_mut(key).map(|o| o.as_mut())
+        else {
+            return;
+        };
+
+        match &data.role {
+            Some(SurfaceRole::Popup(Some(popup))) => {
+                let (visible_x, visible_y, width, height) =
+                    visible_geometry(dims, win.attrs.frame_extents);
+                popup.placement.moved(
+                    &popup.positioner,
+                    event.x() as i32 - win.output_offset.x + visible_x - parent_x,
+                    event.y() as i32 - win.output_offset.y + visible_y - parent_y,
+                );
+                let (width, height) = if win.attrs.frame_extents == FrameExtents::default() {
+                    positioner_size(event.window(), dims)
+                } else {
+                    (width, height)
+                };
+                popup.positioner.set_size(width, height);
+                popup.popup.reposition(&popup.positioner, 0);
+            }
+            other => {
+                let error = format!("non popup ({other:?}) can't be reconfigured once mapped");
+                self.report_error(event.window(), error);
+            }
+        }
+    }
+
+    pub fn map_window(&mut self, window: x::Window) {
+        debug!("mapping {window:?}");
+
+        let Some(win) = self.windows.get_mut(&window) else {
+            debug!("not mapping unknown window {window:?}");
+            return;
+        };
+        win.mapped = true;
+        if !win.attrs.override_redirect {
+            self.stacking.add(window);
+        }
+    }
+
+    pub fn unmap_window(&mut self, window: x::Window) {
+        if !self.windows.get(&window).is_some_and(|win| win.mapped) {
+            return;
+        }
+        debug!("unmapping {window:?}");
+        self.promote_orphans(window);
+        let win = self.windows.get_mut(&window).unwrap();
+
+        if matches!(self.last_focused_toplevel, Some(x) if x == window) {
+            self.last_focused_toplevel.take();
+        }
+        if self.last_hovered == Some(window) {
+            self.last_hovered.take();
+        }
+        win.mapped = false;
+        win.orphaned = false;
+        self.stacking.unmapped(window);
+        if self
+            .move_resize
+            .as_ref()
+            .is_some_and(|active| active.window == window)
+        {
+            self.move_resize = None;
+        }
+
+        if let Some(key) = win.surface_key.take() {
+            let Some(object) = self.objects.get_mut(key) else {
+                warn!("could not unmap {window:?}: stale surface");
+                return;
+            };
+            let surface: &mut SurfaceData = object.as_mut();
+            surface.content_deadline = None;
+            // Toplevels are kept until Xwayland destroys the surface, at which point they linger.
+            if matches!(surface.role, Some(SurfaceRole::Popup(Some(_)))) {
+                self.destroy_submenu_popups(window);
+            }
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            if self.config.unmap_grace.is_none()
+                || !matches!(surface.role, Some(SurfaceRole::Toplevel(Some(_))))
+            {
+                surface.destroy_role();
+            }
+        }
+    }
+
+    pub fn set_fullscreen(&mut self, window: x::Window, state: super::xstate::SetState) {
+        let Some(win) = self.windows.get(&window) else {
+            warn!("Tried to set unknown window {window:?} fullscreen");
+            return;
+        };
+        let output = self.fullscreen_output(&win.attrs);
+        let Some(key) = win.surface_key else {
+            self.report_error(window, "can't set fullscreen before being mapped".into());
+            return;
+        };
+        let Some(object) = self.objects.get_mut(key) else {
+            warn!("Could not set fullscreen on {window:?}: stale surface");
+            return;
+        };
+        let surface: &mut SurfaceData = object.as_mut();
+        let Some(SurfaceRole::Toplevel(Some(ref toplevel))) = surface.role else {
+            self.report_error(window, "only toplevels can be set fullscreen".into());
+            return;
+        };
+
+        use crate::xstate::SetState;
+        match state {
+            SetState::Add => toplevel.toplevel.set_fullscreen(output.as_ref()),
+            SetState::Remove => toplevel.toplevel.unset_fullscreen(),
+            SetState::Toggle => {
+                if toplevel.fullscreen {
+                    toplevel.toplevel.unset_fullscreen()
+                } else {
+                    toplevel.toplevel.set_fullscreen(output.as_ref())
+                }
+            }
+        }
+    }
+
+    pub fn set_maximized(&mut self, window: x::Window, state: super::xstate::SetState) {
+        let Some(win) = self.windows.get(&window) else {
+            warn!("Tried to set unknown window {window:?} maximized");
+            return;
+        };
+        let Some(key) = win.surface_key else {
+            self.report_error(window, "can't set maximized before being mapped".into());
+            return;
+        };
+        let Some(object) = self.objects.get_mut(key) else {
+            warn!("Could not set maximized on {window:?}: stale surface");
+            return;
+        };
         let surface: &mut SurfaceData = object.as_mut();
         let Some(SurfaceRole::Toplevel(Some(ref toplevel))) = surface.role else {
-            warn!("Tried to set an unmapped toplevel or non toplevel fullscreen: {window:?}");
+            self.report_error(window, "only toplevels can be set maximized".into());
+            return;
+        };
+
+        use crate::xstate::SetState;
+        let maximize = match state {
+            SetState::Add => true,
+            SetState::Remove => false,
+            SetState::Toggle => !toplevel.maximized,
+        };
+        if maximize {
+            toplevel.toplevel.set_maximized();
+        } else {
+            toplevel.toplevel.unset_maximized();
+        }
+    }
+
+    /// What the compositor told us about each of its outputs.
+    pub fn output_identities(&self) -> Vec<OutputIdentity> {
+        self.objects
+            .values()
+            .filter_map(|object| match &object.0 {
+                Some(Object::Output(output)) => Some(output.identity()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Handles _NET_WM_FULLSCREEN_MONITORS, with the area the picked monitors span. A window
+    /// that is already fullscreen moves over right away.
+    pub fn set_fullscreen_monitors(&mut self, window: x::Window, area: (i32, i32, i32, i32)) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        debug!("{window:?} wants to be fullscreen on {area:?}");
+        win.attrs.fullscreen_monitors = Some(area);
+        let win = &self.windows[&window];
+        let output = self.fullscreen_output(&win.attrs);
+        let Some(surface) = win
+            .surface_key
+            .and_then(|key| self.objects.get(key))
+            .map(AsRef::<SurfaceData>::as_ref)
+        else {
+            return;
+        };
+        if let Some(SurfaceRole::Toplevel(Some(toplevel))) = &surface.role {
+            if toplevel.fullscreen {
+                toplevel.toplevel.set_fullscreen(output.as_ref());
+            }
+        }
+    }
+
+    pub fn set_bypass_compositor(&mut self, window: x::Window, bypass: bool) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        win.attrs.bypass_compositor = bypass;
+        let Some(surface) = win
+            .surface_key
+            .and_then(|key| self.objects.get_mut(key))
+            .map(AsMut::<SurfaceData>::as_mut)
+        else {
+            return;
+        };
+        surface.update_game_hints(&self.game_globals, &self.qh, bypass);
+    }
+
+    pub fn set_opaque(&mut self, window: x::Window, opaque: bool) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        win.attrs.opaque = opaque;
+        if let Some(key) = win.surface_key {
+            self.update_opaque_region(key);
+        }
+    }
+
+    pub fn set_role_override(&mut self, window: x::Window, role: Option<RoleOverride>) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            debug!("{window:?} role override: {role:?}");
+            win.attrs.role_override = role;
+        }
+    }
+
+    pub fn set_wm_role(&mut self, window: x::Window, role: Option<String>) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            debug!("{window:?} WM_WINDOW_ROLE: {role:?}");
+            win.attrs.wm_role = role;
+        }
+    }
+
+    pub fn set_motif_no_resize(&mut self, window: x::Window, no_resize: bool) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        if win.attrs.motif_no_resize == no_resize {
+            return;
+        }
+        let was_pinned = win.attrs.pinned_size();
+        win.attrs.motif_no_resize = no_resize;
+        self.update_size_limits(window, was_pinned);
+    }
+
+    /// Sends a mapped toplevel its size limits again, see [`WindowAttributes::apply_size_limits`].
+    /// A size that is no longer pinned is cleared first, in case size hints don't replace it.
+    fn update_size_limits(&self, window: x::Window, was_pinned: bool) {
+        let Some((win, toplevel)) = self.windows.get(&window).zip(self.window_toplevel(window))
+        else {
+            return;
+        };
+        if was_pinned && !win.attrs.pinned_size() {
+            toplevel.set_min_size(0, 0);
+            toplevel.set_max_size(0, 0);
+        }
+        win.attrs.apply_size_limits(toplevel);
+    }
+
+    pub fn set_no_input(&mut self, window: x::Window, no_input: bool) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.attrs.no_input = no_input;
+        }
+    }
+
+    pub fn set_take_focus(&mut self, window: x::Window, take_focus: bool) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.attrs.take_focus = take_focus;
+        }
+    }
+
+    pub fn set_window_type(&mut self, window: x::Window, window_type: WindowType) {
+        if let Some(win) = self.windows.get_mut(&window) {
+            win.attrs.window_type = window_type;
+        }
+    }
+
+    pub fn set_frame_extents(&mut self, window: x::Window, extents: FrameExtents) {
+        let Some(win) = self.windows.get_mut(&window) else {
+            return;
+        };
+        if win.attrs.frame_extents == extents {
+            return;
+        }
+        debug!("{window:?} frame extents: {extents:?}");
+        win.attrs.frame_extents = extents;
+
+        let Some(surface) = win
+            .surface_key
+            .and_then(|key| self.objects.get(key))
+            .map(AsRef::<SurfaceData>::as_ref)
+        else {
+            return;
+        };
+        if let Some(xdg) = surface.role.as_ref().and_then(|_| surface.xdg()) {
+            // Without extents this resets the geometry to the whole window.
+            let (x, y, width, height) = win.attrs.visible_geometry();
+            xdg.surface.set_window_geometry(x, y, width, height);
+        }
+        // Pinned sizes and size hints are both sent without the extents.
+        let pinned = win.attrs.pinned_size();
+        self.update_size_limits(window, pinned);
+    }
+
+    pub fn set_minimized(&mut self, window: x::Window) {
+        let Some(win) = self.windows.get(&window) else {
+            warn!("Tried to minimize unknown window {window:?}");
+            return;
+        };
+        let Some(key) = win.surface_key else {
+            self.report_error(window, "can't minimize before being mapped".into());
+            return;
+        };
+        let Some(object) = self.objects.get_mut(key) else {
+            warn!("Could not minimize {window:?}: stale surface");
+            return;
+        };
+        let surface: &mut SurfaceData = object.as_mut();
+        let Some(SurfaceRole::Toplevel(Some(ref mut toplevel))) = surface.role else {
+            self.report_error(window, "only toplevels can be minimized".into());
+            return;
+        };
+
+        toplevel.toplevel.set_minimized();
+        if !toplevel.minimized {
+            toplevel.minimized = true;
+            let data = C::ExtraData::create(self);
+            self.connection
+                .as_mut()
+                .unwrap()
+                .set_minimized(window, true, data);
+        }
+    }
+
+    /// Popups can't outlive their parent on Wayland, so the windows shown as popups of a window
+    /// that goes away become toplevels, keeping their contents.
+    fn promote_orphans(&mut self, parent: x::Window) {
+        // Override-redirect menus and tooltips go away along with their parent, so they'd only
+        // flash up as toplevels.
+        let orphans: Vec<_> = self
+            .windows
+            .values()
+            .filter(|win| win.mapped && win.forced_popup && win.attrs.popup_for == Some(parent))
+            .filter_map(|win| win.surface_key.map(|key| (win.window, key)))
+            .collect();
+        for (window, key) in orphans {
+            let surface: &SurfaceData = self.objects[key].as_ref();
+            if !matches!(surface.role, Some(SurfaceRole::Popup(_))) {
+                continue;
+            }
+            // Popups have to be destroyed from the top down.
+            self.promote_orphans(window);
+
+            debug!("{parent:?} went away, making its popup {window:?} a toplevel");
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            surface.destroy_role();
+            // A wl_surface can't go from being a popup to a toplevel.
+            let client = self.compositor.create_surface(&self.qh, key);
+            self.replace_client_surface(key, client);
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            if surface.attach.is_none() {
+                surface.attach = surface.buffer.clone().map(|buffer| SurfaceAttach {
+                    buffer: Some(buffer),
+                    x: 0,
+                    y: 0,
+                });
+            }
+            self.windows.get_mut(&window).unwrap().orphaned = true;
+            self.create_role_window(window, key);
+        }
+    }
+
+    /// Moves a surface over to another wl_surface on the compositor side. Everything attached to
+    /// the old wl_surface is destroyed along with it, so it's recreated on the new one, including
+    /// the frame callbacks the compositor hasn't answered yet.
+    fn replace_client_surface(&mut self, key: ObjectKey, client: client::wl_surface::WlSurface) {
+        let surface: &mut SurfaceData = self.objects[key].as_mut();
+        if let Some(viewport) = &surface.viewport {
+            viewport.viewport.destroy();
+        }
+        let had_hints = surface
+            .game_hints
+            .take()
+            .map(game::GameHints::destroy)
+            .is_some();
+        surface.client.destroy();
+        surface.client = client;
+
+        // The viewporter may have been destroyed since, so there's nothing to recreate it from.
+        surface.viewport = surface
+            .viewport
+            .take()
+            .filter(|viewport| viewport.viewporter.is_alive());
+        if let Some(viewport) = &mut surface.viewport {
+            viewport.viewport = viewport
+                .viewporter
+                .get_viewport(&surface.client, &self.qh, ());
+            if let Some((x, y, width, height)) = viewport.source {
+                viewport.viewport.set_source(x, y, width, height);
+            }
+            if let Some((width, height)) = surface.geometry.viewport_destination {
+                viewport.viewport.set_destination(width, height);
+            }
+        }
+        if had_hints {
+            surface.game_hints = Some(game::GameHints::new(
+                &self.game_globals,
+                &surface.client,
+                &self.qh,
+            ));
+        }
+        if surface.geometry.scale != 1 {
+            surface.client.set_buffer_scale(surface.geometry.scale);
+        }
+        // Set again with the next commit.
+        surface.opaque_region = None;
+        for callback in &surface.pending_frames {
+            surface.client.frame(&self.qh, (callback.clone(), key));
+        }
+    }
+
+    pub fn destroy_window(&mut self, window: x::Window) {
+        self.promote_orphans(window);
+        if let Some(surface_key) = self.windows.remove(&window).and_then(|win| win.surface_key) {
+            self.pointer_constraints
+                .retain(|_, constrained| constrained.surface_key != surface_key);
+        }
+        self.stacking.remove(window);
+        if let Some(lingering) = self.lingering.remove(&window) {
+            self.destroy_lingering(lingering);
+        }
+    }
+
+    /// Keeps the toplevel of a destroyed surface alive if its window is configured to linger.
+    /// Returns false if the surface's role and clientside surface should be destroyed instead.
+    fn linger(&mut self, surface: &mut SurfaceData) -> bool {
+        let Some(grace) = self.config.unmap_grace else {
+            return false;
+        };
+        let Some(window) = surface.window.filter(|w| self.windows.contains_key(w)) else {
+            return false;
+        };
+        let Some(SurfaceRole::Toplevel(Some(_))) = &surface.role else {
+            return false;
+        };
+        let Some(SurfaceRole::Toplevel(Some(toplevel))) = surface.role.take() else {
+            unreachable!();
+        };
+
+        let key = self
+            .surface_aliases
+            .iter()
+            .find_map(|(from, to)| (*to == surface.key).then_some(*from))
+            .unwrap_or(surface.key);
+        self.surface_aliases.remove(&key);
+
+        debug!("keeping toplevel of {window:?} around for {grace:?}");
+        let prev = self.lingering.insert(
+            window,
+            LingeringToplevel {
+                key,
+                client: surface.client.clone(),
+                toplevel,
+                output_key: surface.output_key,
+                expires: Instant::now() + grace,
+            },
+        );
+        if let Some(prev) = prev {
+            self.destroy_lingering(prev);
+        }
+        true
+    }
+
+    /// Gives a lingering toplevel to the new surface of its window.
+    fn adopt_lingering(
+        &mut self,
+        window: x::Window,
+        surface_key: ObjectKey,
+        lingering: LingeringToplevel,
+    ) {
+        debug!("reusing toplevel for {window:?}");
+        self.replace_client_surface(surface_key, lingering.client);
+        let surface: &mut SurfaceData = self.objects[surface_key].as_mut();
+        surface.window = Some(window);
+        surface.output_key = lingering.output_key;
+        surface.role = Some(SurfaceRole::Toplevel(Some(lingering.toplevel)));
+        if lingering.key != surface_key {
+            self.surface_aliases.insert(lingering.key, surface_key);
+        }
+
+        let surface: &SurfaceData = self.objects[surface_key].as_ref();
+        let Some(SurfaceRole::Toplevel(Some(toplevel))) = &surface.role else {
+            unreachable!();
+        };
+        self.windows
+            .get_mut(&window)
+            .unwrap()
+            .apply_pending_attrs(Some(&toplevel.toplevel), self.config.app_id_source);
+    }
+
+    fn destroy_lingering(&mut self, lingering: LingeringToplevel) {
+        if let Some(dialog) = lingering.toplevel.dialog {
+            dialog.destroy();
+        }
+        if let Some(decoration) = lingering.toplevel.decoration {
+            decoration.destroy();
+        }
+        lingering.toplevel.toplevel.destroy();
+        lingering.toplevel.xdg.surface.destroy();
+        lingering.client.destroy();
+        self.surface_aliases.remove(&lingering.key);
+    }
+
+    /// Gives a window its role once its surface has something to show, or once it waited long
+    /// enough. Compositors animate new toplevels as soon as they have a buffer, which is then
+    /// often still blank.
+    fn map_when_drawn(&mut self, window: x::Window, surface_key: ObjectKey) {
+        let Some(timeout) = self.config.content_timeout else {
+            self.create_role_window(window, surface_key);
             return;
         };
+        if self.lingering.contains_key(&window) {
+            // It was drawn before it was unmapped.
+            self.create_role_window(window, surface_key);
+            return;
+        }
+        debug!("waiting for {window:?} to draw before mapping it");
+        let surface: &mut SurfaceData = self.objects[surface_key].as_mut();
+        surface.content_deadline = Some(Instant::now() + timeout);
+    }
 
-        use crate::xstate::SetState;
-        match state {
-            SetState::Add => toplevel.toplevel.set_fullscreen(None),
-            SetState::Remove => toplevel.toplevel.unset_fullscreen(),
-            SetState::Toggle => {
-                if toplevel.fullscreen {
-                    toplevel.toplevel.unset_fullscreen()
-                } else {
-                    toplevel.toplevel.set_fullscreen(None)
-                }
-            }
+    /// Called for commits of surfaces waiting for content.
+    fn content_committed(&mut self, surface_key: ObjectKey) {
+        let surface: &mut SurfaceData = self.objects[surface_key].as_mut();
+        if surface
+            .attach
+            .as_ref()
+            .is_none_or(|attach| attach.buffer.is_none())
+        {
+            return;
+        }
+        surface.content_deadline = None;
+        if let Some(window) = surface
+            .window
+            .or(self.associated_windows.get(surface_key).copied())
+        {
+            debug!("{window:?} has drawn, mapping it");
+            self.create_role_window(window, surface_key);
         }
     }
 
-    pub fn destroy_window(&mut self, window: x::Window) {
-        let _ = self.windows.remove(&window);
+    fn expire_content_waits(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .windows
+            .values()
+            .filter_map(|win| win.surface_key.map(|key| (win.window, key)))
+            .filter(|(_, key)| {
+                self.objects.get(*key).is_some_and(|object| {
+                    let surface: &SurfaceData = object.as_ref();
+                    surface
+                        .content_deadline
+                        .is_some_and(|deadline| deadline <= now)
+                })
+            })
+            .collect();
+        for (window, key) in expired {
+            debug!("{window:?} didn't draw in time, mapping it anyway");
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            surface.content_deadline = None;
+            self.create_role_window(window, key);
+        }
+    }
+
+    fn expire_lingering(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<_> = self
+            .lingering
+            .iter()
+            .filter_map(|(window, l)| (l.expires <= now).then_some(*window))
+            .collect();
+        for window in expired {
+            debug!("toplevel of {window:?} expired");
+            let lingering = self.lingering.remove(&window).unwrap();
+            self.destroy_lingering(lingering);
+        }
+    }
+
+    /// How long until there is something for us to do, even if no events arrive.
+    pub fn next_timeout(&self) -> Option<Duration> {
+        let content_deadlines = self
+            .windows
+            .values()
+            .filter_map(|win| win.surface_key)
+            .filter_map(|key| self.objects.get(key))
+            .filter_map(|object| AsRef::<SurfaceData>::as_ref(object).content_deadline);
+        self.lingering
+            .values()
+            .map(|l| l.expires)
+            .chain(content_deadlines)
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+            .chain(self.connection.as_ref().and_then(C::next_timeout))
+            .min()
+    }
+
+    fn ping_windows(&mut self) {
+        if self.connection.is_none() {
+            return;
+        }
+        let windows: Vec<x::Window> = self
+            .windows
+            .values()
+            .filter(|win| win.mapped && !win.attrs.override_redirect)
+            .map(|win| win.window)
+            .collect();
+        for window in windows {
+            let data = C::ExtraData::create(self);
+            self.connection.as_mut().unwrap().ping(window, data);
+        }
+    }
+
+    /// Gets the key of the surface a clientside surface currently belongs to.
+    fn client_surface_key(&self, surface: &client::wl_surface::WlSurface) -> ObjectKey {
+        let key: ObjectKey = surface.data().copied().unwrap();
+        self.surface_aliases.get(&key).copied().unwrap_or(key)
     }
 
     pub(crate) fn set_copy_paste_source(&mut self, selection: &Rc<C::X11Selection>) {
         if let Some(d) = &mut self.clipboard_data {
-            let src = d
-                .manager
-                .create_copy_paste_source(&self.qh, selection.mime_types());
+            let src = d.backend.create_source(&self.qh, selection.mime_types());
             let data = CopyPasteData::X11 {
                 inner: src,
                 data: Rc::downgrade(selection),
@@ -819,8 +2279,25 @@ impl<C: XConnection> ServerState<C> {
             let CopyPasteData::X11 { inner, .. } = d.source.insert(data) else {
                 unreachable!();
             };
-            if let Some(serial) = self.last_kb_serial.as_ref().copied() {
-                inner.set_selection(d.device.as_ref().unwrap(), serial);
+            match (inner, &d.backend) {
+                (
+                    SelectionSource::DataDevice(inner),
+                    ClipboardBackend::DataDevice { device, .. },
+                ) => {
+                    if let Some(serial) = self.last_kb_serial.as_ref().copied() {
+                        inner.set_selection(device.as_ref().unwrap(), serial);
+                    }
+                }
+                (
+                    SelectionSource::DataControl(inner),
+                    ClipboardBackend::DataControl { device, .. },
+                ) => {
+                    // Data control doesn't need focus, so the selection can be set right away.
+                    if let Some(device) = device {
+                        device.set_selection(Some(inner));
+                    }
+                }
+                _ => unreachable!(),
             }
         }
     }
@@ -843,7 +2320,10 @@ impl<C: XConnection> ServerState<C> {
     pub fn handle_clientside_events(&mut self) {
         self.handle_new_globals();
 
-        for (key, event) in self.clientside.read_events() {
+        let mut events = std::mem::take(&mut self.event_buffer);
+        self.clientside.read_events(&mut events);
+        for (key, event) in events.drain(..) {
+            let key = self.surface_aliases.get(&key).copied().unwrap_or(key);
             let Some(object) = &mut self.objects.get_mut(key) else {
                 warn!("could not handle clientside event: stale surface");
                 continue;
@@ -853,18 +2333,57 @@ impl<C: XConnection> ServerState<C> {
             let ret = self.objects[key].0.replace(object); // safe indexed access?
             debug_assert!(ret.is_none());
         }
+        self.event_buffer = events;
+
+        let mut frames = std::mem::take(&mut self.frame_buffer);
+        std::mem::swap(&mut frames, &mut self.clientside.globals.frames_done);
+        for frame in frames.drain(..) {
+            let key = self.surface_aliases.get(&frame.surface).copied();
+            let key = key.unwrap_or(frame.surface);
+            let Some(surface) = self.objects.get_mut(key) else {
+                frame.callback.done(frame.callback_data);
+                continue;
+            };
+            let surface: &mut SurfaceData = surface.as_mut();
+            surface.pending_frames.retain(|cb| *cb != frame.callback);
+            // Suspended windows can't be seen, so they don't need to draw.
+            if surface.suspended {
+                surface.held_frames.push(frame);
+                continue;
+            }
+            surface.pacing.frame_done(frame.time);
+            frame.callback.done(frame.callback_data);
+        }
+        self.frame_buffer = frames;
+
+        // The compositor checks whether we're responsive, take the opportunity to check whether
+        // our clients are.
+        if std::mem::take(&mut self.clientside.globals.pinged) {
+            self.ping_windows();
+        }
+
+        for window in std::mem::take(&mut self.dismissed_popups) {
+            self.dismiss_menu(window);
+        }
 
         {
-            if let Some(FocusData {
-                window,
-                output_name,
-            }) = self.to_focus.take()
-            {
+            if let Some(FocusData { window, output }) = self.to_focus.take() {
                 let data = C::ExtraData::create(self);
                 let conn = self.connection.as_mut().unwrap();
                 debug!("focusing window {window:?}");
-                conn.focus_window(window, output_name, data);
-                self.last_focused_toplevel = Some(window);
+                conn.focus_window(window, output, data);
+                if self.config.raise_policy == RaisePolicy::Focus {
+                    self.stacking.raise(window, &self.windows);
+                }
+                // X input stays with the previous window, so it's still the one popups and
+                // selections belong to. Globally Active windows take focus themselves though.
+                if !self
+                    .windows
+                    .get(&window)
+                    .is_some_and(|win| win.attrs.no_input && !win.attrs.take_focus)
+                {
+                    self.last_focused_toplevel = Some(window);
+                }
             } else if self.unfocus {
                 let data = C::ExtraData::create(self);
                 let conn = self.connection.as_mut().unwrap();
@@ -874,6 +2393,12 @@ impl<C: XConnection> ServerState<C> {
         }
 
         self.handle_clipboard_events();
+        self.expire_lingering();
+        self.expire_content_waits();
+        self.startup_tokens.expire();
+        if let Some(connection) = self.connection.as_mut() {
+            self.stacking.reconcile(connection);
+        }
         self.clientside
             .queue
             .flush()
@@ -906,12 +2431,21 @@ impl<C: XConnection> ServerState<C> {
             }
 
             if clipboard.source.is_none() || globals.cancelled {
-                if globals.selection.take().is_some() {
-                    let device = clipboard.device.as_ref().unwrap();
-                    let offer = device.data().selection_offer().unwrap();
-                    let mime_types: Box<[String]> = offer.with_mime_types(|mimes| mimes.into());
+                let offer = match &clipboard.backend {
+                    ClipboardBackend::DataDevice { device, .. } => {
+                        globals.selection.take().map(|_| {
+                            let device = device.as_ref().unwrap();
+                            SelectionOfferKind::DataDevice(device.data().selection_offer().unwrap())
+                        })
+                    }
+                    ClipboardBackend::DataControl { .. } => globals
+                        .control_selection
+                        .take()
+                        .map(SelectionOfferKind::DataControl),
+                };
+                if let Some(offer) = offer {
                     let foreign = ForeignSelection {
-                        mime_types,
+                        mime_types: offer.mime_types(),
                         inner: offer,
                     };
                     clipboard.source = Some(CopyPasteData::Foreign(foreign));
@@ -922,6 +2456,22 @@ impl<C: XConnection> ServerState<C> {
     }
 
     fn create_role_window(&mut self, window: x::Window, surface_key: ObjectKey) {
+        if let Some(lingering) = self.lingering.remove(&window) {
+            self.adopt_lingering(window, surface_key, lingering);
+            return;
+        }
+
+        // Menus that say which window they belong to can be parented to it, even when that
+        // window is in the background.
+        let transient_for = self.windows[&window]
+            .attrs
+            .transient_for
+            .filter(|parent| self.window_toplevel(*parent).is_some());
+        let emulated_size = {
+            let surface: &SurfaceData = self.objects[surface_key].as_ref();
+            surface.geometry.viewport_destination
+        };
+        let covered_output = self.covered_output(&self.windows[&window].attrs, emulated_size);
         let surface: &mut SurfaceData = self.objects[surface_key].as_mut();
         surface.window = Some(window);
         let client = &surface.client;
@@ -933,9 +2483,42 @@ impl<C: XConnection> ServerState<C> {
             .get_xdg_surface(client, &self.qh, surface_key);
 
         let window_data = self.windows.get_mut(&window).unwrap();
-        if window_data.attrs.override_redirect {
+        window_data.apply_pending_attrs(None, self.config.app_id_source);
+        // Torn-off menus and tool palettes keep the type of a menu, but say what they are in
+        // their WM_WINDOW_ROLE.
+        let toplevel_role = window_data
+            .attrs
+            .wm_role
+            .as_deref()
+            .is_some_and(|role| self.config.is_toplevel_role(role));
+        let role_override = window_data
+            .attrs
+            .role_override
+            .or(toplevel_role.then_some(RoleOverride::Toplevel))
+            .or_else(|| {
+                self.config.window_role(
+                    window_data.attrs.window_type,
+                    window_data.attrs.class.as_ref(),
+                )
+            })
+            // Splash screens are often override-redirect, but belong to no other window.
+            .or((window_data.attrs.window_type == WindowType::Splash)
+                .then_some(RoleOverride::Toplevel))
+            .or(covered_output.is_some().then_some(RoleOverride::Fullscreen));
+        window_data.forced_popup = role_override == Some(RoleOverride::Popup);
+        if window_data.orphaned
+            || matches!(
+                role_override,
+                Some(RoleOverride::Toplevel | RoleOverride::Fullscreen)
+            )
+        {
+            window_data.attrs.popup_for = None;
+        } else if window_data.attrs.override_redirect || role_override == Some(RoleOverride::Popup)
+        {
             // Override redirect is hard to convert to Wayland!
-            if let Some(win) = self.last_hovered {
+            if let Some(win) = transient_for {
+                window_data.attrs.popup_for = Some(win);
+            } else if let Some(win) = self.last_hovered {
                 window_data.attrs.popup_for = Some(win);
             } else if let Some(win) = self.last_focused_toplevel {
                 window_data.attrs.popup_for = Some(win);
@@ -957,19 +2540,57 @@ impl<C: XConnection> ServerState<C> {
                 self.objects[parent_window.surface_key.unwrap()].as_ref();
             let parent_dims = parent_window.attrs.dims;
 
-            let x = window.attrs.dims.x - parent_dims.x;
-            let y = window.attrs.dims.y - parent_dims.y;
+            // Positioners work in terms of window geometry, so GTK's shadows on either window
+            // need to be left out.
+            let (visible_x, visible_y, visible_width, visible_height) =
+                window.attrs.visible_geometry();
+            let (parent_x, parent_y, parent_width, parent_height) =
+                parent_window.attrs.visible_geometry();
+            let x =
+                i32::from(window.attrs.dims.x) - i32::from(parent_dims.x) + visible_x - parent_x;
+            let y =
+                i32::from(window.attrs.dims.y) - i32::from(parent_dims.y) + visible_y - parent_y;
 
             let positioner = self.xdg_wm_base.create_positioner(&self.qh, ());
-            positioner.set_size(window.attrs.dims.width as _, window.attrs.dims.height as _);
-            positioner.set_offset(x as i32, y as i32);
-            positioner.set_anchor(Anchor::TopLeft);
-            positioner.set_gravity(Gravity::BottomRight);
-            positioner.set_anchor_rect(
-                0,
-                0,
-                parent_window.attrs.dims.width as _,
-                parent_window.attrs.dims.height as _,
+            let (width, height) = if window.attrs.frame_extents == FrameExtents::default() {
+                positioner_size(window.window, window.attrs.dims)
+            } else {
+                (visible_width, visible_height)
+            };
+            positioner.set_size(width, height);
+            let (anchor_width, anchor_height) =
+                if parent_window.attrs.frame_extents == FrameExtents::default() {
+                    (
+                        parent_window.attrs.dims.width as _,
+                        parent_window.attrs.dims.height as _,
+                    )
+                } else {
+                    (parent_width, parent_height)
+                };
+            let constraints = self.config.popup_constraints(window.attrs.window_type);
+            positioner.set_constraint_adjustment(constraints);
+            let pointer = (window.attrs.override_redirect && self.last_hovered == Some(parent))
+                .then(|| {
+                    let (pointer_x, pointer_y) = self.pointer_position;
+                    (pointer_x as i32 - parent_x, pointer_y as i32 - parent_y)
+                });
+            // Popups often have no WM_CLASS of their own.
+            let class = window
+                .attrs
+                .class
+                .as_ref()
+                .or(parent_window.attrs.class.as_ref());
+            let mut placement = placement::strategy(self.config.popup_anchor(class));
+            placement.place(
+                &positioner,
+                &placement::PopupGeometry {
+                    x,
+                    y,
+                    parent_width: anchor_width,
+                    parent_height: anchor_height,
+                    pointer,
+                    constraints,
+                },
             );
             let popup = xdg_surface.get_popup(
                 Some(&parent_surface.xdg().unwrap().surface),
@@ -977,6 +2598,30 @@ impl<C: XConnection> ServerState<C> {
                 &self.qh,
                 surface_key,
             );
+            window.attrs.apply_window_geometry(&xdg_surface);
+
+            // X menus grab the pointer and keyboard, which on the Wayland side takes a popup
+            // grab, or some compositors won't dismiss the menu on clicks elsewhere. Grabbing
+            // popups can only be stacked on toplevels and other grabbing popups.
+            let parent_grabbable = match &parent_surface.role {
+                Some(SurfaceRole::Toplevel(_)) => true,
+                Some(SurfaceRole::Popup(Some(parent))) => parent.grabbed,
+                _ => false,
+            };
+            let wants_grab = window.attrs.override_redirect
+                && parent_grabbable
+                && (window.attrs.window_type == WindowType::Menu
+                    || self
+                        .connection
+                        .as_ref()
+                        .is_some_and(|c| c.pointer_grabbed()));
+            let grab = wants_grab
+                .then(|| self.seat.as_ref().zip(self.last_input_serial))
+                .flatten();
+            if let Some((seat, serial)) = grab {
+                debug!("grabbing popup for {:?} ({serial})", window.window);
+                popup.grab(seat, serial);
+            }
             let popup = PopupData {
                 popup,
                 positioner,
@@ -985,10 +2630,17 @@ impl<C: XConnection> ServerState<C> {
                     configured: false,
                     pending: None,
                 },
+                placement,
+                grabbed: grab.is_some(),
+                dismissed: false,
             };
             SurfaceRole::Popup(Some(popup))
         } else {
             let data = self.create_toplevel(window, surface_key, xdg_surface);
+            if role_override == Some(RoleOverride::Fullscreen) {
+                data.toplevel.set_fullscreen(covered_output.as_ref());
+            }
+            window.attrs.apply_window_geometry(&data.xdg.surface);
             SurfaceRole::Toplevel(Some(data))
         };
 
@@ -1017,24 +2669,33 @@ impl<C: XConnection> ServerState<C> {
         debug!("creating toplevel for {:?}", window.window);
 
         let toplevel = xdg.get_toplevel(&self.qh, surface_key);
-        if let Some(hints) = &window.attrs.size_hints {
-            if let Some(min) = &hints.min_size {
-                toplevel.set_min_size(min.width, min.height);
-            }
-            if let Some(max) = &hints.max_size {
-                toplevel.set_max_size(max.width, max.height);
-            }
-        }
+        window.attrs.apply_size_limits(&toplevel);
 
         let group = window.attrs.group.and_then(|win| self.windows.get(&win));
-        if let Some(class) = window
+        let class = window
             .attrs
             .class
             .as_ref()
-            .or(group.and_then(|g| g.attrs.class.as_ref()))
-        {
-            toplevel.set_app_id(class.to_string());
+            .or(group.and_then(|g| g.attrs.class.as_ref()));
+        if let Some(class) = class {
+            toplevel.set_app_id(class.app_id(self.config.app_id_source).to_string());
         }
+        let splash = window.attrs.window_type == WindowType::Splash;
+        // Splash screens are drawn as they are, without a title bar or borders around them.
+        let decoration = self
+            .config
+            .decoration_mode(class)
+            .or(splash.then_some(DecorationMode::Client))
+            .zip(self.decoration_manager.as_ref())
+            .map(|(mode, manager)| {
+                debug!("forcing {mode:?} decorations on {:?}", window.window);
+                let decoration = manager.get_toplevel_decoration(&toplevel, &self.qh, ());
+                decoration.set_mode(match mode {
+                    DecorationMode::Client => zxdg_toplevel_decoration_v1::Mode::ClientSide,
+                    DecorationMode::Server => zxdg_toplevel_decoration_v1::Mode::ServerSide,
+                });
+                decoration
+            });
         if let Some(title) = window
             .attrs
             .title
@@ -1044,6 +2705,32 @@ impl<C: XConnection> ServerState<C> {
             toplevel.set_title(title.name().to_string());
         }
 
+        let parent = window
+            .attrs
+            .transient_for
+            .filter(|_| !splash)
+            .and_then(|parent| self.window_toplevel(parent));
+        if let Some(parent) = parent {
+            toplevel.set_parent(Some(parent));
+        }
+        if window.attrs.is_wine_desktop() {
+            debug!("{:?} is a Wine virtual desktop", window.window);
+            match self.config.wine_desktop {
+                WineDesktop::Window => {}
+                WineDesktop::Maximized => toplevel.set_maximized(),
+                WineDesktop::Fullscreen => toplevel.set_fullscreen(None),
+            }
+        }
+        let dialog = self
+            .xdg_wm_dialog
+            .as_ref()
+            .filter(|_| window.attrs.modal && parent.is_some())
+            .map(|wm_dialog| {
+                let dialog = wm_dialog.get_xdg_dialog(&toplevel, &self.qh, ());
+                dialog.set_modal();
+                dialog
+            });
+
         ToplevelData {
             xdg: XdgSurfaceData {
                 surface: xdg,
@@ -1052,6 +2739,103 @@ impl<C: XConnection> ServerState<C> {
             },
             toplevel,
             fullscreen: false,
+            maximized: false,
+            minimized: false,
+            dialog,
+            decoration,
+            bounds: None,
+            tiled: TiledEdges::empty(),
+        }
+    }
+
+    /// Where the window geometry of a popup's parent starts, relative to the parent window.
+    fn parent_visible_offset(&self, window: x::Window) -> (i32, i32) {
+        self.windows
+            .get(&window)
+            .and_then(|win| win.attrs.popup_for)
+            .and_then(|parent| self.windows.get(&parent))
+            .map_or((0, 0), |parent| {
+                let (x, y, _, _) = parent.attrs.visible_geometry();
+                (x, y)
+            })
+    }
+
+    /// The output to fullscreen a window on because it covers it exactly. Games and video
+    /// players often do that with an override-redirect window instead of asking to be fullscreen,
+    /// and some games size their window to span all outputs.
+    ///
+    /// While a game has switched to one of the lower resolutions Xwayland emulates through RandR,
+    /// Xwayland scales its window up with a viewport, so `emulated_size` is the size it really
+    /// covers.
+    fn covered_output(
+        &self,
+        attrs: &WindowAttributes,
+        emulated_size: Option<(i32, i32)>,
+    ) -> Option<client::wl_output::WlOutput> {
+        let (x, y) = (i32::from(attrs.dims.x), i32::from(attrs.dims.y));
+        let (width, height) =
+            emulated_size.unwrap_or((i32::from(attrs.dims.width), i32::from(attrs.dims.height)));
+        let mut outputs: Vec<_> = self
+            .objects
+            .values()
+            .filter_map(|object| match &object.0 {
+                Some(Object::Output(output)) => output.rect().map(|rect| (rect, &output.client)),
+                _ => None,
+            })
+            .filter(|((output_x, output_y, output_width, output_height), _)| {
+                *output_x < x + width
+                    && output_x + output_width > x
+                    && *output_y < y + height
+                    && output_y + output_height > y
+            })
+            .collect();
+
+        match outputs.as_slice() {
+            [] => None,
+            [(rect, output)] => (attrs.override_redirect && *rect == (x, y, width, height))
+                .then(|| (*output).clone()),
+            spanned => {
+                if self.config.spanning_windows == SpanningWindows::Keep {
+                    return None;
+                }
+                let left = spanned.iter().map(|((x, ..), _)| *x).min().unwrap();
+                let top = spanned.iter().map(|((_, y, ..), _)| *y).min().unwrap();
+                let right = spanned.iter().map(|((x, _, w, _), _)| x + w).max().unwrap();
+                let bottom = spanned.iter().map(|((_, y, _, h), _)| y + h).max().unwrap();
+                if (left, top, right - left, bottom - top) != (x, y, width, height) {
+                    return None;
+                }
+                // There's no fullscreening on several outputs, so the window goes to the one at
+                // its top left.
+                debug!("window spans {} outputs", spanned.len());
+                outputs.sort_by_key(|((x, y, ..), _)| (*y, *x));
+                Some(outputs[0].1.clone())
+            }
+        }
+    }
+
+    /// The output picked with _NET_WM_FULLSCREEN_MONITORS. There's no fullscreening on several
+    /// outputs, so it's the one at the top left of the monitors.
+    fn fullscreen_output(&self, attrs: &WindowAttributes) -> Option<client::wl_output::WlOutput> {
+        let (x, y, ..) = attrs.fullscreen_monitors?;
+        self.objects.values().find_map(|object| match &object.0 {
+            Some(Object::Output(output)) => output
+                .rect()
+                .filter(|(output_x, output_y, width, height)| {
+                    (*output_x..output_x + width).contains(&x)
+                        && (*output_y..output_y + height).contains(&y)
+                })
+                .map(|_| output.client.clone()),
+            _ => None,
+        })
+    }
+
+    fn window_toplevel(&self, window: x::Window) -> Option<&XdgToplevel> {
+        let key = self.windows.get(&window)?.surface_key?;
+        let surface: &SurfaceData = self.objects.get(key)?.as_ref();
+        match &surface.role {
+            Some(SurfaceRole::Toplevel(Some(data))) => Some(&data.toplevel),
+            _ => None,
         }
     }
 
@@ -1059,8 +2843,8 @@ impl<C: XConnection> ServerState<C> {
         &self,
         surface: client::wl_surface::WlSurface,
     ) -> Option<&WlSurface> {
-        let key: &ObjectKey = surface.data().unwrap();
-        let surface: &SurfaceData = self.objects.get(*key)?.as_ref();
+        let key = self.client_surface_key(&surface);
+        let surface: &SurfaceData = self.objects.get(key)?.as_ref();
         Some(&surface.server)
     }
 
@@ -1073,7 +2857,224 @@ impl<C: XConnection> ServerState<C> {
         Some(&surface.client)
     }
 
-    fn close_x_window(&mut self, window: x::Window) {
+    /// Marks the whole surface as opaque if its window has no alpha channel, so the compositor
+    /// can skip blending it. Takes effect with the next commit.
+    fn update_opaque_region(&mut self, key: ObjectKey) {
+        let surface: &mut SurfaceData = self.objects[key].as_mut();
+        let opaque = surface
+            .window
+            .and_then(|window| self.windows.get(&window))
+            .is_some_and(|win| win.attrs.opaque);
+        let size = opaque.then(|| surface.geometry.size()).flatten();
+        if size == surface.opaque_region {
+            return;
+        }
+
+        surface.opaque_region = size;
+        match size {
+            Some((width, height)) => {
+                let region = self.compositor.create_region(&self.qh, ());
+                region.add(0, 0, width, height);
+                surface.client.set_opaque_region(Some(&region));
+                region.destroy();
+            }
+            None => surface.client.set_opaque_region(None),
+        }
+    }
+
+    /// Logs whatever doesn't add up about the state of a surface that was just committed.
+    fn check_surface(&self, key: ObjectKey) {
+        // The checks format their findings, which isn't worth doing on every commit if they won't
+        // be shown.
+        if !cfg!(debug_assertions) || !log::log_enabled!(log::Level::Warn) {
+            return;
+        }
+        let surface: &SurfaceData = self.objects[key].as_ref();
+        let dims = surface
+            .window
+            .and_then(|window| self.windows.get(&window))
+            .map(|win| &win.attrs.dims);
+        for violation in invariants::check_surface(&surface.geometry, dims) {
+            warn!(
+                "surface {} of {:?}: {violation}",
+                surface.server.id().protocol_id(),
+                surface.window
+            );
+        }
+    }
+
+    /// Follows the popup_for chain of a window up to the window holding the grab of the menu it
+    /// belongs to. Returns that window and how many popups deep `window` is.
+    fn menu_owner(&self, window: x::Window) -> (x::Window, usize) {
+        let mut owner = window;
+        let mut depth = 0;
+        // A client could get its popups to form a loop, so don't follow the chain forever.
+        while depth < self.windows.len() {
+            match self.windows.get(&owner).and_then(|win| win.attrs.popup_for) {
+                Some(parent) => owner = parent,
+                None => break,
+            }
+            depth += 1;
+        }
+        (owner, depth)
+    }
+
+    /// The popups of the menu `window` belongs to, along with how deep into the menu they are.
+    fn menu_popups(&self, window: x::Window) -> Vec<(usize, x::Window, ObjectKey)> {
+        let (owner, _) = self.menu_owner(window);
+        self.windows
+            .values()
+            .filter_map(|win| {
+                let key = win.surface_key?;
+                let surface: &SurfaceData = self.objects.get(key)?.as_ref();
+                let Some(SurfaceRole::Popup(Some(_))) = surface.role else {
+                    return None;
+                };
+                let (popup_owner, depth) = self.menu_owner(win.window);
+                (popup_owner == owner).then_some((depth, win.window, key))
+            })
+            .collect()
+    }
+
+    /// Destroys the popups of the submenus opened from `window`, which xdg_popup requires to go
+    /// before the popup they were opened from.
+    fn destroy_submenu_popups(&mut self, window: x::Window) {
+        let mut submenus: Vec<_> = self
+            .menu_popups(window)
+            .into_iter()
+            .filter(|&(_, popup, _)| self.popup_ancestors(popup).contains(&window))
+            .collect();
+        submenus.sort_by_key(|&(depth, ..)| std::cmp::Reverse(depth));
+        for (_, _, key) in submenus {
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            surface.destroy_role();
+        }
+    }
+
+    fn popup_ancestors(&self, window: x::Window) -> Vec<x::Window> {
+        let mut ancestors = Vec::new();
+        let mut current = window;
+        while let Some(parent) = self
+            .windows
+            .get(&current)
+            .and_then(|win| win.attrs.popup_for)
+        {
+            // A client could get its popups to form a loop.
+            if ancestors.contains(&parent) {
+                break;
+            }
+            ancestors.push(parent);
+            current = parent;
+        }
+        ancestors
+    }
+
+    /// Wine and GTK menus are often several override redirect windows sharing one grab, so
+    /// when the compositor dismisses one of them, the whole menu goes. The X client still has
+    /// its menu open and the pointer grabbed, so the popups are kept until it closes the menu
+    /// itself, which it does on the next click into its windows.
+    fn dismiss_menu(&mut self, window: x::Window) {
+        let (owner, _) = self.menu_owner(window);
+        let mut dismissed = false;
+        for (_, _, key) in self.menu_popups(window) {
+            let surface: &mut SurfaceData = self.objects[key].as_mut();
+            if let Some(SurfaceRole::Popup(Some(popup))) = &mut surface.role {
+                dismissed |= !std::mem::replace(&mut popup.dismissed, true);
+            }
+        }
+        if !dismissed {
+            return;
+        }
+        debug!("menu of {owner:?} was dismissed");
+
+        // Hand focus back to the window that owned the menu, unless the compositor moved it
+        // somewhere else in the meantime.
+        if self.to_focus.is_none() && !self.unfocus && self.last_focused_toplevel == Some(owner) {
+            let output = self
+                .windows
+                .get(&owner)
+                .and_then(|win| win.surface_key)
+                .and_then(|key| self.objects.get(key))
+                .and_then(|object| <_ as AsRef<SurfaceData>>::as_ref(object).get_output(self));
+            self.to_focus = Some(FocusData {
+                window: owner,
+                output,
+            });
+        }
+    }
+
+    /// Lets X clients know whether the compositor granted a pointer constraint, since a game's
+    /// pointer escaping its window looks the same whether the constraint was refused or never
+    /// asked for.
+    fn pointer_constraint_changed(&mut self, key: ObjectKey, active: bool) {
+        let Some(constrained) = self.pointer_constraints.get_mut(key) else {
+            return;
+        };
+        if constrained.active == active {
+            return;
+        }
+        constrained.active = active;
+        let constrained = *constrained;
+        let Some(window) = self
+            .objects
+            .get(constrained.surface_key)
+            .and_then(|object| <_ as AsRef<SurfaceData>>::as_ref(object).window)
+        else {
+            return;
+        };
+
+        debug!(
+            "{:?} pointer of {window:?} {}",
+            constrained.constraint,
+            if active { "granted" } else { "released" }
+        );
+        let data = C::ExtraData::create(self);
+        self.connection.as_mut().unwrap().set_pointer_constraint(
+            window,
+            active.then_some(constrained.constraint),
+            data,
+        );
+    }
+
+    /// Updates _NET_WORKAREA from the configure bounds of a toplevel, which are the usable area
+    /// of the output it's on. X only has one work area, so it spans the areas of all outputs.
+    fn update_work_area(&mut self, window: x::Window, (width, height): (i32, i32)) {
+        let Some(win) = self.windows.get(&window) else {
+            return;
+        };
+        let Some(output_key) = win.output_key else {
+            return;
+        };
+        let output_area = (win.output_offset.x, win.output_offset.y, width, height);
+        self.output_work_areas.insert(output_key, output_area);
+        let objects = &self.objects;
+        self.output_work_areas.retain(|key, _| {
+            objects
+                .get(*key)
+                .is_some_and(|object| matches!(object.0, Some(Object::Output(_))))
+        });
+
+        let Some(area) = self
+            .output_work_areas
+            .values()
+            .map(|&(x, y, width, height)| (x, y, x + width, y + height))
+            .reduce(|(x1, y1, x2, y2), (x3, y3, x4, y4)| {
+                (x1.min(x3), y1.min(y3), x2.max(x4), y2.max(y4))
+            })
+            .map(|(x1, y1, x2, y2)| (x1, y1, x2 - x1, y2 - y1))
+        else {
+            return;
+        };
+        if self.work_area == Some(area) {
+            return;
+        }
+        debug!("work area is now {area:?}");
+        self.work_area = Some(area);
+        let data = C::ExtraData::create(self);
+        self.connection.as_mut().unwrap().set_work_area(area, data);
+    }
+
+    pub fn close_x_window(&mut self, window: x::Window) {
         debug!("sending close request to {window:?}");
         let data = C::ExtraData::create(self);
         self.connection.as_mut().unwrap().close_window(window, data);
@@ -1094,40 +3095,228 @@ pub struct PendingSurfaceState {
     pub height: i32,
 }
 
+/// Converts an X button number to the evdev code compositors use for it.
+fn evdev_button(button: u32) -> Option<u32> {
+    // From linux/input-event-codes.h. X buttons 4 to 7 are scrolling.
+    match button {
+        1 => Some(0x110), // BTN_LEFT
+        2 => Some(0x112), // BTN_MIDDLE
+        3 => Some(0x111), // BTN_RIGHT
+        8 => Some(0x113), // BTN_SIDE
+        9 => Some(0x114), // BTN_EXTRA
+        _ => None,
+    }
+}
+
+/// An interactive move or resize started on behalf of a window.
+#[derive(Debug)]
+struct ActiveMoveResize {
+    window: x::Window,
+    op: MoveResize,
+    /// The geometry of the window when the move or resize started.
+    original: WindowDims,
+}
+
+bitflags! {
+    /// The edges of a toplevel that the compositor tiled against something.
+    #[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+    pub struct TiledEdges: u8 {
+        const TOP = 1;
+        const RIGHT = 2;
+        const BOTTOM = 4;
+        const LEFT = 8;
+    }
+}
+
+/// A pointer constraint X clients can ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerConstraint {
+    Locked,
+    Confined,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ConstrainedSurface {
+    surface_key: ObjectKey,
+    constraint: PointerConstraint,
+    /// Whether the compositor currently has the constraint in effect.
+    active: bool,
+}
+
+/// The compositor output a focused window is on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FocusedOutput {
+    /// The name the compositor gave the output.
+    pub name: String,
+    /// The logical position of the output, which matches its CRTC position in Xwayland.
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A compositor output, as described by wl_output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutputIdentity {
+    pub output: FocusedOutput,
+    pub make: String,
+    pub model: String,
+    /// In millimeters, or 0x0 if unknown.
+    pub physical_size: (i32, i32),
+}
+
 struct ClipboardData<X: X11Selection> {
-    manager: DataDeviceManagerState,
-    device: Option<DataDevice>,
+    backend: ClipboardBackend,
     source: Option<CopyPasteData<X>>,
 }
 
+/// The protocol used to exchange the clipboard with the compositor.
+/// ext-data-control is preferred when available, since unlike wl_data_device it works without
+/// keyboard focus, so selection changes are picked up no matter which client is focused.
+enum ClipboardBackend {
+    DataDevice {
+        manager: DataDeviceManagerState,
+        device: Option<DataDevice>,
+    },
+    DataControl {
+        manager: ExtDataControlManagerV1,
+        device: Option<ExtDataControlDeviceV1>,
+    },
+}
+
+impl ClipboardBackend {
+    fn bind(global_list: &GlobalList, qh: &ClientQueueHandle) -> Option<Self> {
+        if let Ok(manager) = global_list.bind::<ExtDataControlManagerV1, _, _>(qh, 1..=1, ()) {
+            debug!("Using ext-data-control for the clipboard");
+            return Some(Self::DataControl {
+                manager,
+                device: None,
+            });
+        }
+
+        DataDeviceManagerState::bind(global_list, qh)
+            .inspect_err(|e| {
+                warn!("Could not bind data device manager ({e:?}). Clipboard will not work.")
+            })
+            .ok()
+            .map(|manager| Self::DataDevice {
+                manager,
+                device: None,
+            })
+    }
+
+    fn create_device(&mut self, qh: &ClientQueueHandle, seat: &client::wl_seat::WlSeat) {
+        match self {
+            Self::DataDevice { manager, device } => {
+                *device = Some(manager.get_data_device(qh, seat));
+            }
+            Self::DataControl { manager, device } => {
+                *device = Some(manager.get_data_device(seat, qh, ()));
+            }
+        }
+    }
+
+    fn create_source(&self, qh: &ClientQueueHandle, mime_types: Vec<&str>) -> SelectionSource {
+        match self {
+            Self::DataDevice { manager, .. } => {
+                SelectionSource::DataDevice(manager.create_copy_paste_source(qh, mime_types))
+            }
+            Self::DataControl { manager, .. } => {
+                let source = manager.create_data_source(qh, ());
+                for mime in mime_types {
+                    source.offer(mime.to_string());
+                }
+                SelectionSource::DataControl(source)
+            }
+        }
+    }
+}
+
+enum SelectionSource {
+    DataDevice(CopyPasteSource),
+    DataControl(ExtDataControlSourceV1),
+}
+
+impl Drop for SelectionSource {
+    fn drop(&mut self) {
+        // CopyPasteSource destroys itself
+        if let Self::DataControl(source) = self {
+            source.destroy();
+        }
+    }
+}
+
+enum SelectionOfferKind {
+    DataDevice(SelectionOffer),
+    DataControl(ExtDataControlOfferV1),
+}
+
+impl SelectionOfferKind {
+    fn mime_types(&self) -> Box<[String]> {
+        match self {
+            Self::DataDevice(offer) => offer.with_mime_types(|mimes| mimes.into()),
+            Self::DataControl(offer) => offer
+                .data::<crate::data_control::OfferMimeTypes>()
+                .unwrap()
+                .lock()
+                .unwrap()
+                .as_slice()
+                .into(),
+        }
+    }
+}
+
 pub struct ForeignSelection {
     pub mime_types: Box<[String]>,
-    inner: SelectionOffer,
+    inner: SelectionOfferKind,
 }
 
 impl ForeignSelection {
+    /// Returns `None` if the data is bigger than the configured maximum clipboard size.
     pub(crate) fn receive(
         &self,
         mime_type: String,
         state: &ServerState<impl XConnection>,
-    ) -> Vec<u8> {
-        let mut pipe = self.inner.receive(mime_type).unwrap();
+    ) -> Option<Vec<u8>> {
+        let mut pipe = match &self.inner {
+            SelectionOfferKind::DataDevice(offer) => offer.receive(mime_type.clone()).unwrap(),
+            SelectionOfferKind::DataControl(offer) => {
+                let (read, write) = pipe_with(PipeFlags::CLOEXEC).unwrap();
+                offer.receive(mime_type.clone(), write.as_fd());
+                // Our copy of the write end has to be gone for the read to ever end.
+                drop(write);
+                ReadPipe::from(read)
+            }
+        };
         state.clientside.queue.flush().unwrap();
         let mut data = Vec::new();
-        pipe.read_to_end(&mut data).unwrap();
-        data
+        match state.config.clipboard_max_size {
+            Some(max) => {
+                // Read one byte past the limit to tell if it was exceeded
+                pipe.take(max as u64 + 1).read_to_end(&mut data).unwrap();
+                if data.len() > max {
+                    warn!("Refusing to forward {mime_type} from Wayland: data is larger than the maximum clipboard size");
+                    return None;
+                }
+            }
+            None => {
+                pipe.read_to_end(&mut data).unwrap();
+            }
+        }
+        Some(data)
     }
 }
 
 impl Drop for ForeignSelection {
     fn drop(&mut self) {
-        self.inner.destroy();
+        match &self.inner {
+            SelectionOfferKind::DataDevice(offer) => offer.destroy(),
+            SelectionOfferKind::DataControl(offer) => offer.destroy(),
+        }
     }
 }
 
 enum CopyPasteData<X: X11Selection> {
     X11 {
-        inner: CopyPasteSource,
+        inner: SelectionSource,
         data: Weak<X>,
     },
     Foreign(ForeignSelection),
@@ -0,0 +1,118 @@
+//! Frame pacing statistics for the state dump.
+//!
+//! When a window stutters, the time between frame callbacks shows whether the compositor is
+//! pacing it unevenly, and the time from a configure to the surface commit answering it shows how
+//! long satellite, Xwayland and the client take to follow a resize.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// Only recent samples are kept, so the numbers reflect what's happening now. At 60Hz this is
+/// the last two seconds of frames.
+const MAX_SAMPLES: usize = 120;
+
+#[derive(Debug, Default)]
+struct Samples(VecDeque<Duration>);
+
+impl Samples {
+    fn push(&mut self, sample: Duration) {
+        if self.0.len() == MAX_SAMPLES {
+            self.0.pop_front();
+        }
+        self.0.push_back(sample);
+    }
+
+    /// The 50th, 90th and 99th percentiles, using the nearest rank.
+    fn percentiles(&self) -> Option<[Duration; 3]> {
+        if self.0.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<_> = self.0.iter().copied().collect();
+        sorted.sort_unstable();
+        Some([50, 90, 99].map(|percentile| {
+            let rank = (sorted.len() * percentile).div_ceil(100);
+            sorted[rank.saturating_sub(1)]
+        }))
+    }
+}
+
+impl fmt::Display for Samples {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.percentiles() {
+            Some([p50, p90, p99]) => write!(
+                f,
+                "p50 {p50:.1?}, p90 {p90:.1?}, p99 {p99:.1?} (n={})",
+                self.0.len()
+            ),
+            None => f.write_str("no samples"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub(super) struct PacingStats {
+    last_frame: Option<Instant>,
+    frame_intervals: Samples,
+    /// When the configure that hasn't been answered with a commit yet arrived.
+    pending_configure: Option<Instant>,
+    configure_latency: Samples,
+}
+
+impl PacingStats {
+    pub(super) fn frame_done(&mut self, time: Instant) {
+        if let Some(last) = self.last_frame.replace(time) {
+            self.frame_intervals.push(time - last);
+        }
+    }
+
+    pub(super) fn configured(&mut self, time: Instant) {
+        // If several configures arrive before a commit, the commit answers the first one late.
+        self.pending_configure.get_or_insert(time);
+    }
+
+    pub(super) fn committed(&mut self, time: Instant) {
+        if let Some(configured) = self.pending_configure.take() {
+            self.configure_latency.push(time - configured);
+        }
+    }
+}
+
+impl fmt::Display for PacingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "frame intervals: {}; configure to commit: {}",
+            self.frame_intervals, self.configure_latency
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Samples, MAX_SAMPLES};
+    use std::time::Duration;
+
+    #[test]
+    fn percentiles() {
+        let mut samples = Samples::default();
+        assert_eq!(samples.percentiles(), None);
+
+        for ms in 1..=100 {
+            samples.push(Duration::from_millis(ms));
+        }
+        assert_eq!(
+            samples.percentiles(),
+            Some([50, 90, 99].map(Duration::from_millis))
+        );
+
+        // Old samples make way for new ones.
+        for _ in 0..MAX_SAMPLES {
+            samples.push(Duration::from_millis(16));
+        }
+        assert_eq!(
+            samples.percentiles(),
+            Some([16, 16, 16].map(Duration::from_millis))
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! How popups are placed relative to their parent.
+//!
+//! X gives popups absolute positions, while xdg_positioner wants them relative to an anchor on
+//! the parent, which also decides how the compositor moves them when they don't fit. No one
+//! choice of anchor works for every toolkit, so the anchoring is picked per popup.
+
+use crate::config::PopupAnchor;
+use log::debug;
+use wayland_protocols::xdg::shell::client::xdg_positioner::{
+    Anchor, ConstraintAdjustment, Gravity, XdgPositioner,
+};
+
+/// Where a new popup goes, relative to its parent's window geometry.
+pub(super) struct PopupGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub parent_width: i32,
+    pub parent_height: i32,
+    /// The pointer position, if it's over the parent.
+    pub pointer: Option<(i32, i32)>,
+    pub constraints: ConstraintAdjustment,
+}
+
+pub(super) trait PlacementStrategy: std::fmt::Debug {
+    /// Sets up the anchor, gravity and offset of a new popup.
+    fn place(&mut self, positioner: &XdgPositioner, geometry: &PopupGeometry);
+    /// Updates the offset after X moved the popup to `x`, `y` relative to its parent's window
+    /// geometry.
+    fn moved(&self, positioner: &XdgPositioner, x: i32, y: i32);
+}
+
+pub(super) fn strategy(anchor: PopupAnchor) -> Box<dyn PlacementStrategy> {
+    match anchor {
+        PopupAnchor::Parent => Box::new(RelativeToParent::default()),
+        PopupAnchor::Pointer => Box::new(PointerAnchored::default()),
+        PopupAnchor::Center => Box::new(CenterOnParent),
+    }
+}
+
+/// Anchors a popup to a single point of its parent, or to all of it without one, returning the
+/// point actually used.
+fn anchor_to(
+    positioner: &XdgPositioner,
+    geometry: &PopupGeometry,
+    point: Option<(i32, i32)>,
+) -> Option<(i32, i32)> {
+    positioner.set_anchor(Anchor::TopLeft);
+    positioner.set_gravity(Gravity::BottomRight);
+    // The anchor rect has to stay within the parent's window geometry.
+    let point = point.map(|(x, y)| {
+        (
+            x.clamp(0, geometry.parent_width - 1),
+            y.clamp(0, geometry.parent_height - 1),
+        )
+    });
+    match point {
+        Some((anchor_x, anchor_y)) => {
+            positioner.set_offset(geometry.x - anchor_x, geometry.y - anchor_y);
+            positioner.set_anchor_rect(anchor_x, anchor_y, 1, 1);
+        }
+        None => {
+            positioner.set_offset(geometry.x, geometry.y);
+            positioner.set_anchor_rect(0, 0, geometry.parent_width, geometry.parent_height);
+        }
+    }
+    point
+}
+
+/// The popup keeps the offset X gave it relative to the parent.
+#[derive(Debug, Default)]
+struct RelativeToParent {
+    anchor_point: Option<(i32, i32)>,
+}
+
+impl PlacementStrategy for RelativeToParent {
+    fn place(&mut self, positioner: &XdgPositioner, geometry: &PopupGeometry) {
+        // Flipping around the whole parent would send the popup to its far side, rather than
+        // just to the other side of where X put it.
+        let flips = geometry
+            .constraints
+            .intersects(ConstraintAdjustment::FlipX | ConstraintAdjustment::FlipY);
+        let point = flips.then_some((geometry.x, geometry.y));
+        self.anchor_point = anchor_to(positioner, geometry, point);
+    }
+
+    fn moved(&self, positioner: &XdgPositioner, x: i32, y: i32) {
+        let (anchor_x, anchor_y) = self.anchor_point.unwrap_or_default();
+        positioner.set_offset(x - anchor_x, y - anchor_y);
+    }
+}
+
+/// The popup is placed relative to the pointer, so it still opens at the pointer when the
+/// parent's position is out of date, and flips around the pointer instead of the parent.
+#[derive(Debug, Default)]
+struct PointerAnchored {
+    fallback: RelativeToParent,
+}
+
+impl PlacementStrategy for PointerAnchored {
+    fn place(&mut self, positioner: &XdgPositioner, geometry: &PopupGeometry) {
+        if geometry.pointer.is_none() {
+            self.fallback.place(positioner, geometry);
+            return;
+        }
+        debug!("anchoring popup to the pointer");
+        self.fallback.anchor_point = anchor_to(positioner, geometry, geometry.pointer);
+    }
+
+    fn moved(&self, positioner: &XdgPositioner, x: i32, y: i32) {
+        self.fallback.moved(positioner, x, y);
+    }
+}
+
+/// The popup is centered on its parent wherever X puts it, for splash-like popups.
+#[derive(Debug)]
+struct CenterOnParent;
+
+impl PlacementStrategy for CenterOnParent {
+    fn place(&mut self, positioner: &XdgPositioner, geometry: &PopupGeometry) {
+        positioner.set_anchor_rect(0, 0, geometry.parent_width, geometry.parent_height);
+        positioner.set_anchor(Anchor::None);
+        positioner.set_gravity(Gravity::None);
+        positioner.set_offset(0, 0);
+    }
+
+    fn moved(&self, _: &XdgPositioner, _: i32, _: i32) {}
+}
@@ -1,8 +1,8 @@
-use super::{ServerState, WindowDims};
-use crate::xstate::{SetState, WmName};
+use super::{FocusedOutput, ServerState, WindowDims};
+use crate::xstate::{MoveResize, SetState, WindowType, WmClass, WmName};
 use paste::paste;
 use rustix::event::{poll, PollFd, PollFlags};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::io::Write;
 use std::os::fd::{AsRawFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
@@ -28,7 +28,10 @@ use wayland_client::{
 use wayland_protocols::{
     wp::{
         linux_dmabuf::zv1::client::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
-        pointer_constraints::zv1::client::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
+        pointer_constraints::zv1::client::{
+            zwp_locked_pointer_v1::ZwpLockedPointerV1,
+            zwp_pointer_constraints_v1::{self, ZwpPointerConstraintsV1},
+        },
         relative_pointer::zv1::client::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1,
         tablet::zv2::client::{
             zwp_tablet_manager_v2::{self, ZwpTabletManagerV2},
@@ -45,7 +48,7 @@ use wayland_protocols::{
             zwp_tablet_tool_v2::{self, ZwpTabletToolV2},
             zwp_tablet_v2::{self, ZwpTabletV2},
         },
-        viewporter::client::wp_viewporter::WpViewporter,
+        viewporter::client::{wp_viewport::WpViewport, wp_viewporter::WpViewporter},
     },
     xdg::{
         shell::server::{xdg_positioner, xdg_toplevel},
@@ -110,7 +113,9 @@ struct Compositor {
     shm: TestObject<WlShm>,
     shell: TestObject<XwaylandShellV1>,
     seat: TestObject<WlSeat>,
-    tablet_man: TestObject<ZwpTabletManagerV2>
+    tablet_man: TestObject<ZwpTabletManagerV2>,
+    viewporter: TestObject<WpViewporter>,
+    pointer_constraints: TestObject<ZwpPointerConstraintsV1>
 }
 
 }
@@ -152,12 +157,22 @@ impl Compositor {
 struct WindowData {
     mapped: bool,
     fullscreen: bool,
+    minimized: bool,
     dims: WindowDims,
 }
 struct FakeXConnection {
     root: Window,
     focused_window: Option<Window>,
+    /// Bottom to top.
+    stack: Vec<Window>,
     windows: HashMap<Window, WindowData>,
+    pinged: Vec<Window>,
+    errors: HashMap<Window, String>,
+    pointer_constraints: HashMap<Window, super::PointerConstraint>,
+    pointer_grabbed: bool,
+    work_area: Option<(i32, i32, i32, i32)>,
+    tiled: HashMap<Window, super::TiledEdges>,
+    maximized: HashSet<Window>,
 }
 
 impl FakeXConnection {
@@ -174,7 +189,15 @@ impl Default for FakeXConnection {
         Self {
             root: unsafe { Window::new(9001) },
             focused_window: None,
+            stack: Vec::new(),
             windows: HashMap::new(),
+            pinged: Vec::new(),
+            errors: HashMap::new(),
+            pointer_constraints: HashMap::new(),
+            pointer_grabbed: false,
+            work_area: None,
+            tiled: HashMap::new(),
+            maximized: HashSet::new(),
         }
     }
 }
@@ -222,6 +245,19 @@ impl super::XConnection for FakeXConnection {
         self.window(window).fullscreen = fullscreen;
     }
 
+    #[track_caller]
+    fn set_minimized(&mut self, window: xcb::x::Window, minimized: bool, _: ()) {
+        self.window(window).minimized = minimized;
+    }
+
+    fn set_maximized(&mut self, window: xcb::x::Window, maximized: bool, _: ()) {
+        if maximized {
+            self.maximized.insert(window);
+        } else {
+            self.maximized.remove(&window);
+        }
+    }
+
     #[track_caller]
     fn set_window_dims(&mut self, window: Window, state: super::PendingSurfaceState) {
         self.window(window).dims = WindowDims {
@@ -233,7 +269,7 @@ impl super::XConnection for FakeXConnection {
     }
 
     #[track_caller]
-    fn focus_window(&mut self, window: Window, _output_name: Option<String>, _: ()) {
+    fn focus_window(&mut self, window: Window, _output: Option<FocusedOutput>, _: ()) {
         assert!(
             self.windows.contains_key(&window),
             "Unknown window: {window:?}"
@@ -241,11 +277,55 @@ impl super::XConnection for FakeXConnection {
         self.focused_window = window.into();
     }
 
-    fn raise_to_top(&mut self, window: Window) {
+    fn ping(&mut self, window: Window, _: ()) {
+        self.pinged.push(window);
+    }
+
+    fn next_timeout(&self) -> Option<std::time::Duration> {
+        None
+    }
+
+    fn report_error(&mut self, window: Window, error: &str, _: ()) {
+        self.errors.insert(window, error.to_string());
+    }
+
+    fn pointer_grabbed(&self) -> bool {
+        self.pointer_grabbed
+    }
+
+    fn set_tiled_edges(&mut self, window: Window, edges: super::TiledEdges, _: ()) {
+        self.tiled.insert(window, edges);
+    }
+
+    fn set_work_area(&mut self, area: (i32, i32, i32, i32), _: ()) {
+        self.work_area = Some(area);
+    }
+
+    fn set_pointer_constraint(
+        &mut self,
+        window: Window,
+        constraint: Option<super::PointerConstraint>,
+        _: (),
+    ) {
+        match constraint {
+            Some(constraint) => self.pointer_constraints.insert(window, constraint),
+            None => self.pointer_constraints.remove(&window),
+        };
+    }
+
+    fn stack_above(&mut self, window: Window, sibling: Window) {
         assert!(
             self.windows.contains_key(&window),
             "Unknown window: {window:?}"
         );
+        self.stack.retain(|w| *w != window);
+        let idx = self
+            .stack
+            .iter()
+            .position(|w| *w == sibling)
+            .unwrap_or_else(|| panic!("{sibling:?} isn't stacked"))
+            + 1;
+        self.stack.insert(idx, window);
     }
 }
 
@@ -266,6 +346,11 @@ static INIT: std::sync::Once = std::sync::Once::new();
 
 impl TestFixture {
     fn new() -> Self {
+        Self::new_preset(|_| {})
+    }
+
+    /// Lets the test compositor offer more globals before satellite binds them.
+    fn new_preset(setup: impl FnOnce(&mut testwl::Server)) -> Self {
         INIT.call_once(|| {
             env_logger::builder()
                 .is_test(true)
@@ -275,6 +360,7 @@ impl TestFixture {
 
         let (client_s, server_s) = UnixStream::pair().unwrap();
         let mut testwl = testwl::Server::new(true);
+        setup(&mut testwl);
         let display = Display::<FakeServerState>::new().unwrap();
         testwl.connect(server_s);
         // Handle initial globals roundtrip setup requirement
@@ -286,7 +372,8 @@ impl TestFixture {
             testwl.dispatch();
             testwl
         });
-        let mut satellite = FakeServerState::new(display.handle(), Some(client_s));
+        let mut satellite =
+            FakeServerState::new(display.handle(), Some(client_s), Default::default());
         let testwl = thread.join().unwrap();
 
         let (fake_client, xwls_server) = UnixStream::pair().unwrap();
@@ -363,6 +450,10 @@ impl TestFixture {
                     x if x == XwaylandShellV1::interface().name => bind!(shell),
                     x if x == WlSeat::interface().name => bind!(seat),
                     x if x == ZwpTabletManagerV2::interface().name => bind!(tablet_man),
+                    x if x == WpViewporter::interface().name => bind!(viewporter),
+                    x if x == ZwpPointerConstraintsV1::interface().name => {
+                        bind!(pointer_constraints)
+                    }
                     _ => {}
                 }
             }
@@ -498,12 +589,11 @@ impl TestFixture {
     }
 
     fn register_window(&mut self, window: Window, data: WindowData) {
-        self.satellite
-            .connection
-            .as_mut()
-            .unwrap()
-            .windows
-            .insert(window, data);
+        let connection = self.satellite.connection.as_mut().unwrap();
+        connection.windows.insert(window, data);
+        // New windows go on top.
+        connection.stack.retain(|w| *w != window);
+        connection.stack.push(window);
     }
 
     fn new_window(
@@ -586,6 +676,7 @@ impl TestFixture {
                 height: 50,
             },
             fullscreen: false,
+            minimized: false,
         };
 
         self.new_window(window, false, data, None);
@@ -656,6 +747,7 @@ impl TestFixture {
                 height: 50,
             },
             fullscreen: false,
+            minimized: false,
         };
         let dims = data.dims;
         self.new_window(window, true, data, None);
@@ -962,6 +1054,222 @@ fn last_activated_toplevel_is_focused() {
     );
 }
 
+#[test]
+fn toplevel_survives_remap() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.unmap_grace = Some(std::time::Duration::from_secs(60));
+    let win = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, win);
+
+    f.satellite.unmap_window(win);
+    surface.obj.destroy();
+    f.run();
+
+    let data = f
+        .testwl
+        .get_surface_data(id)
+        .expect("surface was destroyed");
+    assert!(data.toplevel().toplevel.is_alive());
+
+    let (buffer, surface) = comp.create_surface();
+    f.satellite.map_window(win);
+    f.associate_window(&comp, win, &surface.obj);
+    f.run();
+    surface
+        .send_request(Req::<WlSurface>::Attach {
+            buffer: Some(buffer.obj.clone()),
+            x: 0,
+            y: 0,
+        })
+        .unwrap();
+    surface.send_request(Req::<WlSurface>::Commit).unwrap();
+    f.run();
+
+    // The old toplevel is used for the new surface
+    let new_id = f.testwl.last_created_surface_id().unwrap();
+    assert_ne!(new_id, id);
+    assert!(f.testwl.get_surface_data(new_id).is_none());
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(data.toplevel().toplevel.is_alive());
+    assert!(data.buffer.is_some());
+
+    // Events for the old toplevel reach the new surface
+    f.testwl.close_toplevel(id);
+    f.run();
+    assert!(!f.connection().windows[&win].mapped);
+}
+
+#[test]
+fn lingering_toplevel_expires() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.unmap_grace = Some(std::time::Duration::ZERO);
+    let win = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, win);
+
+    f.satellite.unmap_window(win);
+    surface.obj.destroy();
+    f.run();
+    f.run();
+
+    assert!(f.testwl.get_surface_data(id).is_none());
+}
+
+#[test]
+fn map_waits_for_content() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.content_timeout = Some(std::time::Duration::from_secs(60));
+    let data = || WindowData {
+        mapped: true,
+        dims: WindowDims {
+            width: 50,
+            height: 50,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let win = unsafe { Window::new(1) };
+    f.new_window(win, false, data(), None);
+
+    let (buffer, surface) = comp.create_surface();
+    f.satellite.map_window(win);
+    f.associate_window(&comp, win, &surface.obj);
+    f.run();
+    let id = f.check_new_surface();
+
+    // A commit without a buffer doesn't count.
+    surface.send_request(Req::<WlSurface>::Commit).unwrap();
+    f.run();
+    assert!(f.testwl.get_surface_data(id).unwrap().role.is_none());
+
+    surface
+        .send_request(Req::<WlSurface>::Attach {
+            buffer: Some(buffer.obj.clone()),
+            x: 0,
+            y: 0,
+        })
+        .unwrap();
+    surface.send_request(Req::<WlSurface>::Commit).unwrap();
+    f.run();
+    let surface_data = f.testwl.get_surface_data(id).unwrap();
+    assert!(
+        matches!(surface_data.role, Some(testwl::SurfaceRole::Toplevel(_))),
+        "surface role: {:?}",
+        surface_data.role
+    );
+
+    // The buffer is shown once the toplevel is configured.
+    f.testwl.configure_toplevel(id, 100, 100, vec![]);
+    f.run();
+    assert!(f.testwl.get_surface_data(id).unwrap().buffer.is_some());
+
+    // Windows that never draw are mapped anyway.
+    f.satellite.config.content_timeout = Some(std::time::Duration::ZERO);
+    let win = unsafe { Window::new(2) };
+    f.new_window(win, false, data(), None);
+    let (_, surface) = comp.create_surface();
+    f.satellite.map_window(win);
+    f.associate_window(&comp, win, &surface.obj);
+    f.run();
+    f.run();
+    let id = f.check_new_surface();
+    assert!(f.testwl.get_surface_data(id).unwrap().role.is_some());
+}
+
+#[test]
+fn orphaned_popup_becomes_toplevel() {
+    use crate::xstate::RoleOverride;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let t_win = unsafe { Window::new(1) };
+    let (t_surface, _) = f.create_toplevel(&comp, t_win);
+    f.satellite.last_hovered = Some(t_win);
+
+    let win = unsafe { Window::new(2) };
+    let (buffer, surface) = comp.create_surface();
+    let viewport = TestObject::<WpViewport>::from_request(
+        &comp.viewporter.obj,
+        Req::<WpViewporter>::GetViewport {
+            surface: surface.obj.clone(),
+        },
+    );
+    viewport.obj.set_destination(100, 100);
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            x: 10,
+            y: 10,
+            width: 50,
+            height: 50,
+        },
+        ..Default::default()
+    };
+    f.new_window(win, false, data, None);
+    f.satellite
+        .set_role_override(win, Some(RoleOverride::Popup));
+    f.map_window(&comp, win, &surface.obj, &buffer);
+    f.run();
+    let popup_id = f.check_new_surface();
+    assert!(matches!(
+        f.testwl.get_surface_data(popup_id).unwrap().role,
+        Some(testwl::SurfaceRole::Popup(_))
+    ));
+
+    f.satellite.unmap_window(t_win);
+    t_surface.obj.destroy();
+    f.run();
+
+    assert!(f.testwl.get_surface_data(popup_id).is_none());
+    let id = f.check_new_surface();
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(
+        matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_))),
+        "surface role: {:?}",
+        data.role
+    );
+    // The viewport moves over to the new surface.
+    assert_eq!(
+        data.viewport,
+        Some(testwl::Viewport {
+            destination: Some(testwl::Vec2 { x: 100, y: 100 })
+        })
+    );
+
+    // The popup's contents carry over.
+    f.testwl.configure_toplevel(id, 50, 50, vec![]);
+    f.run();
+    assert!(f.testwl.get_surface_data(id).unwrap().buffer.is_some());
+
+    // and the viewport keeps working.
+    viewport.obj.set_destination(200, 200);
+    f.run();
+    assert_eq!(
+        f.testwl.get_surface_data(id).unwrap().viewport,
+        Some(testwl::Viewport {
+            destination: Some(testwl::Vec2 { x: 200, y: 200 })
+        })
+    );
+}
+
+#[test]
+fn orphaned_override_redirect_popup_stays() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let t_win = unsafe { Window::new(1) };
+    let (t_surface, toplevel_id) = f.create_toplevel(&comp, t_win);
+    let win = unsafe { Window::new(2) };
+    let (_, popup_id) = f.create_popup(&comp, win, t_win, toplevel_id, 10, 10);
+
+    f.satellite.unmap_window(t_win);
+    t_surface.obj.destroy();
+    f.run();
+
+    // A menu outliving its window isn't turned into a toplevel.
+    assert_eq!(f.testwl.last_created_surface_id(), Some(popup_id));
+    assert!(!f
+        .testwl
+        .get_surface_data(popup_id)
+        .is_some_and(|data| matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_)))));
+}
+
 #[test]
 fn popup_window_changes_surface() {
     let (mut f, comp) = TestFixture::new_with_compositor();
@@ -1077,580 +1385,2813 @@ fn fullscreen() {
 }
 
 #[test]
-fn window_title_and_class() {
+fn bypass_compositor_game_hints() {
+    use wayland_protocols::wp::{
+        content_type::v1::server::wp_content_type_v1,
+        tearing_control::v1::server::wp_tearing_control_v1,
+    };
+
     let (mut f, comp) = TestFixture::new_with_compositor();
     let win = unsafe { Window::new(1) };
     let (_, id) = f.create_toplevel(&comp, win);
 
-    f.satellite
-        .set_win_title(win, WmName::WmName("window".into()));
-    f.satellite.set_win_class(win, "class".into());
+    // Windowed, so not a game yet
+    f.satellite.set_bypass_compositor(win, true);
     f.run();
-
     let data = f.testwl.get_surface_data(id).unwrap();
-    assert_eq!(data.toplevel().title, Some("window".into()));
-    assert_eq!(data.toplevel().app_id, Some("class".into()));
+    assert_eq!(data.content_type, None);
+    assert!(!data.idle_inhibited);
 
-    f.satellite
-        .set_win_title(win, WmName::NetWmName("superwindow".into()));
+    f.satellite.set_fullscreen(win, SetState::Add);
+    f.run();
     f.run();
-
     let data = f.testwl.get_surface_data(id).unwrap();
-    assert_eq!(data.toplevel().title, Some("superwindow".into()));
+    assert_eq!(data.content_type, Some(wp_content_type_v1::Type::Game));
+    assert_eq!(
+        data.presentation_hint,
+        Some(wp_tearing_control_v1::PresentationHint::Async)
+    );
+    assert!(data.idle_inhibited);
 
-    f.satellite
-        .set_win_title(win, WmName::WmName("shwindow".into()));
+    f.satellite.set_bypass_compositor(win, false);
     f.run();
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.content_type, None);
+    assert_eq!(data.presentation_hint, None);
+    assert!(!data.idle_inhibited);
 
+    f.satellite.set_bypass_compositor(win, true);
+    f.run();
+    f.satellite.set_fullscreen(win, SetState::Remove);
+    f.run();
+    f.run();
     let data = f.testwl.get_surface_data(id).unwrap();
-    assert_eq!(data.toplevel().title, Some("superwindow".into()));
+    assert_eq!(data.content_type, None);
+    assert!(!data.idle_inhibited);
 }
 
 #[test]
-fn window_group_properties() {
+fn gtk_frame_extents() {
+    use crate::xstate::FrameExtents;
+
     let (mut f, comp) = TestFixture::new_with_compositor();
-    let prop_win = unsafe { Window::new(1) };
-    f.satellite.new_window(
-        prop_win,
-        false,
-        super::WindowDims {
-            width: 1,
-            height: 1,
+    let toplevel = unsafe { Window::new(1) };
+    let (_, toplevel_id) = f.create_toplevel(&comp, toplevel);
+    let geometry = |f: &TestFixture, id| {
+        let data = f.testwl.get_surface_data(id).unwrap();
+        let rect = data.xdg().window_geometry.clone().unwrap();
+        (rect.offset.x, rect.offset.y, rect.size.x, rect.size.y)
+    };
+
+    f.satellite.set_frame_extents(
+        toplevel,
+        FrameExtents {
+            left: 10,
+            right: 10,
+            top: 5,
+            bottom: 15,
+        },
+    );
+    f.run();
+    assert_eq!(geometry(&f, toplevel_id), (10, 5, 80, 80));
+
+    // The configured size is the size of the visible part.
+    f.testwl
+        .configure_toplevel(toplevel_id, 200, 150, vec![xdg_toplevel::State::Activated]);
+    f.run();
+    let dims = f.connection().windows[&toplevel].dims;
+    assert_eq!((dims.width, dims.height), (220, 170));
+    assert_eq!(geometry(&f, toplevel_id), (10, 5, 200, 150));
+
+    // Size limits leave out what's outside of the frame extents, and 0 stays unlimited.
+    f.satellite.set_size_hints(
+        toplevel,
+        crate::xstate::WmNormalHints {
+            min_size: Some(crate::xstate::WinSize {
+                width: 30,
+                height: 30,
+            }),
+            max_size: Some(crate::xstate::WinSize {
+                width: 220,
+                height: 0,
+            }),
             ..Default::default()
         },
-        None,
     );
-    f.satellite
-        .set_win_title(prop_win, WmName::WmName("window".into()));
-    f.satellite.set_win_class(prop_win, "class".into());
+    f.run();
+    let data = f.testwl.get_surface_data(toplevel_id).unwrap();
+    assert_eq!(
+        data.toplevel().min_size,
+        Some(testwl::Vec2 { x: 10, y: 10 })
+    );
+    assert_eq!(
+        data.toplevel().max_size,
+        Some(testwl::Vec2 { x: 200, y: 0 })
+    );
 
-    let win = unsafe { Window::new(2) };
+    let popup = unsafe { Window::new(2) };
+    let (buffer, surface) = comp.create_surface();
     let data = WindowData {
         mapped: true,
         dims: WindowDims {
+            x: 30,
+            y: 40,
             width: 50,
             height: 50,
-            ..Default::default()
         },
         fullscreen: false,
+        minimized: false,
     };
-
-    let (_, surface) = comp.create_surface();
-    let dims = data.dims;
-    f.register_window(win, data);
-    f.satellite.new_window(win, false, dims, None);
-    f.satellite.set_win_hints(
-        win,
-        super::WmHints {
-            window_group: Some(prop_win),
+    f.new_window(popup, true, data, None);
+    f.satellite.set_frame_extents(
+        popup,
+        FrameExtents {
+            left: 4,
+            right: 4,
+            top: 4,
+            bottom: 4,
         },
     );
-    f.satellite.map_window(win);
-    f.associate_window(&comp, win, &surface.obj);
+    f.map_window(&comp, popup, &surface.obj, &buffer);
     f.run();
+    let popup_id = f.check_new_surface();
 
-    let id = f.testwl.last_created_surface_id().unwrap();
-    let data = f.testwl.get_surface_data(id).unwrap();
+    // Positioned by the visible parts of both windows
+    let pos = f
+        .testwl
+        .get_surface_data(popup_id)
+        .unwrap()
+        .popup()
+        .positioner_state
+        .clone();
+    assert_eq!(pos.offset, testwl::Vec2 { x: 24, y: 39 });
+    assert_eq!(pos.size, Some(testwl::Vec2 { x: 42, y: 42 }));
+    assert_eq!(
+        pos.anchor_rect.unwrap().size,
+        testwl::Vec2 { x: 200, y: 150 }
+    );
+    assert_eq!(geometry(&f, popup_id), (4, 4, 42, 42));
 
-    assert_eq!(data.toplevel().title, Some("window".into()));
-    assert_eq!(data.toplevel().app_id, Some("class".into()));
+    f.testwl.configure_popup(popup_id);
+    f.run();
+    let dims = f.connection().windows[&popup].dims;
+    assert_eq!(
+        dims,
+        WindowDims {
+            x: 30,
+            y: 40,
+            width: 50,
+            height: 50
+        }
+    );
 }
 
 #[test]
-fn copy_from_x11() {
+fn minimize() {
     let (mut f, comp) = TestFixture::new_with_compositor();
     let win = unsafe { Window::new(1) };
-    let (_surface, _id) = f.create_toplevel(&comp, win);
+    let (_, id) = f.create_toplevel(&comp, win);
 
-    let mimes = std::rc::Rc::new(vec![
-        testwl::PasteData {
-            mime_type: "text".to_string(),
-            data: b"abc".to_vec(),
-        },
-        testwl::PasteData {
-            mime_type: "data".to_string(),
-            data: vec![1, 2, 3, 4, 6, 10],
-        },
-    ]);
+    f.satellite.set_minimized(win);
+    f.run();
+    f.run();
 
-    f.satellite.set_copy_paste_source(&mimes);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(data.toplevel().minimized);
+    assert!(f.connection().windows[&win].minimized);
+
+    f.testwl
+        .configure_toplevel(id, 100, 100, vec![xdg_toplevel::State::Activated]);
+    f.run();
     f.run();
 
-    let server_mimes = f.testwl.data_source_mimes();
-    for mime in mimes.iter() {
-        assert!(server_mimes.contains(&mime.mime_type));
-    }
-
-    let data = f.testwl.paste_data(|_, _| {
-        f.satellite.run();
-        true
-    });
-    assert_eq!(*mimes, data);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(!data.toplevel().minimized);
+    assert!(!f.connection().windows[&win].minimized);
 }
 
 #[test]
-fn copy_from_wayland() {
+fn compositor_ping_pings_windows() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    TestObject::<WlKeyboard>::from_request(&comp.seat.obj, wl_seat::Request::GetKeyboard {});
     let win = unsafe { Window::new(1) };
-    let (_surface, _id) = f.create_toplevel(&comp, win);
+    f.create_toplevel(&comp, win);
+    let unmapped = unsafe { Window::new(2) };
+    f.satellite
+        .new_window(unmapped, false, WindowDims::default(), None);
 
-    let mimes = vec![
-        testwl::PasteData {
-            mime_type: "text".to_string(),
-            data: b"abc".to_vec(),
-        },
-        testwl::PasteData {
-            mime_type: "data".to_string(),
-            data: vec![1, 2, 3, 4, 6, 10],
-        },
-    ];
-    f.testwl.create_data_offer(mimes.clone());
+    f.testwl.ping(42);
+    f.run();
     f.run();
 
-    let selection = f.satellite.new_selection().expect("No new selection");
-    for mime in &mimes {
-        let data = std::thread::scope(|s| {
-            // receive requires a queue flush - dispatch testwl from another thread
-            s.spawn(|| {
-                let pollfd = unsafe { BorrowedFd::borrow_raw(f.testwl.poll_fd().as_raw_fd()) };
-                let mut pollfd = [PollFd::from_borrowed_fd(pollfd, PollFlags::IN)];
-                if poll(&mut pollfd, 100).unwrap() == 0 {
-                    panic!("Did not get events for testwl!");
-                }
-                f.testwl.dispatch();
-                while poll(&mut pollfd, 100).unwrap() > 0 {
-                    f.testwl.dispatch();
-                }
-            });
-            selection.receive(mime.mime_type.clone(), &f.satellite)
-        });
-        f.run();
-        assert_eq!(data, mime.data);
-    }
+    assert_eq!(f.testwl.last_pong(), Some(42));
+    assert_eq!(f.connection().pinged, vec![win]);
 }
 
 #[test]
-fn clipboard_x11_then_wayland() {
+fn urgency_requests_attention() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    TestObject::<WlKeyboard>::from_request(&comp.seat.obj, wl_seat::Request::GetKeyboard {});
     let win = unsafe { Window::new(1) };
-    let (_surface, _id) = f.create_toplevel(&comp, win);
-
-    let x11data = std::rc::Rc::new(vec![
-        testwl::PasteData {
-            mime_type: "text".to_string(),
-            data: b"abc".to_vec(),
-        },
-        testwl::PasteData {
-            mime_type: "data".to_string(),
-            data: vec![1, 2, 3, 4, 6, 10],
-        },
-    ]);
-
-    f.satellite.set_copy_paste_source(&x11data);
-    f.run();
+    let (_, id) = f.create_toplevel(&comp, win);
 
-    let waylanddata = vec![
-        testwl::PasteData {
-            mime_type: "asdf".to_string(),
-            data: b"fdaa".to_vec(),
-        },
-        testwl::PasteData {
-            mime_type: "boing".to_string(),
-            data: vec![10, 20, 40, 50],
-        },
-    ];
-    f.testwl.create_data_offer(waylanddata.clone());
+    let urgent = |urgent| super::WmHints {
+        urgent,
+        ..Default::default()
+    };
+    f.satellite.set_win_hints(win, urgent(true));
     f.run();
     f.run();
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(data.toplevel().attention_requested);
 
-    let selection = f.satellite.new_selection().expect("No new selection");
-    for mime in &waylanddata {
-        let data = std::thread::scope(|s| {
-            // receive requires a queue flush - dispatch testwl from another thread
-            s.spawn(|| {
-                let pollfd = unsafe { BorrowedFd::borrow_raw(f.testwl.poll_fd().as_raw_fd()) };
-                let mut pollfd = [PollFd::from_borrowed_fd(pollfd, PollFlags::IN)];
-                if poll(&mut pollfd, 100).unwrap() == 0 {
-                    panic!("Did not get events for testwl!");
-                }
-                f.testwl.dispatch();
-                while poll(&mut pollfd, 100).unwrap() > 0 {
-                    f.testwl.dispatch();
-                }
-            });
-            selection.receive(mime.mime_type.clone(), &f.satellite)
-        });
-        f.run();
-        assert_eq!(data, mime.data);
-    }
-}
-
-#[test]
-fn raise_window_on_pointer_event() {
-    let (mut f, comp) = TestFixture::new_with_compositor();
-    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
-    let win1 = unsafe { Window::new(1) };
-    let (_, id1) = f.create_toplevel(&comp, win1);
-    f.testwl.configure_toplevel(id1, 100, 100, vec![]);
-
-    let win2 = unsafe { Window::new(2) };
-    let (_, id2) = f.create_toplevel(&comp, win2);
-    assert_eq!(f.connection().focused_window, Some(win2));
-
-    f.testwl.move_pointer_to(id2, 0.0, 0.0);
-    f.run();
-    assert_eq!(f.connection().focused_window, Some(win2));
-    assert_eq!(f.satellite.last_hovered, Some(win2));
+    // The urgency hint doesn't set the state, so toggling the state still adds it.
+    assert!(!f.satellite.window_demands_attention(win));
+    f.satellite.set_demands_attention(win, true);
+    f.satellite.set_win_hints(win, urgent(false));
+    assert!(f.satellite.window_demands_attention(win));
+    assert!(f.satellite.windows[&win].wants_attention());
 
-    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.satellite.set_demands_attention(win, false);
     f.run();
-    assert_eq!(f.connection().focused_window, Some(win2));
-    assert_eq!(f.satellite.last_hovered, Some(win1));
+    assert!(!f.satellite.window_demands_attention(win));
+    assert!(!f.satellite.windows[&win].wants_attention());
 }
 
 #[test]
-fn override_redirect_choose_hover_window() {
+fn user_time_focus_stealing_prevention() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
-    let win1 = unsafe { Window::new(1) };
-    let (_, id1) = f.create_toplevel(&comp, win1);
-    f.testwl.configure_toplevel(id1, 100, 100, vec![]);
+    let focused = unsafe { Window::new(1) };
+    f.create_toplevel(&comp, focused);
+    f.satellite.set_user_time(focused, Some(100));
 
-    let win2 = unsafe { Window::new(2) };
-    let _ = f.create_toplevel(&comp, win2);
-    assert_eq!(f.connection().focused_window, Some(win2));
+    let mut map_with_user_time = |window, time| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            fullscreen: false,
+            minimized: false,
+        };
+        f.new_window(window, false, data, None);
+        f.satellite.set_user_time(window, Some(time));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        let id = f.check_new_surface();
+        f.testwl.configure_toplevel(id, 100, 100, vec![]);
+        f.run();
+        f.run();
+        let data = f.testwl.get_surface_data(id).unwrap();
+        let toplevel = data.toplevel();
+        (toplevel.activation_serial, toplevel.attention_requested)
+    };
 
-    f.testwl.move_pointer_to(id1, 0.0, 0.0);
-    f.run();
-    assert_eq!(f.satellite.last_hovered, Some(win1));
+    // Opened by the user after they last interacted with the focused window
+    let (serial, attention) = map_with_user_time(unsafe { Window::new(2) }, 200);
+    assert!(serial.is_some());
+    assert!(!attention);
 
-    let win3 = unsafe { Window::new(3) };
-    let (buffer, surface) = comp.create_surface();
-    f.new_window(win3, true, WindowData::default(), None);
-    f.map_window(&comp, win3, &surface.obj, &buffer);
-    f.run();
-    let id3 = f.check_new_surface();
-    let popup_data = f.testwl.get_surface_data(id3).unwrap();
-    let win1_xdg = &f.testwl.get_surface_data(id1).unwrap().xdg().surface;
-    assert_eq!(&popup_data.popup().parent, win1_xdg);
+    // Opened in the background
+    let (serial, attention) = map_with_user_time(unsafe { Window::new(3) }, 50);
+    assert!(serial.is_none());
+    assert!(attention);
 }
 
 #[test]
-fn output_offset() {
+fn startup_tokens_are_kept_per_client() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    let (output_obj, output) = f.new_output(0, 0);
-    let man = f.enable_xdg_output();
-    f.create_xdg_output(&man, output_obj.obj);
-    f.testwl.move_xdg_output(&output, 500, 100);
-    f.run();
-    let window = unsafe { Window::new(1) };
+    let first_token = f.testwl.issue_activation_token(Some(10));
+    let second_token = f.testwl.issue_activation_token(Some(20));
+    let second_token_copy = second_token.clone();
+    // Both apps were launched before either of their windows showed up. The first one announced
+    // its token on its (never mapped) leader window.
+    let leader = unsafe { Window::new(10) };
+    f.new_window(leader, false, WindowData::default(), None);
+    f.satellite
+        .set_startup_id(leader, Some(100), Some(first_token));
 
-    {
-        let (surface, surface_id) = f.create_toplevel(&comp, window);
-        f.testwl.move_surface_to_output(surface_id, &output);
+    let mut map_with_startup_id = |window, pid, token, user_time| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            fullscreen: false,
+            minimized: false,
+        };
+        f.new_window(window, false, data, None);
+        f.satellite.set_startup_id(window, Some(pid), token);
+        f.satellite.set_user_time(window, user_time);
+        f.map_window(&comp, window, &surface.obj, &buffer);
         f.run();
-        let data = &f.connection().windows[&window];
-        assert_eq!(data.dims.x, 500);
-        assert_eq!(data.dims.y, 100);
-
-        f.satellite.unmap_window(window);
-        surface.obj.destroy();
+        let id = f.check_new_surface();
+        f.testwl.configure_toplevel(id, 100, 100, vec![]);
         f.run();
-    }
-
-    let (t_buffer, t_surface) = comp.create_surface();
-    f.map_window(&comp, window, &t_surface.obj, &t_buffer);
-    f.run();
-    let t_id = f.testwl.last_created_surface_id().unwrap();
-    f.testwl.move_surface_to_output(t_id, &output);
-    f.run();
-    {
-        let data = f.testwl.get_surface_data(t_id).unwrap();
-        assert!(
-            matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_))),
-            "surface role: {:?}",
-            data.role
-        );
-    }
-    f.testwl.configure_toplevel(t_id, 100, 100, vec![]);
-    f.testwl.focus_toplevel(t_id);
-    f.run();
-
-    {
-        let data = &f.connection().windows[&window];
-        assert_eq!(data.dims.x, 500);
-        assert_eq!(data.dims.y, 100);
-    }
+        f.run();
+        f.testwl
+            .get_surface_data(id)
+            .unwrap()
+            .toplevel()
+            .activation_serial
+    };
 
-    let popup = unsafe { Window::new(2) };
-    let (p_surface, p_id) = f.create_popup(&comp, popup, window, t_id, 510, 110);
-    f.testwl.move_surface_to_output(p_id, &output);
-    f.run();
-    let data = f.testwl.get_surface_data(p_id).unwrap();
     assert_eq!(
-        data.popup().positioner_state.offset,
-        testwl::Vec2 { x: 10, y: 10 }
+        map_with_startup_id(unsafe { Window::new(2) }, 200, Some(second_token), None),
+        Some(20)
     );
-
-    f.satellite.unmap_window(popup);
-    p_surface.obj.destroy();
-    f.run();
-
-    let (buffer, surface) = comp.create_surface();
-    f.map_window(&comp, popup, &surface.obj, &buffer);
-    f.run();
-    let p_id = f.testwl.last_created_surface_id().unwrap();
-    f.testwl.move_surface_to_output(p_id, &output);
-    f.testwl.configure_popup(p_id);
-    f.run();
-    let data = f.testwl.get_surface_data(p_id).unwrap();
+    // A window that doesn't want focus leaves the token alone.
     assert_eq!(
-        data.popup().positioner_state.offset,
-        testwl::Vec2 { x: 10, y: 10 }
+        map_with_startup_id(unsafe { Window::new(3) }, 100, None, Some(0)),
+        None
+    );
+    assert_eq!(
+        map_with_startup_id(unsafe { Window::new(1) }, 100, None, None),
+        Some(10)
+    );
+    // The token is still on the client's windows, but it was used up.
+    assert_eq!(
+        map_with_startup_id(
+            unsafe { Window::new(4) },
+            200,
+            Some(second_token_copy),
+            None
+        ),
+        None
     );
 }
 
 #[test]
-fn output_offset_change() {
+fn refused_requests_are_reported() {
     let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    f.create_toplevel(&comp, win);
 
-    let (output_obj, output) = f.new_output(500, 100);
-    let window = unsafe { Window::new(1) };
-    let (_, id) = f.create_toplevel(&comp, window);
-    f.testwl.move_surface_to_output(id, &output);
+    f.satellite.set_size_hints(
+        win,
+        crate::xstate::WmNormalHints {
+            min_size: Some(crate::xstate::WinSize {
+                width: 100,
+                height: 100,
+            }),
+            max_size: Some(crate::xstate::WinSize {
+                width: 50,
+                height: 50,
+            }),
+            ..Default::default()
+        },
+    );
     f.run();
+    let error = f.connection().errors.get(&win).expect("no error reported");
+    assert!(error.contains("maximum size"), "wrong error: {error}");
+}
 
-    let test_position = |f: &TestFixture, x, y| {
-        let data = &f.connection().windows[&window];
-        assert_eq!(data.dims.x, x);
-        assert_eq!(data.dims.y, y);
-    };
-    test_position(&f, 500, 100);
-
-    f.testwl.move_output(&output, 600, 200);
-    f.run();
+fn wm_class(instance: &str, class: &str) -> WmClass {
+    WmClass {
+        instance: instance.into(),
+        class: class.into(),
+    }
+}
+
+#[test]
+fn window_title_and_class() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (_, id) = f.create_toplevel(&comp, win);
+
+    f.satellite
+        .set_win_title(win, WmName::WmName("window".into()));
+    f.satellite
+        .set_win_class(win, wm_class("instance", "class"));
     f.run();
-    test_position(&f, 600, 200);
 
-    let man = f.enable_xdg_output();
-    f.create_xdg_output(&man, output_obj.obj);
-    // testwl inits xdg output position to 0, and it should take priority over wl_output position
-    test_position(&f, 0, 0);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().title, Some("window".into()));
+    assert_eq!(data.toplevel().app_id, Some("class".into()));
 
-    f.testwl.move_xdg_output(&output, 1000, 22);
+    f.satellite
+        .set_win_title(win, WmName::NetWmName("superwindow".into()));
     f.run();
-    test_position(&f, 1000, 22);
 
-    f.testwl.move_output(&output, 600, 200);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().title, Some("superwindow".into()));
+
+    f.satellite
+        .set_win_title(win, WmName::WmName("shwindow".into()));
     f.run();
+
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().title, Some("superwindow".into()));
+}
+
+#[test]
+fn window_group_properties() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let prop_win = unsafe { Window::new(1) };
+    f.satellite.new_window(
+        prop_win,
+        false,
+        super::WindowDims {
+            width: 1,
+            height: 1,
+            ..Default::default()
+        },
+        None,
+    );
+    f.satellite
+        .set_win_title(prop_win, WmName::WmName("window".into()));
+    f.satellite
+        .set_win_class(prop_win, wm_class("instance", "class"));
+
+    let win = unsafe { Window::new(2) };
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            width: 50,
+            height: 50,
+            ..Default::default()
+        },
+        fullscreen: false,
+        minimized: false,
+    };
+
+    let (_, surface) = comp.create_surface();
+    let dims = data.dims;
+    f.register_window(win, data);
+    f.satellite.new_window(win, false, dims, None);
+    f.satellite.set_win_hints(
+        win,
+        super::WmHints {
+            window_group: Some(prop_win),
+            ..Default::default()
+        },
+    );
+    f.satellite.map_window(win);
+    f.associate_window(&comp, win, &surface.obj);
     f.run();
-    test_position(&f, 1000, 22);
+
+    let id = f.testwl.last_created_surface_id().unwrap();
+    let data = f.testwl.get_surface_data(id).unwrap();
+
+    assert_eq!(data.toplevel().title, Some("window".into()));
+    assert_eq!(data.toplevel().app_id, Some("class".into()));
 }
 
 #[test]
-fn reposition_popup() {
+fn modal_dialog() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    let toplevel = unsafe { Window::new(1) };
-    let (_, t_id) = f.create_toplevel(&comp, toplevel);
+    let parent = unsafe { Window::new(1) };
+    let (_, parent_id) = f.create_toplevel(&comp, parent);
 
-    let popup = unsafe { Window::new(2) };
-    let (_, p_id) = f.create_popup(&comp, popup, toplevel, t_id, 20, 40);
+    let dialog = |f: &mut TestFixture, win: Window, modal: bool| {
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                width: 50,
+                height: 50,
+                ..Default::default()
+            },
+            fullscreen: false,
+            minimized: false,
+        };
+        let (_, surface) = comp.create_surface();
+        let dims = data.dims;
+        f.register_window(win, data);
+        f.satellite.new_window(win, false, dims, None);
+        f.satellite.set_transient_for(win, Some(parent));
+        f.satellite.set_modal(win, modal);
+        f.satellite.map_window(win);
+        f.associate_window(&comp, win, &surface.obj);
+        f.run();
+        f.run();
+        f.testwl.last_created_surface_id().unwrap()
+    };
 
-    f.satellite.reconfigure_window(x::ConfigureNotifyEvent::new(
-        popup,
-        popup,
-        x::WINDOW_NONE,
-        40,  // x
-        60,  // y
-        80,  // width
-        100, // height
-        0,
-        true,
-    ));
+    let parent_toplevel = f
+        .testwl
+        .get_surface_data(parent_id)
+        .unwrap()
+        .toplevel()
+        .toplevel
+        .clone();
+
+    let id = dialog(&mut f, unsafe { Window::new(2) }, true);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().parent, Some(parent_toplevel.clone()));
+    assert!(data.toplevel().modal);
+
+    // Modality changing while mapped reaches the compositor too.
+    f.satellite.set_modal(unsafe { Window::new(2) }, false);
     f.run();
     f.run();
-    let data = f.testwl.get_surface_data(p_id).unwrap();
-    assert_eq!(
-        data.popup().positioner_state.offset,
-        testwl::Vec2 { x: 40, y: 60 }
-    );
-    assert_eq!(
-        data.popup().positioner_state.size,
-        Some(testwl::Vec2 { x: 80, y: 100 })
-    );
-    let win_data = &f.connection().windows[&popup];
-    assert_eq!(
-        win_data.dims,
-        WindowDims {
-            x: 40,
-            y: 60,
-            width: 80,
-            height: 100
-        }
-    );
+    assert!(!f.testwl.get_surface_data(id).unwrap().toplevel().modal);
+
+    let win = unsafe { Window::new(3) };
+    let id = dialog(&mut f, win, false);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().parent, Some(parent_toplevel));
+    assert!(!data.toplevel().modal);
+
+    f.satellite.set_modal(win, true);
+    f.run();
+    f.run();
+    assert!(f.testwl.get_surface_data(id).unwrap().toplevel().modal);
 }
 
 #[test]
-fn ignore_toplevel_reconfigure() {
+fn degenerate_geometry() {
     let (mut f, comp) = TestFixture::new_with_compositor();
     let toplevel = unsafe { Window::new(1) };
-    let _ = f.create_toplevel(&comp, toplevel);
+    let (_, toplevel_id) = f.create_toplevel(&comp, toplevel);
 
-    f.satellite.reconfigure_window(x::ConfigureNotifyEvent::new(
+    f.satellite.set_size_hints(
         toplevel,
-        toplevel,
-        x::WINDOW_NONE,
-        40,  // x
-        60,  // y
-        80,  // width
-        100, // height
-        0,
-        true,
-    ));
+        crate::xstate::WmNormalHints {
+            min_size: Some(crate::xstate::WinSize {
+                width: -5,
+                height: 10,
+            }),
+            max_size: Some(crate::xstate::WinSize {
+                width: 5,
+                height: 5,
+            }),
+            ..Default::default()
+        },
+    );
+    f.run();
+    f.run();
+
+    let data = f.testwl.get_surface_data(toplevel_id).unwrap();
+    assert_eq!(data.toplevel().min_size, Some(testwl::Vec2 { x: 0, y: 10 }));
+    assert_eq!(data.toplevel().max_size, None);
 
+    let popup = unsafe { Window::new(2) };
+    let (buffer, surface) = comp.create_surface();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            x: i16::MIN,
+            y: i16::MIN,
+            width: 0,
+            height: 0,
+        },
+        fullscreen: false,
+        minimized: false,
+    };
+    f.new_window(popup, true, data, None);
+    f.map_window(&comp, popup, &surface.obj, &buffer);
     f.run();
-    let win_data = &f.connection().windows[&toplevel];
+
+    let id = f.check_new_surface();
+    let data = f.testwl.get_surface_data(id).unwrap();
+    let positioner = &data.popup().positioner_state;
+    assert_eq!(positioner.size, Some(testwl::Vec2 { x: 1, y: 1 }));
     assert_eq!(
-        win_data.dims,
-        WindowDims {
-            x: 0,
-            y: 0,
-            width: 100,
-            height: 100
+        positioner.offset,
+        testwl::Vec2 {
+            x: i16::MIN.into(),
+            y: i16::MIN.into()
         }
     );
 }
 
-type EventMatcher<'a, Event> = Box<dyn FnMut(&Event) -> bool + 'a>;
-#[track_caller]
-fn events_check<Event: std::fmt::Debug, const N: usize>(
-    mut it: impl Iterator<Item = Event>,
-    mut matchers: [EventMatcher<'_, Event>; N],
-) {
-    for (idx, matcher) in matchers.iter_mut().enumerate() {
-        let item = it.next();
-        if item.is_none() {
-            panic!("event {idx} does not exist");
-        }
-        if !matcher(item.as_ref().unwrap()) {
-            panic!("event {idx} was wrong ({item:?})");
-        }
-    }
+#[test]
+fn window_attributes_wait_for_first_configure() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (buffer, surface) = comp.create_surface();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            width: 50,
+            height: 50,
+            ..Default::default()
+        },
+        fullscreen: false,
+        minimized: false,
+    };
+    f.new_window(win, false, data, None);
+    f.map_window(&comp, win, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
 
-    let mut remaining = it.peekable();
-    if remaining.peek().is_some() {
-        panic!("remaining events: {:?}", remaining.collect::<Vec<_>>());
-    }
+    f.satellite
+        .set_win_title(win, WmName::WmName("window".into()));
+    f.satellite
+        .set_win_class(win, wm_class("instance", "class"));
+    f.satellite.set_size_hints(
+        win,
+        crate::xstate::WmNormalHints {
+            min_size: Some(crate::xstate::WinSize {
+                width: 20,
+                height: 20,
+            }),
+            max_size: None,
+            ..Default::default()
+        },
+    );
+    f.run();
+
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().title, None);
+    assert_eq!(data.toplevel().app_id, None);
+    assert_eq!(data.toplevel().min_size, None);
+
+    f.testwl
+        .configure_toplevel(id, 100, 100, vec![xdg_toplevel::State::Activated]);
+    f.run();
+
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().title, Some("window".into()));
+    assert_eq!(data.toplevel().app_id, Some("class".into()));
+    assert_eq!(
+        data.toplevel().min_size,
+        Some(testwl::Vec2 { x: 20, y: 20 })
+    );
 }
 
 #[test]
-fn tablet_smoke_test() {
+fn copy_from_x11() {
     let (mut f, comp) = TestFixture::new_with_compositor();
-    let seat = TestObject::<ZwpTabletSeatV2>::from_request(
-        &comp.tablet_man.obj,
-        zwp_tablet_manager_v2::Request::GetTabletSeat {
-            seat: comp.seat.obj,
-        },
-    );
-    // Not sure why exactly this requires 4 runs but it works so idk
-    for _ in 0..4 {
-        f.run();
-    }
+    let win = unsafe { Window::new(1) };
+    let (_surface, _id) = f.create_toplevel(&comp, win);
 
-    let events = std::mem::take(&mut *seat.data.events.lock().unwrap()).into_iter();
-    let (mut tab_id, mut tool_id, mut pad_id) = (None, None, None);
-    events_check(
-        events,
-        [
-            Box::new(|e| match e {
-                zwp_tablet_seat_v2::Event::TabletAdded { id } => {
-                    tab_id = Some(id.clone());
-                    true
+    let mimes = std::rc::Rc::new(vec![
+        testwl::PasteData {
+            mime_type: "text".to_string(),
+            data: b"abc".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "data".to_string(),
+            data: vec![1, 2, 3, 4, 6, 10],
+        },
+    ]);
+
+    f.satellite.set_copy_paste_source(&mimes);
+    f.run();
+
+    let server_mimes = f.testwl.data_source_mimes();
+    for mime in mimes.iter() {
+        assert!(server_mimes.contains(&mime.mime_type));
+    }
+
+    let data = f.testwl.paste_data(|_, _| {
+        f.satellite.run();
+        true
+    });
+    assert_eq!(*mimes, data);
+}
+
+#[test]
+fn copy_from_wayland() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlKeyboard>::from_request(&comp.seat.obj, wl_seat::Request::GetKeyboard {});
+    let win = unsafe { Window::new(1) };
+    let (_surface, _id) = f.create_toplevel(&comp, win);
+
+    let mimes = vec![
+        testwl::PasteData {
+            mime_type: "text".to_string(),
+            data: b"abc".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "data".to_string(),
+            data: vec![1, 2, 3, 4, 6, 10],
+        },
+    ];
+    f.testwl.create_data_offer(mimes.clone());
+    f.run();
+
+    let selection = f.satellite.new_selection().expect("No new selection");
+    for mime in &mimes {
+        let data = std::thread::scope(|s| {
+            // receive requires a queue flush - dispatch testwl from another thread
+            s.spawn(|| {
+                let pollfd = unsafe { BorrowedFd::borrow_raw(f.testwl.poll_fd().as_raw_fd()) };
+                let mut pollfd = [PollFd::from_borrowed_fd(pollfd, PollFlags::IN)];
+                if poll(&mut pollfd, 100).unwrap() == 0 {
+                    panic!("Did not get events for testwl!");
                 }
-                _ => false,
-            }),
-            Box::new(|e| match e {
-                zwp_tablet_seat_v2::Event::ToolAdded { id } => {
-                    tool_id = Some(id.clone());
-                    true
+                f.testwl.dispatch();
+                while poll(&mut pollfd, 100).unwrap() > 0 {
+                    f.testwl.dispatch();
                 }
-                _ => false,
-            }),
-            Box::new(|e| match e {
-                zwp_tablet_seat_v2::Event::PadAdded { id } => {
-                    pad_id = Some(id.clone());
-                    true
+            });
+            selection
+                .receive(mime.mime_type.clone(), &f.satellite)
+                .unwrap()
+        });
+        f.run();
+        assert_eq!(data, mime.data);
+    }
+}
+
+#[test]
+fn copy_from_x11_data_control() {
+    let mut f = TestFixture::new_preset(testwl::Server::enable_data_control);
+    let comp = f.compositor();
+    let win = unsafe { Window::new(1) };
+    let (_surface, _id) = f.create_toplevel(&comp, win);
+
+    let mimes = std::rc::Rc::new(vec![testwl::PasteData {
+        mime_type: "text".to_string(),
+        data: b"abc".to_vec(),
+    }]);
+
+    // No keyboard focus is needed with data control.
+    f.satellite.set_copy_paste_source(&mimes);
+    f.run();
+    assert_eq!(f.testwl.data_source_mimes(), ["text"]);
+
+    let data = f.testwl.paste_data(|_, _| {
+        f.satellite.run();
+        true
+    });
+    assert_eq!(*mimes, data);
+}
+
+#[test]
+fn copy_from_wayland_data_control() {
+    let mut f = TestFixture::new_preset(testwl::Server::enable_data_control);
+    let comp = f.compositor();
+    let win = unsafe { Window::new(1) };
+    let (_surface, _id) = f.create_toplevel(&comp, win);
+
+    let mimes = vec![
+        testwl::PasteData {
+            mime_type: "text".to_string(),
+            data: b"abc".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "data".to_string(),
+            data: vec![1, 2, 3, 4, 6, 10],
+        },
+    ];
+    f.testwl.create_data_offer(mimes.clone());
+    f.run();
+
+    let selection = f.satellite.new_selection().expect("No new selection");
+    assert_eq!(*selection.mime_types, ["text", "data"]);
+    for mime in &mimes {
+        let data = std::thread::scope(|s| {
+            // receive requires a queue flush - dispatch testwl from another thread
+            s.spawn(|| {
+                let pollfd = unsafe { BorrowedFd::borrow_raw(f.testwl.poll_fd().as_raw_fd()) };
+                let mut pollfd = [PollFd::from_borrowed_fd(pollfd, PollFlags::IN)];
+                if poll(&mut pollfd, 100).unwrap() == 0 {
+                    panic!("Did not get events for testwl!");
                 }
-                _ => false,
-            }),
+                f.testwl.dispatch();
+            });
+            selection
+                .receive(mime.mime_type.clone(), &f.satellite)
+                .unwrap()
+        });
+        f.run();
+        assert_eq!(data, mime.data);
+    }
+}
+
+#[test]
+fn clipboard_x11_then_wayland() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlKeyboard>::from_request(&comp.seat.obj, wl_seat::Request::GetKeyboard {});
+    let win = unsafe { Window::new(1) };
+    let (_surface, _id) = f.create_toplevel(&comp, win);
+
+    let x11data = std::rc::Rc::new(vec![
+        testwl::PasteData {
+            mime_type: "text".to_string(),
+            data: b"abc".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "data".to_string(),
+            data: vec![1, 2, 3, 4, 6, 10],
+        },
+    ]);
+
+    f.satellite.set_copy_paste_source(&x11data);
+    f.run();
+
+    let waylanddata = vec![
+        testwl::PasteData {
+            mime_type: "asdf".to_string(),
+            data: b"fdaa".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "boing".to_string(),
+            data: vec![10, 20, 40, 50],
+        },
+    ];
+    f.testwl.create_data_offer(waylanddata.clone());
+    f.run();
+    f.run();
+
+    let selection = f.satellite.new_selection().expect("No new selection");
+    for mime in &waylanddata {
+        let data = std::thread::scope(|s| {
+            // receive requires a queue flush - dispatch testwl from another thread
+            s.spawn(|| {
+                let pollfd = unsafe { BorrowedFd::borrow_raw(f.testwl.poll_fd().as_raw_fd()) };
+                let mut pollfd = [PollFd::from_borrowed_fd(pollfd, PollFlags::IN)];
+                if poll(&mut pollfd, 100).unwrap() == 0 {
+                    panic!("Did not get events for testwl!");
+                }
+                f.testwl.dispatch();
+                while poll(&mut pollfd, 100).unwrap() > 0 {
+                    f.testwl.dispatch();
+                }
+            });
+            selection
+                .receive(mime.mime_type.clone(), &f.satellite)
+                .unwrap()
+        });
+        f.run();
+        assert_eq!(data, mime.data);
+    }
+}
+
+#[test]
+fn raise_window_on_pointer_event() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win1 = unsafe { Window::new(1) };
+    let (_, id1) = f.create_toplevel(&comp, win1);
+    f.testwl.configure_toplevel(id1, 100, 100, vec![]);
+
+    let win2 = unsafe { Window::new(2) };
+    let (_, id2) = f.create_toplevel(&comp, win2);
+    assert_eq!(f.connection().focused_window, Some(win2));
+
+    f.testwl.move_pointer_to(id2, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().focused_window, Some(win2));
+    assert_eq!(f.satellite.last_hovered, Some(win2));
+
+    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().focused_window, Some(win2));
+    assert_eq!(f.satellite.last_hovered, Some(win1));
+}
+
+#[test]
+fn app_id_from_instance() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.app_id_source = crate::config::AppIdSource::Instance;
+    let win = unsafe { Window::new(1) };
+    let (_, id) = f.create_toplevel(&comp, win);
+
+    f.satellite
+        .set_win_class(win, wm_class("Navigator", "firefox"));
+    f.run();
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert_eq!(data.toplevel().app_id, Some("Navigator".into()));
+}
+
+#[test]
+fn raise_policy() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win1 = unsafe { Window::new(1) };
+    let (_, id1) = f.create_toplevel(&comp, win1);
+    f.testwl.configure_toplevel(id1, 100, 100, vec![]);
+    let win2 = unsafe { Window::new(2) };
+    let (_, id2) = f.create_toplevel(&comp, win2);
+
+    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().stack.last(), Some(&win1));
+
+    f.satellite.config.raise_policy = crate::config::RaisePolicy::Focus;
+    f.testwl.move_pointer_to(id2, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().stack.last(), Some(&win1));
+    f.testwl.focus_toplevel(id2);
+    f.run();
+    assert_eq!(f.connection().stack.last(), Some(&win2));
+
+    f.satellite.config.raise_policy = crate::config::RaisePolicy::Never;
+    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.testwl.focus_toplevel(id1);
+    f.run();
+    assert_eq!(f.connection().focused_window, Some(win1));
+    assert_eq!(f.connection().stack.last(), Some(&win2));
+}
+
+#[test]
+fn raise_keeps_transients_above_parent() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let parent = unsafe { Window::new(1) };
+    let (_, parent_id) = f.create_toplevel(&comp, parent);
+    let dialog = unsafe { Window::new(2) };
+    f.create_toplevel(&comp, dialog);
+    f.satellite.set_transient_for(dialog, Some(parent));
+    let other = unsafe { Window::new(3) };
+    let (_, other_id) = f.create_toplevel(&comp, other);
+    assert_eq!(f.connection().stack, [parent, dialog, other]);
+
+    f.testwl.move_pointer_to(parent_id, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().stack, [other, parent, dialog]);
+
+    f.testwl.move_pointer_to(other_id, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.connection().stack, [parent, dialog, other]);
+}
+
+#[test]
+fn restack_requests() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let [a, b, c] = [1, 2, 3].map(|id| unsafe { Window::new(id) });
+    for window in [a, b, c] {
+        f.create_toplevel(&comp, window);
+    }
+    let dialog = unsafe { Window::new(4) };
+    f.create_toplevel(&comp, dialog);
+    f.satellite.set_transient_for(dialog, Some(c));
+    assert_eq!(f.connection().stack, [a, b, c, dialog]);
+
+    f.satellite.restack_window(c, Some(a), x::StackMode::Below);
+    f.run();
+    assert_eq!(f.connection().stack, [c, dialog, a, b]);
+
+    f.satellite.restack_window(a, Some(b), x::StackMode::Above);
+    f.run();
+    assert_eq!(f.connection().stack, [c, dialog, b, a]);
+
+    f.satellite.restack_window(a, None, x::StackMode::Below);
+    f.run();
+    assert_eq!(f.connection().stack, [a, c, dialog, b]);
+
+    // A window can't be stacked relative to its own transient.
+    f.satellite
+        .restack_window(c, Some(dialog), x::StackMode::Above);
+    f.satellite.restack_window(b, None, x::StackMode::TopIf);
+    f.run();
+    assert_eq!(f.connection().stack, [a, c, dialog, b]);
+}
+
+#[test]
+fn stacking_follows_x_stack() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            width: 50,
+            height: 50,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let a = unsafe { Window::new(1) };
+    f.new_window(a, false, data, None);
+    let b = unsafe { Window::new(2) };
+    f.create_toplevel(&comp, b);
+    let menu = unsafe { Window::new(3) };
+    let data = WindowData {
+        mapped: true,
+        ..Default::default()
+    };
+    f.new_window(menu, true, data, None);
+
+    // Mapping doesn't raise, whether the window is managed or override-redirect.
+    let (buffer, surface) = comp.create_surface();
+    f.map_window(&comp, a, &surface.obj, &buffer);
+    f.satellite.map_window(menu);
+    f.run();
+    assert_eq!(f.connection().stack, [a, b, menu]);
+
+    // Override-redirect windows stay where their client put them.
+    f.satellite.restack_window(a, Some(b), x::StackMode::Above);
+    f.run();
+    assert_eq!(f.connection().stack, [b, a, menu]);
+
+    // The stack is restored after something else restacks.
+    f.satellite.connection.as_mut().unwrap().stack = vec![a, b, menu];
+    f.satellite.reconfigure_window(x::ConfigureNotifyEvent::new(
+        a,
+        a,
+        x::WINDOW_NONE,
+        0,
+        0,
+        50,
+        50,
+        0,
+        false,
+    ));
+    f.run();
+    assert_eq!(f.connection().stack, [b, a, menu]);
+}
+
+#[test]
+fn pager_restack_reactivates_focused_window() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let below = unsafe { Window::new(1) };
+    let (_, below_id) = f.create_toplevel(&comp, below);
+    let focused = unsafe { Window::new(2) };
+    let (_, focused_id) = f.create_toplevel(&comp, focused);
+    assert_eq!(f.connection().focused_window, Some(focused));
+
+    f.satellite
+        .pager_restack_window(below, None, x::StackMode::Above);
+    f.run();
+    f.run();
+    assert_eq!(f.connection().stack, [focused, below]);
+    let data = f.testwl.get_surface_data(below_id).unwrap();
+    assert!(data.toplevel().activation_serial.is_none());
+
+    f.satellite
+        .pager_restack_window(focused, None, x::StackMode::Above);
+    f.run();
+    f.run();
+    assert_eq!(f.connection().stack, [below, focused]);
+    let data = f.testwl.get_surface_data(focused_id).unwrap();
+    assert!(data.toplevel().activation_serial.is_some());
+}
+
+#[test]
+fn move_resize() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win = unsafe { Window::new(1) };
+    let (_, id) = f.create_toplevel(&comp, win);
+    let grab = |f: &TestFixture| f.testwl.get_surface_data(id).unwrap().toplevel().grab;
+
+    // Nothing was clicked yet.
+    f.satellite.start_move_resize(win, MoveResize::Move, 1);
+    f.run();
+    assert_eq!(grab(&f), None);
+
+    f.testwl.move_pointer_to(id, 10.0, 10.0);
+    f.testwl.press_button(30, 0x110);
+    f.run();
+    f.satellite.start_move_resize(win, MoveResize::Move, 1);
+    f.run();
+    assert_eq!(grab(&f), Some(testwl::ToplevelGrab::Move { serial: 30 }));
+
+    f.satellite
+        .start_move_resize(win, MoveResize::SizeKeyboard, 0);
+    f.run();
+    let Some(testwl::ToplevelGrab::Resize { serial, edges }) = grab(&f) else {
+        panic!("no resize started: {:?}", grab(&f));
+    };
+    assert_eq!(Some(serial), f.satellite.last_kb_serial);
+    assert_eq!(edges, xdg_toplevel::ResizeEdge::BottomRight);
+
+    // Moves can be started from other buttons too, with the serial of that button's press.
+    f.testwl.press_button(40, 0x111);
+    f.run();
+    f.satellite.start_move_resize(win, MoveResize::Move, 1);
+    f.run();
+    assert_eq!(grab(&f), Some(testwl::ToplevelGrab::Move { serial: 30 }));
+    f.satellite.start_move_resize(win, MoveResize::Move, 3);
+    f.run();
+    assert_eq!(grab(&f), Some(testwl::ToplevelGrab::Move { serial: 40 }));
+    f.satellite.start_move_resize(win, MoveResize::Move, 2);
+    f.run();
+    assert_eq!(grab(&f), Some(testwl::ToplevelGrab::Move { serial: 40 }));
+
+    // Without a button, the latest press counts, even after the serial wrapped around.
+    f.testwl.press_button(5, 0x110);
+    f.run();
+    f.satellite.start_move_resize(win, MoveResize::Move, 0);
+    f.run();
+    assert_eq!(grab(&f), Some(testwl::ToplevelGrab::Move { serial: 5 }));
+}
+
+#[test]
+fn cancel_move_resize() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win = unsafe { Window::new(1) };
+    let (_, id) = f.create_toplevel(&comp, win);
+    f.testwl.move_pointer_to(id, 10.0, 10.0);
+    f.testwl.press_button(30, 0x110);
+    f.run();
+
+    // The client changed its mind before the compositor did anything.
+    f.satellite
+        .start_move_resize(win, MoveResize::Size(super::ResizeEdge::Right), 1);
+    f.run();
+    let original = f.connection().windows[&win].dims;
+    let stale = WindowDims {
+        width: 300,
+        ..original
+    };
+    f.satellite.windows.get_mut(&win).unwrap().attrs.dims = stale;
+    f.satellite.cancel_move_resize(win);
+    assert!(f.satellite.move_resize.is_none());
+    assert_eq!(f.satellite.windows[&win].attrs.dims, original);
+    assert_eq!(f.connection().windows[&win].dims, original);
+
+    // The compositor already resized the window, so that sticks.
+    f.satellite
+        .start_move_resize(win, MoveResize::Size(super::ResizeEdge::Right), 1);
+    f.run();
+    f.testwl.configure_toplevel(id, 150, 100, vec![]);
+    f.run();
+    assert!(f.satellite.move_resize.is_none());
+    f.satellite.cancel_move_resize(win);
+    assert_eq!(f.connection().windows[&win].dims.width, 150);
+
+    // Getting the release means the compositor never grabbed the pointer.
+    f.satellite
+        .start_move_resize(win, MoveResize::Size(super::ResizeEdge::Right), 1);
+    f.run();
+    let original = f.connection().windows[&win].dims;
+    f.satellite.windows.get_mut(&win).unwrap().attrs.dims = stale;
+    f.testwl.release_button(31, 0x110);
+    f.run();
+    assert!(f.satellite.move_resize.is_none());
+    assert_eq!(f.connection().windows[&win].dims, original);
+}
+
+#[test]
+fn pointer_constraint_status() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let pointer =
+        TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, win);
+
+    let lock = |f: &mut TestFixture| {
+        let locked = TestObject::<ZwpLockedPointerV1>::from_request(
+            &comp.pointer_constraints.obj,
+            Req::<ZwpPointerConstraintsV1>::LockPointer {
+                surface: surface.obj.clone(),
+                pointer: pointer.obj.clone(),
+                region: None,
+                lifetime: WEnum::Value(zwp_pointer_constraints_v1::Lifetime::Persistent),
+            },
+        );
+        f.run();
+        locked
+    };
+
+    let locked = lock(&mut f);
+    assert!(f.connection().pointer_constraints.is_empty());
+    f.testwl.set_pointer_constraint_active(id, true);
+    f.run();
+    assert_eq!(
+        f.connection().pointer_constraints.get(&win),
+        Some(&super::PointerConstraint::Locked)
+    );
+    f.testwl.set_pointer_constraint_active(id, false);
+    f.run();
+    assert!(f.connection().pointer_constraints.is_empty());
+
+    locked.obj.destroy();
+    f.run();
+    assert!(f.satellite.pointer_constraints.is_empty());
+
+    // Constraints of a destroyed window don't stick around until Xwayland destroys them.
+    let _locked = lock(&mut f);
+    assert_eq!(f.satellite.pointer_constraints.len(), 1);
+    f.satellite.destroy_window(win);
+    assert!(f.satellite.pointer_constraints.is_empty());
+}
+
+#[test]
+fn override_redirect_choose_hover_window() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win1 = unsafe { Window::new(1) };
+    let (_, id1) = f.create_toplevel(&comp, win1);
+    f.testwl.configure_toplevel(id1, 100, 100, vec![]);
+
+    let win2 = unsafe { Window::new(2) };
+    let _ = f.create_toplevel(&comp, win2);
+    assert_eq!(f.connection().focused_window, Some(win2));
+
+    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.satellite.last_hovered, Some(win1));
+
+    let win3 = unsafe { Window::new(3) };
+    let (buffer, surface) = comp.create_surface();
+    f.new_window(win3, true, WindowData::default(), None);
+    f.map_window(&comp, win3, &surface.obj, &buffer);
+    f.run();
+    let id3 = f.check_new_surface();
+    let popup_data = f.testwl.get_surface_data(id3).unwrap();
+    let win1_xdg = &f.testwl.get_surface_data(id1).unwrap().xdg().surface;
+    assert_eq!(&popup_data.popup().parent, win1_xdg);
+}
+
+#[test]
+fn override_redirect_prefers_transient_for() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let win1 = unsafe { Window::new(1) };
+    let (_, id1) = f.create_toplevel(&comp, win1);
+    let win2 = unsafe { Window::new(2) };
+    let (_, id2) = f.create_toplevel(&comp, win2);
+    f.testwl.move_pointer_to(id1, 0.0, 0.0);
+    f.run();
+    assert_eq!(f.satellite.last_hovered, Some(win1));
+
+    // A menu of the window in the background.
+    let win3 = unsafe { Window::new(3) };
+    let (buffer, surface) = comp.create_surface();
+    f.new_window(win3, true, WindowData::default(), None);
+    f.satellite.set_transient_for(win3, Some(win2));
+    f.map_window(&comp, win3, &surface.obj, &buffer);
+    f.run();
+    let id3 = f.check_new_surface();
+    let popup_data = f.testwl.get_surface_data(id3).unwrap();
+    let win2_xdg = &f.testwl.get_surface_data(id2).unwrap().xdg().surface;
+    assert_eq!(&popup_data.popup().parent, win2_xdg);
+}
+
+#[test]
+fn popup_done_dismisses_whole_menu() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let toplevel = unsafe { Window::new(1) };
+    let (_, t_id) = f.create_toplevel(&comp, toplevel);
+    assert_eq!(f.connection().focused_window, Some(toplevel));
+
+    let menu = unsafe { Window::new(2) };
+    let (_, menu_id) = f.create_popup(&comp, menu, toplevel, t_id, 10, 10);
+    f.satellite.last_hovered = Some(menu);
+    let submenu = unsafe { Window::new(3) };
+    let (_, submenu_id) = f.create_popup(&comp, submenu, menu, menu_id, 60, 20);
+
+    f.satellite.connection.as_mut().unwrap().focused_window = None;
+    f.testwl.popup_done(menu_id);
+    f.run();
+    assert_eq!(f.connection().focused_window, Some(toplevel));
+
+    // The rest of the menu being dismissed too shouldn't do anything.
+    f.satellite.connection.as_mut().unwrap().focused_window = None;
+    f.testwl.popup_done(submenu_id);
+    f.run();
+    assert_eq!(f.connection().focused_window, None);
+
+    // The X client still has its menu open, so the popups stay until it closes it.
+    for id in [menu_id, submenu_id] {
+        let data = f.testwl.get_surface_data(id).unwrap();
+        assert!(data.popup().popup.is_alive());
+    }
+    // Submenus go first, even if the menu is unmapped before them.
+    f.satellite.unmap_window(menu);
+    f.run();
+    for id in [menu_id, submenu_id] {
+        let data = f.testwl.get_surface_data(id).unwrap();
+        assert!(!data.popup().popup.is_alive());
+    }
+    f.satellite.unmap_window(submenu);
+    f.run();
+}
+
+#[test]
+fn output_offset() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let (output_obj, output) = f.new_output(0, 0);
+    let man = f.enable_xdg_output();
+    f.create_xdg_output(&man, output_obj.obj);
+    f.testwl.move_xdg_output(&output, 500, 100);
+    f.run();
+    let window = unsafe { Window::new(1) };
+
+    {
+        let (surface, surface_id) = f.create_toplevel(&comp, window);
+        f.testwl.move_surface_to_output(surface_id, &output);
+        f.run();
+        let data = &f.connection().windows[&window];
+        assert_eq!(data.dims.x, 500);
+        assert_eq!(data.dims.y, 100);
+
+        f.satellite.unmap_window(window);
+        surface.obj.destroy();
+        f.run();
+    }
+
+    let (t_buffer, t_surface) = comp.create_surface();
+    f.map_window(&comp, window, &t_surface.obj, &t_buffer);
+    f.run();
+    let t_id = f.testwl.last_created_surface_id().unwrap();
+    f.testwl.move_surface_to_output(t_id, &output);
+    f.run();
+    {
+        let data = f.testwl.get_surface_data(t_id).unwrap();
+        assert!(
+            matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_))),
+            "surface role: {:?}",
+            data.role
+        );
+    }
+    f.testwl.configure_toplevel(t_id, 100, 100, vec![]);
+    f.testwl.focus_toplevel(t_id);
+    f.run();
+
+    {
+        let data = &f.connection().windows[&window];
+        assert_eq!(data.dims.x, 500);
+        assert_eq!(data.dims.y, 100);
+    }
+
+    let popup = unsafe { Window::new(2) };
+    let (p_surface, p_id) = f.create_popup(&comp, popup, window, t_id, 510, 110);
+    f.testwl.move_surface_to_output(p_id, &output);
+    f.run();
+    let data = f.testwl.get_surface_data(p_id).unwrap();
+    assert_eq!(
+        data.popup().positioner_state.offset,
+        testwl::Vec2 { x: 10, y: 10 }
+    );
+
+    f.satellite.unmap_window(popup);
+    p_surface.obj.destroy();
+    f.run();
+
+    let (buffer, surface) = comp.create_surface();
+    f.map_window(&comp, popup, &surface.obj, &buffer);
+    f.run();
+    let p_id = f.testwl.last_created_surface_id().unwrap();
+    f.testwl.move_surface_to_output(p_id, &output);
+    f.testwl.configure_popup(p_id);
+    f.run();
+    let data = f.testwl.get_surface_data(p_id).unwrap();
+    assert_eq!(
+        data.popup().positioner_state.offset,
+        testwl::Vec2 { x: 10, y: 10 }
+    );
+}
+
+#[test]
+fn output_offset_change() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+
+    let (output_obj, output) = f.new_output(500, 100);
+    let window = unsafe { Window::new(1) };
+    let (_, id) = f.create_toplevel(&comp, window);
+    f.testwl.move_surface_to_output(id, &output);
+    f.run();
+
+    let test_position = |f: &TestFixture, x, y| {
+        let data = &f.connection().windows[&window];
+        assert_eq!(data.dims.x, x);
+        assert_eq!(data.dims.y, y);
+    };
+    test_position(&f, 500, 100);
+
+    f.testwl.move_output(&output, 600, 200);
+    f.run();
+    f.run();
+    test_position(&f, 600, 200);
+
+    let man = f.enable_xdg_output();
+    f.create_xdg_output(&man, output_obj.obj);
+    // testwl inits xdg output position to 0, and it should take priority over wl_output position
+    test_position(&f, 0, 0);
+
+    f.testwl.move_xdg_output(&output, 1000, 22);
+    f.run();
+    test_position(&f, 1000, 22);
+
+    f.testwl.move_output(&output, 600, 200);
+    f.run();
+    f.run();
+    test_position(&f, 1000, 22);
+}
+
+#[test]
+fn reposition_popup() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let toplevel = unsafe { Window::new(1) };
+    let (_, t_id) = f.create_toplevel(&comp, toplevel);
+
+    let popup = unsafe { Window::new(2) };
+    let (_, p_id) = f.create_popup(&comp, popup, toplevel, t_id, 20, 40);
+
+    f.satellite.reconfigure_window(x::ConfigureNotifyEvent::new(
+        popup,
+        popup,
+        x::WINDOW_NONE,
+        40,  // x
+        60,  // y
+        80,  // width
+        100, // height
+        0,
+        true,
+    ));
+    f.run();
+    f.run();
+    let data = f.testwl.get_surface_data(p_id).unwrap();
+    assert_eq!(
+        data.popup().positioner_state.offset,
+        testwl::Vec2 { x: 40, y: 60 }
+    );
+    assert_eq!(
+        data.popup().positioner_state.size,
+        Some(testwl::Vec2 { x: 80, y: 100 })
+    );
+    let win_data = &f.connection().windows[&popup];
+    assert_eq!(
+        win_data.dims,
+        WindowDims {
+            x: 40,
+            y: 60,
+            width: 80,
+            height: 100
+        }
+    );
+}
+
+#[test]
+fn ignore_toplevel_reconfigure() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+
+    f.satellite.reconfigure_window(x::ConfigureNotifyEvent::new(
+        toplevel,
+        toplevel,
+        x::WINDOW_NONE,
+        40,  // x
+        60,  // y
+        80,  // width
+        100, // height
+        0,
+        true,
+    ));
+
+    f.run();
+    let win_data = &f.connection().windows[&toplevel];
+    assert_eq!(
+        win_data.dims,
+        WindowDims {
+            x: 0,
+            y: 0,
+            width: 100,
+            height: 100
+        }
+    );
+}
+
+type EventMatcher<'a, Event> = Box<dyn FnMut(&Event) -> bool + 'a>;
+#[track_caller]
+fn events_check<Event: std::fmt::Debug, const N: usize>(
+    mut it: impl Iterator<Item = Event>,
+    mut matchers: [EventMatcher<'_, Event>; N],
+) {
+    for (idx, matcher) in matchers.iter_mut().enumerate() {
+        let item = it.next();
+        if item.is_none() {
+            panic!("event {idx} does not exist");
+        }
+        if !matcher(item.as_ref().unwrap()) {
+            panic!("event {idx} was wrong ({item:?})");
+        }
+    }
+
+    let mut remaining = it.peekable();
+    if remaining.peek().is_some() {
+        panic!("remaining events: {:?}", remaining.collect::<Vec<_>>());
+    }
+}
+
+#[test]
+fn shm_formats_are_filtered() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    for _ in 0..3 {
+        f.run();
+    }
+
+    let formats: Vec<_> = std::mem::take(&mut *comp.shm.data.events.lock().unwrap())
+        .into_iter()
+        .map(|event| match event {
+            Ev::<WlShm>::Format { format } => format,
+            other => panic!("unexpected shm event: {other:?}"),
+        })
+        .collect();
+    assert_eq!(
+        formats,
+        [Format::Argb8888, Format::Xrgb8888, Format::Xrgb2101010].map(WEnum::Value)
+    );
+}
+
+#[test]
+fn surface_invariants() {
+    use super::invariants::{check_surface, SurfaceGeometry};
+    let dims = WindowDims {
+        x: 0,
+        y: 0,
+        width: 100,
+        height: 100,
+    };
+    let geometry = SurfaceGeometry {
+        buffer_size: Some((100, 100)),
+        ..Default::default()
+    };
+    assert!(check_surface(&geometry, Some(&dims)).is_empty());
+
+    let scaled = SurfaceGeometry {
+        buffer_size: Some((100, 100)),
+        scale: 3,
+        viewport_destination: Some((50, 50)),
+    };
+    let violations = check_surface(&scaled, Some(&dims));
+    assert_eq!(violations.len(), 1, "{violations:?}");
+    assert!(violations[0].contains("divisible"));
+
+    let stale = check_surface(&geometry, Some(&WindowDims { width: 50, ..dims }));
+    assert_eq!(stale.len(), 1, "{stale:?}");
+    assert!(stale[0].contains("window size"));
+}
+
+#[test]
+fn tablet_smoke_test() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let seat = TestObject::<ZwpTabletSeatV2>::from_request(
+        &comp.tablet_man.obj,
+        zwp_tablet_manager_v2::Request::GetTabletSeat {
+            seat: comp.seat.obj,
+        },
+    );
+    // Not sure why exactly this requires 4 runs but it works so idk
+    for _ in 0..4 {
+        f.run();
+    }
+
+    let events = std::mem::take(&mut *seat.data.events.lock().unwrap()).into_iter();
+    let (mut tab_id, mut tool_id, mut pad_id) = (None, None, None);
+    events_check(
+        events,
+        [
+            Box::new(|e| match e {
+                zwp_tablet_seat_v2::Event::TabletAdded { id } => {
+                    tab_id = Some(id.clone());
+                    true
+                }
+                _ => false,
+            }),
+            Box::new(|e| match e {
+                zwp_tablet_seat_v2::Event::ToolAdded { id } => {
+                    tool_id = Some(id.clone());
+                    true
+                }
+                _ => false,
+            }),
+            Box::new(|e| match e {
+                zwp_tablet_seat_v2::Event::PadAdded { id } => {
+                    pad_id = Some(id.clone());
+                    true
+                }
+                _ => false,
+            }),
+        ],
+    );
+    let (tab_id, tool_id, pad_id) = (tab_id.unwrap(), tool_id.unwrap(), pad_id.unwrap());
+
+    // For reasons beyond my mortal understanding, `id.object_data()` does not work properly.
+    let tab_data = f.object_data(&tab_id);
+    let tab_events = std::mem::take(&mut *tab_data.events.lock().unwrap()).into_iter();
+    events_check(
+        tab_events,
+        [
+            Box::new(|e| matches!(e, zwp_tablet_v2::Event::Name { name } if name == "tabby")),
+            Box::new(|e| matches!(e, zwp_tablet_v2::Event::Done)),
+        ],
+    );
+
+    let tool_data = f.object_data(&tool_id);
+    let tool_events = std::mem::take(&mut *tool_data.events.lock().unwrap()).into_iter();
+    events_check(
+        tool_events,
+        [
+            Box::new(|e| {
+                matches!(
+                    e,
+                    zwp_tablet_tool_v2::Event::Type {
+                        tool_type: WEnum::Value(zwp_tablet_tool_v2::Type::Finger)
+                    }
+                )
+            }),
+            Box::new(|e| matches!(e, zwp_tablet_tool_v2::Event::Done)),
+        ],
+    );
+
+    let pad_data = f.object_data(&pad_id);
+    let pad_events = std::mem::take(&mut *pad_data.events.lock().unwrap()).into_iter();
+    let mut group = None;
+    events_check(
+        pad_events,
+        [
+            Box::new(|e| matches!(e, zwp_tablet_pad_v2::Event::Buttons { buttons: 5 })),
+            Box::new(|e| match e {
+                zwp_tablet_pad_v2::Event::Group { pad_group } => {
+                    group = Some(pad_group.clone());
+                    true
+                }
+                _ => false,
+            }),
+            Box::new(|e| matches!(e, zwp_tablet_pad_v2::Event::Done)),
+        ],
+    );
+
+    let group = group.unwrap();
+    let g_data = f.object_data(&group);
+    let g_events = std::mem::take(&mut *g_data.events.lock().unwrap()).into_iter();
+    events_check(
+        g_events,
+        [
+            Box::new(|e| {
+                matches!(e,
+                zwp_tablet_pad_group_v2::Event::Buttons { buttons } if buttons.is_empty())
+            }),
+            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Ring { .. })),
+            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Strip { .. })),
+            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Done)),
+        ],
+    )
+}
+/// See Pointer::handle_event for an explanation.
+#[test]
+fn popup_pointer_motion_workaround() {}
+
+#[test]
+fn opaque_region_without_alpha() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, win);
+    let opaque_region =
+        |f: &TestFixture| f.testwl.get_surface_data(id).unwrap().opaque_region.clone();
+    assert_eq!(opaque_region(&f), None);
+
+    f.satellite.set_opaque(win, true);
+    surface.send_request(Req::<WlSurface>::Commit).unwrap();
+    f.run();
+    assert_eq!(opaque_region(&f), Some(vec![[0, 0, 10, 10]]));
+
+    // Windows with an alpha channel have nothing to mark.
+    f.satellite.set_opaque(win, false);
+    surface.send_request(Req::<WlSurface>::Commit).unwrap();
+    f.run();
+    assert_eq!(opaque_region(&f), None);
+}
+
+#[test]
+fn popup_anchored_to_pointer() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.popup_anchor = crate::config::PopupAnchor::Pointer;
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let toplevel = unsafe { Window::new(1) };
+    let (_, t_id) = f.create_toplevel(&comp, toplevel);
+    f.testwl.move_pointer_to(t_id, 30.0, 40.0);
+    f.run();
+
+    let menu = unsafe { Window::new(2) };
+    let (buffer, surface) = comp.create_surface();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            x: 32,
+            y: 45,
+            width: 50,
+            height: 50,
+        },
+        ..Default::default()
+    };
+    f.new_window(menu, true, data, None);
+    f.map_window(&comp, menu, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
+
+    let positioner = &f
+        .testwl
+        .get_surface_data(id)
+        .unwrap()
+        .popup()
+        .positioner_state;
+    assert_eq!(
+        positioner.anchor_rect,
+        Some(testwl::Rect {
+            size: testwl::Vec2 { x: 1, y: 1 },
+            offset: testwl::Vec2 { x: 30, y: 40 },
+        })
+    );
+    assert_eq!(positioner.offset, testwl::Vec2 { x: 2, y: 5 });
+}
+
+#[test]
+fn menu_popups_grab() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    TestObject::<WlPointer>::from_request(&comp.seat.obj, wl_seat::Request::GetPointer {});
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    // The serial wrapped around between the clicks, the latest one is still the one to use.
+    f.testwl.press_button(u32::MAX - 1, 0x111);
+    f.testwl.press_button(50, 0x110);
+    f.run();
+
+    let map_popup = |f: &mut TestFixture, window: Window, window_type: WindowType| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 10,
+                y: 10,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, true, data, None);
+        f.satellite.set_window_type(window, window_type);
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        f.satellite.last_hovered = Some(window);
+        let id = f.check_new_surface();
+        let grab = f.testwl.get_surface_data(id).unwrap().popup().grab_serial;
+        (surface, grab)
+    };
+
+    // Tooltips and the like don't take a grab.
+    let tooltip = unsafe { Window::new(2) };
+    f.satellite.last_hovered = Some(toplevel);
+    let (_tooltip, grab) = map_popup(&mut f, tooltip, WindowType::Normal);
+    assert_eq!(grab, None);
+
+    let menu = unsafe { Window::new(3) };
+    f.satellite.last_hovered = Some(toplevel);
+    let (_menu, grab) = map_popup(&mut f, menu, WindowType::Menu);
+    assert_eq!(grab, Some(50));
+
+    // Submenus share the grab of their menu, even without a window type, if X has the pointer
+    // grabbed.
+    f.satellite.connection.as_mut().unwrap().pointer_grabbed = true;
+    let submenu = unsafe { Window::new(4) };
+    let (_submenu, grab) = map_popup(&mut f, submenu, WindowType::Normal);
+    assert_eq!(grab, Some(50));
+}
+
+#[test]
+fn popup_constraints_by_window_type() {
+    use wayland_protocols::xdg::shell::client::xdg_positioner::ConstraintAdjustment as Adjustment;
+    use wayland_protocols::xdg::shell::server::xdg_positioner::ConstraintAdjustment;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.popup_constraints.insert(
+        WindowType::Menu,
+        crate::config::parse_constraint_adjustment("flip-x+slide-y").unwrap(),
+    );
+    assert_eq!(
+        f.satellite.config.popup_constraints[&WindowType::Menu],
+        Adjustment::FlipX | Adjustment::SlideY
+    );
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map_popup = |f: &mut TestFixture, window: Window, window_type: WindowType| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 20,
+                y: 30,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, true, data, None);
+        f.satellite.set_window_type(window, window_type);
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        let id = f.check_new_surface();
+        let positioner = f
+            .testwl
+            .get_surface_data(id)
+            .unwrap()
+            .popup()
+            .positioner_state
+            .clone();
+        (surface, positioner)
+    };
+
+    let (_popup, positioner) = map_popup(&mut f, unsafe { Window::new(2) }, WindowType::Normal);
+    assert_eq!(
+        positioner.constraint_adjustment,
+        ConstraintAdjustment::SlideX | ConstraintAdjustment::SlideY
+    );
+    assert_eq!(positioner.offset, testwl::Vec2 { x: 20, y: 30 });
+
+    let (_combo, positioner) = map_popup(&mut f, unsafe { Window::new(3) }, WindowType::Combo);
+    assert_eq!(
+        positioner.constraint_adjustment,
+        ConstraintAdjustment::SlideX | ConstraintAdjustment::ResizeY
+    );
+
+    // Flipped popups are anchored to where X put them, so they flip around that point instead of
+    // around the whole parent.
+    let (_tooltip, positioner) = map_popup(&mut f, unsafe { Window::new(4) }, WindowType::Tooltip);
+    assert_eq!(
+        positioner.constraint_adjustment,
+        ConstraintAdjustment::SlideX | ConstraintAdjustment::FlipY
+    );
+    assert_eq!(
+        positioner.anchor_rect,
+        Some(testwl::Rect {
+            size: testwl::Vec2 { x: 1, y: 1 },
+            offset: testwl::Vec2 { x: 20, y: 30 },
+        })
+    );
+    assert_eq!(positioner.offset, testwl::Vec2 { x: 0, y: 0 });
+
+    let (_menu, positioner) = map_popup(&mut f, unsafe { Window::new(5) }, WindowType::Menu);
+    assert_eq!(
+        positioner.constraint_adjustment,
+        ConstraintAdjustment::FlipX | ConstraintAdjustment::SlideY
+    );
+}
+
+#[test]
+fn state_dump_reports_frame_pacing() {
+    use wayland_client::protocol::{wl_callback::WlCallback, wl_surface};
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let window = unsafe { Window::new(1) };
+    let (surface, _) = f.create_toplevel(&comp, window);
+    f.satellite
+        .set_win_title(window, WmName::WmName("game".into()));
+
+    for _ in 0..3 {
+        let _callback =
+            TestObject::<WlCallback>::from_request(&surface.obj, wl_surface::Request::Frame {});
+        surface.obj.commit();
+        f.run();
+        f.run();
+    }
+
+    let dump = f.satellite.state_dump();
+    assert!(dump.starts_with("1 mapped windows"), "{dump}");
+    assert!(
+        dump.contains("\"game\" (toplevel, 100x100 at 0,0)"),
+        "{dump}"
+    );
+    assert!(
+        dump.contains("frame intervals: p50") && dump.contains("(n=2)"),
+        "{dump}"
+    );
+}
+
+#[test]
+fn popup_anchor_rules() {
+    use crate::config::PopupAnchor;
+    use wayland_protocols::xdg::shell::server::xdg_positioner::{Anchor, Gravity};
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite
+        .config
+        .popup_anchor_rules
+        .insert("java".to_string(), PopupAnchor::Center);
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.set_win_class(
+        toplevel,
+        WmClass {
+            class: "java".into(),
+            instance: "app".into(),
+        },
+    );
+    f.satellite.last_hovered = Some(toplevel);
+
+    let popup = unsafe { Window::new(2) };
+    let (buffer, surface) = comp.create_surface();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            x: 20,
+            y: 30,
+            width: 50,
+            height: 50,
+        },
+        ..Default::default()
+    };
+    f.new_window(popup, true, data, None);
+    f.map_window(&comp, popup, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
+
+    let positioner = &f
+        .testwl
+        .get_surface_data(id)
+        .unwrap()
+        .popup()
+        .positioner_state;
+    assert_eq!(positioner.anchor, Anchor::None);
+    assert_eq!(positioner.gravity, Gravity::None);
+    assert_eq!(positioner.offset, testwl::Vec2 { x: 0, y: 0 });
+}
+
+#[test]
+fn role_override() {
+    use crate::xstate::RoleOverride;
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map = |f: &mut TestFixture, window: Window, override_redirect, role| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, override_redirect, data, None);
+        f.satellite.set_role_override(window, Some(role));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    // An override-redirect window that is really a toplevel.
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(2) },
+        true,
+        RoleOverride::Toplevel,
+    );
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Toplevel(_))
+    ));
+
+    // A managed window that is really a popup.
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(3) },
+        false,
+        RoleOverride::Popup,
+    );
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Popup(_))
+    ));
+
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(4) },
+        true,
+        RoleOverride::Fullscreen,
+    );
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(data
+        .toplevel()
+        .states
+        .contains(&xdg_toplevel::State::Fullscreen));
+}
+
+#[test]
+fn window_role_rules() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.window_roles = vec![
+        "utility=popup".parse().unwrap(),
+        "utility:gimp=toplevel".parse().unwrap(),
+        "utility:game=fullscreen".parse().unwrap(),
+    ];
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map = |f: &mut TestFixture, window: Window, class: &str| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, false, data, None);
+        f.satellite.set_window_type(window, WindowType::Utility);
+        f.satellite.set_win_class(
+            window,
+            WmClass {
+                class: class.into(),
+                instance: class.into(),
+            },
+        );
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(2) }, "gimp");
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Toplevel(_))
+    ));
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(3) }, "other");
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Popup(_))
+    ));
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(4) }, "game");
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(data
+        .toplevel()
+        .states
+        .contains(&xdg_toplevel::State::Fullscreen));
+}
+
+#[test]
+fn toplevel_window_roles() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.toplevel_roles = vec!["Toolbox".into()];
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map = |f: &mut TestFixture, window: Window, role: &str| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, true, data, None);
+        f.satellite.set_window_type(window, WindowType::Menu);
+        f.satellite.set_wm_role(window, Some(role.into()));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(2) }, "gtk-tearoff");
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Toplevel(_))
+    ));
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(3) }, "gimp-toolbox");
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Toplevel(_))
+    ));
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(4) }, "context-menu");
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Popup(_))
+    ));
+}
+
+#[test]
+fn wine_desktop() {
+    use crate::config::WineDesktop;
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.wine_desktop = WineDesktop::Fullscreen;
+
+    let map = |f: &mut TestFixture, window: Window, class: &str, title: &str| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 640,
+                height: 480,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, false, data, None);
+        f.satellite.set_win_class(
+            window,
+            WmClass {
+                class: class.into(),
+                instance: class.to_lowercase(),
+            },
+        );
+        f.satellite
+            .set_win_title(window, WmName::NetWmName(title.into()));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    let fullscreen = |f: &mut TestFixture, id| {
+        f.testwl
+            .get_surface_data(id)
+            .unwrap()
+            .toplevel()
+            .states
+            .contains(&xdg_toplevel::State::Fullscreen)
+    };
+
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(1) },
+        "Explorer.exe",
+        "Default - Wine desktop",
+    );
+    assert!(fullscreen(&mut f, id));
+
+    // explorer.exe also runs Wine's file manager, which is a normal window.
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(3) },
+        "Explorer.exe",
+        "Wine Explorer",
+    );
+    assert!(!fullscreen(&mut f, id));
+
+    let (_surface, id) = map(
+        &mut f,
+        unsafe { Window::new(2) },
+        "Notepad.exe",
+        "Untitled - Notepad",
+    );
+    assert!(!fullscreen(&mut f, id));
+}
+
+#[test]
+fn splash_screens_are_fixed_size_toplevels() {
+    use wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map = |f: &mut TestFixture, window: Window, override_redirect| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 300,
+                height: 200,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, override_redirect, data, None);
+        f.satellite.set_window_type(window, WindowType::Splash);
+        f.satellite.set_transient_for(window, Some(toplevel));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    for (window, override_redirect) in [(2, false), (3, true)] {
+        let (_surface, id) = map(&mut f, unsafe { Window::new(window) }, override_redirect);
+        let data = f.testwl.get_surface_data(id).unwrap();
+        assert!(
+            matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_))),
+            "surface role: {:?}",
+            data.role
+        );
+        let toplevel = data.toplevel();
+        assert!(toplevel.parent.is_none());
+        assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 300, y: 200 }));
+        assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 300, y: 200 }));
+        assert_eq!(toplevel.decoration, Some(Mode::ClientSide));
+
+        // The pinned size follows the window when the compositor resizes it anyway.
+        f.testwl.configure_toplevel(id, 400, 300, vec![]);
+        f.run();
+        f.run();
+        let toplevel = f.testwl.get_surface_data(id).unwrap().toplevel();
+        assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 400, y: 300 }));
+        assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 400, y: 300 }));
+    }
+}
+
+#[test]
+fn fixed_size_windows_keep_their_size() {
+    use crate::xstate::FrameExtents;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+
+    let map = |f: &mut TestFixture, window: Window| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 50,
+                height: 50,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, false, data, None);
+        (buffer, surface)
+    };
+
+    // Equal minimum and maximum sizes
+    let win = unsafe { Window::new(1) };
+    let (buffer, surface) = map(&mut f, win);
+    let size = || crate::xstate::WinSize {
+        width: 50,
+        height: 50,
+    };
+    f.satellite.set_size_hints(
+        win,
+        crate::xstate::WmNormalHints {
+            min_size: Some(size()),
+            max_size: Some(size()),
+            ..Default::default()
+        },
+    );
+    f.map_window(&comp, win, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
+    f.testwl.configure_toplevel(id, 200, 150, vec![]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (50, 50));
+
+    // Motif hints without resizing
+    let win = unsafe { Window::new(2) };
+    let (buffer, surface) = map(&mut f, win);
+    f.satellite.set_motif_no_resize(win, true);
+    f.map_window(&comp, win, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
+    let toplevel = f.testwl.get_surface_data(id).unwrap().toplevel();
+    assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 50, y: 50 }));
+    assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 50, y: 50 }));
+    f.testwl.configure_toplevel(id, 200, 150, vec![]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (50, 50));
+
+    // The pinned size is the window geometry, which GTK's shadows aren't part of.
+    f.satellite.set_frame_extents(
+        win,
+        FrameExtents {
+            left: 5,
+            right: 5,
+            top: 10,
+            bottom: 10,
+        },
+    );
+    f.run();
+    f.run();
+    let toplevel = f.testwl.get_surface_data(id).unwrap().toplevel();
+    assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 40, y: 30 }));
+    assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 40, y: 30 }));
+
+    // Resizing is allowed again once the Motif hints stop leaving it out.
+    f.satellite.set_motif_no_resize(win, false);
+    f.run();
+    f.run();
+    let toplevel = f.testwl.get_surface_data(id).unwrap().toplevel();
+    assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 0, y: 0 }));
+    assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 0, y: 0 }));
+    f.testwl.configure_toplevel(id, 200, 150, vec![]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (210, 170));
+}
+
+#[test]
+fn override_redirect_covering_output_is_fullscreen() {
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let (_output_obj, _output) = f.new_output(1000, 0);
+    let toplevel = unsafe { Window::new(1) };
+    let _ = f.create_toplevel(&comp, toplevel);
+    f.satellite.last_hovered = Some(toplevel);
+
+    let map = |f: &mut TestFixture, window: Window, x, width| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x,
+                y: 0,
+                width,
+                height: 1000,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, true, data, None);
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(2) }, 1000, 1000);
+    let data = f.testwl.get_surface_data(id).unwrap();
+    assert!(
+        matches!(data.role, Some(testwl::SurfaceRole::Toplevel(_))),
+        "surface role: {:?}",
+        data.role
+    );
+    assert!(data
+        .toplevel()
+        .states
+        .contains(&xdg_toplevel::State::Fullscreen));
+
+    // Not quite covering it
+    let (_surface, id) = map(&mut f, unsafe { Window::new(3) }, 1000, 999);
+    assert!(matches!(
+        f.testwl.get_surface_data(id).unwrap().role,
+        Some(testwl::SurfaceRole::Popup(_))
+    ));
+}
+
+#[test]
+fn window_spanning_outputs_is_fullscreen() {
+    use crate::config::SpanningWindows;
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let _left = f.new_output(0, 0);
+    let _right = f.new_output(1000, 0);
+    f.run();
+
+    let map = |f: &mut TestFixture, window: Window| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                x: 0,
+                y: 0,
+                width: 2000,
+                height: 1000,
+            },
+            ..Default::default()
+        };
+        f.new_window(window, false, data, None);
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        (surface, f.check_new_surface())
+    };
+
+    let (_surface, id) = map(&mut f, unsafe { Window::new(1) });
+    assert!(f
+        .testwl
+        .get_surface_data(id)
+        .unwrap()
+        .toplevel()
+        .states
+        .contains(&xdg_toplevel::State::Fullscreen));
+
+    f.satellite.config.spanning_windows = SpanningWindows::Keep;
+    let (_surface, id) = map(&mut f, unsafe { Window::new(2) });
+    assert!(!f
+        .testwl
+        .get_surface_data(id)
+        .unwrap()
+        .toplevel()
+        .states
+        .contains(&xdg_toplevel::State::Fullscreen));
+}
+
+#[test]
+fn configure_bounds_clamp_size() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (buffer, surface) = comp.create_surface();
+    let data = WindowData {
+        mapped: true,
+        dims: WindowDims {
+            x: 0,
+            y: 0,
+            width: 2000,
+            height: 500,
+        },
+        ..Default::default()
+    };
+    f.new_window(win, false, data, None);
+    f.map_window(&comp, win, &surface.obj, &buffer);
+    f.run();
+    let id = f.check_new_surface();
+    let (_output_obj, output) = f.new_output(0, 0);
+    f.testwl.move_surface_to_output(id, &output);
+    f.run();
+
+    f.testwl.configure_toplevel_bounds(id, 1200, 800);
+    f.testwl.configure_toplevel(id, 0, 0, vec![]);
+    f.run();
+
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (1200, 500));
+    assert_eq!(f.connection().work_area, Some((0, 0, 1200, 800)));
+
+    // A toplevel on another output doesn't take the work area over, it spans both outputs.
+    let (_output_obj2, output2) = f.new_output(1200, 0);
+    let win2 = unsafe { Window::new(2) };
+    let (_surface2, id2) = f.create_toplevel(&comp, win2);
+    f.testwl.move_surface_to_output(id2, &output2);
+    f.run();
+    f.testwl.configure_toplevel_bounds(id2, 1000, 700);
+    f.testwl.configure_toplevel(id2, 0, 0, vec![]);
+    f.run();
+    assert_eq!(f.connection().work_area, Some((0, 0, 2200, 800)));
+
+    f.testwl.configure_toplevel_bounds(id, 1200, 800);
+    f.testwl.configure_toplevel(id, 0, 0, vec![]);
+    f.run();
+    assert_eq!(f.connection().work_area, Some((0, 0, 2200, 800)));
+}
+
+#[test]
+fn tiled_edges() {
+    use super::TiledEdges;
+    use wayland_protocols::xdg::shell::server::xdg_toplevel::State;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (_surface, id) = f.create_toplevel(&comp, win);
+    assert_eq!(f.connection().tiled.get(&win), None);
+
+    f.testwl
+        .configure_toplevel(id, 100, 100, vec![State::TiledLeft, State::TiledTop]);
+    f.run();
+    assert_eq!(
+        f.connection().tiled[&win],
+        TiledEdges::LEFT | TiledEdges::TOP
+    );
+    // Tiled on every edge still isn't maximized.
+    f.testwl.configure_toplevel(
+        id,
+        100,
+        100,
+        vec![
+            State::TiledLeft,
+            State::TiledTop,
+            State::TiledRight,
+            State::TiledBottom,
         ],
     );
-    let (tab_id, tool_id, pad_id) = (tab_id.unwrap(), tool_id.unwrap(), pad_id.unwrap());
+    f.run();
+    assert_eq!(f.connection().tiled[&win], TiledEdges::all());
+    assert!(!f.connection().maximized.contains(&win));
 
-    // For reasons beyond my mortal understanding, `id.object_data()` does not work properly.
-    let tab_data = f.object_data(&tab_id);
-    let tab_events = std::mem::take(&mut *tab_data.events.lock().unwrap()).into_iter();
-    events_check(
-        tab_events,
-        [
-            Box::new(|e| matches!(e, zwp_tablet_v2::Event::Name { name } if name == "tabby")),
-            Box::new(|e| matches!(e, zwp_tablet_v2::Event::Done)),
-        ],
+    f.testwl
+        .configure_toplevel(id, 100, 100, vec![State::Maximized]);
+    f.run();
+    assert_eq!(f.connection().tiled[&win], TiledEdges::all());
+    assert!(f.connection().maximized.contains(&win));
+
+    f.testwl.configure_toplevel(id, 100, 100, vec![]);
+    f.run();
+    assert_eq!(f.connection().tiled[&win], TiledEdges::empty());
+    assert!(!f.connection().maximized.contains(&win));
+}
+
+#[test]
+fn set_maximized() {
+    use crate::xstate::SetState;
+    use wayland_protocols::xdg::shell::server::xdg_toplevel::State;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (_surface, id) = f.create_toplevel(&comp, win);
+    let maximized = |f: &mut TestFixture| {
+        f.testwl
+            .get_surface_data(id)
+            .unwrap()
+            .toplevel()
+            .states
+            .contains(&State::Maximized)
+    };
+
+    f.satellite.set_maximized(win, SetState::Add);
+    f.run();
+    f.run();
+    assert!(maximized(&mut f));
+    assert!(f.connection().maximized.contains(&win));
+
+    f.satellite.set_maximized(win, SetState::Toggle);
+    f.run();
+    f.run();
+    assert!(!maximized(&mut f));
+    assert!(!f.connection().maximized.contains(&win));
+
+    f.satellite.set_maximized(win, SetState::Toggle);
+    f.run();
+    f.run();
+    assert!(maximized(&mut f));
+
+    f.satellite.set_maximized(win, SetState::Remove);
+    f.run();
+    f.run();
+    assert!(!maximized(&mut f));
+}
+
+#[test]
+fn suspended_toplevel_holds_frame_callbacks() {
+    use wayland_client::protocol::{wl_callback::WlCallback, wl_surface};
+    use wayland_protocols::xdg::shell::server::xdg_toplevel::State;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let window = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, window);
+
+    f.testwl
+        .configure_toplevel(id, 100, 100, vec![State::Suspended]);
+    f.run();
+    let callback =
+        TestObject::<WlCallback>::from_request(&surface.obj, wl_surface::Request::Frame {});
+    surface.obj.commit();
+    f.run();
+    f.run();
+    assert!(callback.data.events.lock().unwrap().is_empty());
+
+    f.testwl.configure_toplevel(id, 100, 100, vec![]);
+    f.run();
+    f.run();
+    let events = callback.data.events.lock().unwrap();
+    assert!(
+        matches!(events[..], [Ev::<WlCallback>::Done { .. }]),
+        "{events:?}"
     );
+}
 
-    let tool_data = f.object_data(&tool_id);
-    let tool_events = std::mem::take(&mut *tool_data.events.lock().unwrap()).into_iter();
-    events_check(
-        tool_events,
-        [
-            Box::new(|e| {
-                matches!(
-                    e,
-                    zwp_tablet_tool_v2::Event::Type {
-                        tool_type: WEnum::Value(zwp_tablet_tool_v2::Type::Finger)
-                    }
-                )
+#[test]
+fn size_hints_increments_and_aspect() {
+    use crate::xstate::{WinSize, WmNormalHints};
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (_surface, id) = f.create_toplevel(&comp, win);
+    f.satellite.set_size_hints(
+        win,
+        WmNormalHints {
+            max_size: Some(WinSize {
+                width: 500,
+                height: 500,
             }),
-            Box::new(|e| matches!(e, zwp_tablet_tool_v2::Event::Done)),
-        ],
+            resize_increments: Some(WinSize {
+                width: 10,
+                height: 20,
+            }),
+            base_size: Some(WinSize {
+                width: 4,
+                height: 4,
+            }),
+            ..Default::default()
+        },
+    );
+    f.run();
+    let toplevel = f.testwl.get_surface_data(id).unwrap().toplevel();
+    assert_eq!(toplevel.min_size, Some(testwl::Vec2 { x: 4, y: 4 }));
+    assert_eq!(toplevel.max_size, Some(testwl::Vec2 { x: 494, y: 484 }));
+
+    f.testwl.configure_toplevel(id, 205, 153, vec![]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (204, 144));
+
+    // Maximized windows fill the space they're given.
+    f.testwl
+        .configure_toplevel(id, 205, 153, vec![xdg_toplevel::State::Maximized]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (205, 153));
+
+    let ratio = |width, height| WinSize { width, height };
+    let hints = WmNormalHints {
+        aspect: Some((ratio(1, 1), ratio(2, 1))),
+        ..Default::default()
+    };
+    assert_eq!(hints.constrain(100, 200), (100, 100));
+    assert_eq!(hints.constrain(300, 100), (200, 100));
+    assert_eq!(hints.constrain(150, 100), (150, 100));
+}
+
+#[test]
+fn no_input_window_is_not_focused_toplevel() {
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win1 = unsafe { Window::new(1) };
+    let (_, id1) = f.create_toplevel(&comp, win1);
+    let win2 = unsafe { Window::new(2) };
+    let (_, id2) = f.create_toplevel(&comp, win2);
+    f.testwl.focus_toplevel(id1);
+    f.run();
+    assert_eq!(f.satellite.last_focused_toplevel, Some(win1));
+
+    f.satellite.set_win_hints(
+        win2,
+        super::WmHints {
+            input: Some(false),
+            ..Default::default()
+        },
     );
+    f.testwl.focus_toplevel(id2);
+    f.run();
+    assert_eq!(f.satellite.last_focused_toplevel, Some(win1));
 
-    let pad_data = f.object_data(&pad_id);
-    let pad_events = std::mem::take(&mut *pad_data.events.lock().unwrap()).into_iter();
-    let mut group = None;
-    events_check(
-        pad_events,
-        [
-            Box::new(|e| matches!(e, zwp_tablet_pad_v2::Event::Buttons { buttons: 5 })),
-            Box::new(|e| match e {
-                zwp_tablet_pad_v2::Event::Group { pad_group } => {
-                    group = Some(pad_group.clone());
-                    true
-                }
-                _ => false,
-            }),
-            Box::new(|e| matches!(e, zwp_tablet_pad_v2::Event::Done)),
-        ],
+    // A Globally Active window takes focus itself, so it does become the focused toplevel.
+    f.satellite.set_take_focus(win2, true);
+    f.testwl.focus_toplevel(id1);
+    f.run();
+    f.testwl.focus_toplevel(id2);
+    f.run();
+    assert_eq!(f.satellite.last_focused_toplevel, Some(win2));
+}
+
+#[test]
+fn decoration_mode_rules() {
+    use wayland_protocols::xdg::decoration::zv1::server::zxdg_toplevel_decoration_v1::Mode;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    f.satellite.config.decoration_modes = [
+        ("server".to_string(), crate::config::DecorationMode::Server),
+        ("client".to_string(), crate::config::DecorationMode::Client),
+    ]
+    .into();
+
+    let decoration = |f: &mut TestFixture, window: Window, class: &str| {
+        let (buffer, surface) = comp.create_surface();
+        let data = WindowData {
+            mapped: true,
+            dims: WindowDims {
+                width: 50,
+                height: 50,
+                ..Default::default()
+            },
+            fullscreen: false,
+            minimized: false,
+        };
+        f.new_window(window, false, data, None);
+        f.satellite.set_win_class(window, wm_class(class, class));
+        f.map_window(&comp, window, &surface.obj, &buffer);
+        f.run();
+        let id = f.check_new_surface();
+        f.testwl.get_surface_data(id).unwrap().toplevel().decoration
+    };
+    let server = decoration(&mut f, unsafe { Window::new(1) }, "server");
+    let client = decoration(&mut f, unsafe { Window::new(2) }, "client");
+    let other = decoration(&mut f, unsafe { Window::new(3) }, "other");
+    assert_eq!(server, Some(Mode::ServerSide));
+    assert_eq!(client, Some(Mode::ClientSide));
+    assert_eq!(other, None);
+}
+
+#[test]
+fn emulated_resolution_keeps_window_size() {
+    use wayland_protocols::xdg::shell::server::xdg_toplevel;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let win = unsafe { Window::new(1) };
+    let (surface, id) = f.create_toplevel(&comp, win);
+
+    // Xwayland scaling the window up to the output, after the game switched to 100x100
+    let viewport = TestObject::<WpViewport>::from_request(
+        &comp.viewporter.obj,
+        Req::<WpViewporter>::GetViewport {
+            surface: surface.obj.clone(),
+        },
     );
+    viewport.obj.set_destination(1000, 1000);
+    f.run();
 
-    let group = group.unwrap();
-    let g_data = f.object_data(&group);
-    let g_events = std::mem::take(&mut *g_data.events.lock().unwrap()).into_iter();
-    events_check(
-        g_events,
-        [
-            Box::new(|e| {
-                matches!(e,
-                zwp_tablet_pad_group_v2::Event::Buttons { buttons } if buttons.is_empty())
-            }),
-            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Ring { .. })),
-            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Strip { .. })),
-            Box::new(|e| matches!(e, zwp_tablet_pad_group_v2::Event::Done)),
-        ],
-    )
+    f.testwl
+        .configure_toplevel(id, 1000, 1000, vec![xdg_toplevel::State::Fullscreen]);
+    f.run();
+    let dims = f.connection().windows[&win].dims;
+    assert_eq!((dims.width, dims.height), (100, 100));
+
+    // An override-redirect window covers the output it's scaled up to.
+    let attrs = super::WindowAttributes {
+        override_redirect: true,
+        dims: WindowDims {
+            width: 640,
+            height: 480,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let (_output_obj, _output) = f.new_output(0, 0);
+    f.run();
+    assert!(f.satellite.covered_output(&attrs, None).is_none());
+    assert!(f
+        .satellite
+        .covered_output(&attrs, Some((1000, 1000)))
+        .is_some());
 }
-/// See Pointer::handle_event for an explanation.
+
 #[test]
-fn popup_pointer_motion_workaround() {}
+fn fullscreen_monitors() {
+    use crate::xstate::SetState;
+
+    let (mut f, comp) = TestFixture::new_with_compositor();
+    let (_left_obj, left) = f.new_output(0, 0);
+    let (_right_obj, right) = f.new_output(1000, 0);
+    f.run();
+    let win = unsafe { Window::new(1) };
+    let (_surface, id) = f.create_toplevel(&comp, win);
+    let fullscreen_output = |f: &mut TestFixture| {
+        f.testwl
+            .get_surface_data(id)
+            .unwrap()
+            .toplevel()
+            .fullscreen_output
+            .clone()
+    };
+
+    f.satellite
+        .set_fullscreen_monitors(win, (1000, 0, 1000, 1000));
+    f.satellite.set_fullscreen(win, SetState::Add);
+    f.run();
+    f.run();
+    assert_eq!(fullscreen_output(&mut f), Some(right));
+
+    // Spanning both monitors fullscreens on the one at the top left, right away.
+    f.satellite.set_fullscreen_monitors(win, (0, 0, 2000, 1000));
+    f.run();
+    assert_eq!(fullscreen_output(&mut f), Some(left));
+}
+
+#[test]
+fn rotated_output_size() {
+    use wayland_server::protocol::wl_output::Transform;
+
+    let (mut f, _comp) = TestFixture::new_with_compositor();
+    let (_output_obj, output) = f.new_output(0, 0);
+    f.testwl
+        .set_output_mode(&output, (1920, 1080), Transform::_90);
+    f.run();
+    f.run();
+
+    let attrs = |width, height| super::WindowAttributes {
+        override_redirect: true,
+        dims: WindowDims {
+            width,
+            height,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    assert!(f
+        .satellite
+        .covered_output(&attrs(1080, 1920), None)
+        .is_some());
+    assert!(f
+        .satellite
+        .covered_output(&attrs(1920, 1080), None)
+        .is_none());
+}
+
+#[test]
+fn output_refresh_and_physical_size() {
+    use wayland_client::protocol::wl_output::Event;
+
+    let (mut f, _comp) = TestFixture::new_with_compositor();
+    let (output, _) = f.new_output(0, 0);
+    f.run();
+    f.run();
+    // Xwayland fills in the refresh rate and physical size of RandR outputs from these.
+    let events = std::mem::take(&mut *output.data.events.lock().unwrap());
+    assert!(
+        events.iter().any(|event| matches!(
+            event,
+            Event::Geometry {
+                physical_width: 260,
+                physical_height: 260,
+                ..
+            }
+        )),
+        "{events:?}"
+    );
+    assert!(
+        events.iter().any(|event| matches!(
+            event,
+            Event::Mode {
+                width: 1000,
+                height: 1000,
+                refresh: 60000,
+                ..
+            }
+        )),
+        "{events:?}"
+    );
+}
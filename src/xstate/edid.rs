@@ -0,0 +1,115 @@
+//! Minimal EDIDs for RandR outputs, built from what wl_output tells us about the monitor.
+//!
+//! Xwayland leaves the EDID output property unset, so tools that identify monitors through it
+//! (monitor control tools, some games) find nothing. The compositor only passes on the make,
+//! model and physical size, so that's all the EDID describes: there's no serial number and no
+//! timings, since Xwayland's modes aren't real ones anyway.
+//! https://glenwing.github.io/docs/VESA-EEDID-A2.pdf
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+const DESCRIPTORS: usize = 54;
+const DESCRIPTOR_LEN: usize = 18;
+const MONITOR_NAME: u8 = 0xfc;
+const DUMMY: u8 = 0x10;
+
+/// Returns `None` if the compositor didn't say anything about the monitor.
+pub(super) fn edid(make: &str, model: &str, physical_size: (i32, i32)) -> Option<[u8; 128]> {
+    if make.is_empty() && model.is_empty() {
+        return None;
+    }
+
+    let mut edid = [0; 128];
+    edid[..8].copy_from_slice(&HEADER);
+    edid[8..10].copy_from_slice(&manufacturer_id(make).to_be_bytes());
+    // EDID 1.4
+    edid[18] = 1;
+    edid[19] = 4;
+    // A digital input of undefined bit depth and interface.
+    edid[20] = 0x80;
+    let (width, height) = physical_size;
+    edid[21] = centimeters(width);
+    edid[22] = centimeters(height);
+    // A gamma of 2.2, stored as (gamma - 1) * 100.
+    edid[23] = 120;
+    // Unused standard timings.
+    edid[38..54].fill(0x01);
+
+    let name = if model.is_empty() { make } else { model };
+    let mut descriptors =
+        edid[DESCRIPTORS..DESCRIPTORS + 4 * DESCRIPTOR_LEN].chunks_exact_mut(DESCRIPTOR_LEN);
+    display_descriptor(descriptors.next().unwrap(), MONITOR_NAME, name);
+    for descriptor in descriptors {
+        display_descriptor(descriptor, DUMMY, "");
+    }
+
+    let sum = edid[..127].iter().fold(0u8, |sum, b| sum.wrapping_add(*b));
+    edid[127] = sum.wrapping_neg();
+    Some(edid)
+}
+
+/// The three letter PNP ID, packed in 5 bits per letter. Compositors give either the ID itself or
+/// the manufacturer's name, whose first letters often are the ID ("Dell Inc." is DEL).
+fn manufacturer_id(make: &str) -> u16 {
+    let letters: Vec<u16> = make
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .take(3)
+        .map(|c| c.to_ascii_uppercase() as u16 - u16::from(b'A') + 1)
+        .collect();
+    match letters[..] {
+        [a, b, c] => a << 10 | b << 5 | c,
+        _ => 0,
+    }
+}
+
+fn centimeters(millimeters: i32) -> u8 {
+    ((millimeters + 5) / 10).clamp(0, u8::MAX.into()) as u8
+}
+
+/// Text in descriptors is at most 13 bytes, ended by a newline and padded with spaces.
+fn display_descriptor(descriptor: &mut [u8], tag: u8, text: &str) {
+    descriptor[3] = tag;
+    if tag == DUMMY {
+        return;
+    }
+    let field = &mut descriptor[5..];
+    field.fill(b' ');
+    let bytes: Vec<u8> = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() || c == ' ' {
+                c as u8
+            } else {
+                b'?'
+            }
+        })
+        .take(field.len())
+        .collect();
+    field[..bytes.len()].copy_from_slice(&bytes);
+    if let Some(end) = field.get_mut(bytes.len()) {
+        *end = b'\n';
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::edid;
+
+    #[test]
+    fn monitor_edid() {
+        let dell = edid("Dell Inc.", "DELL U2720Q", (597, 336)).unwrap();
+        assert_eq!(dell[..8], [0, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0]);
+        assert_eq!(dell.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)), 0);
+        // "DEL"
+        assert_eq!(dell[8..10], [0x10, 0xac]);
+        assert_eq!(dell[21..23], [60, 34]);
+        assert_eq!(dell[54..59], [0, 0, 0, 0xfc, 0]);
+        assert_eq!(&dell[59..72], b"DELL U2720Q\n ");
+
+        let long = edid("", "A very long monitor name", (0, 0)).unwrap();
+        assert_eq!(long[8..10], [0, 0]);
+        assert_eq!(&long[59..72], b"A very long m");
+
+        assert_eq!(edid("", "", (0, 0)), None);
+    }
+}
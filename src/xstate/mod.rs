@@ -1,13 +1,21 @@
+mod edid;
 mod selection;
+mod text;
 use selection::{Selection, SelectionData};
+use text::{sanitize_utf8, LegacyEncoding, Transliterator};
 
-use crate::{server::WindowAttributes, XConnection};
+use crate::config::{AppIdSource, Config};
+use crate::{
+    server::{FocusedOutput, OutputIdentity, PointerConstraint, TiledEdges, WindowAttributes},
+    XConnection,
+};
 use bitflags::bitflags;
-use log::{debug, trace, warn};
-use std::collections::HashMap;
-use std::ffi::CString;
+use log::{debug, info, trace, warn};
+use std::collections::{HashMap, HashSet};
 use std::os::fd::{AsRawFd, BorrowedFd};
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+use wayland_protocols::xdg::shell::client::xdg_toplevel::ResizeEdge;
 use xcb::{x, Xid, XidNew};
 use xcb_util_cursor::{Cursor, CursorContext};
 
@@ -88,6 +96,22 @@ impl<F: PropertyResolver> PropertyCookieWrapper<'_, F> {
     }
 }
 
+/// https://tronche.com/gui/x/icccm/sec-4.html#s-4.1.2.5
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WmClass {
+    pub instance: String,
+    pub class: String,
+}
+
+impl WmClass {
+    pub fn app_id(&self, source: AppIdSource) -> &str {
+        match source {
+            AppIdSource::Class => &self.class,
+            AppIdSource::Instance => &self.instance,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum WmName {
     WmName(String),
@@ -103,16 +127,29 @@ impl WmName {
     }
 }
 
+/// How many X events are handled before giving Xwayland's Wayland requests a turn. A client
+/// flooding the X server otherwise keeps new surfaces and commits from being processed.
+const EVENT_BUDGET: usize = 256;
+
 pub struct XState {
     connection: Rc<xcb::Connection>,
     atoms: Atoms,
+    window_types: WindowTypes,
     root: x::Window,
     wm_window: x::Window,
     selection_data: SelectionData,
+    config: Config,
+    /// Geometry requested by windows that haven't been mapped yet.
+    pending_configures: HashMap<x::Window, PendingConfigure>,
+    title_transliterator: Option<Transliterator>,
+    /// _NET_WM_USER_TIME_WINDOWs, mapped to the window they hold the user time for.
+    user_time_windows: HashMap<x::Window, x::Window>,
+    /// Windows we've set _NET_WM_VISIBLE_NAME on.
+    visible_names: HashSet<x::Window>,
 }
 
 impl XState {
-    pub fn new(fd: BorrowedFd) -> Self {
+    pub fn new(fd: BorrowedFd, config: Config) -> Self {
         let connection = Rc::new(
             xcb::Connection::connect_to_fd_with_extensions(
                 fd.as_raw_fd(),
@@ -121,6 +158,7 @@ impl XState {
                     xcb::Extension::Composite,
                     xcb::Extension::RandR,
                     xcb::Extension::XFixes,
+                    xcb::Extension::Input,
                 ],
                 &[],
             )
@@ -143,6 +181,7 @@ impl XState {
 
         let atoms = Atoms::intern_all(&connection).unwrap();
         trace!("atoms: {atoms:#?}");
+        let window_types = WindowTypes::intern_all(&connection).unwrap();
 
         // This makes Xwayland spit out damage tracking
         connection
@@ -152,11 +191,12 @@ impl XState {
             })
             .unwrap();
 
-        // Track RandR output changes
+        // Track RandR output changes, and X clients picking the primary output
         connection
             .send_and_check_request(&xcb::randr::SelectInput {
                 window: root,
-                enable: xcb::randr::NotifyMask::RESOURCE_CHANGE,
+                enable: xcb::randr::NotifyMask::RESOURCE_CHANGE
+                    | xcb::randr::NotifyMask::OUTPUT_CHANGE,
             })
             .unwrap();
 
@@ -172,6 +212,18 @@ impl XState {
             reply.major_version(),
             reply.minor_version()
         );
+        // XI 2.1 is needed for raw events to be delivered during grabs
+        let reply = connection
+            .wait_for_reply(connection.send_request(&xcb::xinput::XiQueryVersion {
+                major_version: 2,
+                minor_version: 1,
+            }))
+            .unwrap();
+        log::info!(
+            "xinput version: {}.{}",
+            reply.major_version(),
+            reply.minor_version()
+        );
         use xcb::xfixes::SelectionEventMask;
         connection
             .send_and_check_request(&xcb::xfixes::SelectSelectionInput {
@@ -196,21 +248,28 @@ impl XState {
 
         let wm_window = connection.generate_id();
         let selection_data = SelectionData::new(&connection, root);
+        let title_transliterator = config.title_charset.map(Transliterator::new);
 
         let mut r = Self {
             connection,
             wm_window,
             root,
             atoms,
+            window_types,
             selection_data,
+            config,
+            pending_configures: HashMap::new(),
+            title_transliterator,
+            user_time_windows: HashMap::new(),
+            visible_names: HashSet::new(),
         };
         r.create_ewmh_window();
         r
     }
 
     pub fn server_state_setup(&self, server_state: &mut super::RealServerState) {
-        let mut c = RealConnection::new(self.connection.clone());
-        c.update_outputs(self.root);
+        let mut c = RealConnection::new(self.connection.clone(), self.wm_window, &self.config);
+        c.update_outputs(self.root, &self.atoms, &server_state.output_identities());
         server_state.set_x_connection(c);
         server_state.atoms = Some(self.atoms.clone());
     }
@@ -240,13 +299,47 @@ impl XState {
                 border_width: 0,
                 class: x::WindowClass::InputOnly,
                 visual: x::COPY_FROM_PARENT,
-                value_list: &[],
+                // For timestamps, see RealConnection::request_take_focus
+                value_list: &[x::Cw::EventMask(x::EventMask::PROPERTY_CHANGE)],
             })
             .unwrap();
 
         self.set_root_property(self.atoms.wm_check, x::ATOM_WINDOW, &[self.wm_window]);
         self.set_root_property(self.atoms.active_win, x::ATOM_WINDOW, &[x::Window::none()]);
-        self.set_root_property(self.atoms.supported, x::ATOM_ATOM, &[self.atoms.active_win]);
+        self.set_root_property(
+            self.atoms.supported,
+            x::ATOM_ATOM,
+            &[
+                self.atoms.active_win,
+                self.atoms.net_wm_visible_name,
+                self.atoms.net_wm_ping,
+                self.atoms.net_wm_user_time,
+                self.atoms.net_wm_user_time_window,
+                self.atoms.net_close_window,
+                self.atoms.net_restack_window,
+                self.atoms.net_wm_moveresize,
+                self.atoms.net_wm_bypass_compositor,
+                self.atoms.net_desktop_geometry,
+                self.atoms.net_desktop_viewport,
+                self.atoms.net_workarea,
+                // GTK only reads edge constraints from window managers that list them.
+                self.atoms.gtk_edge_constraints,
+                self.atoms.wm_maximized_vert,
+                self.atoms.wm_maximized_horz,
+                self.atoms.net_wm_fullscreen_monitors,
+                self.atoms.net_number_of_desktops,
+                self.atoms.net_current_desktop,
+            ],
+        );
+        // Wayland has no concept of virtual desktops, so as far as X11 is concerned there's
+        // exactly one, which never scrolls.
+        self.set_root_property(self.atoms.net_number_of_desktops, x::ATOM_CARDINAL, &[1u32]);
+        self.set_root_property(self.atoms.net_current_desktop, x::ATOM_CARDINAL, &[0u32]);
+        self.set_root_property(
+            self.atoms.net_desktop_viewport,
+            x::ATOM_CARDINAL,
+            &[0u32, 0],
+        );
 
         self.connection
             .send_and_check_request(&x::ChangeProperty {
@@ -269,7 +362,9 @@ impl XState {
             .unwrap();
     }
 
-    pub fn handle_events(&mut self, server_state: &mut super::RealServerState) {
+    /// Handles up to [`EVENT_BUDGET`] X events, returning whether there may be more waiting.
+    /// Those are left for the next pass, after the Wayland side has had its turn.
+    pub fn handle_events(&mut self, server_state: &mut super::RealServerState) -> bool {
         macro_rules! unwrap_or_skip_bad_window_cont {
             ($err:expr) => {
                 match $err {
@@ -286,7 +381,12 @@ impl XState {
         }
 
         let mut ignored_windows = Vec::new();
-        while let Some(event) = self.connection.poll_for_event().unwrap() {
+        let mut budget = EVENT_BUDGET;
+        while budget > 0 {
+            let Some(event) = self.connection.poll_for_event().unwrap() else {
+                break;
+            };
+            budget -= 1;
             trace!("x11 event: {event:?}");
 
             if self.handle_selection_event(&event, server_state) {
@@ -303,6 +403,22 @@ impl XState {
                         Some(parent)
                     };
                     server_state.new_window(e.window(), e.override_redirect(), (&e).into(), parent);
+                    if !e.override_redirect() {
+                        // Resizing before the window is mapped goes by its win_gravity, so its
+                        // size hints are needed before MapNotify.
+                        unwrap_or_skip_bad_window_cont!(self.connection.send_and_check_request(
+                            &x::ChangeWindowAttributes {
+                                window: e.window(),
+                                value_list: &[x::Cw::EventMask(x::EventMask::PROPERTY_CHANGE)],
+                            }
+                        ));
+                        let hints = unwrap_or_skip_bad_window_cont!(self
+                            .get_wm_size_hints(e.window())
+                            .resolve());
+                        if let Some(hints) = hints {
+                            server_state.set_size_hints(e.window(), hints);
+                        }
+                    }
                 }
                 xcb::Event::X(x::Event::ReparentNotify(e)) => {
                     debug!("reparent event: {e:?}");
@@ -320,15 +436,22 @@ impl XState {
                         debug!("destroying window since its parent is no longer root!");
                         server_state.destroy_window(e.window());
                         ignored_windows.push(e.window());
+                        // Its toplevel is recreated once it's back under the root window.
+                        if unwrap_or_skip_bad_window_cont!(self.keeps_toplevel(e.window())) {
+                            debug!("moving {:?} back to the root window", e.window());
+                            unwrap_or_skip_bad_window_cont!(self.reparent_to_root(e.window()));
+                        }
                     }
                 }
                 xcb::Event::X(x::Event::MapRequest(e)) => {
                     debug!("requested to map {:?}", e.window());
+                    unwrap_or_skip_bad_window_cont!(self.apply_pending_configure(e.window()));
                     unwrap_or_skip_bad_window_cont!(self
                         .connection
                         .send_and_check_request(&x::MapWindow { window: e.window() }));
                 }
                 xcb::Event::X(x::Event::MapNotify(e)) => {
+                    unwrap_or_skip_bad_window_cont!(self.apply_pending_configure(e.window()));
                     unwrap_or_skip_bad_window_cont!(self.connection.send_and_check_request(
                         &x::ChangeWindowAttributes {
                             window: e.window(),
@@ -337,7 +460,13 @@ impl XState {
                     ));
                     let attrs =
                         unwrap_or_skip_bad_window_cont!(self.get_window_attributes(e.window()));
+                    let leader = attrs.group;
                     self.handle_window_attributes(server_state, e.window(), attrs);
+                    let (pid, startup_id) =
+                        unwrap_or_skip_bad_window_cont!(self.get_startup_id(e.window(), leader));
+                    server_state.set_startup_id(e.window(), pid, startup_id);
+                    let user_time = unwrap_or_skip_bad_window_cont!(self.get_user_time(e.window()));
+                    server_state.set_user_time(e.window(), user_time);
                     server_state.map_window(e.window());
                 }
                 xcb::Event::X(x::Event::ConfigureNotify(e)) => {
@@ -375,8 +504,23 @@ impl XState {
                 }
                 xcb::Event::X(x::Event::DestroyNotify(e)) => {
                     debug!("destroying window {:?}", e.window());
+                    self.pending_configures.remove(&e.window());
+                    self.user_time_windows.retain(|time_window, window| {
+                        ![*time_window, *window].contains(&e.window())
+                    });
+                    self.visible_names.remove(&e.window());
+                    if let Some(connection) = server_state.connection.as_mut() {
+                        connection.pings.remove(&e.window());
+                    }
                     server_state.destroy_window(e.window());
                 }
+                xcb::Event::X(x::Event::PropertyNotify(e)) if e.window() == self.wm_window => {
+                    if let Some(connection) = server_state.connection.as_mut() {
+                        if e.atom() == self.atoms.timestamp_prop {
+                            connection.send_take_focus(e.time(), &self.atoms);
+                        }
+                    }
+                }
                 xcb::Event::X(x::Event::PropertyNotify(e)) => {
                     if ignored_windows.contains(&e.window()) {
                         continue;
@@ -384,34 +528,50 @@ impl XState {
                     self.handle_property_change(e, server_state);
                 }
                 xcb::Event::X(x::Event::ConfigureRequest(e)) => {
+                    let mask = e.value_mask();
+                    if mask.contains(x::ConfigWindowMask::STACK_MODE) {
+                        let sibling = mask
+                            .contains(x::ConfigWindowMask::SIBLING)
+                            .then(|| e.sibling());
+                        server_state.restack_window(e.window(), sibling, e.stack_mode());
+                    }
                     if !server_state.can_reconfigure_window(e.window()) {
                         debug!("ignoring reconfigure request for {:?}", e.window());
                         continue;
                     }
                     debug!("{:?} request: {:?}", e.window(), e.value_mask());
 
-                    let mut list = Vec::new();
-                    let mask = e.value_mask();
-
-                    if mask.contains(x::ConfigWindowMask::X) {
-                        list.push(x::ConfigWindow::X(e.x().into()));
+                    let gravity = Self::resize_gravity(&e, server_state);
+                    let mut pending = self
+                        .pending_configures
+                        .remove(&e.window())
+                        .unwrap_or_default();
+                    if let Some((gravity, geometry)) = gravity {
+                        pending.apply_gravity(&e, gravity, geometry);
                     }
-                    if mask.contains(x::ConfigWindowMask::Y) {
-                        list.push(x::ConfigWindow::Y(e.y().into()));
+                    pending.merge(&e);
+                    if pending.value_list().is_empty() {
+                        continue;
                     }
-                    if mask.contains(x::ConfigWindowMask::WIDTH) {
-                        list.push(x::ConfigWindow::Width(e.width().into()));
+                    if server_state.window_mapped(e.window()) {
+                        unwrap_or_skip_bad_window_cont!(self.connection.send_and_check_request(
+                            &x::ConfigureWindow {
+                                window: e.window(),
+                                value_list: &pending.value_list(),
+                            }
+                        ));
+                        continue;
                     }
-                    if mask.contains(x::ConfigWindowMask::HEIGHT) {
-                        list.push(x::ConfigWindow::Height(e.height().into()));
+                    // Toolkits like to resize their windows a bunch before mapping them, only the
+                    // final geometry matters. Until it's applied, the client is told its request
+                    // went through with a synthetic ConfigureNotify, as ICCCM 4.1.5 asks of
+                    // window managers that don't apply requests as they are.
+                    if let Some(dims) = server_state.window_dims(e.window()) {
+                        unwrap_or_skip_bad_window_cont!(
+                            self.send_configure_notify(e.window(), pending.apply_to(dims))
+                        );
                     }
-
-                    unwrap_or_skip_bad_window_cont!(self.connection.send_and_check_request(
-                        &x::ConfigureWindow {
-                            window: e.window(),
-                            value_list: &list,
-                        }
-                    ));
+                    self.pending_configures.insert(e.window(), pending);
                 }
                 xcb::Event::X(x::Event::ClientMessage(e)) => match e.r#type() {
                     x if x == self.atoms.wl_surface_id => {
@@ -439,27 +599,152 @@ impl XState {
 
                         trace!("_NET_WM_STATE ({action:?}) props: {prop1:?} {prop2:?}");
 
+                        // Wayland can only maximize in both directions, and clients usually ask
+                        // for both in the same message.
+                        let maximized =
+                            [self.atoms.wm_maximized_vert, self.atoms.wm_maximized_horz];
+                        if maximized.contains(&prop1) || maximized.contains(&prop2) {
+                            server_state.set_maximized(e.window(), action);
+                        }
+
                         for prop in [prop1, prop2] {
                             match prop {
                                 x if x == self.atoms.wm_fullscreen => {
                                     server_state.set_fullscreen(e.window(), action);
                                 }
+                                // Wayland has no way to unminimize a window
+                                x if x == self.atoms.wm_hidden
+                                    && !matches!(action, SetState::Remove) =>
+                                {
+                                    server_state.set_minimized(e.window());
+                                }
+                                x if x == self.atoms.wm_modal => {
+                                    let modal = match action {
+                                        SetState::Add => true,
+                                        SetState::Remove => false,
+                                        SetState::Toggle => !server_state.window_modal(e.window()),
+                                    };
+                                    server_state.set_modal(e.window(), modal);
+                                }
+                                x if x == self.atoms.wm_demands_attention => {
+                                    let demands_attention = match action {
+                                        SetState::Add => true,
+                                        SetState::Remove => false,
+                                        SetState::Toggle => {
+                                            !server_state.window_demands_attention(e.window())
+                                        }
+                                    };
+                                    server_state
+                                        .set_demands_attention(e.window(), demands_attention);
+                                }
                                 _ => {}
                             }
                         }
                     }
+                    x if x == self.atoms.wm_protocols => {
+                        let x::ClientMessageData::Data32(data) = e.data() else {
+                            unreachable!();
+                        };
+                        if data[0] == self.atoms.net_wm_ping.resource_id() {
+                            let window = unsafe { x::Window::new(data[2]) };
+                            server_state
+                                .connection
+                                .as_mut()
+                                .unwrap()
+                                .ping_answered(window);
+                        }
+                    }
+                    x if x == self.atoms.wm_change_state => {
+                        let x::ClientMessageData::Data32(data) = e.data() else {
+                            unreachable!();
+                        };
+                        if data[0] == WmState::Iconic as u32 {
+                            server_state.set_minimized(e.window());
+                        }
+                    }
+                    x if x == self.atoms.net_close_window => {
+                        if server_state.window_mapped(e.window()) {
+                            server_state.close_x_window(e.window());
+                        } else {
+                            debug!("not closing unmapped window {:?}", e.window());
+                        }
+                    }
+                    x if x == self.atoms.net_restack_window => {
+                        let x::ClientMessageData::Data32(data) = e.data() else {
+                            unreachable!();
+                        };
+                        let sibling = (data[1] != 0).then(|| unsafe { x::Window::new(data[1]) });
+                        let mode = match data[2] {
+                            0 => x::StackMode::Above,
+                            1 => x::StackMode::Below,
+                            2 => x::StackMode::TopIf,
+                            3 => x::StackMode::BottomIf,
+                            4 => x::StackMode::Opposite,
+                            other => {
+                                warn!("unknown stack mode for _NET_RESTACK_WINDOW: {other}");
+                                continue;
+                            }
+                        };
+                        server_state.pager_restack_window(e.window(), sibling, mode);
+                    }
+                    x if x == self.atoms.net_wm_moveresize => {
+                        let x::ClientMessageData::Data32(data) = e.data() else {
+                            unreachable!();
+                        };
+                        if data[2] == MOVERESIZE_CANCEL {
+                            server_state.cancel_move_resize(e.window());
+                            continue;
+                        }
+                        let Ok(op) = MoveResize::try_from(data[2]) else {
+                            warn!("unknown direction for _NET_WM_MOVERESIZE: {}", data[2]);
+                            continue;
+                        };
+                        server_state.start_move_resize(e.window(), op, data[3]);
+                    }
+                    x if x == self.atoms.net_wm_fullscreen_monitors => {
+                        let x::ClientMessageData::Data32(data) = e.data() else {
+                            unreachable!();
+                        };
+                        let monitors = [data[0], data[1], data[2], data[3]];
+                        let Some(area) = self.monitor_area(monitors) else {
+                            warn!("unknown monitors for _NET_WM_FULLSCREEN_MONITORS: {monitors:?}");
+                            continue;
+                        };
+                        unwrap_or_skip_bad_window_cont!(self.connection.send_and_check_request(
+                            &x::ChangeProperty {
+                                mode: x::PropMode::Replace,
+                                window: e.window(),
+                                property: self.atoms.net_wm_fullscreen_monitors,
+                                r#type: x::ATOM_CARDINAL,
+                                data: &monitors,
+                            }
+                        ));
+                        server_state.set_fullscreen_monitors(e.window(), area);
+                    }
                     t => warn!("unrecognized message: {t:?}"),
                 },
                 xcb::Event::X(x::Event::MappingNotify(_)) => {}
                 xcb::Event::RandR(xcb::randr::Event::Notify(e))
                     if matches!(e.u(), xcb::randr::NotifyData::Rc(_)) =>
+                {
+                    let identities = server_state.output_identities();
+                    server_state.connection.as_mut().unwrap().update_outputs(
+                        self.root,
+                        &self.atoms,
+                        &identities,
+                    );
+                }
+                xcb::Event::RandR(xcb::randr::Event::Notify(e))
+                    if matches!(e.u(), xcb::randr::NotifyData::Oc(_)) =>
                 {
                     server_state
                         .connection
                         .as_mut()
                         .unwrap()
-                        .update_outputs(self.root);
+                        .check_primary(self.root);
                 }
+                // Only selected to wake us up while a focus change is deferred.
+                xcb::Event::Input(_) => {}
                 other => {
                     warn!("unhandled event: {other:?}");
                 }
@@ -467,9 +752,129 @@ impl XState {
 
             server_state.run();
         }
+
+        if let Some(connection) = server_state.connection.as_mut() {
+            connection.retry_deferred_focus();
+            connection.check_pings();
+        }
+        budget == 0
+    }
+
+    fn apply_pending_configure(&mut self, window: x::Window) -> Result<(), xcb::ProtocolError> {
+        let Some(pending) = self.pending_configures.remove(&window) else {
+            return Ok(());
+        };
+        self.connection.send_and_check_request(&x::ConfigureWindow {
+            window,
+            value_list: &pending.value_list(),
+        })
+    }
+
+    fn send_configure_notify(
+        &self,
+        window: x::Window,
+        dims: WindowDims,
+    ) -> Result<(), xcb::ProtocolError> {
+        let event = x::ConfigureNotifyEvent::new(
+            window,
+            window,
+            x::WINDOW_NONE,
+            dims.x,
+            dims.y,
+            dims.width,
+            dims.height,
+            0,
+            false,
+        );
+        self.connection.send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(window),
+            event_mask: x::EventMask::STRUCTURE_NOTIFY,
+            event: &event,
+        })
+    }
+
+    /// The gravity and current geometry of a window that asks to be resized without saying where
+    /// it should go. Without a window manager frame, gravity makes no difference otherwise. Both
+    /// come from what is already tracked for the window, so the request isn't held up on the server.
+    fn resize_gravity(
+        e: &x::ConfigureRequestEvent,
+        server_state: &super::RealServerState,
+    ) -> Option<(WinGravity, WindowDims)> {
+        let mask = e.value_mask();
+        let resized_x =
+            mask.contains(x::ConfigWindowMask::WIDTH) && !mask.contains(x::ConfigWindowMask::X);
+        let resized_y =
+            mask.contains(x::ConfigWindowMask::HEIGHT) && !mask.contains(x::ConfigWindowMask::Y);
+        if !resized_x && !resized_y {
+            return None;
+        }
+        let gravity = server_state
+            .window_gravity(e.window())
+            .filter(|gravity| gravity.reference() != (0, 0))?;
+        Some((gravity, server_state.window_dims(e.window())?))
+    }
+
+    /// The area spanned by the monitors with the given Xinerama indices, which
+    /// _NET_WM_FULLSCREEN_MONITORS gives as the top, bottom, left and right edge. Xwayland lists
+    /// its Xinerama screens in the same order as its RandR monitors.
+    fn monitor_area(&self, indices: [u32; 4]) -> Option<(i32, i32, i32, i32)> {
+        let reply = self
+            .connection
+            .wait_for_reply(self.connection.send_request(&xcb::randr::GetMonitors {
+                window: self.root,
+                get_active: true,
+            }))
+            .ok()?;
+        let monitors: Vec<_> = reply.monitors().collect();
+        let [top, bottom, left, right] = indices.map(|index| monitors.get(index as usize));
+        let (top, bottom, left, right) = (top?, bottom?, left?, right?);
+        let x = i32::from(left.x());
+        let y = i32::from(top.y());
+        Some((
+            x,
+            y,
+            i32::from(right.x()) + i32::from(right.width()) - x,
+            i32::from(bottom.y()) + i32::from(bottom.height()) - y,
+        ))
+    }
+
+    /// Whether the window is one that is kept as a toplevel instead of being embedded, like the
+    /// editors of audio plugins hosted by Wine.
+    fn keeps_toplevel(&self, window: x::Window) -> XResult<bool> {
+        if self.config.keep_toplevel.is_empty() {
+            return Ok(false);
+        }
+        let class = self.get_wm_class(window).resolve()?;
+        Ok(class.is_some_and(|class| {
+            self.config
+                .keep_toplevel
+                .iter()
+                .any(|name| *name == class.class || *name == class.instance)
+        }))
+    }
+
+    /// Moves a window back under the root window, staying where it is on screen.
+    fn reparent_to_root(&self, window: x::Window) -> XResult<()> {
+        let position = self
+            .connection
+            .wait_for_reply(self.connection.send_request(&x::TranslateCoordinates {
+                src_window: window,
+                dst_window: self.root,
+                src_x: 0,
+                src_y: 0,
+            }))?;
+        self.connection
+            .send_and_check_request(&x::ReparentWindow {
+                window,
+                parent: self.root,
+                x: position.dst_x(),
+                y: position.dst_y(),
+            })
+            .map_err(MaybeBadWindow::from)
     }
 
-    fn get_window_attributes(&self, window: x::Window) -> XResult<WindowAttributes> {
+    fn get_window_attributes(&mut self, window: x::Window) -> XResult<WindowAttributes> {
         let geometry = self.connection.send_request(&x::GetGeometry {
             drawable: x::Drawable::Window(window),
         });
@@ -481,18 +886,44 @@ impl XState {
         let class = self.get_wm_class(window);
         let wm_hints = self.get_wm_hints(window);
         let size_hints = self.get_wm_size_hints(window);
+        let transient_for = self.get_transient_for(window);
+        let net_wm_state = self.get_net_wm_state(window);
+        let bypass_compositor = self.get_bypass_compositor(window);
+        let window_type = self.get_window_type(window);
+        let frame_extents = self.get_frame_extents(window);
+        let role_override = self.get_role_override(window);
+        let wm_role = self.get_wm_role(window);
+        let motif_no_resize = self.get_motif_no_resize(window);
+        let take_focus = self.get_take_focus(window);
 
         let geometry = self.connection.wait_for_reply(geometry)?;
         debug!("{window:?} geometry: {geometry:?}");
         let attrs = self.connection.wait_for_reply(attrs)?;
-        let mut title = name.resolve()?;
-        if title.is_none() {
-            title = self.get_wm_name(window).resolve()?;
-        }
+        let title = match name.resolve()? {
+            Some(title) => Some(title),
+            None => self.get_wm_name(window).resolve()?,
+        };
 
         let class = class.resolve()?;
         let wm_hints = wm_hints.resolve()?;
         let size_hints = size_hints.resolve()?;
+        let transient_for = transient_for.resolve()?.flatten();
+        let modal = net_wm_state
+            .resolve()?
+            .is_some_and(|state| state.contains(&self.atoms.wm_modal));
+        let bypass_compositor = bypass_compositor.resolve()?.unwrap_or_default();
+        let window_type = window_type.resolve()?.unwrap_or_default();
+        let frame_extents = frame_extents.resolve()?.unwrap_or_default();
+        let role_override = role_override.resolve()?.flatten();
+        let wm_role = wm_role.resolve()?.flatten();
+        let motif_no_resize = motif_no_resize.resolve()?.unwrap_or_default();
+        let take_focus = take_focus.resolve()?.unwrap_or_default();
+
+        let visible_name = title
+            .as_ref()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| name.name());
+        self.set_visible_name(window, visible_name);
 
         Ok(WindowAttributes {
             override_redirect: attrs.override_redirect(),
@@ -503,10 +934,23 @@ impl XState {
                 width: geometry.width(),
                 height: geometry.height(),
             },
-            title,
+            title: title.map(|(name, _)| name),
             class,
-            group: wm_hints.and_then(|h| h.window_group),
+            group: wm_hints.as_ref().and_then(|h| h.window_group),
+            no_input: wm_hints.is_some_and(|h| h.input == Some(false)),
+            take_focus,
+            fullscreen_monitors: None,
             size_hints,
+            transient_for,
+            modal,
+            bypass_compositor,
+            frame_extents,
+            // 32 bit visuals are the ones with an alpha channel.
+            opaque: geometry.depth() != 32,
+            window_type,
+            role_override,
+            wm_role,
+            motif_no_resize,
         })
     }
 
@@ -525,6 +969,17 @@ impl XState {
         if let Some(hints) = attrs.size_hints {
             server_state.set_size_hints(window, hints);
         }
+        server_state.set_transient_for(window, attrs.transient_for);
+        server_state.set_modal(window, attrs.modal);
+        server_state.set_bypass_compositor(window, attrs.bypass_compositor);
+        server_state.set_frame_extents(window, attrs.frame_extents);
+        server_state.set_opaque(window, attrs.opaque);
+        server_state.set_window_type(window, attrs.window_type);
+        server_state.set_role_override(window, attrs.role_override);
+        server_state.set_wm_role(window, attrs.wm_role);
+        server_state.set_motif_no_resize(window, attrs.motif_no_resize);
+        server_state.set_no_input(window, attrs.no_input);
+        server_state.set_take_focus(window, attrs.take_focus);
     }
 
     fn get_property_cookie(
@@ -547,24 +1002,21 @@ impl XState {
     fn get_wm_class(
         &self,
         window: x::Window,
-    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = String>> {
+    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = WmClass>> {
         let cookie = self.get_property_cookie(window, x::ATOM_WM_CLASS, x::ATOM_STRING, 256);
         let resolver = move |reply: x::GetPropertyReply| {
             let data: &[u8] = reply.value();
             trace!("wm class data: {data:?}");
-            // wm class (normally) is instance + class - ignore instance
-            let class_start = if let Some(p) = data.iter().copied().position(|b| b == 0u8) {
-                p + 1
-            } else {
-                0
-            };
-            let mut data = data[class_start..].to_vec();
-            if data.last().copied().unwrap() != 0 {
-                data.push(0);
-            }
-            let class = CString::from_vec_with_nul(data).unwrap();
+            // wm class is (normally) instance + class, both null terminated
+            let mut parts = data.split(|b| *b == 0).map(sanitize_utf8);
+            let instance = parts.next().unwrap_or_default();
+            let class = parts
+                .next()
+                .filter(|class| !class.is_empty())
+                .unwrap_or_else(|| instance.clone());
+            let class = WmClass { instance, class };
             trace!("{:?} class: {class:?}", window);
-            class.to_string_lossy().to_string()
+            class
         };
         PropertyCookieWrapper {
             connection: &self.connection,
@@ -573,18 +1025,33 @@ impl XState {
         }
     }
 
+    /// Also says whether the name had to be changed before it can be shown, see
+    /// [`Self::set_visible_name`].
     fn get_wm_name(
         &self,
         window: x::Window,
-    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = WmName>> {
-        let cookie = self.get_property_cookie(window, x::ATOM_WM_NAME, x::ATOM_STRING, 256);
-        let resolver = |reply: x::GetPropertyReply| {
+    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = (WmName, bool)>> {
+        let cookie = self.get_property_cookie(window, x::ATOM_WM_NAME, x::ATOM_ANY, 256);
+        let compound_text = self.atoms.compound_text;
+        let transliterator = self.title_transliterator.clone();
+        let resolver = move |reply: x::GetPropertyReply| {
             let data: &[u8] = reply.value();
-            // strip trailing zeros or wayland-rs will lose its mind
-            // https://github.com/Smithay/wayland-rs/issues/748
-            let data = data.split(|byte| *byte == 0).next().unwrap();
-            let name = String::from_utf8_lossy(data).to_string();
-            WmName::WmName(name)
+            // WM_NAME is a text property, so older clients may set it in a legacy encoding
+            let encoding = match reply.r#type() {
+                x::ATOM_STRING => Some(LegacyEncoding::Latin1),
+                ty if ty == compound_text => Some(LegacyEncoding::CompoundText),
+                _ => None,
+            };
+            let name = match encoding {
+                Some(encoding) => sanitize_utf8(encoding.decode(data).as_bytes()),
+                None => sanitize_utf8(data),
+            };
+            let shown = match &transliterator {
+                Some(t) => t.apply(&name),
+                None => name.clone(),
+            };
+            let changed = shown != name;
+            (WmName::WmName(shown), changed)
         };
 
         PropertyCookieWrapper {
@@ -594,17 +1061,68 @@ impl XState {
         }
     }
 
+    /// Also says whether the name had to be changed before it can be shown, see
+    /// [`Self::set_visible_name`].
     fn get_net_wm_name(
         &self,
         window: x::Window,
-    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = WmName>> {
+    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = (WmName, bool)>> {
         let cookie =
             self.get_property_cookie(window, self.atoms.net_wm_name, self.atoms.utf8_string, 256);
-        let resolver = |reply: x::GetPropertyReply| {
+        let transliterator = self.title_transliterator.clone();
+        let resolver = move |reply: x::GetPropertyReply| {
             let data: &[u8] = reply.value();
-            let data = data.split(|byte| *byte == 0).next().unwrap();
-            let name = String::from_utf8_lossy(data).to_string();
-            WmName::NetWmName(name)
+            let name = sanitize_utf8(data);
+            let shown = match &transliterator {
+                Some(t) => t.apply(&name),
+                None => name.clone(),
+            };
+            let changed = shown != name;
+            (WmName::NetWmName(shown), changed)
+        };
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    /// Sets _NET_WM_VISIBLE_NAME on the window, or removes it if `name` is `None`. This lets X11
+    /// pagers and taskbars know when the compositor shows a different title than the window's
+    /// name, i.e. when it had to be transliterated.
+    fn set_visible_name(&mut self, window: x::Window, name: Option<&str>) {
+        let res = match name {
+            Some(name) => {
+                self.visible_names.insert(window);
+                self.connection.send_and_check_request(&x::ChangeProperty {
+                    mode: x::PropMode::Replace,
+                    window,
+                    property: self.atoms.net_wm_visible_name,
+                    r#type: self.atoms.utf8_string,
+                    data: name.as_bytes(),
+                })
+            }
+            // Nothing to remove if we never set one.
+            None if !self.visible_names.remove(&window) => return,
+            None => self.connection.send_and_check_request(&x::DeleteProperty {
+                window,
+                property: self.atoms.net_wm_visible_name,
+            }),
+        };
+        if let Err(e) = res {
+            debug!("Failed to update visible name of {window:?} ({e:?})");
+        }
+    }
+
+    fn get_transient_for(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = Option<x::Window>>> {
+        let cookie = self.get_property_cookie(window, x::ATOM_WM_TRANSIENT_FOR, x::ATOM_WINDOW, 1);
+        let resolver = |reply: x::GetPropertyReply| {
+            let data: &[x::Window] = reply.value();
+            data.first().copied().filter(|parent| !parent.is_none())
         };
 
         PropertyCookieWrapper {
@@ -614,6 +1132,20 @@ impl XState {
         }
     }
 
+    fn get_net_wm_state(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = Vec<x::Atom>>> {
+        let cookie = self.get_property_cookie(window, self.atoms.net_wm_state, x::ATOM_ATOM, 32);
+        let resolver = |reply: x::GetPropertyReply| reply.value::<x::Atom>().to_vec();
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
     fn get_wm_hints(
         &self,
         window: x::Window,
@@ -632,15 +1164,58 @@ impl XState {
         }
     }
 
-    fn get_wm_size_hints(
+    /// Whether the window asked for compositing to be turned off with
+    /// _NET_WM_BYPASS_COMPOSITOR. A value of 2 asks to keep compositing, which is the default here
+    /// anyway.
+    fn get_bypass_compositor(
         &self,
         window: x::Window,
-    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = WmNormalHints>> {
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = bool>> {
+        let cookie = self.get_property_cookie(
+            window,
+            self.atoms.net_wm_bypass_compositor,
+            x::ATOM_CARDINAL,
+            1,
+        );
+        let resolver = |reply: x::GetPropertyReply| reply.value::<u32>().first() == Some(&1);
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    fn get_window_type(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = WindowType>> {
         let cookie =
-            self.get_property_cookie(window, x::ATOM_WM_NORMAL_HINTS, x::ATOM_WM_SIZE_HINTS, 9);
-        let resolver = |reply: x::GetPropertyReply| {
-            let data: &[u32] = reply.value();
-            WmNormalHints::from(data)
+            self.get_property_cookie(window, self.atoms.net_wm_window_type, x::ATOM_ATOM, 8);
+        let window_types = self.window_types.clone();
+        let resolver = move |reply: x::GetPropertyReply| window_types.resolve(reply.value());
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    fn get_role_override(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = Option<RoleOverride>>> {
+        let cookie = self.get_property_cookie(window, self.atoms.role_override, x::ATOM_STRING, 16);
+        let resolver = move |reply: x::GetPropertyReply| {
+            if reply.value::<u8>().is_empty() {
+                return None;
+            }
+            let role = sanitize_utf8(reply.value());
+            role.trim_end_matches('\0')
+                .parse()
+                .inspect_err(|e| warn!("ignoring _XWLS_ROLE of {window:?}: {e}"))
+                .ok()
         };
 
         PropertyCookieWrapper {
@@ -650,60 +1225,302 @@ impl XState {
         }
     }
 
-    fn handle_property_change(
-        &mut self,
-        event: x::PropertyNotifyEvent,
-        server_state: &mut super::RealServerState,
-    ) {
-        if event.state() != x::Property::NewValue {
-            debug!(
-                "ignoring non newvalue for property {:?}",
-                get_atom_name(&self.connection, event.atom())
-            );
-            return;
+    fn get_wm_role(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = Option<String>>> {
+        let cookie =
+            self.get_property_cookie(window, self.atoms.wm_window_role, x::ATOM_STRING, 64);
+        let resolver = |reply: x::GetPropertyReply| {
+            let role = sanitize_utf8(reply.value());
+            let role = role.trim_end_matches('\0');
+            (!role.is_empty()).then(|| role.to_string())
+        };
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
         }
+    }
 
-        let window = event.window();
+    /// Whether the functions in _MOTIF_WM_HINTS leave out resizing.
+    fn get_motif_no_resize(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = bool>> {
+        const HINTS_FUNCTIONS: u32 = 1;
+        const FUNC_ALL: u32 = 1;
+        const FUNC_RESIZE: u32 = 2;
 
-        match event.atom() {
-            x if x == x::ATOM_WM_HINTS => {
-                let hints =
-                    unwrap_or_skip_bad_window!(self.get_wm_hints(window).resolve()).unwrap();
-                server_state.set_win_hints(window, hints);
-            }
-            x if x == x::ATOM_WM_NORMAL_HINTS => {
-                let hints =
-                    unwrap_or_skip_bad_window!(self.get_wm_size_hints(window).resolve()).unwrap();
-                server_state.set_size_hints(window, hints);
-            }
-            x if x == x::ATOM_WM_NAME => {
-                let name = unwrap_or_skip_bad_window!(self.get_wm_name(window).resolve()).unwrap();
-                server_state.set_win_title(window, name);
-            }
-            x if x == self.atoms.net_wm_name => {
-                let name =
-                    unwrap_or_skip_bad_window!(self.get_net_wm_name(window).resolve()).unwrap();
-                server_state.set_win_title(window, name);
-            }
-            x if x == x::ATOM_WM_CLASS => {
-                let class =
-                    unwrap_or_skip_bad_window!(self.get_wm_class(window).resolve()).unwrap();
-                server_state.set_win_class(window, class);
+        let cookie = self.get_property_cookie(window, self.atoms.motif_wm_hints, x::ATOM_ANY, 5);
+        let resolver = |reply: x::GetPropertyReply| {
+            let &[flags, functions, ..] = reply.value::<u32>() else {
+                return false;
+            };
+            if flags & HINTS_FUNCTIONS == 0 {
+                return false;
             }
-            _ => {
-                if !self.handle_selection_property_change(&event)
-                    && log::log_enabled!(log::Level::Debug)
-                {
-                    debug!(
-                        "changed property {:?} for {:?}",
-                        get_atom_name(&self.connection, event.atom()),
-                        window
-                    );
-                }
+            // With FUNC_ALL, the other bits are the functions that are left out.
+            let resize = functions & FUNC_RESIZE != 0;
+            if functions & FUNC_ALL != 0 {
+                resize
+            } else {
+                !resize
             }
+        };
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
         }
     }
-}
+
+    fn get_take_focus(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = bool>> {
+        let cookie = self.get_property_cookie(window, self.atoms.wm_protocols, x::ATOM_ATOM, 10);
+        let take_focus = self.atoms.wm_take_focus;
+        let resolver =
+            move |reply: x::GetPropertyReply| reply.value::<x::Atom>().contains(&take_focus);
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    fn get_frame_extents(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<'_, impl PropertyResolver<Output = FrameExtents>> {
+        let cookie =
+            self.get_property_cookie(window, self.atoms.gtk_frame_extents, x::ATOM_CARDINAL, 4);
+        // Anything bigger than the window itself is nonsense, and gets clamped when it's applied.
+        let resolver = |reply: x::GetPropertyReply| match reply.value::<u32>() {
+            &[left, right, top, bottom] => FrameExtents {
+                left: left.min(i16::MAX as u32) as i32,
+                right: right.min(i16::MAX as u32) as i32,
+                top: top.min(i16::MAX as u32) as i32,
+                bottom: bottom.min(i16::MAX as u32) as i32,
+            },
+            _ => FrameExtents::default(),
+        };
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    /// Reads a window's _NET_WM_USER_TIME, following _NET_WM_USER_TIME_WINDOW if it has one.
+    /// https://specifications.freedesktop.org/wm-spec/1.5/ar01s05.html#id-1.6.13
+    fn get_user_time(&mut self, window: x::Window) -> XResult<Option<u32>> {
+        let cookie = self.get_property_cookie(
+            window,
+            self.atoms.net_wm_user_time_window,
+            x::ATOM_WINDOW,
+            1,
+        );
+        let reply = self.connection.wait_for_reply(cookie)?;
+        let time_window = reply
+            .value::<x::Window>()
+            .first()
+            .copied()
+            .filter(|w| !w.is_none() && *w != window);
+
+        let source = match time_window {
+            Some(time_window) => {
+                // Clients update the user time window instead of the window itself, so we need
+                // its property changes too.
+                if let Err(e) = self
+                    .connection
+                    .send_and_check_request(&x::ChangeWindowAttributes {
+                        window: time_window,
+                        value_list: &[x::Cw::EventMask(x::EventMask::PROPERTY_CHANGE)],
+                    })
+                {
+                    debug!("{window:?} has an invalid user time window ({e:?})");
+                    return Ok(None);
+                }
+                self.user_time_windows.insert(time_window, window);
+                time_window
+            }
+            None => window,
+        };
+
+        let cookie =
+            self.get_property_cookie(source, self.atoms.net_wm_user_time, x::ATOM_CARDINAL, 1);
+        match self.connection.wait_for_reply(cookie) {
+            Ok(reply) => Ok(reply.value::<u32>().first().copied()),
+            Err(_) if source != window => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Gets the _NET_WM_PID of a window, and the _NET_STARTUP_ID it was launched with. Toolkits
+    /// tend to put the startup ID on the group leader instead of the window itself.
+    fn get_startup_id(
+        &self,
+        window: x::Window,
+        leader: Option<x::Window>,
+    ) -> XResult<(Option<u32>, Option<String>)> {
+        let pid = self.get_property_cookie(window, self.atoms.wm_pid, x::ATOM_CARDINAL, 1);
+        let startup_id =
+            |window| self.get_property_cookie(window, self.atoms.net_startup_id, x::ATOM_ANY, 256);
+        let own_id = startup_id(window);
+        let leader_id = leader.filter(|leader| *leader != window).map(startup_id);
+
+        let to_string = |reply: x::GetPropertyReply| {
+            Some(sanitize_utf8(reply.value())).filter(|id| !id.is_empty())
+        };
+        let pid = self
+            .connection
+            .wait_for_reply(pid)?
+            .value::<u32>()
+            .first()
+            .copied();
+        let mut id = to_string(self.connection.wait_for_reply(own_id)?);
+        if let Some(leader_id) = leader_id {
+            // The leader isn't ours to vouch for, so it going away is no reason to skip the window.
+            let leader_id = self
+                .connection
+                .wait_for_reply(leader_id)
+                .ok()
+                .and_then(to_string);
+            id = id.or(leader_id);
+        }
+        Ok((pid, id))
+    }
+
+    fn get_wm_size_hints(
+        &self,
+        window: x::Window,
+    ) -> PropertyCookieWrapper<impl PropertyResolver<Output = WmNormalHints>> {
+        let cookie =
+            self.get_property_cookie(window, x::ATOM_WM_NORMAL_HINTS, x::ATOM_WM_SIZE_HINTS, 18);
+        let resolver = |reply: x::GetPropertyReply| {
+            let data: &[u32] = reply.value();
+            WmNormalHints::from(data)
+        };
+
+        PropertyCookieWrapper {
+            connection: &self.connection,
+            cookie,
+            resolver,
+        }
+    }
+
+    fn handle_property_change(
+        &mut self,
+        event: x::PropertyNotifyEvent,
+        server_state: &mut super::RealServerState,
+    ) {
+        if event.state() != x::Property::NewValue {
+            debug!(
+                "ignoring non newvalue for property {:?}",
+                get_atom_name(&self.connection, event.atom())
+            );
+            return;
+        }
+
+        let window = event.window();
+
+        match event.atom() {
+            x if x == x::ATOM_WM_HINTS => {
+                let hints =
+                    unwrap_or_skip_bad_window!(self.get_wm_hints(window).resolve()).unwrap();
+                server_state.set_win_hints(window, hints);
+            }
+            x if x == x::ATOM_WM_NORMAL_HINTS => {
+                let hints =
+                    unwrap_or_skip_bad_window!(self.get_wm_size_hints(window).resolve()).unwrap();
+                server_state.set_size_hints(window, hints);
+            }
+            x if x == x::ATOM_WM_TRANSIENT_FOR => {
+                let parent =
+                    unwrap_or_skip_bad_window!(self.get_transient_for(window).resolve()).flatten();
+                server_state.set_transient_for(window, parent);
+            }
+            x if x == x::ATOM_WM_NAME => {
+                let (name, changed) =
+                    unwrap_or_skip_bad_window!(self.get_wm_name(window).resolve()).unwrap();
+                // The _NET_WM_NAME is what's shown then, see set_win_title.
+                if !server_state.window_has_net_wm_name(window) {
+                    self.set_visible_name(window, changed.then_some(name.name()));
+                }
+                server_state.set_win_title(window, name);
+            }
+            x if x == self.atoms.net_wm_name => {
+                let (name, changed) =
+                    unwrap_or_skip_bad_window!(self.get_net_wm_name(window).resolve()).unwrap();
+                self.set_visible_name(window, changed.then_some(name.name()));
+                server_state.set_win_title(window, name);
+            }
+            x if x == self.atoms.net_wm_user_time => {
+                let cookie = self.get_property_cookie(window, x, x::ATOM_CARDINAL, 1);
+                let reply = unwrap_or_skip_bad_window!(self.connection.wait_for_reply(cookie));
+                let window = self
+                    .user_time_windows
+                    .get(&window)
+                    .copied()
+                    .unwrap_or(window);
+                server_state.set_user_time(window, reply.value::<u32>().first().copied());
+            }
+            x if x == self.atoms.gtk_frame_extents => {
+                let extents = unwrap_or_skip_bad_window!(self.get_frame_extents(window).resolve())
+                    .unwrap_or_default();
+                server_state.set_frame_extents(window, extents);
+            }
+            x if x == self.atoms.role_override => {
+                let role =
+                    unwrap_or_skip_bad_window!(self.get_role_override(window).resolve()).flatten();
+                server_state.set_role_override(window, role);
+            }
+            x if x == self.atoms.motif_wm_hints => {
+                let no_resize =
+                    unwrap_or_skip_bad_window!(self.get_motif_no_resize(window).resolve())
+                        .unwrap_or_default();
+                server_state.set_motif_no_resize(window, no_resize);
+            }
+            x if x == self.atoms.wm_protocols => {
+                let take_focus = unwrap_or_skip_bad_window!(self.get_take_focus(window).resolve())
+                    .unwrap_or_default();
+                server_state.set_take_focus(window, take_focus);
+            }
+            x if x == self.atoms.wm_window_role => {
+                let role = unwrap_or_skip_bad_window!(self.get_wm_role(window).resolve()).flatten();
+                server_state.set_wm_role(window, role);
+            }
+            x if x == self.atoms.net_wm_bypass_compositor => {
+                let bypass =
+                    unwrap_or_skip_bad_window!(self.get_bypass_compositor(window).resolve())
+                        .unwrap_or_default();
+                server_state.set_bypass_compositor(window, bypass);
+            }
+            x if x == x::ATOM_WM_CLASS => {
+                let class =
+                    unwrap_or_skip_bad_window!(self.get_wm_class(window).resolve()).unwrap();
+                server_state.set_win_class(window, class);
+            }
+            _ => {
+                if !self.handle_selection_property_change(&event)
+                    && log::log_enabled!(log::Level::Debug)
+                {
+                    debug!(
+                        "changed property {:?} for {:?}",
+                        get_atom_name(&self.connection, event.atom()),
+                        window
+                    );
+                }
+            }
+        }
+    }
+}
 
 xcb::atoms_struct! {
     #[derive(Clone, Debug)]
@@ -712,16 +1529,49 @@ xcb::atoms_struct! {
         pub wl_surface_serial => b"WL_SURFACE_SERIAL" only_if_exists = false,
         pub wm_protocols => b"WM_PROTOCOLS" only_if_exists = false,
         pub wm_delete_window => b"WM_DELETE_WINDOW" only_if_exists = false,
+        pub wm_take_focus => b"WM_TAKE_FOCUS" only_if_exists = false,
+        pub timestamp_prop => b"_XWLS_TIMESTAMP" only_if_exists = false,
+        pub last_error => b"_XWLS_LAST_ERROR" only_if_exists = false,
+        pub pointer_constraint => b"_XWLS_POINTER_CONSTRAINT" only_if_exists = false,
+        pub role_override => b"_XWLS_ROLE" only_if_exists = false,
+        pub wm_window_role => b"WM_WINDOW_ROLE" only_if_exists = false,
+        pub motif_wm_hints => b"_MOTIF_WM_HINTS" only_if_exists = false,
+        pub net_wm_ping => b"_NET_WM_PING" only_if_exists = false,
+        pub net_close_window => b"_NET_CLOSE_WINDOW" only_if_exists = false,
+        pub net_restack_window => b"_NET_RESTACK_WINDOW" only_if_exists = false,
+        pub net_startup_id => b"_NET_STARTUP_ID" only_if_exists = false,
+        pub net_wm_moveresize => b"_NET_WM_MOVERESIZE" only_if_exists = false,
+        pub net_wm_fullscreen_monitors => b"_NET_WM_FULLSCREEN_MONITORS" only_if_exists = false,
+        pub net_desktop_geometry => b"_NET_DESKTOP_GEOMETRY" only_if_exists = false,
+        pub net_desktop_viewport => b"_NET_DESKTOP_VIEWPORT" only_if_exists = false,
+        pub net_workarea => b"_NET_WORKAREA" only_if_exists = false,
+        pub net_number_of_desktops => b"_NET_NUMBER_OF_DESKTOPS" only_if_exists = false,
+        pub net_current_desktop => b"_NET_CURRENT_DESKTOP" only_if_exists = false,
+        pub net_wm_user_time => b"_NET_WM_USER_TIME" only_if_exists = false,
+        pub net_wm_user_time_window => b"_NET_WM_USER_TIME_WINDOW" only_if_exists = false,
+        pub net_wm_bypass_compositor => b"_NET_WM_BYPASS_COMPOSITOR" only_if_exists = false,
+        pub net_wm_window_type => b"_NET_WM_WINDOW_TYPE" only_if_exists = false,
+        pub gtk_frame_extents => b"_GTK_FRAME_EXTENTS" only_if_exists = false,
+        pub gtk_edge_constraints => b"_GTK_EDGE_CONSTRAINTS" only_if_exists = false,
         pub wm_transient_for => b"WM_TRANSIENT_FOR" only_if_exists = false,
         pub wm_check => b"_NET_SUPPORTING_WM_CHECK" only_if_exists = false,
         pub net_wm_name => b"_NET_WM_NAME" only_if_exists = false,
+        pub net_wm_visible_name => b"_NET_WM_VISIBLE_NAME" only_if_exists = false,
         pub wm_pid => b"_NET_WM_PID" only_if_exists = false,
         pub net_wm_state => b"_NET_WM_STATE" only_if_exists = false,
         pub wm_fullscreen => b"_NET_WM_STATE_FULLSCREEN" only_if_exists = false,
+        pub wm_maximized_vert => b"_NET_WM_STATE_MAXIMIZED_VERT" only_if_exists = false,
+        pub wm_maximized_horz => b"_NET_WM_STATE_MAXIMIZED_HORZ" only_if_exists = false,
+        pub wm_hidden => b"_NET_WM_STATE_HIDDEN" only_if_exists = false,
+        pub wm_modal => b"_NET_WM_STATE_MODAL" only_if_exists = false,
+        pub wm_demands_attention => b"_NET_WM_STATE_DEMANDS_ATTENTION" only_if_exists = false,
+        pub wm_state => b"WM_STATE" only_if_exists = false,
+        pub wm_change_state => b"WM_CHANGE_STATE" only_if_exists = false,
         pub active_win => b"_NET_ACTIVE_WINDOW" only_if_exists = false,
         pub client_list => b"_NET_CLIENT_LIST" only_if_exists = false,
         pub supported => b"_NET_SUPPORTED" only_if_exists = false,
         pub utf8_string => b"UTF8_STRING" only_if_exists = false,
+        pub compound_text => b"COMPOUND_TEXT" only_if_exists = false,
         pub clipboard => b"CLIPBOARD" only_if_exists = false,
         pub targets => b"TARGETS" only_if_exists = false,
         pub save_targets => b"SAVE_TARGETS" only_if_exists = false,
@@ -729,16 +1579,97 @@ xcb::atoms_struct! {
         pub timestamp => b"TIMESTAMP" only_if_exists = false,
         pub selection_reply => b"_selection_reply" only_if_exists = false,
         pub incr => b"INCR" only_if_exists = false,
+        pub edid => b"EDID" only_if_exists = false,
     }
 }
 
 xcb::atoms_struct! {
+    #[derive(Clone, Debug)]
     pub struct WindowTypes {
         pub normal => b"_NET_WM_WINDOW_TYPE_NORMAL" only_if_exists = false,
         pub dialog => b"_NET_WM_WINDOW_TYPE_DIALOG" only_if_exists = false,
         pub splash => b"_NET_WM_WINDOW_TYPE_SPLASH" only_if_exists = false,
         pub menu => b"_NET_WM_WINDOW_TYPE_MENU" only_if_exists = false,
+        pub dropdown_menu => b"_NET_WM_WINDOW_TYPE_DROPDOWN_MENU" only_if_exists = false,
+        pub popup_menu => b"_NET_WM_WINDOW_TYPE_POPUP_MENU" only_if_exists = false,
         pub utility => b"_NET_WM_WINDOW_TYPE_UTILITY" only_if_exists = false,
+        pub tooltip => b"_NET_WM_WINDOW_TYPE_TOOLTIP" only_if_exists = false,
+        pub combo => b"_NET_WM_WINDOW_TYPE_COMBO" only_if_exists = false,
+    }
+}
+
+/// The role a user forced on a window with _XWLS_ROLE, for when satellite guesses wrong.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RoleOverride {
+    Toplevel,
+    Popup,
+    /// A toplevel that is made fullscreen when it's mapped.
+    Fullscreen,
+}
+
+impl std::str::FromStr for RoleOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toplevel" => Ok(Self::Toplevel),
+            "popup" => Ok(Self::Popup),
+            "fullscreen" => Ok(Self::Fullscreen),
+            other => Err(format!("unknown role: {other}")),
+        }
+    }
+}
+
+/// What a window is for, from _NET_WM_WINDOW_TYPE.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum WindowType {
+    #[default]
+    Normal,
+    Dialog,
+    Splash,
+    /// Menus of any kind, including dropdown and context menus.
+    Menu,
+    Utility,
+    Tooltip,
+    /// The list of a combo box.
+    Combo,
+}
+
+impl std::str::FromStr for WindowType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "normal" => Ok(Self::Normal),
+            "dialog" => Ok(Self::Dialog),
+            "splash" => Ok(Self::Splash),
+            "menu" => Ok(Self::Menu),
+            "utility" => Ok(Self::Utility),
+            "tooltip" => Ok(Self::Tooltip),
+            "combo" => Ok(Self::Combo),
+            other => Err(format!("unknown window type: {other}")),
+        }
+    }
+}
+
+impl WindowTypes {
+    /// Picks the first type in the property that we know, as the spec asks window managers to.
+    fn resolve(&self, types: &[x::Atom]) -> WindowType {
+        types
+            .iter()
+            .find_map(|ty| match *ty {
+                ty if ty == self.normal => Some(WindowType::Normal),
+                ty if ty == self.dialog => Some(WindowType::Dialog),
+                ty if ty == self.splash => Some(WindowType::Splash),
+                ty if [self.menu, self.dropdown_menu, self.popup_menu].contains(&ty) => {
+                    Some(WindowType::Menu)
+                }
+                ty if ty == self.utility => Some(WindowType::Utility),
+                ty if ty == self.tooltip => Some(WindowType::Tooltip),
+                ty if ty == self.combo => Some(WindowType::Combo),
+                _ => None,
+            })
+            .unwrap_or_default()
     }
 }
 
@@ -756,16 +1687,91 @@ bitflags! {
     pub struct WmSizeHintsFlags: u32 {
         const ProgramMinSize = 16;
         const ProgramMaxSize = 32;
+        const ProgramResizeIncrements = 64;
+        const ProgramAspect = 128;
+        const ProgramBaseSize = 256;
+        const ProgramWinGravity = 512;
+    }
+}
+
+/// The point of a window that stays in place when it's resized, from WM_NORMAL_HINTS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum WinGravity {
+    #[default]
+    NorthWest,
+    North,
+    NorthEast,
+    West,
+    Center,
+    East,
+    SouthWest,
+    South,
+    SouthEast,
+    Static,
+}
+
+impl TryFrom<u32> for WinGravity {
+    type Error = ();
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(Self::NorthWest),
+            2 => Ok(Self::North),
+            3 => Ok(Self::NorthEast),
+            4 => Ok(Self::West),
+            5 => Ok(Self::Center),
+            6 => Ok(Self::East),
+            7 => Ok(Self::SouthWest),
+            8 => Ok(Self::South),
+            9 => Ok(Self::SouthEast),
+            10 => Ok(Self::Static),
+            _ => Err(()),
+        }
+    }
+}
+
+impl WinGravity {
+    /// How far across the window the reference point is horizontally and vertically, in halves.
+    fn reference(self) -> (i32, i32) {
+        match self {
+            Self::NorthWest | Self::Static => (0, 0),
+            Self::North => (1, 0),
+            Self::NorthEast => (2, 0),
+            Self::West => (0, 1),
+            Self::Center => (1, 1),
+            Self::East => (2, 1),
+            Self::SouthWest => (0, 2),
+            Self::South => (1, 2),
+            Self::SouthEast => (2, 2),
+        }
+    }
+
+    /// Where a window at `position` has to move to when it's resized from `old` to `new`, so
+    /// that the reference point stays put.
+    fn anchor(self, position: i32, old: i32, new: i32, horizontal: bool) -> i32 {
+        let (x, y) = self.reference();
+        let halves = if horizontal { x } else { y };
+        position + (old - new) * halves / 2
     }
 }
 
 bitflags! {
     /// https://tronche.com/gui/x/icccm/sec-4.html#s-4.1.2.4
     pub struct WmHintsFlags: u32 {
+        const Input = 1;
         const WindowGroup = 64;
+        const Urgency = 256;
     }
 }
 
+/// The invisible border GTK draws client-side shadows in, from _GTK_FRAME_EXTENTS.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameExtents {
+    pub left: i32,
+    pub right: i32,
+    pub top: i32,
+    pub bottom: i32,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub struct WinSize {
     pub width: i32,
@@ -776,25 +1782,109 @@ pub struct WinSize {
 pub struct WmNormalHints {
     pub min_size: Option<WinSize>,
     pub max_size: Option<WinSize>,
+    /// The steps the window wants to be resized in, i.e. a terminal's cell size.
+    pub resize_increments: Option<WinSize>,
+    /// The minimum and maximum aspect ratio, each as a width/height fraction.
+    pub aspect: Option<(WinSize, WinSize)>,
+    pub base_size: Option<WinSize>,
+    pub win_gravity: Option<WinGravity>,
+}
+
+impl WmNormalHints {
+    /// The minimum size, which ICCCM says the base size stands in for when it's missing.
+    pub fn effective_min_size(&self) -> Option<&WinSize> {
+        self.min_size.as_ref().or(self.base_size.as_ref())
+    }
+
+    /// Shrinks a size to the closest one the window's aspect ratio and resize increments allow.
+    pub fn constrain(&self, width: i32, height: i32) -> (i32, i32) {
+        let (base_width, base_height) = self
+            .base_size
+            .as_ref()
+            .or(self.min_size.as_ref())
+            .map_or((0, 0), |base| (base.width, base.height));
+        let (mut width, mut height) = (width, height);
+
+        // Per ICCCM, the aspect ratio only applies to the part of the window beyond its base
+        // size, and only an explicit base size counts for that.
+        if let Some((min, max)) = &self.aspect {
+            let (base_width, base_height) = self
+                .base_size
+                .as_ref()
+                .map_or((0, 0), |base| (base.width, base.height));
+            let extra_width = i64::from((width - base_width).max(0));
+            let extra_height = i64::from((height - base_height).max(0));
+            let valid = |ratio: &WinSize| ratio.width > 0 && ratio.height > 0;
+            if valid(min)
+                && extra_width * i64::from(min.height) < extra_height * i64::from(min.width)
+            {
+                // Too narrow, so lose some height.
+                let extra = extra_width * i64::from(min.height) / i64::from(min.width);
+                height = base_height + extra as i32;
+            } else if valid(max)
+                && extra_width * i64::from(max.height) > extra_height * i64::from(max.width)
+            {
+                // Too wide, so lose some width.
+                let extra = extra_height * i64::from(max.width) / i64::from(max.height);
+                width = base_width + extra as i32;
+            }
+        }
+
+        if let Some(increments) = &self.resize_increments {
+            let snap = |size: i32, base: i32, increment: i32| {
+                if increment > 1 && size > base {
+                    size - (size - base) % increment
+                } else {
+                    size
+                }
+            };
+            width = snap(width, base_width, increments.width);
+            height = snap(height, base_height, increments.height);
+        }
+
+        if let Some(min) = self.effective_min_size() {
+            width = width.max(min.width);
+            height = height.max(min.height);
+        }
+        (width, height)
+    }
 }
 
 impl From<&[u32]> for WmNormalHints {
     fn from(value: &[u32]) -> Self {
         let mut ret = Self::default();
-        let flags = WmSizeHintsFlags::from_bits_truncate(value[0]);
+        let Some(flags) = value.first() else {
+            return ret;
+        };
+        let flags = WmSizeHintsFlags::from_bits_truncate(*flags);
+        // Truncated properties just leave out the hints that didn't fit
+        let size = |idx: usize| {
+            value.get(idx..idx + 2).map(|size| WinSize {
+                width: size[0] as _,
+                height: size[1] as _,
+            })
+        };
 
         if flags.contains(WmSizeHintsFlags::ProgramMinSize) {
-            ret.min_size = Some(WinSize {
-                width: value[5] as _,
-                height: value[6] as _,
-            });
+            ret.min_size = size(5);
         }
 
         if flags.contains(WmSizeHintsFlags::ProgramMaxSize) {
-            ret.max_size = Some(WinSize {
-                width: value[7] as _,
-                height: value[8] as _,
-            });
+            ret.max_size = size(7);
+        }
+        if flags.contains(WmSizeHintsFlags::ProgramResizeIncrements) {
+            ret.resize_increments = size(9);
+        }
+        if flags.contains(WmSizeHintsFlags::ProgramAspect) {
+            ret.aspect = size(11).zip(size(13));
+        }
+        if flags.contains(WmSizeHintsFlags::ProgramBaseSize) {
+            ret.base_size = size(15);
+        }
+        if flags.contains(WmSizeHintsFlags::ProgramWinGravity) {
+            ret.win_gravity = value
+                .get(17)
+                .and_then(|gravity| WinGravity::try_from(*gravity).ok());
         }
 
         ret
@@ -803,23 +1893,105 @@ impl From<&[u32]> for WmNormalHints {
 
 #[derive(Default, Debug, PartialEq, Eq)]
 pub struct WmHints {
+    /// Whether the window wants the WM to give it input focus.
+    pub input: Option<bool>,
     pub window_group: Option<x::Window>,
+    pub urgent: bool,
 }
 
 impl From<&[u32]> for WmHints {
     fn from(value: &[u32]) -> Self {
         let mut ret = Self::default();
-        let flags = WmHintsFlags::from_bits_truncate(value[0]);
+        let Some(flags) = value.first() else {
+            return ret;
+        };
+        let flags = WmHintsFlags::from_bits_truncate(*flags);
+        ret.urgent = flags.contains(WmHintsFlags::Urgency);
+
+        if flags.contains(WmHintsFlags::Input) {
+            ret.input = value.get(1).map(|input| *input != 0);
+        }
 
         if flags.contains(WmHintsFlags::WindowGroup) {
-            let window = unsafe { x::Window::new(value[8]) };
-            ret.window_group = Some(window);
+            if let Some(group) = value.get(8) {
+                ret.window_group = Some(unsafe { x::Window::new(*group) });
+            }
         }
 
         ret
     }
 }
 
+/// The geometry changes from one or more ConfigureRequests.
+#[derive(Debug, Default)]
+struct PendingConfigure {
+    x: Option<i32>,
+    y: Option<i32>,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+impl PendingConfigure {
+    fn merge(&mut self, e: &x::ConfigureRequestEvent) {
+        let mask = e.value_mask();
+        if mask.contains(x::ConfigWindowMask::X) {
+            self.x = Some(e.x().into());
+        }
+        if mask.contains(x::ConfigWindowMask::Y) {
+            self.y = Some(e.y().into());
+        }
+        if mask.contains(x::ConfigWindowMask::WIDTH) {
+            self.width = Some(e.width().into());
+        }
+        if mask.contains(x::ConfigWindowMask::HEIGHT) {
+            self.height = Some(e.height().into());
+        }
+    }
+
+    /// Moves the window along with a resize that leaves out the position, so that its gravity's
+    /// reference point stays where it is, like a window manager would.
+    fn apply_gravity(
+        &mut self,
+        e: &x::ConfigureRequestEvent,
+        gravity: WinGravity,
+        geometry: WindowDims,
+    ) {
+        let mask = e.value_mask();
+        if mask.contains(x::ConfigWindowMask::WIDTH) && !mask.contains(x::ConfigWindowMask::X) {
+            let x = self.x.unwrap_or(geometry.x.into());
+            let old = self.width.unwrap_or(geometry.width.into()) as i32;
+            self.x = Some(gravity.anchor(x, old, e.width().into(), true));
+        }
+        if mask.contains(x::ConfigWindowMask::HEIGHT) && !mask.contains(x::ConfigWindowMask::Y) {
+            let y = self.y.unwrap_or(geometry.y.into());
+            let old = self.height.unwrap_or(geometry.height.into()) as i32;
+            self.y = Some(gravity.anchor(y, old, e.height().into(), false));
+        }
+    }
+
+    /// The geometry of a window with these changes applied.
+    fn apply_to(&self, dims: WindowDims) -> WindowDims {
+        WindowDims {
+            x: self.x.map_or(dims.x, |x| x as i16),
+            y: self.y.map_or(dims.y, |y| y as i16),
+            width: self.width.map_or(dims.width, |width| width as u16),
+            height: self.height.map_or(dims.height, |height| height as u16),
+        }
+    }
+
+    fn value_list(&self) -> Vec<x::ConfigWindow> {
+        [
+            self.x.map(x::ConfigWindow::X),
+            self.y.map(x::ConfigWindow::Y),
+            self.width.map(x::ConfigWindow::Width),
+            self.height.map(x::ConfigWindow::Height),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SetState {
     Remove,
@@ -839,118 +2011,416 @@ impl TryFrom<u32> for SetState {
     }
 }
 
-pub struct RealConnection {
-    connection: Rc<xcb::Connection>,
-    outputs: HashMap<String, xcb::randr::Output>,
-    primary_output: xcb::randr::Output,
+const MOVERESIZE_CANCEL: u32 = 11;
+
+/// The direction of a _NET_WM_MOVERESIZE message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MoveResize {
+    Size(ResizeEdge),
+    Move,
+    /// Resizing with the keyboard, i.e. from the window menu.
+    SizeKeyboard,
+    MoveKeyboard,
 }
 
-impl RealConnection {
-    fn new(connection: Rc<xcb::Connection>) -> Self {
-        Self {
-            connection,
-            outputs: Default::default(),
-            primary_output: Xid::none(),
+impl MoveResize {
+    pub fn is_keyboard(self) -> bool {
+        matches!(self, Self::SizeKeyboard | Self::MoveKeyboard)
+    }
+}
+
+impl TryFrom<u32> for MoveResize {
+    type Error = ();
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Size(ResizeEdge::TopLeft)),
+            1 => Ok(Self::Size(ResizeEdge::Top)),
+            2 => Ok(Self::Size(ResizeEdge::TopRight)),
+            3 => Ok(Self::Size(ResizeEdge::Right)),
+            4 => Ok(Self::Size(ResizeEdge::BottomRight)),
+            5 => Ok(Self::Size(ResizeEdge::Bottom)),
+            6 => Ok(Self::Size(ResizeEdge::BottomLeft)),
+            7 => Ok(Self::Size(ResizeEdge::Left)),
+            8 => Ok(Self::Move),
+            9 => Ok(Self::SizeKeyboard),
+            10 => Ok(Self::MoveKeyboard),
+            _ => Err(()),
         }
     }
+}
 
-    fn update_outputs(&mut self, root: x::Window) {
-        self.outputs.clear();
-        let reply = self
-            .connection
-            .wait_for_reply(
-                self.connection
-                    .send_request(&xcb::randr::GetScreenResources { window: root }),
-            )
-            .expect("Couldn't grab screen resources");
+/// Values of the WM_STATE property, from the ICCCM.
+#[derive(Debug, Clone, Copy)]
+enum WmState {
+    Normal = 1,
+    Iconic = 3,
+}
 
-        for output in reply.outputs().iter().copied() {
-            let reply = self
-                .connection
-                .wait_for_reply(self.connection.send_request(&xcb::randr::GetOutputInfo {
-                    output,
-                    config_timestamp: reply.config_timestamp(),
-                }))
-                .expect("Couldn't get output info");
+struct DeferredFocus {
+    window: x::Window,
+    output: Option<FocusedOutput>,
+    atoms: Atoms,
+}
 
-            let name = std::str::from_utf8(reply.name())
-                .unwrap_or_else(|_| panic!("couldn't parse output name: {:?}", reply.name()));
+#[derive(Debug, Clone, Copy)]
+struct RandrOutput {
+    id: xcb::randr::Output,
+    /// The position of the output's CRTC, if it has one.
+    position: Option<(i32, i32)>,
+    /// The size of the output's CRTC, or 0x0 if it has none.
+    size: (u16, u16),
+}
 
-            self.outputs.insert(name.to_string(), output);
-        }
+/// How long a window has to answer a _NET_WM_PING before it's reported as not responding.
+const PING_TIMEOUT: Duration = Duration::from_secs(5);
 
-        self.primary_output = self
-            .connection
-            .wait_for_reply(
-                self.connection
-                    .send_request(&xcb::randr::GetOutputPrimary { window: root }),
-            )
-            .expect("Couldn't get primary output")
-            .output();
+#[derive(Debug)]
+struct PendingPing {
+    sent: Instant,
+    /// The window was asked to close, so it can be killed if it stays unresponsive.
+    closing: bool,
+    unresponsive: bool,
+}
 
-        debug!(
-            "new outputs: {:?} | primary: {:?}",
-            self.outputs, self.primary_output
-        );
+/// Which RandR output each compositor output is.
+#[derive(Debug, Default)]
+struct OutputNames {
+    /// Compositor output names mapped to RandR output names, from [`Config::output_names`].
+    configured: HashMap<String, String>,
+    /// Compositor output names mapped to RandR output names, found by output position.
+    learned: HashMap<String, String>,
+}
+
+impl OutputNames {
+    /// Finds the RandR output for a compositor output. Configured names are used first, then
+    /// RandR outputs with the same name. Failing that, the output is matched to the only CRTC
+    /// at the same position, which is remembered until that RandR output goes away.
+    fn find(
+        &mut self,
+        outputs: &HashMap<String, RandrOutput>,
+        output: &FocusedOutput,
+    ) -> Option<xcb::randr::Output> {
+        if let Some(name) = self.configured.get(&output.name) {
+            let found = outputs.get(name).map(|o| o.id);
+            if found.is_none() {
+                warn!(
+                    "Output {name} (configured for {}) does not exist",
+                    output.name
+                );
+            }
+            return found;
+        }
+
+        if let Some(o) = outputs.get(&output.name).or_else(|| {
+            self.learned
+                .get(&output.name)
+                .and_then(|name| outputs.get(name))
+        }) {
+            return Some(o.id);
+        }
+
+        let position = Some((output.x, output.y));
+        let mut matching = outputs.iter().filter(|(_, o)| o.position == position);
+        let (name, o) = matching.next()?;
+        if matching.next().is_some() {
+            debug!(
+                "multiple outputs at {}x{}, not guessing which is {}",
+                output.x, output.y, output.name
+            );
+            return None;
+        }
+
+        debug!("mapping output {} to {name} by position", output.name);
+        self.learned.insert(output.name.clone(), name.clone());
+        Some(o.id)
+    }
+
+    fn outputs_changed(&mut self, outputs: &HashMap<String, RandrOutput>) {
+        // Xwayland never reuses the name of a RandR output for another Wayland output, so a
+        // mapping holds for as long as its RandR output exists, even after outputs are moved.
+        self.learned.retain(|_, name| outputs.contains_key(name));
     }
 }
 
-impl XConnection for RealConnection {
-    type ExtraData = Atoms;
-    type X11Selection = Selection;
+pub struct RealConnection {
+    connection: Rc<xcb::Connection>,
+    outputs: HashMap<String, RandrOutput>,
+    output_names: OutputNames,
+    primary_output: xcb::randr::Output,
+    /// See [`Config::primary_output`].
+    pinned_primary: Option<String>,
+    /// The primary output an X client picked, which takes precedence over the pinned one.
+    client_primary: Option<xcb::randr::Output>,
+    deferred_focus: Option<DeferredFocus>,
+    pings: HashMap<x::Window, PendingPing>,
+    kill_unresponsive: Option<Duration>,
+    wm_window: x::Window,
+    /// A window waiting for its WM_TAKE_FOCUS message, see [`Self::request_take_focus`].
+    take_focus: Option<x::Window>,
+}
 
-    fn root_window(&self) -> x::Window {
-        self.connection.get_setup().roots().next().unwrap().root()
+impl RealConnection {
+    fn new(connection: Rc<xcb::Connection>, wm_window: x::Window, config: &Config) -> Self {
+        Self {
+            connection,
+            wm_window,
+            take_focus: None,
+            outputs: Default::default(),
+            output_names: OutputNames {
+                configured: config.output_names.clone(),
+                learned: Default::default(),
+            },
+            primary_output: Xid::none(),
+            pinned_primary: config.primary_output.clone(),
+            client_primary: None,
+            deferred_focus: None,
+            pings: Default::default(),
+            kill_unresponsive: config.kill_unresponsive,
+        }
     }
 
-    fn set_window_dims(&mut self, window: x::Window, dims: crate::server::PendingSurfaceState) {
-        trace!("set window dimensions {window:?} {dims:?}");
-        unwrap_or_skip_bad_window!(self.connection.send_and_check_request(&x::ConfigureWindow {
+    fn get_wm_protocols(&self, window: x::Window, atoms: &Atoms) -> xcb::Result<Vec<x::Atom>> {
+        let cookie = self.connection.send_request(&x::GetProperty {
             window,
-            value_list: &[
-                x::ConfigWindow::X(dims.x),
-                x::ConfigWindow::Y(dims.y),
-                x::ConfigWindow::Width(dims.width as _),
-                x::ConfigWindow::Height(dims.height as _),
-            ]
-        }));
+            delete: false,
+            property: atoms.wm_protocols,
+            r#type: x::ATOM_ATOM,
+            long_offset: 0,
+            long_length: 10,
+        });
+        Ok(self.connection.wait_for_reply(cookie)?.value().to_vec())
     }
 
-    fn set_fullscreen(&mut self, window: x::Window, fullscreen: bool, atoms: Self::ExtraData) {
-        let data = if fullscreen {
-            std::slice::from_ref(&atoms.wm_fullscreen)
+    /// https://specifications.freedesktop.org/wm-spec/1.5/ar01s06.html#id-1.7.3
+    fn send_ping(
+        &mut self,
+        window: x::Window,
+        atoms: &Atoms,
+        closing: bool,
+    ) -> Result<(), xcb::ProtocolError> {
+        let data = [
+            atoms.net_wm_ping.resource_id(),
+            x::CURRENT_TIME,
+            window.resource_id(),
+            0,
+            0,
+        ];
+        let event = &x::ClientMessageEvent::new(
+            window,
+            atoms.wm_protocols,
+            x::ClientMessageData::Data32(data),
+        );
+        self.connection.send_and_check_request(&x::SendEvent {
+            destination: x::SendEventDest::Window(window),
+            propagate: false,
+            event_mask: x::EventMask::empty(),
+            event,
+        })?;
+
+        // Answering any ping clears the window, so only the oldest one is timed.
+        let ping = self.pings.entry(window).or_insert_with(|| PendingPing {
+            sent: Instant::now(),
+            closing: false,
+            unresponsive: false,
+        });
+        ping.closing |= closing;
+        Ok(())
+    }
+
+    fn ping_answered(&mut self, window: x::Window) {
+        match self.pings.remove(&window) {
+            Some(ping) if ping.unresponsive => info!("{window:?} is responding again"),
+            Some(_) => trace!("{window:?} answered ping"),
+            None => debug!("unexpected ping reply from {window:?}"),
+        }
+    }
+
+    /// Reports windows that didn't answer a ping in time, and kills the ones that were asked to
+    /// close if [`Config::kill_unresponsive`] is set.
+    fn check_pings(&mut self) {
+        let connection = &self.connection;
+        let kill_after = self.kill_unresponsive;
+        self.pings.retain(|window, ping| {
+            let waited = ping.sent.elapsed();
+            if !ping.unresponsive && waited >= PING_TIMEOUT {
+                warn!("{window:?} is not responding");
+                ping.unresponsive = true;
+            }
+            if ping.closing && kill_after.is_some_and(|after| waited >= after) {
+                warn!("killing unresponsive window {window:?}");
+                let _ = connection.send_and_check_request(&x::KillClient {
+                    resource: window.resource_id(),
+                });
+                return false;
+            }
+            true
+        });
+    }
+
+    /// Raw events are delivered to the root window regardless of any grabs, so they tell us when
+    /// the user may have ended one (i.e. by clicking outside of a menu).
+    fn select_raw_input_events(&self, enable: bool) {
+        let mask = if enable {
+            xcb::xinput::XiEventMask::RAW_KEY_RELEASE
+                | xcb::xinput::XiEventMask::RAW_BUTTON_RELEASE
+                | xcb::xinput::XiEventMask::RAW_MOTION
         } else {
-            &[]
+            xcb::xinput::XiEventMask::empty()
         };
-
         if let Err(e) = self
             .connection
-            .send_and_check_request(&x::ChangeProperty::<x::Atom> {
-                mode: x::PropMode::Replace,
-                window,
-                property: atoms.net_wm_state,
-                r#type: x::ATOM_ATOM,
-                data,
+            .send_and_check_request(&xcb::xinput::XiSelectEvents {
+                window: self.root_window(),
+                masks: &[xcb::xinput::EventMaskBuf::new(
+                    xcb::xinput::Device::AllMaster,
+                    &[mask],
+                )],
             })
         {
-            warn!("Failed to set fullscreen state on {window:?} ({e})");
+            warn!("Couldn't select raw input events: {e:?}");
         }
     }
 
-    fn focus_window(
-        &mut self,
+    /// Focuses the window whose focus was deferred by an active pointer grab, if the grab is over.
+    pub(super) fn retry_deferred_focus(&mut self) {
+        if self.deferred_focus.is_none() || self.pointer_grabbed() {
+            return;
+        }
+
+        let DeferredFocus {
+            window,
+            output,
+            atoms,
+        } = self.deferred_focus.take().unwrap();
+        debug!("pointer grab ended, focusing {window:?}");
+        self.select_raw_input_events(false);
+        self.set_focus(window, output, atoms);
+    }
+
+    /// Adds or removes a single state in the window's _NET_WM_STATE, leaving the others alone.
+    fn update_net_wm_state(
+        &self,
         window: x::Window,
-        output_name: Option<String>,
-        atoms: Self::ExtraData,
-    ) {
-        trace!("{window:?} {output_name:?}");
-        if let Err(e) = self.connection.send_and_check_request(&x::SetInputFocus {
-            focus: window,
-            revert_to: x::InputFocus::None,
-            time: x::CURRENT_TIME,
+        net_wm_state: x::Atom,
+        state: x::Atom,
+        enabled: bool,
+    ) -> xcb::Result<()> {
+        let reply = self
+            .connection
+            .wait_for_reply(self.connection.send_request(&x::GetProperty {
+                delete: false,
+                window,
+                property: net_wm_state,
+                r#type: x::ATOM_ATOM,
+                long_offset: 0,
+                long_length: 32,
+            }))?;
+        let mut states: Vec<x::Atom> = reply
+            .value::<x::Atom>()
+            .iter()
+            .copied()
+            .filter(|s| *s != state)
+            .collect();
+        if enabled {
+            states.push(state);
+        }
+
+        self.connection
+            .send_and_check_request(&x::ChangeProperty {
+                mode: x::PropMode::Replace,
+                window,
+                property: net_wm_state,
+                r#type: x::ATOM_ATOM,
+                data: &states,
+            })
+            .map_err(Into::into)
+    }
+
+    fn find_output(&mut self, output: &FocusedOutput) -> Option<xcb::randr::Output> {
+        self.output_names.find(&self.outputs, output)
+    }
+
+    /// Which of the ICCCM input models the window uses, as whether it wants SetInputFocus and
+    /// whether it wants WM_TAKE_FOCUS.
+    /// https://tronche.com/gui/x/icccm/sec-4.html#s-4.1.7
+    fn input_model(&self, window: x::Window, atoms: &Atoms) -> (bool, bool) {
+        let hints = self.connection.send_request(&x::GetProperty {
+            window,
+            delete: false,
+            property: x::ATOM_WM_HINTS,
+            r#type: x::ATOM_WM_HINTS,
+            long_offset: 0,
+            long_length: 9,
+        });
+        let input = self
+            .connection
+            .wait_for_reply(hints)
+            .ok()
+            .and_then(|reply| WmHints::from(reply.value::<u32>()).input)
+            .unwrap_or(true);
+        let take_focus = self
+            .get_wm_protocols(window, atoms)
+            .is_ok_and(|protocols| protocols.contains(&atoms.wm_take_focus));
+        (input, take_focus)
+    }
+
+    /// WM_TAKE_FOCUS has to carry a real timestamp. The X server hands us one in the
+    /// PropertyNotify for an empty change to one of our properties, so the message is sent from
+    /// [`Self::send_take_focus`] once that arrives.
+    fn request_take_focus(&mut self, window: x::Window, atoms: &Atoms) {
+        if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Append,
+            window: self.wm_window,
+            property: atoms.timestamp_prop,
+            r#type: x::ATOM_INTEGER,
+            data: &[] as &[u32],
         }) {
-            debug!("SetInputFocus failed ({:?}: {:?})", window, e);
+            warn!("Couldn't get a timestamp to focus {window:?} ({e:?})");
+            return;
+        }
+        self.take_focus = Some(window);
+    }
+
+    fn send_take_focus(&mut self, time: x::Timestamp, atoms: &Atoms) {
+        let Some(window) = self.take_focus.take() else {
             return;
+        };
+        let data = [atoms.wm_take_focus.resource_id(), time, 0, 0, 0];
+        let event = &x::ClientMessageEvent::new(
+            window,
+            atoms.wm_protocols,
+            x::ClientMessageData::Data32(data),
+        );
+        if let Err(e) = self.connection.send_and_check_request(&x::SendEvent {
+            destination: x::SendEventDest::Window(window),
+            propagate: false,
+            event_mask: x::EventMask::empty(),
+            event,
+        }) {
+            debug!("Couldn't send WM_TAKE_FOCUS to {window:?} ({e:?})");
+        }
+    }
+
+    fn set_focus(&mut self, window: x::Window, output: Option<FocusedOutput>, atoms: Atoms) {
+        self.take_focus = None;
+        let (accepts_input, take_focus) = if window.is_none() {
+            (true, false)
+        } else {
+            self.input_model(window, &atoms)
+        };
+
+        if accepts_input {
+            if let Err(e) = self.connection.send_and_check_request(&x::SetInputFocus {
+                focus: window,
+                revert_to: x::InputFocus::None,
+                time: x::CURRENT_TIME,
+            }) {
+                debug!("SetInputFocus failed ({:?}: {:?})", window, e);
+                return;
+            }
+        }
+        if take_focus {
+            self.request_take_focus(window, &atoms);
         }
         if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
             mode: x::PropMode::Replace,
@@ -962,8 +2432,12 @@ impl XConnection for RealConnection {
             debug!("ChangeProperty failed ({:?}: {:?})", window, e);
         }
 
-        if let Some(name) = output_name {
-            let Some(output) = self.outputs.get(&name).copied() else {
+        if self.pinned_primary.is_some() || self.client_primary.is_some() {
+            return;
+        }
+        if let Some(focused) = output {
+            let name = &focused.name;
+            let Some(output) = self.find_output(&focused) else {
                 warn!("Couldn't find output {name}, primary output will be wrong");
                 return;
             };
@@ -992,18 +2466,305 @@ impl XConnection for RealConnection {
         }
     }
 
-    fn close_window(&mut self, window: x::Window, atoms: Self::ExtraData) {
-        let cookie = self.connection.send_request(&x::GetProperty {
+    /// Remembers the primary output an X client picked (i.e. with `xrandr --primary`), so that
+    /// focus changes don't override it. Our own changes are already known, so they're ignored.
+    fn check_primary(&mut self, root: x::Window) {
+        let Ok(reply) = self.connection.wait_for_reply(
+            self.connection
+                .send_request(&xcb::randr::GetOutputPrimary { window: root }),
+        ) else {
+            return;
+        };
+        let primary = reply.output();
+        if primary == self.primary_output {
+            return;
+        }
+
+        self.primary_output = primary;
+        if primary.is_none() {
+            debug!("primary output unset by an X client, following focus again");
+            self.client_primary = None;
+        } else {
+            debug!("primary output set to {primary:?} by an X client");
+            self.client_primary = Some(primary);
+        }
+    }
+
+    /// Makes the output from [`Config::primary_output`] primary once it exists, unless an X client
+    /// picked one that's still around.
+    fn pin_primary(&mut self, root: x::Window, identities: &[OutputIdentity]) {
+        if let Some(output) = self.client_primary {
+            if self.outputs.values().any(|o| o.id == output) {
+                return;
+            }
+            debug!("primary output {output:?} picked by an X client is gone");
+            self.client_primary = None;
+        }
+        let Some(name) = self.pinned_primary.clone() else {
+            return;
+        };
+        let output = identities
+            .iter()
+            .find(|identity| identity.output.name == name)
+            .and_then(|identity| self.find_output(&identity.output))
+            .or_else(|| self.outputs.get(&name).map(|o| o.id));
+        let Some(output) = output else {
+            debug!("primary output {name} doesn't exist");
+            return;
+        };
+        if output == self.primary_output {
+            return;
+        }
+
+        if let Err(e) = self
+            .connection
+            .send_and_check_request(&xcb::randr::SetOutputPrimary {
+                window: root,
+                output,
+            })
+        {
+            warn!("Couldn't set output {name} as primary: {e:?}");
+        } else {
+            debug!("pinned {name} as primary output");
+            self.primary_output = output;
+        }
+    }
+
+    /// Gives RandR outputs that have no EDID one made up from what the compositor says about
+    /// the monitor.
+    fn set_edids(&mut self, identities: &[OutputIdentity], atoms: &Atoms) {
+        for identity in identities {
+            let Some(edid) = edid::edid(&identity.make, &identity.model, identity.physical_size)
+            else {
+                continue;
+            };
+            let Some(output) = self.find_output(&identity.output) else {
+                continue;
+            };
+            let existing = self.connection.wait_for_reply(self.connection.send_request(
+                &xcb::randr::GetOutputProperty {
+                    output,
+                    property: atoms.edid,
+                    r#type: x::ATOM_ANY,
+                    long_offset: 0,
+                    long_length: 0,
+                    delete: false,
+                    pending: false,
+                },
+            ));
+            if existing.is_ok_and(|reply| reply.r#type() != x::ATOM_NONE) {
+                continue;
+            }
+
+            if let Err(e) =
+                self.connection
+                    .send_and_check_request(&xcb::randr::ChangeOutputProperty {
+                        output,
+                        property: atoms.edid,
+                        r#type: x::ATOM_INTEGER,
+                        mode: x::PropMode::Replace,
+                        data: &edid[..],
+                    })
+            {
+                warn!("Couldn't set EDID of {}: {e:?}", identity.output.name);
+            } else {
+                debug!("set EDID of {}", identity.output.name);
+            }
+        }
+    }
+
+    /// Sets _NET_DESKTOP_GEOMETRY to the area covered by all outputs, since there's only the one
+    /// desktop.
+    fn update_desktop_geometry(&self, root: x::Window, atoms: &Atoms) {
+        let (width, height) = self
+            .outputs
+            .values()
+            .filter_map(|output| {
+                let (x, y) = output.position?;
+                let (width, height) = output.size;
+                Some((x + i32::from(width), y + i32::from(height)))
+            })
+            .fold((0, 0), |(w, h), (right, bottom)| {
+                (w.max(right), h.max(bottom))
+            });
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: root,
+            property: atoms.net_desktop_geometry,
+            r#type: x::ATOM_CARDINAL,
+            data: &[width as u32, height as u32],
+        }) {
+            warn!("Couldn't set desktop geometry: {e:?}");
+        }
+    }
+
+    fn update_outputs(&mut self, root: x::Window, atoms: &Atoms, identities: &[OutputIdentity]) {
+        self.outputs.clear();
+        let reply = self
+            .connection
+            .wait_for_reply(
+                self.connection
+                    .send_request(&xcb::randr::GetScreenResources { window: root }),
+            )
+            .expect("Couldn't grab screen resources");
+        let config_timestamp = reply.config_timestamp();
+
+        for output in reply.outputs().iter().copied() {
+            // Outputs can go away while we're still looking at them. That changes the screen
+            // resources again, so they're updated once more when that notify comes in.
+            let reply = match self.connection.wait_for_reply(self.connection.send_request(
+                &xcb::randr::GetOutputInfo {
+                    output,
+                    config_timestamp,
+                },
+            )) {
+                Ok(reply) => reply,
+                Err(e) => {
+                    debug!("output {output:?} went away ({e:?})");
+                    continue;
+                }
+            };
+
+            let name = String::from_utf8_lossy(reply.name());
+
+            let crtc = if reply.crtc().is_none() {
+                None
+            } else {
+                self.connection
+                    .wait_for_reply(self.connection.send_request(&xcb::randr::GetCrtcInfo {
+                        crtc: reply.crtc(),
+                        config_timestamp,
+                    }))
+                    .ok()
+            };
+
+            self.outputs.insert(
+                name.to_string(),
+                RandrOutput {
+                    id: output,
+                    position: crtc.as_ref().map(|crtc| (crtc.x().into(), crtc.y().into())),
+                    size: crtc.map_or((0, 0), |crtc| (crtc.width(), crtc.height())),
+                },
+            );
+        }
+        self.output_names.outputs_changed(&self.outputs);
+        self.update_desktop_geometry(root, atoms);
+        self.set_edids(identities, atoms);
+
+        self.primary_output = self
+            .connection
+            .wait_for_reply(
+                self.connection
+                    .send_request(&xcb::randr::GetOutputPrimary { window: root }),
+            )
+            .expect("Couldn't get primary output")
+            .output();
+        self.pin_primary(root, identities);
+
+        debug!(
+            "new outputs: {:?} | primary: {:?}",
+            self.outputs, self.primary_output
+        );
+    }
+}
+
+impl XConnection for RealConnection {
+    type ExtraData = Atoms;
+    type X11Selection = Selection;
+
+    fn root_window(&self) -> x::Window {
+        self.connection.get_setup().roots().next().unwrap().root()
+    }
+
+    fn set_window_dims(&mut self, window: x::Window, dims: crate::server::PendingSurfaceState) {
+        trace!("set window dimensions {window:?} {dims:?}");
+        unwrap_or_skip_bad_window!(self.connection.send_and_check_request(&x::ConfigureWindow {
             window,
-            delete: false,
-            property: atoms.wm_protocols,
-            r#type: x::ATOM_ATOM,
-            long_offset: 0,
-            long_length: 10,
-        });
-        let reply = unwrap_or_skip_bad_window!(self.connection.wait_for_reply(cookie));
+            value_list: &[
+                x::ConfigWindow::X(dims.x),
+                x::ConfigWindow::Y(dims.y),
+                x::ConfigWindow::Width(dims.width as _),
+                x::ConfigWindow::Height(dims.height as _),
+            ]
+        }));
+    }
 
-        if reply.value::<x::Atom>().contains(&atoms.wm_delete_window) {
+    fn set_fullscreen(&mut self, window: x::Window, fullscreen: bool, atoms: Self::ExtraData) {
+        if let Err(e) =
+            self.update_net_wm_state(window, atoms.net_wm_state, atoms.wm_fullscreen, fullscreen)
+        {
+            warn!("Failed to set fullscreen state on {window:?} ({e})");
+        }
+    }
+
+    fn set_minimized(&mut self, window: x::Window, minimized: bool, atoms: Self::ExtraData) {
+        // https://tronche.com/gui/x/icccm/sec-4.html#s-4.1.3.1
+        let state = if minimized {
+            WmState::Iconic
+        } else {
+            WmState::Normal
+        };
+        if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: atoms.wm_state,
+            r#type: atoms.wm_state,
+            data: &[state as u32, x::Window::none().resource_id()],
+        }) {
+            warn!("Failed to set WM_STATE on {window:?} ({e})");
+            return;
+        }
+        if let Err(e) =
+            self.update_net_wm_state(window, atoms.net_wm_state, atoms.wm_hidden, minimized)
+        {
+            warn!("Failed to set hidden state on {window:?} ({e})");
+        }
+    }
+
+    fn set_maximized(&mut self, window: x::Window, maximized: bool, atoms: Self::ExtraData) {
+        for state in [atoms.wm_maximized_vert, atoms.wm_maximized_horz] {
+            if let Err(e) = self.update_net_wm_state(window, atoms.net_wm_state, state, maximized) {
+                warn!("Failed to set maximized state on {window:?} ({e})");
+            }
+        }
+    }
+
+    fn focus_window(
+        &mut self,
+        window: x::Window,
+        output: Option<FocusedOutput>,
+        atoms: Self::ExtraData,
+    ) {
+        trace!("{window:?} {output:?}");
+        // Moving focus away from a client holding a grab (i.e. an open menu) would send its
+        // clicks and key presses to different windows, so wait until it lets go.
+        if self.pointer_grabbed() {
+            debug!("pointer is grabbed, deferring focus of {window:?}");
+            if self.deferred_focus.is_none() {
+                self.select_raw_input_events(true);
+            }
+            self.deferred_focus = Some(DeferredFocus {
+                window,
+                output,
+                atoms,
+            });
+            return;
+        }
+
+        if self.deferred_focus.take().is_some() {
+            self.select_raw_input_events(false);
+        }
+        self.set_focus(window, output, atoms);
+    }
+
+    fn close_window(&mut self, window: x::Window, atoms: Self::ExtraData) {
+        let protocols = unwrap_or_skip_bad_window!(self.get_wm_protocols(window, &atoms));
+
+        if protocols.contains(&atoms.wm_delete_window) {
             let data = [atoms.wm_delete_window.resource_id(), 0, 0, 0, 0];
             let event = &x::ClientMessageEvent::new(
                 window,
@@ -1017,6 +2778,10 @@ impl XConnection for RealConnection {
                 event_mask: x::EventMask::empty(),
                 event,
             }));
+            // A hung client never acts on WM_DELETE_WINDOW, pinging it lets us tell.
+            if protocols.contains(&atoms.net_wm_ping) {
+                unwrap_or_skip_bad_window!(self.send_ping(window, &atoms, true));
+            }
         } else {
             unwrap_or_skip_bad_window!(self.connection.send_and_check_request(&x::KillClient {
                 resource: window.resource_id()
@@ -1024,12 +2789,155 @@ impl XConnection for RealConnection {
         }
     }
 
-    fn raise_to_top(&mut self, window: x::Window) {
-        unwrap_or_skip_bad_window!(self.connection.send_and_check_request(&x::ConfigureWindow {
+    fn ping(&mut self, window: x::Window, atoms: Self::ExtraData) {
+        let protocols = unwrap_or_skip_bad_window!(self.get_wm_protocols(window, &atoms));
+        if protocols.contains(&atoms.net_wm_ping) {
+            unwrap_or_skip_bad_window!(self.send_ping(window, &atoms, false));
+        }
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.pings
+            .values()
+            .flat_map(|ping| {
+                let report = (!ping.unresponsive).then_some(PING_TIMEOUT);
+                let kill = self.kill_unresponsive.filter(|_| ping.closing);
+                [report, kill]
+                    .into_iter()
+                    .flatten()
+                    .map(|deadline| deadline.saturating_sub(ping.sent.elapsed()))
+            })
+            .min()
+    }
+
+    fn report_error(&mut self, window: x::Window, error: &str, atoms: Self::ExtraData) {
+        unwrap_or_skip_bad_window!(self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
             window,
-            value_list: &[x::ConfigWindow::StackMode(x::StackMode::Above)],
+            property: atoms.last_error,
+            r#type: atoms.utf8_string,
+            data: error.as_bytes(),
         }));
     }
+
+    /// X has no way of asking whether the pointer is grabbed, so we try grabbing it ourselves.
+    fn pointer_grabbed(&self) -> bool {
+        let reply = self
+            .connection
+            .wait_for_reply(self.connection.send_request(&x::GrabPointer {
+                owner_events: false,
+                grab_window: self.root_window(),
+                event_mask: x::EventMask::empty(),
+                pointer_mode: x::GrabMode::Async,
+                keyboard_mode: x::GrabMode::Async,
+                confine_to: x::WINDOW_NONE,
+                cursor: x::CURSOR_NONE,
+                time: x::CURRENT_TIME,
+            }));
+
+        match reply.map(|r| r.status()) {
+            Ok(x::GrabStatus::Success) => {
+                self.connection.send_request(&x::UngrabPointer {
+                    time: x::CURRENT_TIME,
+                });
+                false
+            }
+            Ok(x::GrabStatus::AlreadyGrabbed | x::GrabStatus::Frozen) => true,
+            other => {
+                warn!("Couldn't check for pointer grab: {other:?}");
+                false
+            }
+        }
+    }
+
+    fn set_tiled_edges(&mut self, window: x::Window, edges: TiledEdges, atoms: Self::ExtraData) {
+        // Two bits per edge, in the order top, right, bottom and left: whether the edge is tiled,
+        // and whether it can still be resized. Tiled edges are taken to be against the edge of
+        // the output, so they can't.
+        let constraints = [
+            TiledEdges::TOP,
+            TiledEdges::RIGHT,
+            TiledEdges::BOTTOM,
+            TiledEdges::LEFT,
+        ]
+        .into_iter()
+        .enumerate()
+        .fold(0u32, |constraints, (i, edge)| {
+            let bit = if edges.contains(edge) {
+                2 * i
+            } else {
+                2 * i + 1
+            };
+            constraints | 1 << bit
+        });
+        if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window,
+            property: atoms.gtk_edge_constraints,
+            r#type: x::ATOM_CARDINAL,
+            data: &[constraints],
+        }) {
+            warn!("Failed to set edge constraints on {window:?} ({e})");
+        }
+    }
+
+    fn set_work_area(
+        &mut self,
+        (x, y, width, height): (i32, i32, i32, i32),
+        atoms: Self::ExtraData,
+    ) {
+        let area = [x, y, width, height].map(|value| value.max(0) as u32);
+        if let Err(e) = self.connection.send_and_check_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: self.root_window(),
+            property: atoms.net_workarea,
+            r#type: x::ATOM_CARDINAL,
+            data: &area,
+        }) {
+            warn!("Couldn't set work area: {e:?}");
+        }
+    }
+
+    fn set_pointer_constraint(
+        &mut self,
+        window: x::Window,
+        constraint: Option<PointerConstraint>,
+        atoms: Self::ExtraData,
+    ) {
+        let res = match constraint {
+            Some(constraint) => {
+                let value: &[u8] = match constraint {
+                    PointerConstraint::Locked => b"locked",
+                    PointerConstraint::Confined => b"confined",
+                };
+                self.connection.send_and_check_request(&x::ChangeProperty {
+                    mode: x::PropMode::Replace,
+                    window,
+                    property: atoms.pointer_constraint,
+                    r#type: atoms.utf8_string,
+                    data: value,
+                })
+            }
+            None => self.connection.send_and_check_request(&x::DeleteProperty {
+                window,
+                property: atoms.pointer_constraint,
+            }),
+        };
+        unwrap_or_skip_bad_window!(res);
+    }
+
+    fn stack_above(&mut self, window: x::Window, sibling: x::Window) {
+        // Either window may have been destroyed since the stacking order was decided on.
+        if let Err(e) = self.connection.send_and_check_request(&x::ConfigureWindow {
+            window,
+            value_list: &[
+                x::ConfigWindow::Sibling(sibling),
+                x::ConfigWindow::StackMode(x::StackMode::Above),
+            ],
+        }) {
+            debug!("Couldn't stack {window:?} above {sibling:?}: {e:?}");
+        }
+    }
 }
 
 impl super::FromServerState<RealConnection> for Atoms {
@@ -1044,3 +2952,95 @@ fn get_atom_name(connection: &xcb::Connection, atom: x::Atom) -> String {
         Err(err) => format!("<error getting atom name: {err:?}> {atom:?}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{OutputNames, RandrOutput};
+    use crate::server::FocusedOutput;
+    use std::collections::HashMap;
+    use xcb::{randr, XidNew};
+
+    fn randr_outputs(outputs: &[(&str, u32, (i32, i32))]) -> HashMap<String, RandrOutput> {
+        outputs
+            .iter()
+            .map(|&(name, id, position)| {
+                let output = RandrOutput {
+                    id: unsafe { randr::Output::new(id) },
+                    position: Some(position),
+                    size: (100, 100),
+                };
+                (name.to_string(), output)
+            })
+            .collect()
+    }
+
+    fn output(name: &str, x: i32, y: i32) -> FocusedOutput {
+        FocusedOutput {
+            name: name.to_string(),
+            x,
+            y,
+        }
+    }
+
+    fn id(id: u32) -> Option<randr::Output> {
+        Some(unsafe { randr::Output::new(id) })
+    }
+
+    #[test]
+    fn configured_output_name() {
+        let mut names = OutputNames {
+            configured: HashMap::from([("DP-1".to_string(), "XWAYLAND1".to_string())]),
+            learned: HashMap::new(),
+        };
+        let outputs = randr_outputs(&[("XWAYLAND0", 1, (0, 0)), ("XWAYLAND1", 2, (100, 0))]);
+
+        // The configured name wins over the position.
+        assert_eq!(names.find(&outputs, &output("DP-1", 0, 0)), id(2));
+        // A configured output that's gone isn't guessed either.
+        names
+            .configured
+            .insert("DP-2".to_string(), "XWAYLAND5".to_string());
+        assert_eq!(names.find(&outputs, &output("DP-2", 0, 0)), None);
+    }
+
+    #[test]
+    fn same_output_name() {
+        let mut names = OutputNames::default();
+        let outputs = randr_outputs(&[("DP-1", 1, (0, 0)), ("DP-2", 2, (0, 0))]);
+
+        assert_eq!(names.find(&outputs, &output("DP-2", 0, 0)), id(2));
+        assert!(names.learned.is_empty());
+    }
+
+    #[test]
+    fn output_name_learned_by_position() {
+        let mut names = OutputNames::default();
+        let outputs = randr_outputs(&[("XWAYLAND0", 1, (0, 0)), ("XWAYLAND1", 2, (100, 0))]);
+
+        assert_eq!(names.find(&outputs, &output("DP-2", 100, 0)), id(2));
+        assert_eq!(names.learned["DP-2"], "XWAYLAND1");
+        // Ambiguous positions aren't guessed.
+        let overlapping = randr_outputs(&[("XWAYLAND0", 1, (0, 0)), ("XWAYLAND1", 2, (0, 0))]);
+        assert_eq!(names.find(&overlapping, &output("DP-1", 0, 0)), None);
+        assert!(!names.learned.contains_key("DP-1"));
+    }
+
+    #[test]
+    fn learned_output_name_survives_output_changes() {
+        let mut names = OutputNames::default();
+        let outputs = randr_outputs(&[("XWAYLAND0", 1, (0, 0)), ("XWAYLAND1", 2, (100, 0))]);
+        assert_eq!(names.find(&outputs, &output("DP-2", 100, 0)), id(2));
+
+        // The outputs trade places, the mapping stays with the RandR output.
+        let swapped = randr_outputs(&[("XWAYLAND0", 1, (100, 0)), ("XWAYLAND1", 2, (0, 0))]);
+        names.outputs_changed(&swapped);
+        assert_eq!(names.find(&swapped, &output("DP-2", 0, 0)), id(2));
+
+        // Once its RandR output is gone, the output is matched by position again.
+        let replaced = randr_outputs(&[("XWAYLAND0", 1, (100, 0)), ("XWAYLAND2", 3, (0, 0))]);
+        names.outputs_changed(&replaced);
+        assert!(names.learned.is_empty());
+        assert_eq!(names.find(&replaced, &output("DP-2", 0, 0)), id(3));
+        assert_eq!(names.learned["DP-2"], "XWAYLAND2");
+    }
+}
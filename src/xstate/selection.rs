@@ -1,9 +1,13 @@
+use super::text::LegacyEncoding;
 use super::{get_atom_name, XState};
 use crate::server::ForeignSelection;
 use crate::{RealServerState, X11Selection};
 use log::{debug, error, warn};
 use smithay_client_toolkit::data_device_manager::WritePipe;
 use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, VecDeque};
+use std::hash::{Hash, Hasher};
 use std::io::Write;
 use std::rc::Rc;
 use xcb::x;
@@ -13,12 +17,26 @@ struct SelectionTargetId {
     name: String,
     atom: x::Atom,
     source: Option<String>,
+    /// Set if the data for this target must be converted to or from UTF-8.
+    encoding: Option<LegacyEncoding>,
 }
 
 struct PendingSelectionData {
     target: x::Atom,
     pipe: WritePipe,
     incr: bool,
+    encoding: Option<LegacyEncoding>,
+    /// Data for targets that need converting, which is only done once all of it is received.
+    buffer: Vec<u8>,
+    /// How many bytes have been received so far.
+    received: usize,
+}
+
+/// A request for selection data waiting for the transfer in progress to finish.
+struct QueuedSelectionRequest {
+    target: x::Atom,
+    encoding: Option<LegacyEncoding>,
+    pipe: WritePipe,
 }
 
 pub struct Selection {
@@ -26,6 +44,10 @@ pub struct Selection {
     connection: Rc<xcb::Connection>,
     window: x::Window,
     pending: RefCell<Vec<PendingSelectionData>>,
+    /// Transfers are done one at a time, since the selection owner writes to the same property
+    /// of our window for every request of a given target.
+    queued: RefCell<VecDeque<QueuedSelectionRequest>>,
+    max_size: Option<usize>,
     clipboard: x::Atom,
     selection_time: u32,
     incr: x::Atom,
@@ -41,26 +63,17 @@ impl X11Selection for Selection {
 
     fn write_to(&self, mime: &str, pipe: WritePipe) {
         if let Some(target) = self.mimes.iter().find(|target| target.name == mime) {
-            // We use the target as the property to write to
-            if let Err(e) = self
-                .connection
-                .send_and_check_request(&x::ConvertSelection {
-                    requestor: self.window,
-                    selection: self.clipboard,
-                    target: target.atom,
-                    property: target.atom,
-                    time: self.selection_time,
-                })
-            {
-                error!("Failed to request clipboard data (mime type: {mime}, error: {e})");
-                return;
-            }
-
-            self.pending.borrow_mut().push(PendingSelectionData {
+            let request = QueuedSelectionRequest {
                 target: target.atom,
+                encoding: target.encoding,
                 pipe,
-                incr: false,
-            })
+            };
+            if self.pending.borrow().is_empty() {
+                self.request_data(request);
+            } else {
+                debug!("queueing request for mime type {mime}");
+                self.queued.borrow_mut().push_back(request);
+            }
         } else {
             warn!("Could not find mime type {mime}");
         }
@@ -68,7 +81,74 @@ impl X11Selection for Selection {
 }
 
 impl Selection {
+    /// Returns false if the request could not be made.
+    fn request_data(&self, request: QueuedSelectionRequest) -> bool {
+        let QueuedSelectionRequest {
+            target,
+            encoding,
+            pipe,
+        } = request;
+
+        // We use the target as the property to write to
+        if let Err(e) = self
+            .connection
+            .send_and_check_request(&x::ConvertSelection {
+                requestor: self.window,
+                selection: self.clipboard,
+                target,
+                property: target,
+                time: self.selection_time,
+            })
+        {
+            error!(
+                "Failed to request clipboard data (target: {}, error: {e})",
+                get_atom_name(&self.connection, target)
+            );
+            return false;
+        }
+
+        self.pending.borrow_mut().push(PendingSelectionData {
+            target,
+            pipe,
+            incr: false,
+            encoding,
+            buffer: Vec::new(),
+            received: 0,
+        });
+        true
+    }
+
+    fn request_next(&self) {
+        if !self.pending.borrow().is_empty() {
+            return;
+        }
+        loop {
+            let Some(request) = self.queued.borrow_mut().pop_front() else {
+                return;
+            };
+            if self.request_data(request) {
+                return;
+            }
+        }
+    }
+
     fn handle_notify(&self, target: x::Atom) {
+        self.receive_data(target);
+        self.request_next();
+    }
+
+    /// The owner refused to convert the selection to this target.
+    fn handle_refusal(&self, target: x::Atom) {
+        let mut pending = self.pending.borrow_mut();
+        if let Some(idx) = pending.iter().position(|t| t.target == target) {
+            // Dropping the pipe lets the Wayland client know there's no data coming.
+            pending.swap_remove(idx);
+        }
+        drop(pending);
+        self.request_next();
+    }
+
+    fn receive_data(&self, target: x::Atom) {
         let mut pending = self.pending.borrow_mut();
         let Some(idx) = pending.iter().position(|t| t.target == target) else {
             warn!(
@@ -82,6 +162,9 @@ impl Selection {
             mut pipe,
             incr,
             target,
+            encoding,
+            mut buffer,
+            mut received,
         } = pending.swap_remove(idx);
         let reply = match get_property_any(&self.connection, self.window, target) {
             Ok(reply) => reply,
@@ -101,6 +184,14 @@ impl Selection {
         );
 
         if reply.r#type() == self.incr {
+            // The INCR property holds a lower bound on the size of the data
+            let size = match reply.format() {
+                32 => reply.value::<u32>().first().copied().unwrap_or(0) as usize,
+                _ => 0,
+            };
+            if self.exceeds_max_size(target, size) {
+                return;
+            }
             debug!(
                 "beginning incr for {}",
                 get_atom_name(&self.connection, target)
@@ -109,6 +200,9 @@ impl Selection {
                 target,
                 pipe,
                 incr: true,
+                encoding,
+                buffer,
+                received,
             });
             return;
         }
@@ -122,7 +216,27 @@ impl Selection {
             }
         };
 
-        if !incr || !data.is_empty() {
+        received += data.len();
+        if self.exceeds_max_size(target, received) {
+            // Dropping the pipe cuts the transfer off.
+            return;
+        }
+
+        if let Some(encoding) = encoding {
+            buffer.extend_from_slice(data);
+            if incr && !data.is_empty() {
+                pending.push(PendingSelectionData {
+                    target,
+                    pipe,
+                    incr: true,
+                    encoding: Some(encoding),
+                    buffer,
+                    received,
+                });
+            } else if let Err(e) = pipe.write_all(encoding.decode(&buffer).as_bytes()) {
+                warn!("Failed to write selection data: {e:?}");
+            }
+        } else if !incr || !data.is_empty() {
             if let Err(e) = pipe.write_all(data) {
                 warn!("Failed to write selection data: {e:?}");
             } else if incr {
@@ -134,6 +248,9 @@ impl Selection {
                     target,
                     pipe,
                     incr: true,
+                    encoding: None,
+                    buffer,
+                    received,
                 })
             }
         } else if incr {
@@ -145,6 +262,17 @@ impl Selection {
         }
     }
 
+    fn exceeds_max_size(&self, target: x::Atom, size: usize) -> bool {
+        let exceeds = self.max_size.is_some_and(|max| size > max);
+        if exceeds {
+            warn!(
+                "Refusing to forward {} from X11: data is larger than the maximum clipboard size",
+                get_atom_name(&self.connection, target)
+            );
+        }
+        exceeds
+    }
+
     fn check_for_incr(&self, event: &x::PropertyNotifyEvent) -> bool {
         if event.window() != self.window || event.state() != x::Property::NewValue {
             return false;
@@ -169,10 +297,49 @@ enum CurrentSelection {
         inner: ForeignSelection,
     },
 }
+/// What we handed out while owning a selection forwarded from Wayland. X clipboard managers will
+/// often take ownership of the clipboard right after copying its contents, which we would then
+/// forward back to Wayland, where a clipboard manager might do the same.
+struct ForwardedSelection {
+    targets: Vec<x::Atom>,
+    /// Hashes of the data sent to X clients, by target.
+    served: HashMap<x::Atom, u64>,
+}
+
+impl ForwardedSelection {
+    /// Returns a target that can be used to compare a new selection with this one, if the new
+    /// selection could be a copy of it.
+    fn comparable_target(&self, mimes: &[SelectionTargetId]) -> Option<x::Atom> {
+        if !mimes.iter().all(|t| self.targets.contains(&t.atom)) {
+            return None;
+        }
+        mimes
+            .iter()
+            .map(|t| t.atom)
+            .find(|atom| self.served.contains_key(atom))
+    }
+}
+
+/// A new X11 selection waiting to be compared with the one we forwarded.
+struct LoopCheck {
+    target: x::Atom,
+    mimes: Vec<SelectionTargetId>,
+}
+
+fn hash_data(data: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    data.hash(&mut hasher);
+    hasher.finish()
+}
+
 pub(crate) struct SelectionData {
     last_selection_timestamp: u32,
+    /// The time at which we last became the CLIPBOARD owner, used to answer TIMESTAMP requests.
+    acquired_timestamp: u32,
     target_window: x::Window,
     current_selection: Option<CurrentSelection>,
+    forwarded: Option<ForwardedSelection>,
+    loop_check: Option<LoopCheck>,
 }
 
 impl SelectionData {
@@ -196,8 +363,11 @@ impl SelectionData {
             .expect("Couldn't create window for selections");
         Self {
             last_selection_timestamp: x::CURRENT_TIME,
+            acquired_timestamp: x::CURRENT_TIME,
             target_window,
             current_selection: None,
+            forwarded: None,
+            loop_check: None,
         }
     }
 }
@@ -224,55 +394,67 @@ impl XState {
                 "Could not get CLIPBOARD selection (owned by {:?})",
                 reply.owner()
             );
+        } else {
+            // If we used CurrentTime, this will be replaced with the real server time
+            // once the XFixes SelectionNotify for our ownership comes in.
+            self.selection_data.acquired_timestamp = self.selection_data.last_selection_timestamp;
         }
     }
 
     pub(crate) fn set_clipboard(&mut self, selection: ForeignSelection) {
-        let mut utf8_xwl = false;
-        let mut utf8_wl = false;
+        let filter = &self.config.clipboard_to_x11;
+        let intern = |name: &str| {
+            self.connection
+                .wait_for_reply(self.connection.send_request(&x::InternAtom {
+                    only_if_exists: false,
+                    name: name.as_bytes(),
+                }))
+                .unwrap()
+                .atom()
+        };
         let mut mimes: Vec<SelectionTargetId> = selection
             .mime_types
             .iter()
-            .map(|mime| {
-                match mime.as_str() {
-                    "UTF8_STRING" => utf8_xwl = true,
-                    "text/plain;charset=utf-8" => utf8_wl = true,
-                    _ => {}
-                }
-
-                let atom = self
-                    .connection
-                    .wait_for_reply(self.connection.send_request(&x::InternAtom {
-                        only_if_exists: false,
-                        name: mime.as_bytes(),
-                    }))
-                    .unwrap();
-
-                SelectionTargetId {
-                    name: mime.clone(),
-                    atom: atom.atom(),
-                    source: None,
+            .filter(|mime| {
+                let forward = filter.forwards(mime);
+                if !forward {
+                    debug!("not forwarding mime type {mime} to X11");
                 }
+                forward
+            })
+            .map(|mime| SelectionTargetId {
+                name: mime.clone(),
+                atom: intern(mime),
+                source: None,
+                encoding: None,
             })
             .collect();
 
-        if utf8_wl && !utf8_xwl {
-            let name = "UTF8_STRING".to_string();
-            let atom = self
-                .connection
-                .wait_for_reply(self.connection.send_request(&x::InternAtom {
-                    only_if_exists: false,
-                    name: name.as_bytes(),
-                }))
-                .unwrap()
-                .atom();
-            mimes.push(SelectionTargetId {
-                name,
-                atom,
-                source: Some("text/plain;charset=utf-8".to_string()),
-            });
+        // Offer text in the formats X clients may be looking for, if they weren't already offered.
+        let utf8_source = ["text/plain;charset=utf-8", "UTF8_STRING"]
+            .into_iter()
+            .find(|mime| mimes.iter().any(|target| target.name == *mime));
+        if let Some(source) = utf8_source {
+            for (name, encoding) in [
+                ("UTF8_STRING", None),
+                ("COMPOUND_TEXT", Some(LegacyEncoding::CompoundText)),
+                ("STRING", Some(LegacyEncoding::Latin1)),
+            ] {
+                if filter.forwards(name) && !mimes.iter().any(|target| target.name == name) {
+                    mimes.push(SelectionTargetId {
+                        name: name.to_string(),
+                        atom: intern(name),
+                        source: Some(source.to_string()),
+                        encoding,
+                    });
+                }
+            }
         }
 
+        self.selection_data.forwarded = Some(ForwardedSelection {
+            targets: mimes.iter().map(|t| t.atom).collect(),
+            served: HashMap::new(),
+        });
         self.selection_data.current_selection = Some(CurrentSelection::Wayland {
             mimes,
             inner: selection,
@@ -293,7 +475,27 @@ impl XState {
             }
             xcb::Event::X(x::Event::SelectionNotify(e)) => {
                 if e.property() == x::ATOM_NONE {
-                    warn!("selection notify fail?");
+                    match self.selection_data.loop_check.take() {
+                        Some(check)
+                            if e.requestor() == self.wm_window && e.target() == check.target =>
+                        {
+                            self.import_selection(check.mimes, server_state);
+                        }
+                        check => {
+                            self.selection_data.loop_check = check;
+                            warn!(
+                                "selection owner refused target {}",
+                                get_atom_name(&self.connection, e.target())
+                            );
+                            if let Some(CurrentSelection::X11(selection)) =
+                                &self.selection_data.current_selection
+                            {
+                                if e.requestor() == self.selection_data.target_window {
+                                    selection.handle_refusal(e.target());
+                                }
+                            }
+                        }
+                    }
                     return true;
                 }
 
@@ -308,6 +510,14 @@ impl XState {
                         x if x == self.atoms.targets => {
                             self.handle_target_list(e.property(), server_state)
                         }
+                        x if self
+                            .selection_data
+                            .loop_check
+                            .as_ref()
+                            .is_some_and(|check| check.target == x) =>
+                        {
+                            self.finish_loop_check(e.property(), server_state)
+                        }
                         other => warn!(
                             "got unexpected selection notify for target {}",
                             get_atom_name(&self.connection, other)
@@ -328,20 +538,21 @@ impl XState {
             }
             xcb::Event::X(x::Event::SelectionRequest(e)) => {
                 let send_notify = |property| {
-                    self.connection
-                        .send_and_check_request(&x::SendEvent {
-                            propagate: false,
-                            destination: x::SendEventDest::Window(e.requestor()),
-                            event_mask: x::EventMask::empty(),
-                            event: &x::SelectionNotifyEvent::new(
-                                e.time(),
-                                e.requestor(),
-                                e.selection(),
-                                e.target(),
-                                property,
-                            ),
-                        })
-                        .unwrap();
+                    // The requestor may be gone already, which is its own problem.
+                    if let Err(err) = self.connection.send_and_check_request(&x::SendEvent {
+                        propagate: false,
+                        destination: x::SendEventDest::Window(e.requestor()),
+                        event_mask: x::EventMask::empty(),
+                        event: &x::SelectionNotifyEvent::new(
+                            e.time(),
+                            e.requestor(),
+                            e.selection(),
+                            e.target(),
+                            property,
+                        ),
+                    }) {
+                        warn!("Failed to notify selection requestor: {err:?}");
+                    }
                 };
                 let refuse = || send_notify(x::ATOM_NONE);
                 let success = || send_notify(e.property());
@@ -367,19 +578,39 @@ impl XState {
 
                 match e.target() {
                     x if x == self.atoms.targets => {
-                        let atoms: Box<[x::Atom]> = mimes.iter().map(|t| t.atom).collect();
-
-                        self.connection
-                            .send_and_check_request(&x::ChangeProperty {
-                                mode: x::PropMode::Replace,
-                                window: e.requestor(),
-                                property: e.property(),
-                                r#type: x::ATOM_ATOM,
-                                data: &atoms,
-                            })
-                            .unwrap();
-
-                        success();
+                        let atoms: Box<[x::Atom]> = [self.atoms.targets, self.atoms.timestamp]
+                            .into_iter()
+                            .chain(mimes.iter().map(|t| t.atom))
+                            .collect();
+
+                        match self.connection.send_and_check_request(&x::ChangeProperty {
+                            mode: x::PropMode::Replace,
+                            window: e.requestor(),
+                            property: e.property(),
+                            r#type: x::ATOM_ATOM,
+                            data: &atoms,
+                        }) {
+                            Ok(_) => success(),
+                            Err(e) => {
+                                warn!("Failed setting selection targets: {e:?}");
+                                refuse();
+                            }
+                        }
+                    }
+                    x if x == self.atoms.timestamp => {
+                        match self.connection.send_and_check_request(&x::ChangeProperty {
+                            mode: x::PropMode::Replace,
+                            window: e.requestor(),
+                            property: e.property(),
+                            r#type: x::ATOM_INTEGER,
+                            data: &[self.selection_data.acquired_timestamp],
+                        }) {
+                            Ok(_) => success(),
+                            Err(e) => {
+                                warn!("Failed setting selection timestamp: {e:?}");
+                                refuse();
+                            }
+                        }
                     }
                     other => {
                         let Some(target) = mimes.iter().find(|t| t.atom == other) else {
@@ -396,7 +627,17 @@ impl XState {
                             .as_ref()
                             .cloned()
                             .unwrap_or_else(|| target.name.clone());
-                        let data = inner.receive(mime_name, server_state);
+                        let Some(data) = inner.receive(mime_name, server_state) else {
+                            refuse();
+                            return true;
+                        };
+                        let data = match target.encoding {
+                            Some(encoding) => encoding.encode(&String::from_utf8_lossy(&data)),
+                            None => data,
+                        };
+                        if let Some(forwarded) = &mut self.selection_data.forwarded {
+                            forwarded.served.insert(target.atom, hash_data(&data));
+                        }
                         match self.connection.send_and_check_request(&x::ChangeProperty {
                             mode: x::PropMode::Replace,
                             window: e.requestor(),
@@ -419,6 +660,7 @@ impl XState {
                 match e.subtype() {
                     xcb::xfixes::SelectionEvent::SetSelectionOwner => {
                         if e.owner() == self.wm_window {
+                            self.selection_data.acquired_timestamp = e.selection_timestamp();
                             return true;
                         }
 
@@ -440,6 +682,7 @@ impl XState {
     fn handle_new_selection_owner(&mut self, owner: x::Window, timestamp: u32) {
         debug!("new selection owner: {:?}", owner);
         self.selection_data.last_selection_timestamp = timestamp;
+        self.selection_data.loop_check = None;
         // Grab targets
         self.connection
             .send_and_check_request(&x::ConvertSelection {
@@ -497,7 +740,7 @@ impl XState {
             debug!("got targets: {targets_str:?}");
         }
 
-        let mimes = targets
+        let mut mimes: Vec<SelectionTargetId> = targets
             .iter()
             .copied()
             .filter(|atom| {
@@ -505,6 +748,7 @@ impl XState {
                     self.atoms.targets,
                     self.atoms.multiple,
                     self.atoms.save_targets,
+                    self.atoms.timestamp,
                 ]
                 .contains(atom)
             })
@@ -512,14 +756,108 @@ impl XState {
                 name: get_atom_name(&self.connection, target_atom),
                 atom: target_atom,
                 source: None,
+                encoding: None,
             })
             .collect();
 
+        // Older clients may only offer text in a legacy encoding, which Wayland clients won't understand.
+        let has_utf8 = mimes.iter().any(|target| {
+            ["UTF8_STRING", "text/plain;charset=utf-8"].contains(&target.name.as_str())
+        });
+        if !has_utf8 {
+            let legacy = [
+                ("COMPOUND_TEXT", LegacyEncoding::CompoundText),
+                ("STRING", LegacyEncoding::Latin1),
+            ]
+            .into_iter()
+            .find_map(|(name, encoding)| {
+                mimes
+                    .iter()
+                    .find(|target| target.name == name)
+                    .map(|target| (target.atom, encoding))
+            });
+            if let Some((atom, encoding)) = legacy {
+                mimes.push(SelectionTargetId {
+                    name: "text/plain;charset=utf-8".to_string(),
+                    atom,
+                    source: None,
+                    encoding: Some(encoding),
+                });
+            }
+        }
+
+        mimes.retain(|target| {
+            let forward = self.config.clipboard_to_wayland.forwards(&target.name);
+            if !forward {
+                debug!("not forwarding target {} to Wayland", target.name);
+            }
+            forward
+        });
+
+        let comparable_target = self
+            .selection_data
+            .forwarded
+            .as_ref()
+            .and_then(|forwarded| forwarded.comparable_target(&mimes));
+        if let Some(target) = comparable_target {
+            // The new owner only offers what we offered - grab some data to see if it's a copy.
+            self.connection
+                .send_and_check_request(&x::ConvertSelection {
+                    requestor: self.wm_window,
+                    selection: self.atoms.clipboard,
+                    target,
+                    property: self.atoms.selection_reply,
+                    time: self.selection_data.last_selection_timestamp,
+                })
+                .unwrap();
+            self.selection_data.loop_check = Some(LoopCheck { target, mimes });
+            return;
+        }
+
+        self.import_selection(mimes, server_state);
+    }
+
+    fn finish_loop_check(&mut self, property: x::Atom, server_state: &mut RealServerState) {
+        let LoopCheck { target, mimes } = self.selection_data.loop_check.take().unwrap();
+        let served = self
+            .selection_data
+            .forwarded
+            .as_ref()
+            .and_then(|forwarded| forwarded.served.get(&target).copied());
+
+        let is_copy = match get_property_any(&self.connection, self.wm_window, property) {
+            Ok(reply) if reply.r#type() != self.atoms.incr => {
+                let data = match reply.format() {
+                    8 => reply.value::<u8>(),
+                    32 => unsafe { reply.value::<u32>().align_to().1 },
+                    _ => &[],
+                };
+                served == Some(hash_data(data))
+            }
+            // Anything big enough to need INCR is imported either way.
+            _ => false,
+        };
+
+        if is_copy {
+            debug!("New X11 selection is a copy of the one we forwarded from Wayland, not importing it");
+            return;
+        }
+        self.import_selection(mimes, server_state);
+    }
+
+    fn import_selection(
+        &mut self,
+        mimes: Vec<SelectionTargetId>,
+        server_state: &mut RealServerState,
+    ) {
+        self.selection_data.forwarded = None;
         let selection = Rc::new(Selection {
             mimes,
             connection: self.connection.clone(),
             window: self.selection_data.target_window,
             pending: RefCell::default(),
+            queued: RefCell::default(),
+            max_size: self.config.clipboard_max_size,
             clipboard: self.atoms.clipboard,
             selection_time: self.selection_data.last_selection_timestamp,
             incr: self.atoms.incr,
@@ -0,0 +1,374 @@
+//! Conversions between UTF-8 and the legacy text encodings used by older X clients.
+//! https://www.x.org/releases/X11R7.6/doc/xorg-docs/specs/CTEXT/ctext.html
+
+use crate::config::TitleCharset;
+
+const ESC: u8 = 0x1b;
+const CSI: u8 = 0x9b;
+const UTF8_SEGMENT_START: &[u8] = &[ESC, b'%', b'G'];
+const UTF8_SEGMENT_END: &[u8] = &[ESC, b'%', b'@'];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum LegacyEncoding {
+    /// The ICCCM STRING type, which is ISO-8859-1.
+    Latin1,
+    CompoundText,
+}
+
+impl LegacyEncoding {
+    pub fn decode(self, data: &[u8]) -> String {
+        match self {
+            Self::Latin1 => data.iter().copied().map(char::from).collect(),
+            Self::CompoundText => decode_compound_text(data),
+        }
+    }
+
+    pub fn encode(self, text: &str) -> Vec<u8> {
+        match self {
+            Self::Latin1 => text
+                .chars()
+                .map(|c| u8::try_from(c).unwrap_or(b'?'))
+                .collect(),
+            Self::CompoundText => encode_compound_text(text),
+        }
+    }
+}
+
+/// Turns text from an X11 property into something safe to send over Wayland: the text ends at the
+/// first NUL (wayland-rs refuses strings containing them, see
+/// https://github.com/Smithay/wayland-rs/issues/748), invalid UTF-8 is replaced with U+FFFD,
+/// whitespace control characters become spaces and all other control characters are dropped.
+pub(crate) fn sanitize_utf8(data: &[u8]) -> String {
+    let data = data.split(|byte| *byte == 0).next().unwrap();
+    String::from_utf8_lossy(data)
+        .chars()
+        .filter_map(|c| match c {
+            '\t' | '\n' | '\r' => Some(' '),
+            c if c.is_control() => None,
+            c => Some(c),
+        })
+        .collect()
+}
+
+/// Rewrites text to only use characters from a limited set, for bars and task switchers whose
+/// fonts can't render anything else.
+#[derive(Clone, Debug)]
+pub(crate) struct Transliterator {
+    charset: TitleCharset,
+    /// The language of the user's locale, which decides how some letters are spelled out (i.e.
+    /// `ü` is `ue` in German, but `u` elsewhere).
+    language: String,
+}
+
+impl Transliterator {
+    pub fn new(charset: TitleCharset) -> Self {
+        let language = ["LC_ALL", "LC_CTYPE", "LANG"]
+            .into_iter()
+            .filter_map(|var| std::env::var(var).ok())
+            .find(|locale| !locale.is_empty())
+            .and_then(|locale| locale.split(['_', '.', '@']).next().map(str::to_string))
+            .unwrap_or_default();
+        Self { charset, language }
+    }
+
+    /// Characters outside of the charset are replaced with the closest match from it, or `?` if
+    /// there is none.
+    pub fn apply(&self, text: &str) -> String {
+        let mut ret = String::with_capacity(text.len());
+        for c in text.chars() {
+            let fits = match self.charset {
+                TitleCharset::Ascii => c.is_ascii(),
+                TitleCharset::Latin1 => u8::try_from(c).is_ok(),
+            };
+            if fits {
+                ret.push(c);
+            } else {
+                ret.push_str(self.fallback(c).unwrap_or("?"));
+            }
+        }
+        ret
+    }
+
+    fn fallback(&self, c: char) -> Option<&'static str> {
+        const GERMAN: &[(char, &str)] = &[
+            ('Ä', "Ae"),
+            ('Ö', "Oe"),
+            ('Ü', "Ue"),
+            ('ä', "ae"),
+            ('ö', "oe"),
+            ('ü', "ue"),
+        ];
+        const NORDIC: &[(char, &str)] = &[
+            ('Å', "Aa"),
+            ('Ä', "Ae"),
+            ('Ö', "Oe"),
+            ('Ø', "Oe"),
+            ('å', "aa"),
+            ('ä', "ae"),
+            ('ö', "oe"),
+            ('ø', "oe"),
+        ];
+        let localized = match self.language.as_str() {
+            "de" => GERMAN,
+            "da" | "fi" | "nb" | "nn" | "no" | "sv" => NORDIC,
+            _ => &[],
+        };
+        if let Some((_, s)) = localized.iter().find(|(l, _)| *l == c) {
+            return Some(s);
+        }
+
+        Some(match c {
+            '\u{a0}' => " ",
+            '«' => "<<",
+            '»' => ">>",
+            '©' => "(C)",
+            '®' => "(R)",
+            '·' => ".",
+            '\u{c0}'..='\u{17f}' => LATIN[c as usize - 0xc0],
+            '\u{2010}'..='\u{2015}' | '−' => "-",
+            '‘' | '’' | '‚' | '‛' | '′' => "'",
+            '“' | '”' | '„' | '‟' | '″' => "\"",
+            '…' => "...",
+            '•' => "*",
+            '€' => "EUR",
+            '™' => "(TM)",
+            _ => return None,
+        })
+    }
+}
+
+/// ASCII spellings of U+00C0 to U+017F (the letters of Latin-1 and Latin Extended-A), without
+/// their diacritics.
+#[rustfmt::skip]
+const LATIN: [&str; 192] = [
+    "A", "A", "A", "A", "A", "A", "AE", "C", "E", "E", "E", "E", "I", "I", "I", "I",
+    "D", "N", "O", "O", "O", "O", "O", "x", "O", "U", "U", "U", "U", "Y", "Th", "ss",
+    "a", "a", "a", "a", "a", "a", "ae", "c", "e", "e", "e", "e", "i", "i", "i", "i",
+    "d", "n", "o", "o", "o", "o", "o", "/", "o", "u", "u", "u", "u", "y", "th", "y",
+    "A", "a", "A", "a", "A", "a", "C", "c", "C", "c", "C", "c", "C", "c", "D", "d",
+    "D", "d", "E", "e", "E", "e", "E", "e", "E", "e", "E", "e", "G", "g", "G", "g",
+    "G", "g", "G", "g", "H", "h", "H", "h", "I", "i", "I", "i", "I", "i", "I", "i",
+    "I", "i", "IJ", "ij", "J", "j", "K", "k", "q", "L", "l", "L", "l", "L", "l", "L",
+    "l", "L", "l", "N", "n", "N", "n", "N", "n", "'n", "N", "n", "O", "o", "O", "o",
+    "O", "o", "OE", "oe", "R", "r", "R", "r", "R", "r", "S", "s", "S", "s", "S", "s",
+    "S", "s", "T", "t", "T", "t", "T", "t", "U", "u", "U", "u", "U", "u", "U", "u",
+    "U", "u", "U", "u", "W", "w", "Y", "y", "Y", "Z", "z", "Z", "z", "Z", "z", "s",
+];
+
+/// Characters outside of Latin-1 are placed in UTF-8 segments, everything else is left in the
+/// initial state (ASCII in GL, the right half of Latin-1 in GR).
+fn encode_compound_text(text: &str) -> Vec<u8> {
+    let mut ret = Vec::with_capacity(text.len());
+    let mut in_utf8 = false;
+    for c in text.chars() {
+        match u8::try_from(c) {
+            Ok(b) => {
+                if in_utf8 {
+                    ret.extend_from_slice(UTF8_SEGMENT_END);
+                    in_utf8 = false;
+                }
+                ret.push(b);
+            }
+            Err(_) => {
+                if !in_utf8 {
+                    ret.extend_from_slice(UTF8_SEGMENT_START);
+                    in_utf8 = true;
+                }
+                ret.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+        }
+    }
+    if in_utf8 {
+        ret.extend_from_slice(UTF8_SEGMENT_END);
+    }
+    ret
+}
+
+/// Only ASCII, ISO-8859-1 and UTF-8 segments are understood - text in any other character set is
+/// replaced with U+FFFD.
+fn decode_compound_text(data: &[u8]) -> String {
+    let mut ret = String::with_capacity(data.len());
+    let mut gl_ascii = true;
+    let mut gr_latin1 = true;
+    let mut idx = 0;
+
+    while idx < data.len() {
+        match data[idx] {
+            ESC => {
+                let start = idx + 1;
+                let Some(len) = data[start..]
+                    .iter()
+                    .position(|b| !(0x20..=0x2f).contains(b))
+                else {
+                    break;
+                };
+                let intermediate = &data[start..start + len];
+                let final_byte = data[start + len];
+                idx = start + len + 1;
+
+                match (intermediate, final_byte) {
+                    (b"(", f) => gl_ascii = f == b'B' || f == b'J',
+                    (b"-", f) => gr_latin1 = f == b'A',
+                    (b"%", b'G') => {
+                        let end = data[idx..]
+                            .windows(UTF8_SEGMENT_END.len())
+                            .position(|w| w == UTF8_SEGMENT_END)
+                            .map(|pos| idx + pos)
+                            .unwrap_or(data.len());
+                        ret.push_str(&String::from_utf8_lossy(&data[idx..end]));
+                        idx = (end + UTF8_SEGMENT_END.len()).min(data.len());
+                    }
+                    (b"%/", _) => {
+                        // Extended segment with a two byte length
+                        let len = match data.get(idx..idx + 2) {
+                            Some(&[m, l]) => ((m & 0x7f) as usize) << 7 | (l & 0x7f) as usize,
+                            _ => data.len(),
+                        };
+                        idx = (idx + 2 + len).min(data.len());
+                        ret.push(char::REPLACEMENT_CHARACTER);
+                    }
+                    ([b'$', b'(', ..] | [b'$'], _) => gl_ascii = false,
+                    ([b'$', ..] | [b')'], _) => gr_latin1 = false,
+                    _ => {}
+                }
+                continue;
+            }
+            CSI => {
+                // Directionality control - we don't do anything with this.
+                idx = data[idx..]
+                    .iter()
+                    .position(|b| *b == b']')
+                    .map(|pos| idx + pos)
+                    .unwrap_or(data.len());
+            }
+            b @ (b'\t' | b'\n') => ret.push(char::from(b)),
+            b @ 0x20..=0x7e => ret.push(if gl_ascii {
+                char::from(b)
+            } else {
+                char::REPLACEMENT_CHARACTER
+            }),
+            b @ 0xa0..=0xff => ret.push(if gr_latin1 {
+                char::from(b)
+            } else {
+                char::REPLACEMENT_CHARACTER
+            }),
+            _ => {}
+        }
+        idx += 1;
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sanitize_utf8, LegacyEncoding, Transliterator};
+    use crate::config::TitleCharset;
+
+    #[test]
+    fn sanitize() {
+        assert_eq!(sanitize_utf8(b"title\0\0"), "title");
+        assert_eq!(sanitize_utf8(b"one\0two"), "one");
+        assert_eq!(sanitize_utf8(b"line\nbreak\ttab"), "line break tab");
+        assert_eq!(sanitize_utf8(b"\x1b[31mred\x7f"), "[31mred");
+        assert_eq!(
+            sanitize_utf8(b"bad \xff\xfe utf8"),
+            "bad \u{fffd}\u{fffd} utf8"
+        );
+        assert_eq!(sanitize_utf8("c1 \u{85}\u{9b}".as_bytes()), "c1 ");
+        assert_eq!(sanitize_utf8("ünïcødé 🦀".as_bytes()), "ünïcødé 🦀");
+    }
+
+    #[test]
+    fn transliterate() {
+        let t = |charset, language: &str, text| {
+            Transliterator {
+                charset,
+                language: language.to_string(),
+            }
+            .apply(text)
+        };
+
+        assert_eq!(t(TitleCharset::Ascii, "en", "plain"), "plain");
+        assert_eq!(
+            t(TitleCharset::Ascii, "en", "Škoda – Ærø…"),
+            "Skoda - AEro..."
+        );
+        assert_eq!(t(TitleCharset::Ascii, "en", "Müller"), "Muller");
+        assert_eq!(t(TitleCharset::Ascii, "de", "Müller"), "Mueller");
+        assert_eq!(
+            t(TitleCharset::Ascii, "sv", "Malmö Ångström"),
+            "Malmoe Aangstroem"
+        );
+        assert_eq!(t(TitleCharset::Ascii, "en", "日本 🦀"), "?? ?");
+        assert_eq!(t(TitleCharset::Latin1, "de", "Müller “ő”"), "Müller \"o\"");
+    }
+
+    #[test]
+    fn latin1_round_trip() {
+        let latin1 = LegacyEncoding::Latin1;
+        assert_eq!(latin1.encode("café ÿ"), b"caf\xe9 \xff");
+        assert_eq!(latin1.decode(b"caf\xe9 \xff"), "café ÿ");
+
+        let all: Vec<u8> = (0..=255).collect();
+        assert_eq!(latin1.encode(&latin1.decode(&all)), all);
+
+        assert_eq!(latin1.encode("日本 €"), b"?? ?");
+    }
+
+    #[test]
+    fn compound_text_round_trip() {
+        let ctext = LegacyEncoding::CompoundText;
+        let encoded = ctext.encode("café 日本!");
+        assert_eq!(
+            encoded,
+            [b"caf\xe9 \x1b%G", "日本".as_bytes(), b"\x1b%@!"].concat()
+        );
+        assert_eq!(ctext.decode(&encoded), "café 日本!");
+
+        for text in ["", "plain", "ÿ", "🦀", "a🦀b€c", "tab\tand\nnewline"] {
+            assert_eq!(ctext.decode(&ctext.encode(text)), text);
+        }
+
+        // Explicit designations of the default sets
+        assert_eq!(ctext.decode(b"\x1b(Babc\x1b-A\xe9"), "abcé");
+        // JIS X 0208 in GL, then back to ASCII
+        assert_eq!(ctext.decode(b"\x1b$(B0!\x1b(Bok"), "\u{fffd}\u{fffd}ok");
+        // Unterminated UTF-8 segment
+        assert_eq!(ctext.decode(b"\x1b%G\xc3\xa9"), "é");
+    }
+
+    #[test]
+    fn sanitize_fuzz() {
+        // xorshift, so the inputs are the same every run
+        let mut state = 0x2545f4914f6cdd1d_u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+
+        for _ in 0..10_000 {
+            let len = (next() % 64) as usize;
+            let data: Vec<u8> = (0..len)
+                .map(|_| match next() % 4 {
+                    // bias towards the interesting bytes
+                    0 => [0, b'\n', 0x1b, 0x7f, 0x80, 0xc2, 0xe2, 0xff][(next() % 8) as usize],
+                    _ => next() as u8,
+                })
+                .collect();
+
+            let sanitized = sanitize_utf8(&data);
+            assert!(
+                !sanitized.chars().any(char::is_control),
+                "{data:?} sanitized to {sanitized:?}"
+            );
+            assert_eq!(
+                sanitize_utf8(sanitized.as_bytes()),
+                sanitized,
+                "sanitizing {data:?} is not idempotent"
+            );
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use rustix::event::{poll, PollFd, PollFlags};
+use std::io::Read;
 use std::mem::ManuallyDrop;
 use std::os::fd::{AsRawFd, BorrowedFd};
 use std::os::unix::net::UnixStream;
@@ -20,15 +21,19 @@ struct TestDataInner {
     server_connected: AtomicBool,
     display: Mutex<Option<String>>,
     server: Mutex<Option<UnixStream>>,
+    config: xwls::config::Config,
+    /// The display satellite is asked to claim, instead of one Xwayland picks.
+    wanted_display: Option<String>,
 }
 
 #[derive(Default, Clone)]
 struct TestData(Arc<TestDataInner>);
 
 impl TestData {
-    fn new(server: UnixStream) -> Self {
+    fn new(server: UnixStream, config: xwls::config::Config) -> Self {
         Self(Arc::new(TestDataInner {
             server: Mutex::new(server.into()),
+            config,
             ..Default::default()
         }))
     }
@@ -55,7 +60,7 @@ impl xwls::RunData for TestData {
     }
 
     fn display(&self) -> Option<&str> {
-        None
+        self.wanted_display.as_deref()
     }
 
     fn server(&self) -> Option<UnixStream> {
@@ -63,6 +68,10 @@ impl xwls::RunData for TestData {
         assert!(server.is_some());
         server.take()
     }
+
+    fn config(&self) -> xwls::config::Config {
+        self.0.config.clone()
+    }
 }
 
 struct Fixture {
@@ -83,6 +92,13 @@ impl Drop for Fixture {
 
 impl Fixture {
     fn new_preset(pre_connect: impl FnOnce(&mut testwl::Server)) -> Self {
+        Self::new_with_config(Default::default(), pre_connect)
+    }
+
+    fn new_with_config(
+        config: xwls::config::Config,
+        pre_connect: impl FnOnce(&mut testwl::Server),
+    ) -> Self {
         static INIT: Once = Once::new();
         INIT.call_once(|| {
             env_logger::builder()
@@ -96,7 +112,7 @@ impl Fixture {
         let mut testwl = testwl::Server::new(false);
         pre_connect(&mut testwl);
         testwl.connect(a);
-        let our_data = TestData::new(b);
+        let our_data = TestData::new(b, config);
         let data = our_data.clone();
         let thread = std::thread::spawn(move || xwls::main(data));
 
@@ -260,10 +276,18 @@ xcb::atoms_struct! {
         wm_protocols => b"WM_PROTOCOLS",
         net_active_window => b"_NET_ACTIVE_WINDOW",
         wm_delete_window => b"WM_DELETE_WINDOW",
+        wm_take_focus => b"WM_TAKE_FOCUS",
+        net_close_window => b"_NET_CLOSE_WINDOW" only_if_exists = false,
+        net_desktop_geometry => b"_NET_DESKTOP_GEOMETRY" only_if_exists = false,
+        net_number_of_desktops => b"_NET_NUMBER_OF_DESKTOPS" only_if_exists = false,
         clipboard => b"CLIPBOARD",
         targets => b"TARGETS",
         multiple => b"MULTIPLE",
+        timestamp => b"TIMESTAMP",
         wm_check => b"_NET_SUPPORTING_WM_CHECK",
+        net_wm_name => b"_NET_WM_NAME" only_if_exists = false,
+        net_wm_visible_name => b"_NET_WM_VISIBLE_NAME" only_if_exists = false,
+        utf8_string => b"UTF8_STRING" only_if_exists = false,
         mime1 => b"text/plain" only_if_exists = false,
         mime2 => b"blah/blah" only_if_exists = false,
         incr => b"INCR",
@@ -664,6 +688,45 @@ fn window_properties_after_reparent() {
     f.wait_and_dispatch();
 }
 
+#[test]
+fn keep_toplevel_undoes_reparent() {
+    let config = xwls::config::Config {
+        keep_toplevel: vec!["plugin".to_string()],
+        ..Default::default()
+    };
+    let mut f = Fixture::new_with_config(config, |_| {});
+    let mut connection = Connection::new(&f.display);
+
+    let host = connection.new_window(connection.root, 0, 0, 100, 100, false);
+    let editor = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.set_property(
+        editor,
+        x::ATOM_STRING,
+        x::ATOM_WM_CLASS,
+        b"editor\0plugin\0",
+    );
+    connection
+        .send_and_check_request(&x::ReparentWindow {
+            window: editor,
+            parent: host,
+            x: 10,
+            y: 10,
+        })
+        .unwrap();
+    f.wait_and_dispatch();
+
+    let tree = connection.get_reply(&x::QueryTree { window: editor });
+    assert_eq!(tree.parent(), connection.root);
+
+    connection.map_window(editor);
+    f.wait_and_dispatch();
+    let surface = f
+        .testwl
+        .last_created_surface_id()
+        .expect("No surface created!");
+    f.configure_and_verify_new_toplevel(&mut connection, editor, surface);
+}
+
 #[test]
 fn input_focus() {
     let mut f = Fixture::new();
@@ -725,6 +788,111 @@ fn input_focus() {
     f.wm_delete_window(&mut connection, win1, surface1);
 }
 
+#[test]
+fn input_focus_deferred_during_pointer_grab() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+
+    let win1 = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    let surface1 = f.map_as_toplevel(&mut connection, win1);
+    let win2 = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    let surface2 = f.map_as_toplevel(&mut connection, win2);
+
+    f.testwl.focus_toplevel(surface1);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(connection.get_reply(&x::GetInputFocus {}).focus(), win1);
+
+    // Simulate an open menu in the first window
+    let grab = connection.get_reply(&x::GrabPointer {
+        owner_events: true,
+        grab_window: win1,
+        event_mask: x::EventMask::BUTTON_PRESS,
+        pointer_mode: x::GrabMode::Async,
+        keyboard_mode: x::GrabMode::Async,
+        confine_to: x::WINDOW_NONE,
+        cursor: x::CURSOR_NONE,
+        time: x::CURRENT_TIME,
+    });
+    assert_eq!(grab.status(), x::GrabStatus::Success);
+
+    f.testwl.focus_toplevel(surface2);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(
+        connection.get_reply(&x::GetInputFocus {}).focus(),
+        win1,
+        "focus changed while pointer was grabbed"
+    );
+
+    connection
+        .send_and_check_request(&x::UngrabPointer {
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    f.testwl.focus_toplevel(surface2);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(connection.get_reply(&x::GetInputFocus {}).focus(), win2);
+}
+
+#[test]
+fn deferred_focus_applied_after_grab_under_pointer() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+
+    let win1 = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    let surface1 = f.map_as_toplevel(&mut connection, win1);
+    let win2 = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    let surface2 = f.map_as_toplevel(&mut connection, win2);
+
+    f.testwl.focus_toplevel(surface1);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(connection.get_reply(&x::GetInputFocus {}).focus(), win1);
+
+    // Grabbing for the window the pointer is in already causes no crossing events.
+    connection
+        .send_and_check_request(&x::WarpPointer {
+            src_window: x::WINDOW_NONE,
+            dst_window: win1,
+            src_x: 0,
+            src_y: 0,
+            src_width: 0,
+            src_height: 0,
+            dst_x: 5,
+            dst_y: 5,
+        })
+        .unwrap();
+    let grab = connection.get_reply(&x::GrabPointer {
+        owner_events: true,
+        grab_window: win1,
+        event_mask: x::EventMask::BUTTON_PRESS,
+        pointer_mode: x::GrabMode::Async,
+        keyboard_mode: x::GrabMode::Async,
+        confine_to: x::WINDOW_NONE,
+        cursor: x::CURSOR_NONE,
+        time: x::CURRENT_TIME,
+    });
+    assert_eq!(grab.status(), x::GrabStatus::Success);
+
+    f.testwl.focus_toplevel(surface2);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(
+        connection.get_reply(&x::GetInputFocus {}).focus(),
+        win1,
+        "focus changed while pointer was grabbed"
+    );
+
+    // Once the grab is gone, the next time satellite wakes up it applies the focus change it
+    // held back, without the compositor having to activate the window again.
+    connection
+        .send_and_check_request(&x::UngrabPointer {
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    connection.set_property(win2, x::ATOM_STRING, x::ATOM_WM_NAME, b"wake up");
+    f.wait_and_dispatch();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(connection.get_reply(&x::GetInputFocus {}).focus(), win2);
+}
+
 #[test]
 fn quick_delete() {
     let mut f = Fixture::new();
@@ -921,7 +1089,11 @@ fn copy_from_wayland() {
         long_length: 10,
     });
     let targets: &[x::Atom] = reply.value();
-    assert_eq!(targets.len(), 2);
+    assert_eq!(targets.len(), 4);
+    assert_eq!(
+        targets[..2],
+        [connection.atoms.targets, connection.atoms.timestamp]
+    );
 
     for testwl::PasteData { mime_type, data } in offer {
         let atom = connection
@@ -1074,6 +1246,185 @@ fn funny_window_title() {
     assert_eq!(data.toplevel().title, Some("title".into()));
 }
 
+#[test]
+fn legacy_window_title() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    // STRING is Latin-1
+    connection.set_property(window, x::ATOM_STRING, x::ATOM_WM_NAME, b"caf\xe9");
+    let surface = f.map_as_toplevel(&mut connection, window);
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("café".into()));
+
+    let compound_text = connection
+        .get_reply(&x::InternAtom {
+            only_if_exists: false,
+            name: b"COMPOUND_TEXT",
+        })
+        .atom();
+    connection.set_property(
+        window,
+        compound_text,
+        x::ATOM_WM_NAME,
+        b"\xe9t\xe9 \x1b%G\xe2\x98\x83\x1b%@",
+    );
+    f.wait_and_dispatch();
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("été ☃".into()));
+}
+
+#[test]
+fn premap_configure_requests_coalesced() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 1, 1, false);
+    connection
+        .send_and_check_request(&x::ChangeWindowAttributes {
+            window,
+            value_list: &[x::Cw::EventMask(x::EventMask::STRUCTURE_NOTIFY)],
+        })
+        .unwrap();
+    for size in 2..20 {
+        connection
+            .send_and_check_request(&x::ConfigureWindow {
+                window,
+                value_list: &[x::ConfigWindow::Width(size), x::ConfigWindow::Height(size)],
+            })
+            .unwrap();
+    }
+    connection
+        .send_and_check_request(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::Width(40)],
+        })
+        .unwrap();
+    f.wait_and_dispatch();
+
+    let geometry = |connection: &mut Connection| {
+        let reply = connection.get_reply(&x::GetGeometry {
+            drawable: x::Drawable::Window(window),
+        });
+        (reply.width(), reply.height())
+    };
+    // Nothing is applied before the window is mapped
+    assert_eq!(geometry(&mut connection), (1, 1));
+    // but the client is told about the size it'll get.
+    let mut notified = None;
+    while let Some(event) = connection.poll_for_event().unwrap() {
+        if let xcb::Event::X(x::Event::ConfigureNotify(e)) = event {
+            notified = Some((e.width(), e.height()));
+        }
+    }
+    assert_eq!(notified, Some((40, 19)));
+
+    connection.map_window(window);
+    f.wait_and_dispatch();
+    assert_eq!(geometry(&mut connection), (40, 19));
+
+    let surface = f
+        .testwl
+        .last_created_surface_id()
+        .expect("No surface created");
+    f.configure_and_verify_new_toplevel(&mut connection, window, surface);
+}
+
+#[test]
+fn premap_resize_keeps_gravity() {
+    let mut f = Fixture::new();
+    let connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 100, 100, 50, 50, false);
+    // SouthEastGravity
+    let mut hints = [0; 18];
+    hints[0] = WmSizeHintsFlags::ProgramWinGravity.bits();
+    hints[17] = 9;
+    connection.set_property(
+        window,
+        x::ATOM_WM_SIZE_HINTS,
+        x::ATOM_WM_NORMAL_HINTS,
+        &hints,
+    );
+    connection
+        .send_and_check_request(&x::ConfigureWindow {
+            window,
+            value_list: &[x::ConfigWindow::Width(30), x::ConfigWindow::Height(20)],
+        })
+        .unwrap();
+    f.wait_and_dispatch();
+    connection.map_window(window);
+    f.wait_and_dispatch();
+
+    // The bottom right corner stays where it was.
+    let reply = connection.get_reply(&x::GetGeometry {
+        drawable: x::Drawable::Window(window),
+    });
+    assert_eq!(
+        (reply.x(), reply.y(), reply.width(), reply.height()),
+        (120, 130, 30, 20)
+    );
+}
+
+#[test]
+fn visible_window_title() {
+    let config = xwls::config::Config {
+        title_charset: Some(xwls::config::TitleCharset::Ascii),
+        ..Default::default()
+    };
+    let mut f = Fixture::new_with_config(config, |_| {});
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.set_property(
+        window,
+        connection.atoms.utf8_string,
+        connection.atoms.net_wm_name,
+        "Škoda – two\nlines".as_bytes(),
+    );
+    let surface = f.map_as_toplevel(&mut connection, window);
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("Skoda - two lines".into()));
+
+    let visible_name = |connection: &mut Connection, window| {
+        connection
+            .get_reply(&x::GetProperty {
+                delete: false,
+                window,
+                property: connection.atoms.net_wm_visible_name,
+                r#type: connection.atoms.utf8_string,
+                long_offset: 0,
+                long_length: 256,
+            })
+            .value::<u8>()
+            .to_vec()
+    };
+    assert_eq!(visible_name(&mut connection, window), b"Skoda - two lines");
+
+    // Sanitizing alone doesn't need a visible name
+    connection.set_property(
+        window,
+        connection.atoms.utf8_string,
+        connection.atoms.net_wm_name,
+        b"two\nlines",
+    );
+    f.wait_and_dispatch();
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("two lines".into()));
+    assert!(visible_name(&mut connection, window).is_empty());
+
+    // WM_NAME titles get one too
+    let legacy = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.set_property(legacy, x::ATOM_STRING, x::ATOM_WM_NAME, b"caf\xe9");
+    let surface = f.map_as_toplevel(&mut connection, legacy);
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("cafe".into()));
+    assert_eq!(visible_name(&mut connection, legacy), b"cafe");
+
+    connection.set_property(legacy, x::ATOM_STRING, x::ATOM_WM_NAME, b"plain");
+    f.wait_and_dispatch();
+    let data = f.testwl.get_surface_data(surface).unwrap();
+    assert_eq!(data.toplevel().title, Some("plain".into()));
+    assert!(visible_name(&mut connection, legacy).is_empty());
+}
+
 #[test]
 fn close_window() {
     let mut f = Fixture::new();
@@ -1166,6 +1517,99 @@ fn primary_output() {
     assert_eq!(reply.output(), output3);
 }
 
+#[test]
+fn pinned_primary_output() {
+    let config = xwls::config::Config {
+        primary_output: Some("WL-2".to_string()),
+        ..Default::default()
+    };
+    let mut f = Fixture::new_with_config(config, |testwl| {
+        testwl.new_output(0, 0); // WL-1
+        testwl.new_output(500, 500); // WL-2
+    });
+    let mut conn = Connection::new(&f.display);
+    let wl_output1 = f.testwl.get_output("WL-1").unwrap();
+
+    let window = conn.new_window(conn.root, 0, 0, 20, 20, false);
+    let surface = f.map_as_toplevel(&mut conn, window);
+    f.testwl.move_surface_to_output(surface, &wl_output1);
+    f.wait_and_dispatch();
+    f.testwl.focus_toplevel(surface);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    let reply = conn.get_reply(&xcb::randr::GetOutputPrimary { window: conn.root });
+    let reply = conn.get_reply(&xcb::randr::GetOutputInfo {
+        output: reply.output(),
+        config_timestamp: x::CURRENT_TIME,
+    });
+    assert_eq!(reply.name(), b"WL-2");
+}
+
+#[test]
+fn primary_output_set_by_client() {
+    let mut f = Fixture::new_preset(|testwl| {
+        testwl.new_output(0, 0); // WL-1
+        testwl.new_output(500, 500); // WL-2
+    });
+    let mut conn = Connection::new(&f.display);
+    let wl_output1 = f.testwl.get_output("WL-1").unwrap();
+
+    let reply = conn.get_reply(&xcb::randr::GetScreenResources { window: conn.root });
+    let output2 = reply
+        .outputs()
+        .iter()
+        .copied()
+        .find(|&output| {
+            let reply = conn.get_reply(&xcb::randr::GetOutputInfo {
+                output,
+                config_timestamp: reply.config_timestamp(),
+            });
+            reply.name() == b"WL-2"
+        })
+        .expect("Couldn't find WL-2");
+    conn.send_and_check_request(&xcb::randr::SetOutputPrimary {
+        window: conn.root,
+        output: output2,
+    })
+    .unwrap();
+
+    let window = conn.new_window(conn.root, 0, 0, 20, 20, false);
+    let surface = f.map_as_toplevel(&mut conn, window);
+    f.testwl.move_surface_to_output(surface, &wl_output1);
+    f.wait_and_dispatch();
+    f.testwl.focus_toplevel(surface);
+    std::thread::sleep(std::time::Duration::from_millis(10));
+
+    let reply = conn.get_reply(&xcb::randr::GetOutputPrimary { window: conn.root });
+    assert_eq!(reply.output(), output2);
+}
+
+#[test]
+fn xinerama_follows_outputs() {
+    let mut f = Fixture::new_preset(|testwl| {
+        testwl.new_output(0, 0); // WL-1
+        testwl.new_output(1000, 0); // WL-2
+    });
+    let conn = Connection::new(&f.display);
+    let screens = |conn: &Connection| {
+        let reply = conn.get_reply(&xcb::xinerama::QueryScreens {});
+        let mut screens: Vec<_> = reply
+            .screen_info()
+            .iter()
+            .map(|s| (s.x_org, s.y_org, s.width, s.height))
+            .collect();
+        screens.sort();
+        screens
+    };
+    assert_eq!(screens(&conn), [(0, 0, 1000, 1000), (1000, 0, 1000, 1000)]);
+
+    let output = f.testwl.get_output("WL-2").unwrap();
+    f.testwl.move_output(&output, 0, 1000);
+    f.wait_and_dispatch();
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    assert_eq!(screens(&conn), [(0, 0, 1000, 1000), (0, 1000, 1000, 1000)]);
+}
+
 #[test]
 fn incr_copy_from_x11() {
     let mut f = Fixture::new();
@@ -1273,6 +1717,91 @@ fn incr_copy_from_x11() {
     assert_eq!(&paste_data.data, &data);
 }
 
+/// Takes the selection with TARGETS, mime1 and mime2.
+fn own_selection_with_two_targets(f: &mut Fixture, connection: &mut Connection) {
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    f.map_as_toplevel(connection, window);
+    connection.set_selection_owner(window);
+
+    let request = connection.await_selection_request();
+    assert_eq!(request.target(), connection.atoms.targets);
+    connection.set_property(
+        request.requestor(),
+        x::ATOM_ATOM,
+        request.property(),
+        &[
+            connection.atoms.targets,
+            connection.atoms.mime1,
+            connection.atoms.mime2,
+        ],
+    );
+    connection.send_selection_notify(&request);
+    f.wait_and_dispatch();
+}
+
+/// Answers the next selection request, after checking that it's the only one.
+#[track_caller]
+fn answer_queued_request(connection: &mut Connection, target: x::Atom, data: &[u8]) {
+    let request = connection.await_selection_request();
+    assert_eq!(request.target(), target);
+    assert!(
+        poll(&mut [connection.pollfd.clone()], 50).unwrap() == 0
+            && connection.poll_for_event().unwrap().is_none(),
+        "Got a second selection request while a transfer was in progress"
+    );
+    connection.set_property(request.requestor(), target, request.property(), data);
+    connection.send_selection_notify(&request);
+}
+
+#[test]
+fn queued_copies_from_x11() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    own_selection_with_two_targets(&mut f, &mut connection);
+    let (mime1, mime2) = (connection.atoms.mime1, connection.atoms.mime2);
+
+    // Two clients paste at once, one of them asking for both targets.
+    let mut first = f.testwl.request_paste("text/plain");
+    let mut second = f.testwl.request_paste("blah/blah");
+    let mut third = f.testwl.request_paste("text/plain");
+
+    answer_queued_request(&mut connection, mime1, b"first");
+    answer_queued_request(&mut connection, mime2, &[1, 2, 3]);
+    answer_queued_request(&mut connection, mime1, b"third");
+
+    let read = |file: &mut std::fs::File| {
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        data
+    };
+    assert_eq!(read(&mut first), b"first");
+    assert_eq!(read(&mut second), [1, 2, 3]);
+    assert_eq!(read(&mut third), b"third");
+}
+
+#[test]
+fn queued_copy_from_x11_outlives_gone_requestor() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    own_selection_with_two_targets(&mut f, &mut connection);
+    let (mime1, mime2) = (connection.atoms.mime1, connection.atoms.mime2);
+
+    let first = f.testwl.request_paste("text/plain");
+    let second = f.testwl.request_paste("blah/blah");
+    let mut third = f.testwl.request_paste("text/plain");
+    // The first two requestors go away while their transfers are in progress or queued.
+    drop(first);
+    drop(second);
+
+    answer_queued_request(&mut connection, mime1, b"first");
+    answer_queued_request(&mut connection, mime2, &[1, 2, 3]);
+    answer_queued_request(&mut connection, mime1, b"third");
+
+    let mut data = Vec::new();
+    third.read_to_end(&mut data).unwrap();
+    assert_eq!(data, b"third");
+}
+
 #[test]
 fn wayland_then_x11_clipboard_owner() {
     let mut f = Fixture::new();
@@ -1307,6 +1836,83 @@ fn wayland_then_x11_clipboard_owner() {
     assert_eq!(request.target(), connection.atoms.targets);
 }
 
+#[test]
+fn clipboard_manager_copy_not_reimported() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.get_selection_owner_change_events(true, window);
+    f.map_as_toplevel(&mut connection, window);
+
+    let data = b"boingloings";
+    f.testwl.create_data_offer(vec![testwl::PasteData {
+        mime_type: "text/plain".into(),
+        data: data.to_vec(),
+    }]);
+    connection.await_selection_owner_change();
+    connection.verify_clipboard_owner(connection.wm_window);
+    connection.get_selection_owner_change_events(false, window);
+
+    // Act like a clipboard manager: copy the contents, then take ownership
+    connection
+        .send_and_check_request(&x::ConvertSelection {
+            requestor: window,
+            selection: connection.atoms.clipboard,
+            target: connection.atoms.mime1,
+            property: connection.atoms.mime1,
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    f.wait_and_dispatch();
+    let notify = connection.await_selection_notify();
+    assert_eq!(notify.property(), connection.atoms.mime1);
+    let reply = connection.get_reply(&x::GetProperty {
+        delete: true,
+        window,
+        property: connection.atoms.mime1,
+        r#type: x::ATOM_ANY,
+        long_offset: 0,
+        long_length: 10,
+    });
+    assert_eq!(reply.value::<u8>(), data);
+
+    let serve = |connection: &mut Connection, content: &[u8]| {
+        let request = connection.await_selection_request();
+        assert_eq!(request.target(), connection.atoms.targets);
+        connection.set_property(
+            request.requestor(),
+            x::ATOM_ATOM,
+            request.property(),
+            &[connection.atoms.mime1],
+        );
+        connection.send_selection_notify(&request);
+
+        let request = connection.await_selection_request();
+        assert_eq!(request.target(), connection.atoms.mime1);
+        connection.set_property(
+            request.requestor(),
+            connection.atoms.mime1,
+            request.property(),
+            content,
+        );
+        connection.send_selection_notify(&request);
+    };
+
+    connection.set_selection_owner(window);
+    serve(&mut connection, data);
+    f.wait_and_dispatch();
+    assert!(
+        !f.testwl.has_selection(),
+        "copy of forwarded selection was sent back to Wayland"
+    );
+
+    // Different contents are a real copy
+    connection.set_selection_owner(window);
+    serve(&mut connection, b"something else");
+    f.wait_and_dispatch();
+    assert_eq!(f.testwl.data_source_mimes(), vec!["text/plain".to_string()]);
+}
+
 #[test]
 fn fake_selection_targets() {
     let mut f = Fixture::new();
@@ -1362,3 +1968,541 @@ fn fake_selection_targets() {
         std::str::from_utf8(data).unwrap()
     );
 }
+
+#[test]
+fn selection_timestamp_target() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.get_selection_owner_change_events(true, window);
+
+    f.map_as_toplevel(&mut connection, window);
+    f.testwl.create_data_offer(vec![testwl::PasteData {
+        mime_type: "text/plain".into(),
+        data: b"boingloings".to_vec(),
+    }]);
+
+    let owner_change = connection.await_selection_owner_change();
+    connection.verify_clipboard_owner(connection.wm_window);
+    connection.get_selection_owner_change_events(false, window);
+
+    let dest_atom = connection
+        .get_reply(&x::InternAtom {
+            name: b"dest",
+            only_if_exists: false,
+        })
+        .atom();
+
+    connection
+        .send_and_check_request(&x::ConvertSelection {
+            requestor: window,
+            selection: connection.atoms.clipboard,
+            target: connection.atoms.timestamp,
+            property: dest_atom,
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+
+    let notify = connection.await_selection_notify();
+    assert_eq!(notify.target(), connection.atoms.timestamp);
+    assert_eq!(notify.property(), dest_atom, "TIMESTAMP conversion failed");
+
+    let reply = connection.get_reply(&x::GetProperty {
+        delete: true,
+        window,
+        property: dest_atom,
+        r#type: x::ATOM_INTEGER,
+        long_offset: 0,
+        long_length: 1,
+    });
+    let timestamp: &[u32] = reply.value();
+    assert_eq!(timestamp, &[owner_change.selection_timestamp()]);
+
+    // A requestor that's gone doesn't take satellite down with it.
+    let gone = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection
+        .send_and_check_request(&x::ConvertSelection {
+            requestor: gone,
+            selection: connection.atoms.clipboard,
+            target: connection.atoms.timestamp,
+            property: dest_atom,
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    connection
+        .send_and_check_request(&x::DestroyWindow { window: gone })
+        .unwrap();
+    connection
+        .send_and_check_request(&x::ConvertSelection {
+            requestor: window,
+            selection: connection.atoms.clipboard,
+            target: connection.atoms.timestamp,
+            property: dest_atom,
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    let notify = connection.await_selection_notify();
+    assert_eq!(notify.requestor(), window);
+    assert_eq!(notify.property(), dest_atom);
+}
+
+#[test]
+fn clipboard_max_size() {
+    let config = xwls::config::Config {
+        clipboard_max_size: Some(8),
+        ..Default::default()
+    };
+    let mut f = Fixture::new_with_config(config, |_| {});
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.get_selection_owner_change_events(true, window);
+    f.map_as_toplevel(&mut connection, window);
+
+    f.testwl.create_data_offer(vec![
+        testwl::PasteData {
+            mime_type: "text/plain".into(),
+            data: b"boingloings".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "blah/blah".into(),
+            data: vec![1, 2, 3, 2, 1],
+        },
+    ]);
+    connection.await_selection_owner_change();
+    connection.verify_clipboard_owner(connection.wm_window);
+    connection.get_selection_owner_change_events(false, window);
+
+    for (target, allowed) in [
+        (connection.atoms.mime1, false),
+        (connection.atoms.mime2, true),
+    ] {
+        connection
+            .send_and_check_request(&x::ConvertSelection {
+                requestor: window,
+                selection: connection.atoms.clipboard,
+                target,
+                property: target,
+                time: x::CURRENT_TIME,
+            })
+            .unwrap();
+        f.wait_and_dispatch();
+        let notify = connection.await_selection_notify();
+        assert_eq!(notify.target(), target);
+        if allowed {
+            assert_eq!(notify.property(), target);
+        } else {
+            assert_eq!(notify.property(), x::ATOM_NONE);
+        }
+    }
+}
+
+#[test]
+fn clipboard_mime_filters() {
+    let mut config = xwls::config::Config::default();
+    config.clipboard_to_x11.deny.push("yah/*".into());
+    config.clipboard_to_wayland.allow.push("text/*".into());
+    let mut f = Fixture::new_with_config(config, |_| {});
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.get_selection_owner_change_events(true, window);
+    f.map_as_toplevel(&mut connection, window);
+
+    // Wayland -> X11
+    f.testwl.create_data_offer(vec![
+        testwl::PasteData {
+            mime_type: "text/plain".into(),
+            data: b"boingloings".to_vec(),
+        },
+        testwl::PasteData {
+            mime_type: "yah/hah".into(),
+            data: vec![1, 2, 3, 2, 1],
+        },
+    ]);
+    connection.await_selection_owner_change();
+    connection.verify_clipboard_owner(connection.wm_window);
+    connection.get_selection_owner_change_events(false, window);
+
+    let dest_atom = connection
+        .get_reply(&x::InternAtom {
+            name: b"dest",
+            only_if_exists: false,
+        })
+        .atom();
+    connection
+        .send_and_check_request(&x::ConvertSelection {
+            requestor: window,
+            selection: connection.atoms.clipboard,
+            target: connection.atoms.targets,
+            property: dest_atom,
+            time: x::CURRENT_TIME,
+        })
+        .unwrap();
+    connection.await_selection_notify();
+    let reply = connection.get_reply(&x::GetProperty {
+        delete: true,
+        window,
+        property: dest_atom,
+        r#type: x::ATOM_ATOM,
+        long_offset: 0,
+        long_length: 10,
+    });
+    let targets: &[x::Atom] = reply.value();
+    assert_eq!(
+        targets,
+        &[
+            connection.atoms.targets,
+            connection.atoms.timestamp,
+            connection.atoms.mime1
+        ]
+    );
+
+    // X11 -> Wayland
+    connection.set_selection_owner(window);
+    let request = connection.await_selection_request();
+    assert_eq!(request.target(), connection.atoms.targets);
+    connection.set_property(
+        request.requestor(),
+        x::ATOM_ATOM,
+        request.property(),
+        &[connection.atoms.mime1, connection.atoms.mime2],
+    );
+    connection.send_selection_notify(&request);
+    f.wait_and_dispatch();
+
+    assert_eq!(f.testwl.data_source_mimes(), vec!["text/plain".to_string()]);
+}
+
+#[test]
+fn legacy_text_copy_from_x11() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    f.map_as_toplevel(&mut connection, window);
+    connection.set_selection_owner(window);
+
+    let request = connection.await_selection_request();
+    assert_eq!(request.target(), connection.atoms.targets);
+    connection.set_property(
+        request.requestor(),
+        x::ATOM_ATOM,
+        request.property(),
+        &[x::ATOM_STRING],
+    );
+    connection.send_selection_notify(&request);
+    f.wait_and_dispatch();
+
+    let mut advertised_mimes = f.testwl.data_source_mimes();
+    advertised_mimes.sort();
+    assert_eq!(advertised_mimes, ["STRING", "text/plain;charset=utf-8"]);
+
+    let data = f.testwl.paste_data(|_, _| {
+        let request = connection.await_selection_request();
+        assert_eq!(request.target(), x::ATOM_STRING);
+        connection.set_property(
+            request.requestor(),
+            x::ATOM_STRING,
+            request.property(),
+            b"caf\xe9",
+        );
+        connection.send_selection_notify(&request);
+        true
+    });
+
+    for testwl::PasteData { mime_type, data } in data {
+        match mime_type.as_str() {
+            "STRING" => assert_eq!(data, b"caf\xe9"),
+            "text/plain;charset=utf-8" => assert_eq!(data, "café".as_bytes()),
+            other => panic!("unexpected mime type: {other}"),
+        }
+    }
+}
+
+#[test]
+fn legacy_text_copy_from_wayland() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.get_selection_owner_change_events(true, window);
+
+    f.map_as_toplevel(&mut connection, window);
+    f.testwl.create_data_offer(vec![testwl::PasteData {
+        mime_type: "text/plain;charset=utf-8".into(),
+        data: "café ☕".as_bytes().to_vec(),
+    }]);
+
+    connection.await_selection_owner_change();
+    connection.verify_clipboard_owner(connection.wm_window);
+    connection.get_selection_owner_change_events(false, window);
+
+    let compound_text = connection
+        .get_reply(&x::InternAtom {
+            only_if_exists: false,
+            name: b"COMPOUND_TEXT",
+        })
+        .atom();
+
+    let truth: [(x::Atom, &[u8]); 2] = [
+        (x::ATOM_STRING, b"caf\xe9 ?"),
+        (compound_text, b"caf\xe9 \x1b%G\xe2\x98\x95\x1b%@"),
+    ];
+    for (target, expected) in truth {
+        connection
+            .send_and_check_request(&x::ConvertSelection {
+                requestor: window,
+                selection: connection.atoms.clipboard,
+                target,
+                property: target,
+                time: x::CURRENT_TIME,
+            })
+            .unwrap();
+
+        f.wait_and_dispatch();
+        let notify = connection.await_selection_notify();
+        assert_eq!(notify.property(), target, "ConvertSelection failed");
+
+        let reply = connection.get_reply(&x::GetProperty {
+            delete: true,
+            window,
+            property: target,
+            r#type: target,
+            long_offset: 0,
+            long_length: 20,
+        });
+        let data: &[u8] = reply.value();
+        assert_eq!(data, expected);
+    }
+}
+
+#[test]
+fn globally_active_focus() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.set_property(
+        window,
+        x::ATOM_ATOM,
+        connection.atoms.wm_protocols,
+        &[connection.atoms.wm_take_focus],
+    );
+    // InputHint flag with input = False
+    connection.set_property(window, x::ATOM_WM_HINTS, x::ATOM_WM_HINTS, &[1u32, 0]);
+    f.map_as_toplevel(&mut connection, window);
+
+    let event = loop {
+        match connection.inner.poll_for_event().unwrap() {
+            Some(xcb::Event::X(x::Event::ClientMessage(event))) => break event,
+            Some(_) => {}
+            None => connection.await_event(),
+        }
+    };
+    assert_eq!(event.window(), window);
+    assert_eq!(event.r#type(), connection.atoms.wm_protocols);
+    let x::ClientMessageData::Data32(data) = event.data() else {
+        panic!("wrong data type: {:?}", event.data());
+    };
+    assert_eq!(data[0], connection.atoms.wm_take_focus.resource_id());
+    assert_ne!(
+        data[1],
+        x::CURRENT_TIME,
+        "WM_TAKE_FOCUS needs a real timestamp"
+    );
+
+    let focus = connection.get_reply(&x::GetInputFocus {}).focus();
+    assert_ne!(focus, window, "window with input = False was focused");
+}
+
+#[test]
+fn net_close_window() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.set_property(
+        window,
+        x::ATOM_ATOM,
+        connection.atoms.wm_protocols,
+        &[connection.atoms.wm_delete_window],
+    );
+    f.map_as_toplevel(&mut connection, window);
+
+    // What pagers and wmctrl send
+    connection
+        .send_and_check_request(&x::SendEvent {
+            propagate: false,
+            destination: x::SendEventDest::Window(connection.root),
+            event_mask: x::EventMask::SUBSTRUCTURE_NOTIFY | x::EventMask::SUBSTRUCTURE_REDIRECT,
+            event: &x::ClientMessageEvent::new(
+                window,
+                connection.atoms.net_close_window,
+                x::ClientMessageData::Data32([x::CURRENT_TIME, 2, 0, 0, 0]),
+            ),
+        })
+        .unwrap();
+
+    let event = loop {
+        match connection.inner.poll_for_event().unwrap() {
+            Some(xcb::Event::X(x::Event::ClientMessage(event))) => break event,
+            Some(_) => {}
+            None => connection.await_event(),
+        }
+    };
+    assert_eq!(event.window(), window);
+    assert_eq!(event.r#type(), connection.atoms.wm_protocols);
+    let x::ClientMessageData::Data32(data) = event.data() else {
+        panic!("wrong data type: {:?}", event.data());
+    };
+    assert_eq!(data[0], connection.atoms.wm_delete_window.resource_id());
+}
+
+#[test]
+fn desktop_geometry() {
+    let mut f = Fixture::new();
+    let connection = Connection::new(&f.display);
+
+    let get_cardinals = |property| {
+        let reply = connection.get_reply(&x::GetProperty {
+            delete: false,
+            window: connection.root,
+            property,
+            r#type: x::ATOM_CARDINAL,
+            long_offset: 0,
+            long_length: 2,
+        });
+        reply.value::<u32>().to_vec()
+    };
+
+    assert_eq!(get_cardinals(connection.atoms.net_number_of_desktops), [1]);
+
+    f.create_output(0, 0);
+    f.create_output(1000, 0);
+    f.wait_and_dispatch();
+    assert_eq!(
+        get_cardinals(connection.atoms.net_desktop_geometry),
+        [2000, 1000]
+    );
+}
+
+#[test]
+fn x_event_flood_does_not_starve_wayland() {
+    let mut f = Fixture::new();
+    let mut connection = Connection::new(&f.display);
+    let busy = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    let busy_surface = f.map_as_toplevel(&mut connection, busy);
+
+    // A second client keeps satellite busy with title changes while a new window is mapped.
+    let flooder = Connection::new(&f.display);
+    for i in 0..5000 {
+        flooder.send_request(&x::ChangeProperty {
+            mode: x::PropMode::Replace,
+            window: busy,
+            property: flooder.atoms.net_wm_name,
+            r#type: flooder.atoms.utf8_string,
+            data: format!("title {i}").as_bytes(),
+        });
+    }
+    flooder.flush().unwrap();
+    let window = connection.new_window(connection.root, 0, 0, 20, 20, false);
+    connection.map_window(window);
+
+    let mut pollfd = [f.pollfd.clone()];
+    let start = Instant::now();
+    let mut surface = None;
+    let mut title = None;
+    // The flooded window's title at the time the new window's surface showed up.
+    let mut title_at_surface = None;
+    while (surface.is_none() || title.as_deref() != Some("title 4999"))
+        && start.elapsed() < Duration::from_secs(5)
+    {
+        if poll(&mut pollfd, 50).unwrap() > 0 {
+            f.testwl.dispatch();
+            f.pollfd.clear_revents();
+        }
+        title = f
+            .testwl
+            .get_surface_data(busy_surface)
+            .unwrap()
+            .toplevel()
+            .title
+            .clone();
+        surface = f
+            .testwl
+            .last_created_surface_id()
+            .filter(|id| *id != busy_surface);
+        if surface.is_some() && title_at_surface.is_none() {
+            title_at_surface = Some(title.clone());
+        }
+    }
+
+    let surface = surface.expect("No surface created during the flood");
+    assert_ne!(
+        title_at_surface.flatten().as_deref(),
+        Some("title 4999"),
+        "the surface only showed up after the flood was handled"
+    );
+    f.configure_and_verify_new_toplevel(&mut connection, window, surface);
+    assert_eq!(title.as_deref(), Some("title 4999"));
+}
+
+#[test]
+fn lazy_xwayland_starts_on_first_client() {
+    // Far outside the range X servers pick displays from, so nothing else should be using it.
+    let display = (90..128)
+        .map(|n| format!(":{n}"))
+        .find(|display| !std::path::Path::new(&format!("/tmp/.X{}-lock", &display[1..])).exists())
+        .expect("no free display");
+    let socket = format!("/tmp/.X11-unix/X{}", &display[1..]);
+
+    let (a, b) = UnixStream::pair().unwrap();
+    let mut testwl = testwl::Server::new(false);
+    testwl.connect(a);
+    let data = TestData(Arc::new(TestDataInner {
+        server: Mutex::new(Some(b)),
+        config: xwls::config::Config {
+            lazy: true,
+            ..Default::default()
+        },
+        wanted_display: Some(display.clone()),
+        ..Default::default()
+    }));
+    let thread_data = data.clone();
+    let _thread = std::thread::spawn(move || xwls::main(thread_data));
+
+    let fd = unsafe { BorrowedFd::borrow_raw(testwl.poll_fd().as_raw_fd()) };
+    let mut pollfd = [PollFd::from_borrowed_fd(fd, PollFlags::IN)];
+    let start = Instant::now();
+    while !std::path::Path::new(&socket).exists() && start.elapsed() < Duration::from_secs(1) {
+        if poll(&mut pollfd, 10).unwrap() > 0 {
+            testwl.dispatch();
+        }
+    }
+    assert!(
+        std::path::Path::new(&socket).exists(),
+        "display wasn't bound"
+    );
+
+    // Nothing connected yet, so Xwayland isn't running either.
+    std::thread::sleep(Duration::from_millis(200));
+    testwl.dispatch();
+    assert!(!data.server_connected.load(Ordering::Relaxed));
+    assert!(data.display.lock().unwrap().is_none());
+
+    // The connection waits in the socket's backlog until Xwayland accepts it from -listenfd.
+    let client_display = display.clone();
+    let client = std::thread::spawn(move || {
+        let (connection, _) = xcb::Connection::connect(Some(&client_display)).unwrap();
+        connection.get_setup().roots().count()
+    });
+    let start = Instant::now();
+    while !client.is_finished() && start.elapsed() < Duration::from_secs(5) {
+        if poll(&mut pollfd, 10).unwrap() > 0 {
+            testwl.dispatch();
+        }
+    }
+    assert!(client.is_finished(), "Xwayland didn't accept the client");
+    assert!(client.join().unwrap() > 0);
+    assert!(data.server_connected.load(Ordering::Relaxed));
+    assert_eq!(
+        data.display.lock().unwrap().as_deref(),
+        Some(display.as_str())
+    );
+}
@@ -1,4 +1,4 @@
-use std::collections::{hash_map, HashMap, HashSet};
+use std::collections::{hash_map, HashMap};
 use std::io::Read;
 use std::io::Write;
 use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
@@ -6,9 +6,27 @@ use std::os::unix::net::UnixStream;
 use std::sync::{Arc, Mutex, OnceLock};
 use std::time::Instant;
 use wayland_protocols::{
+    ext::data_control::v1::server::{
+        ext_data_control_device_v1::{self, ExtDataControlDeviceV1},
+        ext_data_control_manager_v1::{self, ExtDataControlManagerV1},
+        ext_data_control_offer_v1::{self, ExtDataControlOfferV1},
+        ext_data_control_source_v1::{self, ExtDataControlSourceV1},
+    },
     wp::{
+        content_type::v1::server::{
+            wp_content_type_manager_v1::{self, WpContentTypeManagerV1},
+            wp_content_type_v1::{self, WpContentTypeV1},
+        },
+        idle_inhibit::zv1::server::{
+            zwp_idle_inhibit_manager_v1::{self, ZwpIdleInhibitManagerV1},
+            zwp_idle_inhibitor_v1::{self, ZwpIdleInhibitorV1},
+        },
         linux_dmabuf::zv1::server::zwp_linux_dmabuf_v1::ZwpLinuxDmabufV1,
-        pointer_constraints::zv1::server::zwp_pointer_constraints_v1::ZwpPointerConstraintsV1,
+        pointer_constraints::zv1::server::{
+            zwp_confined_pointer_v1::{self, ZwpConfinedPointerV1},
+            zwp_locked_pointer_v1::{self, ZwpLockedPointerV1},
+            zwp_pointer_constraints_v1::{self, ZwpPointerConstraintsV1},
+        },
         relative_pointer::zv1::server::zwp_relative_pointer_manager_v1::ZwpRelativePointerManagerV1,
         tablet::zv2::server::{
             zwp_tablet_manager_v2::ZwpTabletManagerV2,
@@ -20,9 +38,28 @@ use wayland_protocols::{
             zwp_tablet_tool_v2::{self, ZwpTabletToolV2},
             zwp_tablet_v2::ZwpTabletV2,
         },
-        viewporter::server::wp_viewporter::WpViewporter,
+        tearing_control::v1::server::{
+            wp_tearing_control_manager_v1::{self, WpTearingControlManagerV1},
+            wp_tearing_control_v1::{self, WpTearingControlV1},
+        },
+        viewporter::server::{
+            wp_viewport::{self, WpViewport},
+            wp_viewporter::{self, WpViewporter},
+        },
     },
     xdg::{
+        activation::v1::server::{
+            xdg_activation_token_v1::{self, XdgActivationTokenV1},
+            xdg_activation_v1::{self, XdgActivationV1},
+        },
+        decoration::zv1::server::{
+            zxdg_decoration_manager_v1::{self, ZxdgDecorationManagerV1},
+            zxdg_toplevel_decoration_v1::{self, ZxdgToplevelDecorationV1},
+        },
+        dialog::v1::server::{
+            xdg_dialog_v1::{self, XdgDialogV1},
+            xdg_wm_dialog_v1::{self, XdgWmDialogV1},
+        },
         shell::server::{
             xdg_popup::{self, XdgPopup},
             xdg_positioner::{self, XdgPositioner},
@@ -53,12 +90,13 @@ use wayland_server::{
         wl_keyboard::{self, WlKeyboard},
         wl_output::{self, WlOutput},
         wl_pointer::{self, WlPointer},
+        wl_region::{self, WlRegion},
         wl_seat::{self, WlSeat},
         wl_shm::WlShm,
         wl_shm_pool::WlShmPool,
         wl_surface::WlSurface,
     },
-    Client, Dispatch, Display, DisplayHandle, GlobalDispatch, Resource,
+    Client, Dispatch, Display, DisplayHandle, GlobalDispatch, Resource, WEnum,
 };
 use wl_drm::server::wl_drm::WlDrm;
 
@@ -74,9 +112,23 @@ pub struct BufferDamage {
 pub struct SurfaceData {
     pub surface: WlSurface,
     pub buffer: Option<WlBuffer>,
+    /// The size of the attached buffer, if it's a shm buffer.
+    pub buffer_size: Option<Vec2>,
+    pub buffer_scale: i32,
     pub last_damage: Option<BufferDamage>,
     pub role: Option<SurfaceRole>,
     pub last_enter_serial: Option<u32>,
+    pub content_type: Option<wp_content_type_v1::Type>,
+    pub presentation_hint: Option<wp_tearing_control_v1::PresentationHint>,
+    pub idle_inhibited: bool,
+    /// The rectangles of the opaque region, as (x, y, width, height).
+    pub opaque_region: Option<Vec<[i32; 4]>>,
+    pub viewport: Option<Viewport>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Viewport {
+    pub destination: Option<Vec2>,
 }
 
 impl SurfaceData {
@@ -116,9 +168,33 @@ pub struct Toplevel {
     pub min_size: Option<Vec2>,
     pub max_size: Option<Vec2>,
     pub states: Vec<xdg_toplevel::State>,
+    /// The output the client asked to be fullscreened on, if any.
+    pub fullscreen_output: Option<WlOutput>,
     pub closed: bool,
+    pub minimized: bool,
+    /// Set when the client activates the toplevel without a serial.
+    pub attention_requested: bool,
+    /// The serial of the last activation request with one.
+    pub activation_serial: Option<u32>,
+    pub parent: Option<XdgToplevel>,
+    pub modal: bool,
+    /// The decoration mode the client asked for.
+    pub decoration: Option<zxdg_toplevel_decoration_v1::Mode>,
     pub title: Option<String>,
     pub app_id: Option<String>,
+    /// The last interactive move or resize the client started.
+    pub grab: Option<ToplevelGrab>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToplevelGrab {
+    Move {
+        serial: u32,
+    },
+    Resize {
+        serial: u32,
+        edges: xdg_toplevel::ResizeEdge,
+    },
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -127,6 +203,8 @@ pub struct Popup {
     pub parent: XdgSurface,
     pub popup: XdgPopup,
     pub positioner_state: PositionerState,
+    /// The serial the popup grab was taken with.
+    pub grab_serial: Option<u32>,
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
@@ -139,6 +217,7 @@ pub struct Vec2 {
 pub struct XdgSurfaceData {
     pub surface: XdgSurface,
     pub last_configure_serial: u32,
+    pub window_geometry: Option<Rect>,
 }
 
 impl XdgSurfaceData {
@@ -146,6 +225,7 @@ impl XdgSurfaceData {
         Self {
             surface,
             last_configure_serial: 0,
+            window_geometry: None,
         }
     }
 
@@ -166,6 +246,36 @@ struct DataSourceData {
     mimes: Vec<String>,
 }
 
+/// The clipboard source set by the client, through either wl_data_device or ext-data-control.
+enum Selection {
+    DataDevice(WlDataSource),
+    DataControl(ExtDataControlSourceV1),
+}
+
+impl Selection {
+    fn mimes(&self) -> Vec<String> {
+        let data: &Mutex<DataSourceData> = match self {
+            Self::DataDevice(source) => source.data().unwrap(),
+            Self::DataControl(source) => source.data().unwrap(),
+        };
+        data.lock().unwrap().mimes.clone()
+    }
+
+    fn send(&self, mime_type: String, fd: BorrowedFd) {
+        match self {
+            Self::DataDevice(source) => source.send(mime_type, fd),
+            Self::DataControl(source) => source.send(mime_type, fd),
+        }
+    }
+
+    fn cancelled(&self) {
+        match self {
+            Self::DataDevice(source) => source.cancelled(),
+            Self::DataControl(source) => source.cancelled(),
+        }
+    }
+}
+
 struct Output {
     name: String,
     xdg: Option<ZxdgOutputV1>,
@@ -180,7 +290,7 @@ struct State {
     surfaces: HashMap<SurfaceId, SurfaceData>,
     outputs: HashMap<WlOutput, Output>,
     positioners: HashMap<PositionerId, PositionerState>,
-    buffers: HashSet<WlBuffer>,
+    buffers: HashMap<WlBuffer, Vec2>,
     begin: Instant,
     last_surface_id: Option<SurfaceId>,
     last_output: Option<WlOutput>,
@@ -188,9 +298,20 @@ struct State {
     pointer: Option<WlPointer>,
     keyboard: Option<KeyboardState>,
     configure_serial: u32,
-    selection: Option<WlDataSource>,
+    selection: Option<Selection>,
     data_device_man: Option<WlDataDeviceManager>,
     data_device: Option<WlDataDevice>,
+    data_control_device: Option<ExtDataControlDeviceV1>,
+    wm_base: Option<XdgWmBase>,
+    last_pong: Option<u32>,
+    /// Committed activation tokens and their serials.
+    activation_tokens: HashMap<String, Option<u32>>,
+    pointer_constraints: HashMap<SurfaceId, PointerConstraint>,
+}
+
+enum PointerConstraint {
+    Locked(ZwpLockedPointerV1),
+    Confined(ZwpConfinedPointerV1),
 }
 
 impl Default for State {
@@ -210,6 +331,11 @@ impl Default for State {
             selection: None,
             data_device_man: None,
             data_device: None,
+            data_control_device: None,
+            wm_base: None,
+            last_pong: None,
+            activation_tokens: HashMap::new(),
+            pointer_constraints: HashMap::new(),
         }
     }
 }
@@ -225,6 +351,9 @@ impl State {
     ) {
         let last_serial = self.configure_serial;
         let toplevel = self.get_toplevel(surface_id);
+        if states.contains(&xdg_toplevel::State::Activated) {
+            toplevel.minimized = false;
+        }
         toplevel.states = states.clone();
         let states: Vec<u8> = states
             .into_iter()
@@ -351,10 +480,16 @@ impl Server {
         dh.create_global::<State, WlSeat, _>(5, ());
         dh.create_global::<State, WlDataDeviceManager, _>(3, ());
         dh.create_global::<State, ZwpTabletManagerV2, _>(1, ());
+        dh.create_global::<State, XdgWmDialogV1, _>(1, ());
+        dh.create_global::<State, ZxdgDecorationManagerV1, _>(1, ());
+        dh.create_global::<State, XdgActivationV1, _>(1, ());
+        dh.create_global::<State, WpContentTypeManagerV1, _>(1, ());
+        dh.create_global::<State, WpViewporter, _>(1, ());
+        dh.create_global::<State, WpTearingControlManagerV1, _>(1, ());
+        dh.create_global::<State, ZwpIdleInhibitManagerV1, _>(1, ());
+        dh.create_global::<State, ZwpPointerConstraintsV1, _>(1, ());
         global_noop!(ZwpLinuxDmabufV1);
         global_noop!(ZwpRelativePointerManagerV1);
-        global_noop!(WpViewporter);
-        global_noop!(ZwpPointerConstraintsV1);
 
         struct HandlerData;
         impl ObjectData<State> for HandlerData {
@@ -444,6 +579,10 @@ impl Server {
         self.state.last_surface_id
     }
 
+    pub fn last_output(&self) -> Option<&WlOutput> {
+        self.state.last_output.as_ref()
+    }
+
     #[track_caller]
     pub fn last_created_output(&self) -> WlOutput {
         self.state
@@ -474,6 +613,12 @@ impl Server {
         self.display.flush_clients().unwrap();
     }
 
+    pub fn configure_toplevel_bounds(&mut self, surface_id: SurfaceId, width: i32, height: i32) {
+        let toplevel = self.state.get_toplevel(surface_id);
+        toplevel.toplevel.configure_bounds(width, height);
+        self.display.flush_clients().unwrap();
+    }
+
     #[track_caller]
     pub fn focus_toplevel(&mut self, surface_id: SurfaceId) {
         self.state.focus_toplevel(surface_id);
@@ -492,6 +637,17 @@ impl Server {
         self.display.flush_clients().unwrap();
     }
 
+    /// Dismisses a popup, like a compositor would when clicking outside of it.
+    #[track_caller]
+    pub fn popup_done(&mut self, surface_id: SurfaceId) {
+        let surface = self.state.surfaces.get(&surface_id).unwrap();
+        let Some(SurfaceRole::Popup(p)) = &surface.role else {
+            panic!("Surface does not have popup role: {:?}", surface.role);
+        };
+        p.popup.popup_done();
+        self.display.flush_clients().unwrap();
+    }
+
     #[track_caller]
     pub fn close_toplevel(&mut self, surface_id: SurfaceId) {
         let toplevel = self.state.get_toplevel(surface_id);
@@ -500,19 +656,41 @@ impl Server {
     }
 
     #[track_caller]
+    pub fn ping(&mut self, serial: u32) {
+        self.state
+            .wm_base
+            .as_ref()
+            .expect("xdg_wm_base not bound")
+            .ping(serial);
+        self.display.flush_clients().unwrap();
+    }
+
+    /// Issues an activation token to another client, like a launcher would get for the app it
+    /// starts.
+    pub fn issue_activation_token(&mut self, serial: Option<u32>) -> String {
+        let name = format!("launcher-token-{}", self.state.activation_tokens.len());
+        self.state.activation_tokens.insert(name.clone(), serial);
+        name
+    }
+
+    pub fn last_pong(&self) -> Option<u32> {
+        self.state.last_pong
+    }
+
     pub fn pointer(&self) -> &WlPointer {
         self.state.pointer.as_ref().unwrap()
     }
 
+    pub fn has_selection(&self) -> bool {
+        self.state.selection.is_some()
+    }
+
     #[track_caller]
     pub fn data_source_mimes(&self) -> Vec<String> {
         let Some(selection) = &self.state.selection else {
             panic!("No selection set on data device");
         };
-
-        let data: &Mutex<DataSourceData> = selection.data().unwrap();
-        let data = data.lock().unwrap();
-        data.mimes.to_vec()
+        selection.mimes()
     }
 
     #[track_caller]
@@ -528,16 +706,12 @@ impl Server {
             panic!("No selection set on data device");
         };
         type PendingRet = Vec<(String, Option<PendingData>)>;
-        let mut pending_ret: PendingRet = {
-            let data: &Mutex<DataSourceData> = selection.data().unwrap();
-            data.lock()
-                .unwrap()
-                .mimes
-                .iter()
-                .rev()
-                .map(|mime| (mime.clone(), None))
-                .collect()
-        };
+        let mut pending_ret: PendingRet = selection
+            .mimes()
+            .into_iter()
+            .rev()
+            .map(|mime| (mime, None))
+            .collect();
 
         let mut ret = Vec::new();
         let mut try_transfer =
@@ -591,21 +765,49 @@ impl Server {
         ret
     }
 
+    /// Asks the selection source for the data of a mime type, without waiting for any of it.
+    #[track_caller]
+    pub fn request_paste(&mut self, mime: &str) -> std::fs::File {
+        let Some(selection) = &self.state.selection else {
+            panic!("No selection set on data device");
+        };
+        let (rx, tx) = rustix::pipe::pipe().unwrap();
+        selection.send(mime.to_string(), tx.as_fd());
+        drop(tx);
+        self.display.flush_clients().unwrap();
+        std::fs::File::from(rx)
+    }
+
     pub fn data_source_exists(&self) -> bool {
         self.state.selection.is_none()
     }
 
     #[track_caller]
     pub fn create_data_offer(&mut self, data: Vec<PasteData>) {
-        let Some(dev) = &self.state.data_device else {
-            panic!("No data device created");
-        };
-
         if let Some(selection) = self.state.selection.take() {
             selection.cancelled();
         }
 
         let mimes: Vec<_> = data.iter().map(|m| m.mime_type.clone()).collect();
+        if let Some(dev) = &self.state.data_control_device {
+            let offer = self
+                .client
+                .as_ref()
+                .unwrap()
+                .create_resource::<ExtDataControlOfferV1, _, State>(&self.dh, 1, data)
+                .unwrap();
+            dev.data_offer(&offer);
+            for mime in mimes {
+                offer.offer(mime);
+            }
+            dev.selection(Some(&offer));
+            self.display.flush_clients().unwrap();
+            return;
+        }
+
+        let Some(dev) = &self.state.data_device else {
+            panic!("No data device created");
+        };
         let offer = self
             .client
             .as_ref()
@@ -630,6 +832,39 @@ impl Server {
         self.display.flush_clients().unwrap();
     }
 
+    /// Grants or takes away the pointer lock or confinement the client asked for on a surface.
+    #[track_caller]
+    pub fn set_pointer_constraint_active(&mut self, surface: SurfaceId, active: bool) {
+        let constraint = self
+            .state
+            .pointer_constraints
+            .get(&surface)
+            .expect("No pointer constraint on surface");
+        match (constraint, active) {
+            (PointerConstraint::Locked(locked), true) => locked.locked(),
+            (PointerConstraint::Locked(locked), false) => locked.unlocked(),
+            (PointerConstraint::Confined(confined), true) => confined.confined(),
+            (PointerConstraint::Confined(confined), false) => confined.unconfined(),
+        }
+        self.display.flush_clients().unwrap();
+    }
+
+    #[track_caller]
+    pub fn press_button(&mut self, serial: u32, button: u32) {
+        let pointer = self.state.pointer.as_ref().expect("No pointer created");
+        pointer.button(serial, 0, button, wl_pointer::ButtonState::Pressed);
+        pointer.frame();
+        self.display.flush_clients().unwrap();
+    }
+
+    #[track_caller]
+    pub fn release_button(&mut self, serial: u32, button: u32) {
+        let pointer = self.state.pointer.as_ref().expect("No pointer created");
+        pointer.button(serial, 0, button, wl_pointer::ButtonState::Released);
+        pointer.frame();
+        self.display.flush_clients().unwrap();
+    }
+
     pub fn new_output(&mut self, x: i32, y: i32) {
         self.dh.create_global::<State, WlOutput, _>(4, (x, y));
         self.display.flush_clients().unwrap();
@@ -657,12 +892,47 @@ impl Server {
         self.display.flush_clients().unwrap();
     }
 
+    pub fn set_output_mode(
+        &mut self,
+        output: &WlOutput,
+        (width, height): (i32, i32),
+        transform: wl_output::Transform,
+    ) {
+        output.geometry(
+            0,
+            0,
+            0,
+            0,
+            wl_output::Subpixel::None,
+            "".into(),
+            "".into(),
+            transform,
+        );
+        output.mode(wl_output::Mode::Current, width, height, 0);
+        output.done();
+        self.display.flush_clients().unwrap();
+    }
+
+    pub fn set_output_scale(&mut self, output: &WlOutput, factor: i32) {
+        output.scale(factor);
+        output.done();
+        self.display.flush_clients().unwrap();
+    }
+
     pub fn move_surface_to_output(&mut self, surface: SurfaceId, output: &WlOutput) {
         let data = self.state.surfaces.get(&surface).expect("No such surface");
         data.surface.enter(output);
         self.display.flush_clients().unwrap();
     }
 
+    /// Offers ext-data-control, which satellite prefers over wl_data_device for the clipboard.
+    /// Has to be called before satellite binds globals.
+    pub fn enable_data_control(&mut self) {
+        self.dh
+            .create_global::<State, ExtDataControlManagerV1, _>(1, ());
+        self.display.flush_clients().unwrap();
+    }
+
     pub fn enable_xdg_output_manager(&mut self) {
         self.dh
             .create_global::<State, ZxdgOutputManagerV1, _>(3, ());
@@ -686,11 +956,52 @@ pub struct PasteData {
     pub data: Vec<u8>,
 }
 
-simple_global_dispatch!(WlShm);
+impl GlobalDispatch<WlShm, ()> for State {
+    fn bind(
+        _: &mut Self,
+        _: &DisplayHandle,
+        _: &wayland_server::Client,
+        resource: wayland_server::New<WlShm>,
+        _: &(),
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        use proto::wl_shm::Format;
+        let shm = data_init.init(resource, ());
+        for format in [
+            Format::Argb8888,
+            Format::Xrgb8888,
+            Format::Xrgb2101010,
+            Format::Abgr16161616f,
+        ] {
+            shm.format(format);
+        }
+    }
+}
 simple_global_dispatch!(WlCompositor);
-simple_global_dispatch!(XdgWmBase);
 simple_global_dispatch!(ZxdgOutputManagerV1);
 simple_global_dispatch!(ZwpTabletManagerV2);
+simple_global_dispatch!(XdgWmDialogV1);
+simple_global_dispatch!(ZxdgDecorationManagerV1);
+simple_global_dispatch!(XdgActivationV1);
+simple_global_dispatch!(WpContentTypeManagerV1);
+simple_global_dispatch!(WpViewporter);
+simple_global_dispatch!(WpTearingControlManagerV1);
+simple_global_dispatch!(ZwpIdleInhibitManagerV1);
+simple_global_dispatch!(ZwpPointerConstraintsV1);
+simple_global_dispatch!(ExtDataControlManagerV1);
+
+impl GlobalDispatch<XdgWmBase, ()> for State {
+    fn bind(
+        state: &mut Self,
+        _: &DisplayHandle,
+        _: &wayland_server::Client,
+        resource: wayland_server::New<XdgWmBase>,
+        _: &(),
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        state.wm_base = Some(data_init.init(resource, ()));
+    }
+}
 
 impl Dispatch<ZwpTabletManagerV2, ()> for State {
     fn request(
@@ -817,8 +1128,8 @@ impl GlobalDispatch<WlOutput, (i32, i32)> for State {
         output.geometry(
             x,
             y,
-            0,
-            0,
+            260,
+            260,
             wl_output::Subpixel::None,
             "xwls".to_string(),
             "fake monitor".to_string(),
@@ -826,7 +1137,7 @@ impl GlobalDispatch<WlOutput, (i32, i32)> for State {
         );
         let name = format!("WL-{}", state.outputs.len() + 1);
         output.name(name.clone());
-        output.mode(wl_output::Mode::Current, 1000, 1000, 0);
+        output.mode(wl_output::Mode::Current, 1000, 1000, 60000);
         output.done();
         state
             .outputs
@@ -879,8 +1190,8 @@ impl Dispatch<WlDataOffer, Vec<PasteData>> for State {
                     .position(|data| data.mime_type == mime_type)
                     .unwrap_or_else(|| panic!("Invalid mime type: {mime_type}"));
 
-                let mut stream = UnixStream::from(fd);
-                stream.write_all(&data[pos].data).unwrap();
+                let mut pipe = std::fs::File::from(fd);
+                pipe.write_all(&data[pos].data).unwrap();
             }
             wl_data_offer::Request::Destroy => {}
             other => todo!("unhandled request: {other:?}"),
@@ -923,7 +1234,7 @@ impl Dispatch<WlDataDevice, WlSeat> for State {
     ) {
         match request {
             wl_data_device::Request::SetSelection { source, .. } => {
-                state.selection = source;
+                state.selection = source.map(Selection::DataDevice);
             }
             wl_data_device::Request::Release => {
                 state.data_device = None;
@@ -955,6 +1266,99 @@ impl Dispatch<WlDataDeviceManager, ()> for State {
     }
 }
 
+impl Dispatch<ExtDataControlManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ExtDataControlManagerV1,
+        request: <ExtDataControlManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_data_control_manager_v1::Request::CreateDataSource { id } => {
+                data_init.init(id, DataSourceData::default().into());
+            }
+            ext_data_control_manager_v1::Request::GetDataDevice { id, seat } => {
+                state.data_control_device = Some(data_init.init(id, seat));
+            }
+            ext_data_control_manager_v1::Request::Destroy => {}
+            other => todo!("unhandled request: {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ExtDataControlDeviceV1, WlSeat> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ExtDataControlDeviceV1,
+        request: <ExtDataControlDeviceV1 as Resource>::Request,
+        _: &WlSeat,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_data_control_device_v1::Request::SetSelection { source } => {
+                state.selection = source.map(Selection::DataControl);
+            }
+            ext_data_control_device_v1::Request::Destroy => {
+                state.data_control_device = None;
+            }
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ExtDataControlSourceV1, Mutex<DataSourceData>> for State {
+    fn request(
+        state: &mut Self,
+        _: &Client,
+        _: &ExtDataControlSourceV1,
+        request: <ExtDataControlSourceV1 as Resource>::Request,
+        data: &Mutex<DataSourceData>,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_data_control_source_v1::Request::Offer { mime_type } => {
+                data.lock().unwrap().mimes.push(mime_type);
+            }
+            ext_data_control_source_v1::Request::Destroy => {
+                state.selection = None;
+            }
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ExtDataControlOfferV1, Vec<PasteData>> for State {
+    fn request(
+        _: &mut Self,
+        _: &Client,
+        _: &ExtDataControlOfferV1,
+        request: <ExtDataControlOfferV1 as Resource>::Request,
+        data: &Vec<PasteData>,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            ext_data_control_offer_v1::Request::Receive { mime_type, fd } => {
+                let pos = data
+                    .iter()
+                    .position(|data| data.mime_type == mime_type)
+                    .unwrap_or_else(|| panic!("Invalid mime type: {mime_type}"));
+
+                let mut pipe = std::fs::File::from(fd);
+                pipe.write_all(&data[pos].data).unwrap();
+            }
+            ext_data_control_offer_v1::Request::Destroy => {}
+            other => todo!("unhandled request: {other:?}"),
+        }
+    }
+}
+
 impl GlobalDispatch<WlSeat, ()> for State {
     fn bind(
         _: &mut Self,
@@ -1050,15 +1454,44 @@ impl Dispatch<WlKeyboard, ()> for State {
 impl Dispatch<XdgPopup, SurfaceId> for State {
     fn request(
         state: &mut Self,
-        _: &Client,
-        _: &XdgPopup,
+        client: &Client,
+        resource: &XdgPopup,
         request: <XdgPopup as Resource>::Request,
         surface_id: &SurfaceId,
-        _: &DisplayHandle,
+        dh: &DisplayHandle,
         _: &mut wayland_server::DataInit<'_, Self>,
     ) {
         match request {
-            xdg_popup::Request::Destroy => {}
+            xdg_popup::Request::Destroy => {
+                let Some(SurfaceRole::Popup(p)) = &state.surfaces[surface_id].role else {
+                    unreachable!();
+                };
+                let has_child = state.surfaces.values().any(|data| {
+                    matches!(
+                        &data.role,
+                        Some(SurfaceRole::Popup(child))
+                            if child.parent == p.xdg.surface && child.popup.is_alive()
+                    )
+                });
+                if has_child {
+                    client.kill(
+                        dh,
+                        ProtocolError {
+                            code: xdg_wm_base::Error::NotTheTopmostPopup.into(),
+                            object_id: resource.id().protocol_id(),
+                            object_interface: XdgPopup::interface().name.to_string(),
+                            message: "destroyed popup with a child popup".to_string(),
+                        },
+                    );
+                }
+            }
+            xdg_popup::Request::Grab { serial, .. } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Popup(p)) = &mut data.role else {
+                    unreachable!();
+                };
+                p.grab_serial = Some(serial);
+            }
             xdg_popup::Request::Reposition { positioner, token } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Popup(p)) = &mut data.role else {
@@ -1079,13 +1512,22 @@ impl Dispatch<XdgToplevel, SurfaceId> for State {
     fn request(
         state: &mut Self,
         _: &wayland_server::Client,
-        _: &XdgToplevel,
+        resource: &XdgToplevel,
         request: <XdgToplevel as Resource>::Request,
         surface_id: &SurfaceId,
         _: &DisplayHandle,
         _: &mut wayland_server::DataInit<'_, Self>,
     ) {
         match request {
+            xdg_toplevel::Request::SetMinSize { width, height }
+            | xdg_toplevel::Request::SetMaxSize { width, height }
+                if width < 0 || height < 0 =>
+            {
+                resource.post_error(
+                    xdg_toplevel::Error::InvalidSize,
+                    format!("invalid size limit {width}x{height}"),
+                );
+            }
             xdg_toplevel::Request::SetMinSize { width, height } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
@@ -1106,15 +1548,25 @@ impl Dispatch<XdgToplevel, SurfaceId> for State {
                     y: height,
                 });
             }
-            xdg_toplevel::Request::SetFullscreen { .. } => {
+            xdg_toplevel::Request::SetFullscreen { output } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
                     unreachable!();
                 };
+                toplevel.fullscreen_output = output;
                 toplevel.states.push(xdg_toplevel::State::Fullscreen);
                 let states = toplevel.states.clone();
                 state.configure_toplevel(*surface_id, 100, 100, states);
             }
+            xdg_toplevel::Request::SetMaximized => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.states.push(xdg_toplevel::State::Maximized);
+                let states = toplevel.states.clone();
+                state.configure_toplevel(*surface_id, 100, 100, states);
+            }
             xdg_toplevel::Request::UnsetFullscreen { .. } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
@@ -1132,28 +1584,503 @@ impl Dispatch<XdgToplevel, SurfaceId> for State {
                 let states = toplevel.states.clone();
                 state.configure_toplevel(*surface_id, 100, 100, states);
             }
-            xdg_toplevel::Request::Destroy => {}
-            xdg_toplevel::Request::SetTitle { title } => {
+            xdg_toplevel::Request::UnsetMaximized => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
                     unreachable!();
                 };
-                toplevel.title = title.into();
+                let Some(pos) = toplevel
+                    .states
+                    .iter()
+                    .copied()
+                    .position(|p| p == xdg_toplevel::State::Maximized)
+                else {
+                    return;
+                };
+                toplevel.states.swap_remove(pos);
+                let states = toplevel.states.clone();
+                state.configure_toplevel(*surface_id, 100, 100, states);
             }
-            xdg_toplevel::Request::SetAppId { app_id } => {
+            xdg_toplevel::Request::SetParent { parent } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
                     unreachable!();
                 };
-                toplevel.app_id = app_id.into();
+                toplevel.parent = parent;
             }
-            other => todo!("unhandled request {other:?}"),
-        }
-    }
-}
-
-impl Dispatch<XdgSurface, SurfaceId> for State {
-    fn request(
+            xdg_toplevel::Request::SetMinimized => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.minimized = true;
+            }
+            xdg_toplevel::Request::Destroy => {}
+            xdg_toplevel::Request::Move { serial, .. } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.grab = Some(ToplevelGrab::Move { serial });
+            }
+            xdg_toplevel::Request::Resize { serial, edges, .. } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.grab = Some(ToplevelGrab::Resize {
+                    serial,
+                    edges: edges.into_result().unwrap(),
+                });
+            }
+            xdg_toplevel::Request::SetTitle { title } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.title = title.into();
+            }
+            xdg_toplevel::Request::SetAppId { app_id } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let Some(SurfaceRole::Toplevel(toplevel)) = &mut data.role else {
+                    unreachable!();
+                };
+                toplevel.app_id = app_id.into();
+            }
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<XdgWmDialogV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &wayland_server::Client,
+        _: &XdgWmDialogV1,
+        request: <XdgWmDialogV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_wm_dialog_v1::Request::GetXdgDialog { id, toplevel } => {
+                let surface_id: SurfaceId = *toplevel.data().unwrap();
+                data_init.init(id, surface_id);
+            }
+            xdg_wm_dialog_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ZxdgDecorationManagerV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZxdgDecorationManagerV1,
+        request: <ZxdgDecorationManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zxdg_decoration_manager_v1::Request::GetToplevelDecoration { id, toplevel } => {
+                let surface_id: SurfaceId = *toplevel.data().unwrap();
+                data_init.init(id, surface_id);
+            }
+            zxdg_decoration_manager_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<XdgActivationV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &XdgActivationV1,
+        request: <XdgActivationV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_activation_v1::Request::GetActivationToken { id } => {
+                data_init.init(id, Mutex::new(None));
+            }
+            xdg_activation_v1::Request::Activate { token, surface } => {
+                let surface_id = SurfaceId(surface.id().protocol_id());
+                let Some(SurfaceRole::Toplevel(toplevel)) = state
+                    .surfaces
+                    .get_mut(&surface_id)
+                    .and_then(|data| data.role.as_mut())
+                else {
+                    panic!("activated {surface_id:?} ({token}), which is not a toplevel");
+                };
+                match state.activation_tokens.remove(&token) {
+                    Some(Some(serial)) => toplevel.activation_serial = Some(serial),
+                    Some(None) => toplevel.attention_requested = true,
+                    None => panic!("unknown activation token {token}"),
+                }
+            }
+            xdg_activation_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<WpContentTypeManagerV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &wayland_server::Client,
+        _: &WpContentTypeManagerV1,
+        request: <WpContentTypeManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_content_type_manager_v1::Request::GetSurfaceContentType { id, surface } => {
+                data_init.init(id, SurfaceId(surface.id().protocol_id()));
+            }
+            wp_content_type_manager_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<WpContentTypeV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &WpContentTypeV1,
+        request: <WpContentTypeV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        let content_type = match request {
+            wp_content_type_v1::Request::SetContentType {
+                content_type: WEnum::Value(content_type),
+            } => Some(content_type),
+            wp_content_type_v1::Request::Destroy => None,
+            other => todo!("unhandled request {other:?}"),
+        };
+        if let Some(data) = state.surfaces.get_mut(surface_id) {
+            data.content_type = content_type;
+        }
+    }
+}
+
+impl Dispatch<WpViewporter, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        viewporter: &WpViewporter,
+        request: <WpViewporter as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_viewporter::Request::GetViewport { id, surface } => {
+                let surface_id = SurfaceId(surface.id().protocol_id());
+                data_init.init(id, surface_id);
+                let data = state.surfaces.get_mut(&surface_id).unwrap();
+                if data.viewport.is_some() {
+                    viewporter.post_error(
+                        wp_viewporter::Error::ViewportExists,
+                        "surface already has a viewport",
+                    );
+                    return;
+                }
+                data.viewport = Some(Viewport { destination: None });
+            }
+            wp_viewporter::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<WpViewport, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        viewport: &WpViewport,
+        request: <WpViewport as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        let data = state.surfaces.get_mut(surface_id);
+        match request {
+            wp_viewport::Request::Destroy => {
+                if let Some(data) = data {
+                    data.viewport = None;
+                }
+            }
+            wp_viewport::Request::SetSource { .. } if data.is_some() => {}
+            wp_viewport::Request::SetDestination { width, height } if data.is_some() => {
+                data.unwrap().viewport.as_mut().unwrap().destination =
+                    (width != -1).then_some(Vec2 {
+                        x: width,
+                        y: height,
+                    });
+            }
+            _ => viewport.post_error(
+                wp_viewport::Error::NoSurface,
+                "the viewport's surface is gone",
+            ),
+        }
+    }
+}
+
+impl Dispatch<WpTearingControlManagerV1, ()> for State {
+    fn request(
+        _: &mut Self,
+        _: &wayland_server::Client,
+        _: &WpTearingControlManagerV1,
+        request: <WpTearingControlManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_tearing_control_manager_v1::Request::GetTearingControl { id, surface } => {
+                data_init.init(id, SurfaceId(surface.id().protocol_id()));
+            }
+            wp_tearing_control_manager_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<WpTearingControlV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &WpTearingControlV1,
+        request: <WpTearingControlV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        let hint = match request {
+            wp_tearing_control_v1::Request::SetPresentationHint {
+                hint: WEnum::Value(hint),
+            } => Some(hint),
+            wp_tearing_control_v1::Request::Destroy => None,
+            other => todo!("unhandled request {other:?}"),
+        };
+        if let Some(data) = state.surfaces.get_mut(surface_id) {
+            data.presentation_hint = hint;
+        }
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitManagerV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZwpIdleInhibitManagerV1,
+        request: <ZwpIdleInhibitManagerV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_idle_inhibit_manager_v1::Request::CreateInhibitor { id, surface } => {
+                let surface_id = SurfaceId(surface.id().protocol_id());
+                if let Some(data) = state.surfaces.get_mut(&surface_id) {
+                    data.idle_inhibited = true;
+                }
+                data_init.init(id, surface_id);
+            }
+            zwp_idle_inhibit_manager_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ZwpIdleInhibitorV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZwpIdleInhibitorV1,
+        request: <ZwpIdleInhibitorV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_idle_inhibitor_v1::Request::Destroy => {
+                if let Some(data) = state.surfaces.get_mut(surface_id) {
+                    data.idle_inhibited = false;
+                }
+            }
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ZwpPointerConstraintsV1, ()> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZwpPointerConstraintsV1,
+        request: <ZwpPointerConstraintsV1 as Resource>::Request,
+        _: &(),
+        _: &DisplayHandle,
+        data_init: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_pointer_constraints_v1::Request::LockPointer { id, surface, .. } => {
+                let surface_id = SurfaceId(surface.id().protocol_id());
+                let locked = data_init.init(id, surface_id);
+                state
+                    .pointer_constraints
+                    .insert(surface_id, PointerConstraint::Locked(locked));
+            }
+            zwp_pointer_constraints_v1::Request::ConfinePointer { id, surface, .. } => {
+                let surface_id = SurfaceId(surface.id().protocol_id());
+                let confined = data_init.init(id, surface_id);
+                state
+                    .pointer_constraints
+                    .insert(surface_id, PointerConstraint::Confined(confined));
+            }
+            zwp_pointer_constraints_v1::Request::Destroy => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ZwpLockedPointerV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZwpLockedPointerV1,
+        request: <ZwpLockedPointerV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_locked_pointer_v1::Request::Destroy => {
+                state.pointer_constraints.remove(surface_id);
+            }
+            zwp_locked_pointer_v1::Request::SetCursorPositionHint { .. }
+            | zwp_locked_pointer_v1::Request::SetRegion { .. } => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<ZwpConfinedPointerV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZwpConfinedPointerV1,
+        request: <ZwpConfinedPointerV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            zwp_confined_pointer_v1::Request::Destroy => {
+                state.pointer_constraints.remove(surface_id);
+            }
+            zwp_confined_pointer_v1::Request::SetRegion { .. } => {}
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+/// The serial an activation token was created with, if any.
+type ActivationTokenData = Mutex<Option<u32>>;
+
+impl Dispatch<XdgActivationTokenV1, ActivationTokenData> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        token: &XdgActivationTokenV1,
+        request: <XdgActivationTokenV1 as Resource>::Request,
+        serial: &ActivationTokenData,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            xdg_activation_token_v1::Request::SetSerial { serial: s, .. } => {
+                *serial.lock().unwrap() = Some(s);
+            }
+            xdg_activation_token_v1::Request::SetSurface { .. }
+            | xdg_activation_token_v1::Request::SetAppId { .. }
+            | xdg_activation_token_v1::Request::Destroy => {}
+            xdg_activation_token_v1::Request::Commit => {
+                let name = format!("token-{}", token.id().protocol_id());
+                state
+                    .activation_tokens
+                    .insert(name.clone(), *serial.lock().unwrap());
+                token.done(name);
+            }
+            other => todo!("unhandled request {other:?}"),
+        }
+    }
+}
+
+impl Dispatch<XdgDialogV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &XdgDialogV1,
+        request: <XdgDialogV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        let modal = match request {
+            xdg_dialog_v1::Request::SetModal => true,
+            xdg_dialog_v1::Request::UnsetModal | xdg_dialog_v1::Request::Destroy => false,
+            other => todo!("unhandled request {other:?}"),
+        };
+        // The toplevel may already be gone when the dialog is destroyed
+        if let Some(SurfaceRole::Toplevel(toplevel)) = state
+            .surfaces
+            .get_mut(surface_id)
+            .and_then(|data| data.role.as_mut())
+        {
+            toplevel.modal = modal;
+        }
+    }
+}
+
+impl Dispatch<ZxdgToplevelDecorationV1, SurfaceId> for State {
+    fn request(
+        state: &mut Self,
+        _: &wayland_server::Client,
+        _: &ZxdgToplevelDecorationV1,
+        request: <ZxdgToplevelDecorationV1 as Resource>::Request,
+        surface_id: &SurfaceId,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        let mode = match request {
+            zxdg_toplevel_decoration_v1::Request::SetMode { mode } => mode.into_result().ok(),
+            zxdg_toplevel_decoration_v1::Request::UnsetMode
+            | zxdg_toplevel_decoration_v1::Request::Destroy => None,
+            other => todo!("unhandled request {other:?}"),
+        };
+        if let Some(SurfaceRole::Toplevel(toplevel)) = state
+            .surfaces
+            .get_mut(surface_id)
+            .and_then(|data| data.role.as_mut())
+        {
+            toplevel.decoration = mode;
+        }
+    }
+}
+
+impl Dispatch<XdgSurface, SurfaceId> for State {
+    fn request(
         state: &mut Self,
         client: &wayland_server::Client,
         resource: &XdgSurface,
@@ -1173,9 +2100,17 @@ impl Dispatch<XdgSurface, SurfaceId> for State {
                     min_size: None,
                     max_size: None,
                     states: Vec::new(),
+                    fullscreen_output: None,
                     closed: false,
+                    minimized: false,
+                    attention_requested: false,
+                    activation_serial: None,
+                    parent: None,
+                    modal: false,
+                    decoration: None,
                     title: None,
                     app_id: None,
+                    grab: None,
                 };
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 data.role = Some(SurfaceRole::Toplevel(t));
@@ -1193,10 +2128,31 @@ impl Dispatch<XdgSurface, SurfaceId> for State {
                     positioner_state: state.positioners
                         [&PositionerId(positioner.id().protocol_id())]
                         .clone(),
+                    grab_serial: None,
                 };
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 data.role = Some(SurfaceRole::Popup(p));
             }
+            xdg_surface::Request::SetWindowGeometry {
+                x,
+                y,
+                width,
+                height,
+            } => {
+                let data = state.surfaces.get_mut(surface_id).unwrap();
+                let xdg = match data.role.as_mut() {
+                    Some(SurfaceRole::Toplevel(t)) => &mut t.xdg,
+                    Some(SurfaceRole::Popup(p)) => &mut p.xdg,
+                    other => panic!("set window geometry on surface with role {other:?}"),
+                };
+                xdg.window_geometry = Some(Rect {
+                    size: Vec2 {
+                        x: width,
+                        y: height,
+                    },
+                    offset: Vec2 { x, y },
+                });
+            }
             xdg_surface::Request::AckConfigure { serial } => {
                 let data = state.surfaces.get_mut(surface_id).unwrap();
                 assert_eq!(data.xdg().last_configure_serial, serial);
@@ -1239,6 +2195,7 @@ pub struct PositionerState {
     pub offset: Vec2,
     pub anchor: xdg_positioner::Anchor,
     pub gravity: xdg_positioner::Gravity,
+    pub constraint_adjustment: xdg_positioner::ConstraintAdjustment,
 }
 
 impl Default for PositionerState {
@@ -1249,6 +2206,7 @@ impl Default for PositionerState {
             offset: Vec2 { x: 0, y: 0 },
             anchor: xdg_positioner::Anchor::None,
             gravity: xdg_positioner::Gravity::None,
+            constraint_adjustment: xdg_positioner::ConstraintAdjustment::empty(),
         }
     }
 }
@@ -1271,6 +2229,13 @@ impl Dispatch<XdgPositioner, ()> for State {
         };
         match request {
             xdg_positioner::Request::SetSize { width, height } => {
+                if width <= 0 || height <= 0 {
+                    resource.post_error(
+                        xdg_positioner::Error::InvalidInput,
+                        format!("invalid size {width}x{height}"),
+                    );
+                    return;
+                }
                 data.get_mut().size = Some(Vec2 {
                     x: width,
                     y: height,
@@ -1282,6 +2247,13 @@ impl Dispatch<XdgPositioner, ()> for State {
                 width,
                 height,
             } => {
+                if width < 0 || height < 0 {
+                    resource.post_error(
+                        xdg_positioner::Error::InvalidInput,
+                        format!("invalid anchor rect size {width}x{height}"),
+                    );
+                    return;
+                }
                 data.get_mut().anchor_rect = Some(Rect {
                     size: Vec2 {
                         x: width,
@@ -1299,6 +2271,11 @@ impl Dispatch<XdgPositioner, ()> for State {
             xdg_positioner::Request::SetGravity { gravity } => {
                 data.get_mut().gravity = gravity.into_result().unwrap();
             }
+            xdg_positioner::Request::SetConstraintAdjustment {
+                constraint_adjustment,
+            } => {
+                data.get_mut().constraint_adjustment = constraint_adjustment.into_result().unwrap();
+            }
             xdg_positioner::Request::Destroy => {
                 data.remove();
             }
@@ -1342,6 +2319,9 @@ impl Dispatch<XdgWmBase, ()> for State {
                     PositionerState::default(),
                 );
             }
+            xdg_wm_base::Request::Pong { serial } => {
+                state.last_pong = Some(serial);
+            }
             other => todo!("unhandled request {other:?}"),
         }
     }
@@ -1378,9 +2358,17 @@ impl Dispatch<WlShmPool, ()> for State {
     ) {
         use proto::wl_shm_pool::Request::*;
         match request {
-            CreateBuffer { id, .. } => {
+            CreateBuffer {
+                id, width, height, ..
+            } => {
                 let buf = data_init.init(id, ());
-                state.buffers.insert(buf);
+                state.buffers.insert(
+                    buf,
+                    Vec2 {
+                        x: width,
+                        y: height,
+                    },
+                );
             }
             Destroy => {}
             other => todo!("unhandled request {other:?}"),
@@ -1426,13 +2414,23 @@ impl Dispatch<WlCompositor, ()> for State {
                     SurfaceData {
                         surface,
                         buffer: None,
+                        buffer_size: None,
+                        buffer_scale: 1,
                         last_damage: None,
                         role: None,
                         last_enter_serial: None,
+                        content_type: None,
+                        presentation_hint: None,
+                        idle_inhibited: false,
+                        opaque_region: None,
+                        viewport: None,
                     },
                 );
                 state.last_surface_id = Some(SurfaceId(id));
             }
+            proto::wl_compositor::Request::CreateRegion { id } => {
+                data_init.init(id, Mutex::new(Vec::new()));
+            }
             _ => unreachable!(),
         }
     }
@@ -1457,6 +2455,9 @@ impl Dispatch<WlSurface, ()> for State {
 
         match request {
             Attach { buffer, .. } => {
+                data.buffer_size = buffer
+                    .as_ref()
+                    .and_then(|buffer| state.buffers.get(buffer).copied());
                 data.buffer = buffer;
             }
             Frame { callback } => {
@@ -1491,12 +2492,43 @@ impl Dispatch<WlSurface, ()> for State {
                 state.surfaces.remove(&id);
             }
             SetInputRegion { .. } => {}
-            SetBufferScale { .. } => {}
+            SetOpaqueRegion { region } => {
+                data.opaque_region = region.map(|region| {
+                    let rects: &Mutex<Vec<[i32; 4]>> = region.data().unwrap();
+                    rects.lock().unwrap().clone()
+                });
+            }
+            SetBufferScale { scale } => {
+                data.buffer_scale = scale;
+            }
             other => todo!("unhandled request {other:?}"),
         }
     }
 }
 
+impl Dispatch<WlRegion, Mutex<Vec<[i32; 4]>>> for State {
+    fn request(
+        _: &mut Self,
+        _: &wayland_server::Client,
+        _: &WlRegion,
+        request: <WlRegion as Resource>::Request,
+        rects: &Mutex<Vec<[i32; 4]>>,
+        _: &DisplayHandle,
+        _: &mut wayland_server::DataInit<'_, Self>,
+    ) {
+        match request {
+            wl_region::Request::Add {
+                x,
+                y,
+                width,
+                height,
+            } => rects.lock().unwrap().push([x, y, width, height]),
+            wl_region::Request::Destroy => {}
+            other => todo!("unhandled region request {other:?}"),
+        }
+    }
+}
+
 impl Dispatch<WlCallback, ()> for State {
     fn request(
         _: &mut Self,